@@ -0,0 +1,103 @@
+//! Commands for the running application, over the runtime protocol of the
+//! `device-protocol` crate rather than DFU.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use device_protocol::{Runtime, SelfTest, Settings};
+
+use crate::device::Device;
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Print the firmware version and hardware revision.
+    Version,
+    /// Print the battery voltage and charge.
+    Battery,
+    /// Print the settings, or change those given.
+    Settings(SettingsArgs),
+    /// Run the device's self-test; fails if any check fails.
+    SelfTest(SelfTestArgs),
+    /// Restart the device into its DFU bootloader.
+    RebootDfu,
+}
+
+#[derive(clap::Args)]
+pub struct SettingsArgs {
+    /// LED brightness in percent (0-100).
+    #[clap(long)]
+    brightness: Option<u8>,
+
+    /// Brake-light flash pattern number.
+    #[clap(long)]
+    pattern: Option<u8>,
+
+    /// Deceleration that triggers the brake light, in 0.01 g.
+    #[clap(long)]
+    sensitivity: Option<u8>,
+
+    /// Minutes without motion before the device turns off (0: never).
+    #[clap(long)]
+    auto_off: Option<u16>,
+}
+
+#[derive(clap::Args)]
+pub struct SelfTestArgs {
+    /// How long to wait for the self-test to finish.
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    timeout: Duration,
+}
+
+impl Command {
+    pub fn run(self, device: &Device) -> Result<()> {
+        let usb = device.usb_device()?;
+        let app = Runtime::open(&usb).context("could not open the application interface")?;
+        match self {
+            Command::Version => {
+                let version = app.version()?;
+                println!("Firmware {version}, hardware revision {}", version.hardware);
+            }
+            Command::Battery => println!("Battery {}", app.battery()?),
+            Command::Settings(args) => args.run(&app)?,
+            Command::SelfTest(args) => self_test(&app, args.timeout)?,
+            Command::RebootDfu => {
+                app.reboot_to_dfu()?;
+                println!("Device is restarting into DFU mode");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SettingsArgs {
+    fn run(self, app: &Runtime<rusb::Context>) -> Result<()> {
+        let current = app.settings()?;
+        let settings = Settings {
+            brightness: self.brightness.unwrap_or(current.brightness),
+            pattern: self.pattern.unwrap_or(current.pattern),
+            sensitivity: self.sensitivity.unwrap_or(current.sensitivity),
+            auto_off_minutes: self.auto_off.unwrap_or(current.auto_off_minutes),
+        };
+        if settings != current {
+            app.set_settings(settings)?;
+            println!("Settings updated");
+        }
+        println!("Brightness:  {}%", settings.brightness);
+        println!("Pattern:     {}", settings.pattern);
+        println!("Sensitivity: {:.2} g", settings.sensitivity as f32 / 100.0);
+        match settings.auto_off_minutes {
+            0 => println!("Auto-off:    never"),
+            minutes => println!("Auto-off:    after {minutes} min"),
+        }
+        Ok(())
+    }
+}
+
+/// Run the self-test, printing its outcome and failing if it failed.
+pub fn self_test(app: &Runtime<rusb::Context>, timeout: Duration) -> Result<()> {
+    println!("Running self-test");
+    let result = app.self_test(timeout)?;
+    println!("Self-test {result}");
+    anyhow::ensure!(result == SelfTest::Passed, "self-test failed");
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Flashing every connected device (`--all`) or each device as it is
+//! plugged in (`--watch`).
+
+use std::time::Duration;
+
+use anyhow::Result;
+use device_watch::DeviceEvent;
+
+use crate::device::Device;
+
+/// How often `--watch` checks for Ctrl-C between device events.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(clap::Args)]
+pub struct BatchArgs {
+    /// Flash every connected device matching `--device` that is in DFU mode,
+    /// one after another.
+    #[clap(long, conflicts_with = "watch")]
+    all: bool,
+
+    /// Keep running and flash each matching device as it enters DFU mode,
+    /// until Ctrl-C.
+    #[clap(long)]
+    watch: bool,
+
+    /// With `--all`/`--watch`, continue with the other devices after a
+    /// failure. The default for `--watch`.
+    #[clap(long, conflicts_with = "fail_fast")]
+    keep_going: bool,
+
+    /// With `--all`/`--watch`, stop at the first failed device. The default
+    /// for `--all`.
+    #[clap(long)]
+    fail_fast: bool,
+}
+
+/// Result of one device in a batch.
+struct Outcome {
+    label: String,
+    result: Result<()>,
+}
+
+impl BatchArgs {
+    pub fn enabled(&self) -> bool {
+        self.all || self.watch
+    }
+
+    fn keep_going(&self) -> bool {
+        self.keep_going || (self.watch && !self.fail_fast)
+    }
+
+    /// Call `flash` for every device, then print a per-device summary. Fails
+    /// if any device failed.
+    pub fn run(&self, device: &Device, mut flash: impl FnMut(&Device) -> Result<()>) -> Result<()> {
+        let mut outcomes = Vec::new();
+        if self.all {
+            let devices = device.all()?;
+            anyhow::ensure!(
+                !devices.is_empty(),
+                "no device {:04x}:{:04x} in DFU mode found",
+                device.vid,
+                device.pid
+            );
+            for unit in &devices {
+                if !self.flash_one(unit, &mut flash, &mut outcomes) {
+                    break;
+                }
+            }
+        } else {
+            let stop = crate::ctrl_c()?;
+
+            let watcher = device_watch::watch_devices(device.vid, device.pid)?;
+            println!("Waiting for devices; press Ctrl-C to stop");
+            while !stop.is_cancelled() {
+                if let Some(DeviceEvent::Arrived(arrived)) = watcher.next_timeout(POLL_INTERVAL)
+                    && arrived.dfu
+                {
+                    let unit = Device {
+                        port: Some(arrived.port()),
+                        ..device.clone()
+                    };
+                    if !self.flash_one(&unit, &mut flash, &mut outcomes) {
+                        break;
+                    }
+                }
+            }
+        }
+        summarize(&outcomes)
+    }
+
+    /// Flash `unit`, recording the outcome. Returns whether to go on.
+    fn flash_one(
+        &self,
+        unit: &Device,
+        flash: &mut impl FnMut(&Device) -> Result<()>,
+        outcomes: &mut Vec<Outcome>,
+    ) -> bool {
+        let label = label(unit);
+        println!("== {label}");
+        let result = flash(unit);
+        if let Err(e) = &result {
+            eprintln!("{label} failed: {e:#}");
+        }
+        let go_on = result.is_ok() || self.keep_going();
+        outcomes.push(Outcome { label, result });
+        go_on
+    }
+}
+
+/// Port and, if the device has one, serial number of `unit`.
+fn label(unit: &Device) -> String {
+    let (bus, address) = unit.port.unwrap_or_default();
+    let mut label = format!("bus {bus:03} address {address:03}");
+    if let Ok(Some(serial)) = unit.serial_number() {
+        label += &format!(" (serial {serial})");
+    }
+    label
+}
+
+fn summarize(outcomes: &[Outcome]) -> Result<()> {
+    println!("Summary:");
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(()) => println!("  {}: ok", outcome.label),
+            Err(e) => println!("  {}: FAILED: {e:#}", outcome.label),
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    println!(
+        "{} devices, {} ok, {failed} failed",
+        outcomes.len(),
+        outcomes.len() - failed
+    );
+    anyhow::ensure!(failed == 0, "{failed} of {} devices failed", outcomes.len());
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bikesafe_core::family::MemoryMap;
+use dfu_core::DfuIo;
+
+use crate::device::Device;
+use crate::dfuse;
+
+#[derive(clap::Args)]
+pub struct BenchmarkArgs {
+    /// Start address of the scratch region. Defaults to the start of the
+    /// device's application flash.
+    #[clap(long, short, value_parser = crate::Cli::parse_address)]
+    address: Option<u32>,
+
+    /// Size of the scratch region, e.g. 4096, 0x1000 or 4K.
+    #[clap(long, short, default_value = "4K", value_parser = crate::Cli::parse_size)]
+    length: u32,
+
+    /// Transfer sizes to try, comma separated.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "64,128,256,512,1024,2048"
+    )]
+    sizes: Vec<usize>,
+
+    /// Also time downloads. This ERASES and overwrites the scratch region.
+    #[clap(long)]
+    write: bool,
+
+    /// Do not ask for confirmation.
+    #[clap(long)]
+    yes: bool,
+}
+
+impl BenchmarkArgs {
+    /// Time uploads (and optionally downloads) of the scratch region at each
+    /// transfer size.
+    pub fn run(self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let address = self.address.unwrap_or(memory.flash.origin);
+        let io = device.open()?.into_inner();
+        let descriptor = *io.functional_descriptor();
+        let max = descriptor.transfer_size as usize;
+        let length = self.length as usize;
+
+        if self.write {
+            anyhow::ensure!(descriptor.can_download, "device does not support download");
+            println!(
+                "The region {:#010X}..{:#010X} will be erased and overwritten.",
+                address,
+                address as u64 + self.length as u64
+            );
+            crate::confirm("Run the download benchmark?", self.yes)?;
+        }
+
+        println!("wTransferSize: {max} bytes");
+        println!("{:>8}  {:>14}  {:>14}", "size", "upload", "download");
+        let pattern: Vec<u8> = (0..length).map(|i| (i * 31 + 7) as u8).collect();
+        for size in self.sizes {
+            if size == 0 || size > max {
+                println!("{size:>8}  skipped (device accepts 1..={max})");
+                continue;
+            }
+
+            let upload = if descriptor.can_upload {
+                let start = Instant::now();
+                dfuse::upload(&io, address, length, size, |_| ())
+                    .with_context(|| format!("upload with {size} byte transfers failed"))?;
+                rate(length, start.elapsed())
+            } else {
+                "n/a".to_string()
+            };
+
+            let download = if self.write {
+                dfuse::erase(&io, address, self.length, |_| ())
+                    .context("could not erase the scratch region")?;
+                let start = Instant::now();
+                dfuse::download(&io, address, &pattern, size, |_| ())
+                    .with_context(|| format!("download with {size} byte transfers failed"))?;
+                rate(length, start.elapsed())
+            } else {
+                "-".to_string()
+            };
+
+            println!("{size:>8}  {upload:>14}  {download:>14}");
+        }
+        Ok(())
+    }
+}
+
+fn rate(bytes: usize, elapsed: Duration) -> String {
+    let per_second = bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    format!("{:.1} KiB/s", per_second / 1024.0)
+}
@@ -0,0 +1,47 @@
+//! The command-line side of release bundles (see
+//! [`firmware_manifest::bundle`]): the key options and the check against
+//! the connected device.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use bikesafe_core::family::MemoryMap;
+use ed25519_dalek::VerifyingKey;
+pub use firmware_manifest::bundle::Bundle;
+
+use crate::device::Device;
+
+/// How bundle signatures are checked.
+#[derive(clap::Args)]
+pub struct KeyArgs {
+    /// Hex-encoded ed25519 public key checking the bundle signature.
+    #[clap(long, value_name = "FILE", env = "BIKESAFE_PUBLIC_KEY")]
+    public_key: Option<PathBuf>,
+
+    /// Accept bundles without checking their signature.
+    #[clap(long, conflicts_with = "public_key")]
+    allow_unsigned: bool,
+}
+
+impl KeyArgs {
+    /// The key to check signatures with, `None` if checking was waived.
+    pub fn key(&self) -> Result<Option<VerifyingKey>> {
+        match &self.public_key {
+            Some(path) => Ok(Some(firmware_manifest::read_public_key(path)?)),
+            None if self.allow_unsigned => Ok(None),
+            None => anyhow::bail!("bundles need --public-key or --allow-unsigned"),
+        }
+    }
+}
+
+/// Fail unless the connected device is listed in the bundle's manifest and
+/// the image fits its chip's flash.
+pub fn check_compatible(bundle: &Bundle, device: &Device, memory: &MemoryMap) -> Result<()> {
+    let identity = device.identify(memory)?;
+    Ok(bundle.check_compatible(
+        identity.vid,
+        identity.pid,
+        &identity.revisions,
+        identity.flash_end,
+    )?)
+}
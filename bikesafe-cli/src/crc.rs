@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use bikesafe_core::family::MemoryMap;
+use bikesafe_core::progress::Counter;
+use bikesafe_core::{Phase, ProgressSink};
+use dfu_core::DfuIo;
+use sha2::{Digest, Sha256};
+
+use crate::device::Device;
+use crate::dfuse;
+use crate::progress::Progress;
+
+#[derive(clap::Args)]
+pub struct CrcArgs {
+    /// Start address of the region. Defaults to the start of the device's
+    /// application flash.
+    #[clap(long, short, value_parser = crate::Cli::parse_address)]
+    address: Option<u32>,
+
+    /// Number of bytes to read, e.g. 49152, 0xC000 or 48K.
+    #[clap(long, short, value_parser = crate::Cli::parse_size)]
+    length: u32,
+}
+
+impl CrcArgs {
+    /// Read the region back from the device and print its checksums.
+    pub fn run(self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let address = self.address.unwrap_or(memory.flash.origin);
+        let io = device.open()?.into_inner();
+        let descriptor = *io.functional_descriptor();
+        anyhow::ensure!(descriptor.can_upload, "device does not support upload");
+
+        let mut progress = Progress::new()?;
+        let mut count = Counter::new(&mut progress, Phase::Upload, self.length as u64);
+        let data = dfuse::upload(
+            &io,
+            address,
+            self.length as usize,
+            descriptor.transfer_size as usize,
+            |n| count.advance(n),
+        )
+        .context("could not read memory")?;
+        progress.finished();
+        anyhow::ensure!(
+            data.len() == self.length as usize,
+            "device returned {} of {} bytes",
+            data.len(),
+            self.length
+        );
+
+        let end = address as u64 + self.length as u64;
+        println!(
+            "Region   {:#010X}..{end:#010X} ({} bytes)",
+            address, self.length
+        );
+        println!("CRC32    {:#010X}", crc32fast::hash(&data));
+        println!("SHA-256  {:x}", Sha256::digest(&data));
+        Ok(())
+    }
+}
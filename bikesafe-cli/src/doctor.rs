@@ -0,0 +1,259 @@
+//! Environment checks answering "why can't it see my device".
+
+use std::path::Path;
+
+use anyhow::Result;
+use localization::tr;
+use rusb::UsbContext;
+
+use crate::device::{self, PROTOCOL_DFU, PROTOCOL_RUNTIME};
+use crate::udev;
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&mut self, message: &str) {
+        println!("[PASS] {message}");
+    }
+
+    fn warn(&mut self, message: &str, fix: &str) {
+        println!("[WARN] {message}");
+        print_fix(fix);
+    }
+
+    fn fail(&mut self, message: &str, fix: &str) {
+        self.failures += 1;
+        println!("[FAIL] {message}");
+        print_fix(fix);
+    }
+}
+
+fn print_fix(fix: &str) {
+    for (i, line) in fix.lines().enumerate() {
+        let prefix = if i == 0 { "fix:" } else { "" };
+        println!("       {prefix:<4} {line}");
+    }
+}
+
+/// A device exposing a DFU interface, in runtime or DFU mode.
+struct Found {
+    device: rusb::Device<rusb::Context>,
+    vid: u16,
+    pid: u16,
+    protocol: u8,
+}
+
+impl Found {
+    fn describe(&self) -> String {
+        tr!(
+            "doctor-device",
+            device = format!("{:04x}:{:04x}", self.vid, self.pid),
+            bus = self.device.bus_number(),
+            address = self.device.address(),
+        )
+    }
+}
+
+/// Run all checks for device `vid:pid`, interface `intf`, failing if any
+/// check failed.
+pub fn run((vid, pid): (u16, u16), intf: u8) -> Result<()> {
+    let mut report = Report::default();
+
+    let version = rusb::version();
+    let context = match rusb::Context::new() {
+        Ok(context) => {
+            let version = format!(
+                "{}.{}.{}",
+                version.major(),
+                version.minor(),
+                version.micro()
+            );
+            report.pass(&tr!("doctor-libusb-ok", version = version));
+            context
+        }
+        Err(e) => {
+            report.fail(
+                &tr!("doctor-libusb-failed", error = e.to_string()),
+                &libusb_fix(),
+            );
+            anyhow::bail!("{}", tr!("doctor-failed", count = 1));
+        }
+    };
+
+    let found = dfu_devices(&context)?;
+    let target = found
+        .iter()
+        .find(|f| (f.vid, f.pid) == (vid, pid) && f.protocol == PROTOCOL_DFU);
+    match target {
+        Some(target) => report.pass(&tr!("doctor-dfu-found", device = target.describe())),
+        None => {
+            let (dfu, runtime): (Vec<_>, Vec<_>) =
+                found.iter().partition(|f| f.protocol == PROTOCOL_DFU);
+            if let Some(runtime) = runtime.iter().find(|f| (f.vid, f.pid) == (vid, pid)) {
+                report.fail(
+                    &tr!("doctor-running-app", device = runtime.describe()),
+                    &tr!("doctor-fix-enter-dfu"),
+                );
+            } else if !dfu.is_empty() {
+                let ids: Vec<_> = dfu.iter().map(|f| f.describe()).collect();
+                report.fail(
+                    &tr!(
+                        "doctor-other-dfu",
+                        device = format!("{vid:04x}:{pid:04x}"),
+                        found = ids.join(", "),
+                    ),
+                    &tr!("doctor-fix-select-device"),
+                );
+            } else {
+                report.fail(
+                    &tr!("doctor-no-dfu", device = format!("{vid:04x}:{pid:04x}")),
+                    &tr!("doctor-fix-enter-dfu"),
+                );
+            }
+        }
+    }
+
+    if cfg!(target_os = "linux") {
+        check_udev_rule(&mut report, vid, pid);
+    }
+
+    if let Some(target) = target {
+        check_access(&mut report, &target.device, intf, vid, pid);
+    }
+
+    match report.failures {
+        0 => {
+            println!("{}", tr!("doctor-passed"));
+            Ok(())
+        }
+        n => anyhow::bail!("{}", tr!("doctor-failed", count = n)),
+    }
+}
+
+/// Every device with a DFU interface in its active configuration.
+fn dfu_devices(context: &rusb::Context) -> Result<Vec<Found>> {
+    let mut found = Vec::new();
+    for device in context.devices()?.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        if let Some((_, protocol @ (PROTOCOL_RUNTIME | PROTOCOL_DFU))) =
+            device::dfu_interface(&device)
+        {
+            found.push(Found {
+                device,
+                vid: desc.vendor_id(),
+                pid: desc.product_id(),
+                protocol,
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Open the device and claim the DFU interface the way a download would.
+fn check_access(
+    report: &mut Report,
+    device: &rusb::Device<rusb::Context>,
+    intf: u8,
+    vid: u16,
+    pid: u16,
+) {
+    let handle = match device.open() {
+        Ok(handle) => {
+            report.pass(&tr!("doctor-can-open"));
+            handle
+        }
+        Err(e @ (rusb::Error::Access | rusb::Error::NotSupported)) => {
+            let fix = if cfg!(windows) {
+                tr!("doctor-fix-zadig")
+            } else {
+                udev_fix(vid, pid)
+            };
+            report.fail(&tr!("doctor-cannot-open", error = e.to_string()), &fix);
+            return;
+        }
+        Err(e) => {
+            report.fail(
+                &tr!("doctor-cannot-open", error = e.to_string()),
+                &tr!("doctor-fix-replug"),
+            );
+            return;
+        }
+    };
+
+    if rusb::supports_detach_kernel_driver() && handle.kernel_driver_active(intf) == Ok(true) {
+        report.warn(
+            &tr!("doctor-kernel-driver", intf = intf),
+            &tr!("doctor-fix-kernel-driver"),
+        );
+    }
+
+    match handle.claim_interface(intf) {
+        Ok(()) => report.pass(&tr!("doctor-can-claim", intf = intf)),
+        Err(rusb::Error::Busy) => report.fail(
+            &tr!("doctor-interface-busy", intf = intf),
+            &tr!("doctor-fix-close-tools"),
+        ),
+        Err(e) => report.fail(
+            &tr!("doctor-cannot-claim", intf = intf, error = e.to_string()),
+            &tr!("doctor-fix-intf"),
+        ),
+    }
+}
+
+/// Look for a udev rule mentioning the device in the usual rule directories.
+fn check_udev_rule(report: &mut Report, vid: u16, pid: u16) {
+    let vendor = format!("{vid:04x}");
+    let product = format!("{pid:04x}");
+    let found = [
+        "/etc/udev/rules.d",
+        "/usr/lib/udev/rules.d",
+        "/lib/udev/rules.d",
+    ]
+    .iter()
+    .filter_map(|dir| std::fs::read_dir(dir).ok())
+    .flatten()
+    .filter_map(|entry| entry.ok())
+    .find(|entry| {
+        std::fs::read_to_string(entry.path()).is_ok_and(|rules| {
+            let rules = rules.to_lowercase();
+            rules.contains(&vendor) && rules.contains(&product)
+        })
+    });
+    match found {
+        Some(entry) => report.pass(&tr!(
+            "doctor-udev-found",
+            device = format!("{vendor}:{product}"),
+            path = entry.path().display().to_string(),
+        )),
+        None if Path::new("/run/udev").exists() => report.warn(
+            &tr!(
+                "doctor-udev-missing",
+                device = format!("{vendor}:{product}")
+            ),
+            &udev_fix(vid, pid),
+        ),
+        None => (),
+    }
+}
+
+fn udev_fix(vid: u16, pid: u16) -> String {
+    tr!(
+        "doctor-fix-udev",
+        device = format!("{vid:04x}:{pid:04x}"),
+        path = udev::RULES_PATH,
+        rule = udev::rule(vid, pid),
+    )
+}
+
+fn libusb_fix() -> String {
+    if cfg!(target_os = "linux") {
+        tr!("doctor-fix-libusb-linux")
+    } else {
+        tr!("doctor-fix-libusb")
+    }
+}
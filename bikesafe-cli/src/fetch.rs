@@ -0,0 +1,145 @@
+//! Download the newest published firmware bundle.
+//!
+//! Releases are listed through the GitHub releases API, where firmware
+//! bundles are the release assets named `firmware-*.zip`, or come from an
+//! update feed: one signed `dfu-packager manifest-dir` index per channel,
+//! at `<feed>/<channel>/index.json`. Responses are cached and downloads
+//! resumed by `update-client`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use update_client::Client;
+
+use crate::bundle::{Bundle, KeyArgs};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/mygnu/bikesafe-util/releases";
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Channel {
+    /// Published releases only.
+    Stable,
+    /// Published releases and pre-releases.
+    Beta,
+}
+
+#[derive(clap::Args)]
+pub struct FetchArgs {
+    /// Release channel to pick the newest bundle from.
+    #[clap(long, value_enum, default_value = "stable")]
+    channel: Channel,
+
+    /// Directory the bundle is saved to.
+    #[clap(long, short, default_value = ".")]
+    output: PathBuf,
+
+    /// Release list endpoint (GitHub releases API format).
+    #[clap(long, default_value = RELEASES_URL)]
+    url: String,
+
+    /// Update feed to use instead of the GitHub releases: base URL of one
+    /// directory per channel, each with a signed `index.json`.
+    #[clap(long, env = "BIKESAFE_FEED", conflicts_with = "url")]
+    feed: Option<String>,
+
+    #[clap(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Asset {
+    fn is_bundle(&self) -> bool {
+        self.name.starts_with("firmware-") && self.name.ends_with(".zip")
+    }
+}
+
+impl FetchArgs {
+    /// Download and verify the bundle, then print its path on stdout.
+    pub fn run(self) -> Result<()> {
+        let key = self.keys.key()?;
+        let client = Client::new(concat!("bikesafe-cli/", env!("CARGO_PKG_VERSION")));
+        std::fs::create_dir_all(&self.output)
+            .with_context(|| format!("could not create `{}`", self.output.display()))?;
+        let path = match &self.feed {
+            Some(feed) => self.fetch_from_feed(&client, feed, key.as_ref())?,
+            None => self.fetch_from_releases(&client)?,
+        };
+
+        if let Err(e) = Bundle::open(&path, key.as_ref()) {
+            let _ = std::fs::remove_file(&path);
+            return Err(anyhow::Error::new(e)
+                .context(format!("downloaded bundle {} is invalid", path.display())));
+        }
+        println!("{}", path.display());
+        Ok(())
+    }
+
+    /// The newest bundle of the channel's index, whose signature is checked
+    /// with the bundle key.
+    fn fetch_from_feed(
+        &self,
+        client: &Client,
+        feed: &str,
+        key: Option<&ed25519_dalek::VerifyingKey>,
+    ) -> Result<PathBuf> {
+        let channel = match self.channel {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+        };
+        let url = format!(
+            "{}/{channel}/{}",
+            feed.trim_end_matches('/'),
+            firmware_manifest::INDEX_FILE
+        );
+        let index = client.index(&url, key)?;
+        let entry = index
+            .newest(|entry| entry.file.ends_with(".bbfw") || entry.file.ends_with(".zip"))
+            .with_context(|| format!("no firmware bundle in the {channel} channel"))?;
+        tracing::info!(
+            "Downloading {} (version {})",
+            entry.file,
+            entry.version.as_deref().unwrap_or_default()
+        );
+        Ok(client.download_entry(&url, entry, &self.output)?)
+    }
+
+    fn fetch_from_releases(&self, client: &Client) -> Result<PathBuf> {
+        let releases: Vec<Release> = serde_json::from_slice(&client.get(&self.url)?)
+            .context("could not parse release list")?;
+        // The API lists releases newest first.
+        let (release, asset) = releases
+            .iter()
+            .filter(|r| !r.draft && (self.channel == Channel::Beta || !r.prerelease))
+            .find_map(|r| Some((r, r.assets.iter().find(|a| a.is_bundle())?)))
+            .with_context(|| format!("no firmware bundle in the {:?} channel", self.channel))?;
+        tracing::info!(
+            "Downloading {} from release {}",
+            asset.name,
+            release.tag_name
+        );
+
+        let path = self.output.join(
+            Path::new(&asset.name)
+                .file_name()
+                .context("invalid asset name")?,
+        );
+        client
+            .download(&asset.browser_download_url, &path, |_| Ok(()))
+            .context("could not download bundle")?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,77 @@
+//! Putting the unit into DFU mode through a bench fixture's GPIO adapter
+//! before talking to it, instead of a button press.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use fixture_gpio::{Adapter, Config, Fixture, Pin};
+
+use crate::device::Device;
+use crate::update;
+
+#[derive(clap::Args)]
+pub struct FixtureArgs {
+    /// GPIO adapter of the bench fixture (`ftdi` or `cp2112`). When set, the
+    /// unit is restarted into DFU mode through its BOOT and reset lines
+    /// unless it already is in DFU mode.
+    #[clap(long, value_name = "ADAPTER", env = fixture_gpio::ADAPTER_ENV, global = true)]
+    fixture: Option<Adapter>,
+
+    /// USB serial number of the adapter, if several are connected.
+    #[clap(long, env = fixture_gpio::SERIAL_ENV, global = true)]
+    fixture_serial: Option<String>,
+
+    /// Adapter pin wired to the unit's BOOT pin, and the level that asserts
+    /// it: `N`, `N:high` or `N:low`.
+    #[clap(
+        long,
+        value_name = "PIN",
+        default_value = fixture_gpio::DEFAULT_BOOT,
+        env = fixture_gpio::BOOT_ENV,
+        global = true
+    )]
+    fixture_boot: Pin,
+
+    /// Adapter pin wired to the unit's reset line, and the level that
+    /// asserts it.
+    #[clap(
+        long,
+        value_name = "PIN",
+        default_value = fixture_gpio::DEFAULT_RESET,
+        env = fixture_gpio::RESET_ENV,
+        global = true
+    )]
+    fixture_reset: Pin,
+
+    /// How long to wait for the bootloader to enumerate after the fixture
+    /// restarted the unit.
+    #[clap(long, default_value = "5s", value_parser = humantime::parse_duration, global = true)]
+    fixture_timeout: Duration,
+}
+
+impl FixtureArgs {
+    /// With `--fixture`, restart `device` into DFU mode unless it already is
+    /// there, and wait for the bootloader.
+    pub fn enter_dfu(&self, device: &Device) -> Result<()> {
+        let Some(adapter) = self.fixture else {
+            return Ok(());
+        };
+        let dfu = (device.vid, device.pid);
+        if update::find(device, dfu, true)?.is_some() {
+            return Ok(());
+        }
+        let config = Config {
+            adapter,
+            serial: self.fixture_serial.clone(),
+            boot: self.fixture_boot,
+            reset: self.fixture_reset,
+        };
+        println!("Restarting the unit into DFU mode through the {adapter} fixture");
+        Fixture::open(&config)?.enter_dfu()?;
+        update::wait(dfu, self.fixture_timeout, || {
+            update::find(device, dfu, true)
+        })?
+        .context("the unit did not enter DFU mode; check the fixture wiring and pins")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,744 @@
+//! Writing firmware to the device, optionally verified and recorded in a
+//! production log.
+
+use std::borrow::Cow;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use bikesafe_core::cancel::Cancellable;
+use bikesafe_core::family::MemoryMap;
+use bikesafe_core::progress::Counter;
+use bikesafe_core::transfer::{compare, download, ensure_upload, erase, first_difference, verify};
+use bikesafe_core::{BikesafeError, Phase, ProgressSink, read_chip_id};
+use dfu_core::sync::DfuSync;
+use dfu_core::{DfuIo, DfuProtocol};
+use sha2::{Digest, Sha256};
+
+use crate::batch::BatchArgs;
+use crate::bundle::{self, Bundle, KeyArgs};
+use crate::device::Device;
+use crate::dfuse;
+use crate::monitor::Monitor;
+use crate::progress::Progress;
+use crate::slot::{self, SlotArg};
+
+/// Columns of the production log.
+const LOG_HEADER: [&str; 7] = [
+    "timestamp",
+    "serial",
+    "firmware_sha256",
+    "duration_s",
+    "verify",
+    "station",
+    "chip_uid",
+];
+
+#[derive(clap::Args)]
+pub struct FlashArgs {
+    /// Path to the firmware file to write to the device. Append `@ADDRESS`
+    /// to write it somewhere other than `--address`; repeat to write several
+    /// images in one session.
+    #[clap(long, short, value_name = "PATH[@ADDRESS]", value_parser = ImageArg::parse)]
+    path: Vec<ImageArg>,
+
+    /// target address to flash the firmware. Defaults to the start of the
+    /// device's application flash.
+    #[clap(
+        long,
+        short,
+        env = "BIKESAFE_ADDRESS",
+        value_parser = crate::Cli::parse_address
+    )]
+    address: Option<u32>,
+
+    /// Write to slot A, slot B or the currently inactive slot of a dual-bank
+    /// bootloader instead of `--address`.
+    #[clap(long, value_enum, conflicts_with_all = ["address", "bundle"])]
+    slot: Option<SlotArg>,
+
+    /// What to do once the firmware is written: `flash=ACTION` with `reset`,
+    /// `leave`, `dfu` or `none` as the action (`flash=` may be left out).
+    /// Defaults to `leave` when writing an image and to `none` otherwise.
+    #[clap(long, value_name = "flash=ACTION", value_parser = After::parse)]
+    after: Option<After>,
+
+    /// Same as `--after reset`.
+    #[clap(short, long, hide = true, conflicts_with = "after")]
+    reset: bool,
+
+    /// Transfer protocol, detected from the interface descriptors by default.
+    /// Plain DFU has no addressing: the image goes to the start of the
+    /// alternate setting's memory.
+    #[clap(long, value_enum)]
+    protocol: Option<Protocol>,
+
+    /// Print DFU state transitions, poll timeouts and DfuSe commands while
+    /// erasing and downloading.
+    #[clap(long)]
+    monitor: bool,
+
+    /// Release bundle (.zip with manifest and signature) to flash instead of
+    /// `--path`. The address comes from the manifest.
+    #[clap(long, conflicts_with = "path")]
+    bundle: Option<PathBuf>,
+
+    #[clap(flatten)]
+    keys: KeyArgs,
+
+    /// Read the firmware back after writing and compare it with the file.
+    #[clap(long)]
+    verify: bool,
+
+    /// Continue an interrupted download at this byte offset into the image,
+    /// rounded down to a page boundary. Earlier pages are left untouched.
+    #[clap(long, value_parser = crate::Cli::parse_size, conflicts_with = "resume")]
+    resume_from: Option<u32>,
+
+    /// Like `--resume-from`, but find the offset by comparing the device
+    /// memory with the image first.
+    #[clap(long)]
+    resume: bool,
+
+    /// Production run: verify the write and append a result row to `--log`.
+    #[clap(long, requires_all = ["log", "station"])]
+    production: bool,
+
+    /// CSV file production results are appended to (with `--production`).
+    #[clap(long, value_name = "CSV", env = "BIKESAFE_LOG")]
+    log: Option<PathBuf>,
+
+    /// Station ID recorded with each production result (with `--production`).
+    #[clap(long, env = "BIKESAFE_STATION")]
+    station: Option<String>,
+
+    /// Shell command to run before writing, e.g. to switch a fixture relay.
+    /// A failing command aborts the write. Gets `BIKESAFE_DEVICE_SERIAL`.
+    #[clap(long, value_name = "COMMAND")]
+    pre_cmd: Option<String>,
+
+    /// Shell command to run after writing, whether it succeeded or not. Gets
+    /// `BIKESAFE_DEVICE_SERIAL`, `BIKESAFE_RESULT` (`ok` or `error`) and
+    /// `BIKESAFE_ERROR`.
+    #[clap(long, value_name = "COMMAND")]
+    post_cmd: Option<String>,
+
+    #[clap(flatten)]
+    batch: BatchArgs,
+}
+
+/// A `--path` value: a file and, optionally, where to write it.
+#[derive(Debug, Clone)]
+pub struct ImageArg {
+    path: PathBuf,
+    address: Option<u32>,
+}
+
+impl ImageArg {
+    /// Parse `PATH` or `PATH@ADDRESS`. A trailing `@...` that is not an
+    /// address is taken as part of the file name.
+    fn parse(s: &str) -> Result<Self> {
+        if let Some((path, address)) = s.rsplit_once('@')
+            && let Ok(address) = crate::Cli::parse_address(address)
+        {
+            return Ok(Self {
+                path: path.into(),
+                address: Some(address),
+            });
+        }
+        Ok(Self {
+            path: s.into(),
+            address: None,
+        })
+    }
+}
+
+/// `--after` values: how to hand over to the new firmware.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum After {
+    /// Detach (if supported) and reset the USB device.
+    Reset,
+    /// Send the DfuSe leave request, jumping to the application.
+    Leave,
+    /// Stay in DFU mode, back in dfuIDLE.
+    Dfu,
+    /// Send nothing more.
+    #[value(name = "none")]
+    Nothing,
+}
+
+impl After {
+    /// Parse `flash=ACTION` or a bare `ACTION`. The write finishing is the
+    /// only event an action can follow so far.
+    fn parse(s: &str) -> Result<Self> {
+        let action = match s.split_once('=') {
+            Some(("flash", action)) => action,
+            Some((event, _)) => anyhow::bail!("unknown event `{event}`, expected `flash`"),
+            None => s,
+        };
+        <Self as clap::ValueEnum>::from_str(action, false).map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+/// `--protocol` values.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Protocol {
+    /// Standard DFU 1.1.
+    Dfu,
+    /// ST's DfuSe extensions (addressed writes, page erase, leave).
+    Dfuse,
+}
+
+/// Firmware image loaded into memory with its target address.
+pub struct Image {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+
+    /// Address and data of the part of the image from `offset` on.
+    fn remaining(&self, offset: usize) -> (u32, &[u8]) {
+        let offset = offset.min(self.data.len());
+        (self.address + offset as u32, &self.data[offset..])
+    }
+}
+
+/// Outcome of the read-back check.
+#[derive(Debug, Clone, Copy)]
+enum Verification {
+    Passed,
+    Skipped,
+}
+
+impl FlashArgs {
+    /// Write the images to `device`, whose memory map is `memory`.
+    pub fn run(mut self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        self.address = self.address.or(Some(memory.flash.origin));
+        if self.batch.enabled() {
+            return self.batch.run(device, |unit| self.run_one(unit, memory));
+        }
+        self.run_one(device, memory)
+    }
+
+    /// Write one device, running the hooks around it.
+    fn run_one(&self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let _lock = device.lock()?;
+        if self.pre_cmd.is_none() && self.post_cmd.is_none() {
+            return self.write(device, memory);
+        }
+        let serial = device.serial_number().ok().flatten().unwrap_or_default();
+        if let Some(command) = &self.pre_cmd {
+            run_hook(command, &[("BIKESAFE_DEVICE_SERIAL", &serial)])
+                .context("--pre-cmd failed")?;
+        }
+        let result = self.write(device, memory);
+        if let Some(command) = &self.post_cmd {
+            let (status, error) = match &result {
+                Ok(()) => ("ok", String::new()),
+                Err(e) => ("error", format!("{e:#}")),
+            };
+            let hook = run_hook(
+                command,
+                &[
+                    ("BIKESAFE_DEVICE_SERIAL", &serial),
+                    ("BIKESAFE_RESULT", status),
+                    ("BIKESAFE_ERROR", &error),
+                ],
+            )
+            .context("--post-cmd failed");
+            // A failed write is the more useful error to report.
+            return result.and(hook);
+        }
+        result
+    }
+
+    fn write(&self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let images = match &self.bundle {
+            Some(path) => {
+                let bundle = Bundle::open(path, self.keys.key()?.as_ref())?;
+                bundle::check_compatible(&bundle, device, memory)?;
+                println!(
+                    "Bundle {} version {} for {:#010X}",
+                    path.display(),
+                    bundle.manifest.version,
+                    bundle.manifest.address
+                );
+                vec![Image {
+                    address: bundle.manifest.address,
+                    data: bundle.firmware,
+                }]
+            }
+            None => {
+                let address = match self.slot {
+                    Some(slot) => {
+                        let slot = slot::resolve(device, slot)?;
+                        println!("Writing to {slot}");
+                        Some(slot.address)
+                    }
+                    None => self.address,
+                };
+                self.load_images(address)?
+            }
+        };
+        if !self.production {
+            return self.flash(device, &images).map(drop);
+        }
+
+        let log = self.log.as_deref().context("--production needs --log")?;
+        let station = self
+            .station
+            .as_deref()
+            .context("--production needs --station")?;
+        anyhow::ensure!(!images.is_empty(), "--production needs --path or --bundle");
+
+        let start = Instant::now();
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+        let serial = device.serial_number().unwrap_or_else(|e| {
+            tracing::warn!("Could not read serial number: {e:#}");
+            None
+        });
+        let chip = read_chip_id(&device.open()?.into_inner(), memory)
+            .inspect_err(|e| tracing::warn!("Could not read the chip ID: {e:#}"))
+            .ok();
+        // With several images, the hash covers all of them in order.
+        let hash = images
+            .iter()
+            .fold(Sha256::new(), |hash, image| hash.chain_update(&image.data))
+            .finalize();
+        let result = self.flash(device, &images);
+        let verify = match &result {
+            Ok(Verification::Passed) => "passed",
+            Ok(Verification::Skipped) => "skipped",
+            Err(e) if matches!(e.downcast_ref(), Some(BikesafeError::VerifyFailed { .. })) => {
+                "failed"
+            }
+            Err(_) => "error",
+        };
+
+        append_log(
+            log,
+            &[
+                &timestamp.to_string(),
+                serial.as_deref().unwrap_or_default(),
+                &format!("{hash:x}"),
+                &format!("{:.1}", start.elapsed().as_secs_f64()),
+                verify,
+                station,
+                &chip.map(|chip| chip.to_string()).unwrap_or_default(),
+            ],
+        )?;
+        println!("Result ({verify}) appended to {}", log.display());
+        result.map(drop)
+    }
+
+    /// Read the `--path` files, rejecting images that overlap. Images
+    /// without an explicit address go to `address`.
+    fn load_images(&self, address: Option<u32>) -> Result<Vec<Image>> {
+        let mut images = Vec::new();
+        for arg in &self.path {
+            let address = arg
+                .address
+                .or(address)
+                .with_context(|| format!("no address for `{}`", arg.path.display()))?;
+            let data = bikesafe_core::read_firmware(&arg.path)?;
+            images.push(Image { address, data });
+        }
+
+        let mut sorted: Vec<_> = images.iter().collect();
+        sorted.sort_by_key(|image| image.address);
+        for pair in sorted.windows(2) {
+            anyhow::ensure!(
+                pair[0].end() <= pair[1].address as u64,
+                "images at {:#010X} and {:#010X} overlap",
+                pair[0].address,
+                pair[1].address
+            );
+        }
+        Ok(images)
+    }
+
+    fn after(&self, images: &[Image]) -> After {
+        match self.after {
+            Some(after) => after,
+            None if self.reset => After::Reset,
+            None if images.is_empty() => After::Nothing,
+            None => After::Leave,
+        }
+    }
+
+    fn flash(&self, device: &Device, images: &[Image]) -> Result<Verification> {
+        let mut after = self.after(images);
+        let mut progress = Progress::new()?;
+        let mut io = Monitor::new(
+            Cancellable::new(device.open()?.into_inner(), crate::ctrl_c()?),
+            self.monitor,
+            progress.bar().clone(),
+        );
+        let detected = match io.protocol() {
+            DfuProtocol::Dfu => Protocol::Dfu,
+            DfuProtocol::Dfuse { .. } => Protocol::Dfuse,
+        };
+        let protocol = self.protocol.unwrap_or(detected);
+        let mut verification = Verification::Skipped;
+        let verifying = self.verify || self.production;
+        let resuming = self.resume || self.resume_from.is_some();
+
+        match images {
+            [] => (),
+            // dfu-core always ends a download by leaving DFU mode.
+            [image]
+                if protocol == detected
+                    && !verifying
+                    && !resuming
+                    && matches!(after, After::Leave | After::Reset) =>
+            {
+                let file_size = image.data.len() as u64;
+                progress.phase(Phase::Download, file_size);
+
+                let mut dfu = DfuSync::new(io);
+                dfu.with_progress({
+                    let mut progress = progress.clone();
+                    let mut done = 0;
+                    move |count| {
+                        done += count as u64;
+                        progress.bytes(done, file_size);
+                        if done == file_size {
+                            progress.finished();
+                        }
+                    }
+                });
+                dfu.override_address(image.address);
+
+                let span = tracing::info_span!("download", length = image.data.len());
+                match span.in_scope(|| dfu.download_from_slice(&image.data)) {
+                    Ok(_) => (),
+                    Err(e) if usb_failure(&e) => {
+                        if progress.bar().is_finished() {
+                            // Some devices reset themselves after a successful
+                            // download, causing a LIBUSB_ERROR_NO_DEVICE error
+                            // when we try to communicate further.
+                            eprintln!("{e:#?}");
+                            println!("Download successful; Device reseted itself");
+                        } else {
+                            eprintln!("Firmware download failed: {e:#?}");
+                        }
+                        return Ok(verification);
+                    }
+                    Err(e) => {
+                        return Err(e).context("could not write firmware to the device");
+                    }
+                }
+                io = dfu.into_inner();
+            }
+            images if protocol == Protocol::Dfu => {
+                let [image] = images else {
+                    anyhow::bail!("plain DFU can write a single image only");
+                };
+                anyhow::ensure!(!resuming, "resuming needs DfuSe addressing");
+                let descriptor = *io.functional_descriptor();
+                if verifying {
+                    ensure_upload(&io)?;
+                    // Uploads need dfuIDLE, which only manifestation-tolerant
+                    // devices return to after a download.
+                    anyhow::ensure!(
+                        descriptor.manifestation_tolerant,
+                        "device is not manifestation tolerant, cannot read the image back"
+                    );
+                }
+
+                let transfer_size = descriptor.transfer_size as usize;
+                let length = image.data.len() as u64;
+                let mut count = Counter::new(&mut progress, Phase::Download, length);
+                dfuse::download_plain(&io, &image.data, transfer_size, |n| count.advance(n))
+                    .context("could not write firmware to the device")?;
+                match dfuse::manifest(&io) {
+                    Ok(_) => (),
+                    Err(e) if usb_failure(&e) => {
+                        println!("Download successful; Device reseted itself");
+                        return Ok(verification);
+                    }
+                    Err(e) => return Err(e).context("could not finish the download"),
+                }
+
+                if verifying {
+                    let _verify =
+                        tracing::info_span!("verify", length = image.data.len()).entered();
+                    let mut count = Counter::new(&mut progress, Phase::Verify, length);
+                    let read_back =
+                        dfuse::upload_plain(&io, image.data.len(), transfer_size, |n| {
+                            count.advance(n)
+                        })
+                        .context("could not read firmware back")?;
+                    if let Some(offset) = first_difference(&image.data, &read_back) {
+                        return Err(BikesafeError::VerifyFailed {
+                            address: offset as u32,
+                        }
+                        .into());
+                    }
+                    progress.message(&format!("Verified {length} bytes"));
+                    verification = Verification::Passed;
+                }
+                progress.finished();
+
+                // Plain DFU has no leave request; a reset starts the new
+                // firmware.
+                if after == After::Leave {
+                    after = After::Reset;
+                }
+            }
+            images => {
+                // dfu-core leaves DFU mode after one image, so several images,
+                // verifying, resuming and staying in DFU go through raw DfuSe
+                // requests.
+                if verifying || self.resume {
+                    ensure_upload(&io)?;
+                }
+                let offset = match images {
+                    [image] => self.resume_offset(&io, image, &mut progress)?,
+                    _ => {
+                        anyhow::ensure!(!resuming, "resuming works with a single image only");
+                        0
+                    }
+                };
+
+                // A resumed write counts from the offset.
+                let total: u64 = images.iter().map(|image| image.data.len() as u64).sum();
+                let mut count = Counter::new(&mut progress, Phase::Erase, total);
+                count.advance(offset);
+                for image in images {
+                    let (address, data) = image.remaining(offset);
+                    erase(&io, address, data, |n| count.advance(n))
+                        .context("could not erase flash")?;
+                }
+                let mut count = Counter::new(&mut progress, Phase::Download, total);
+                count.advance(offset);
+                for image in images {
+                    let (address, data) = image.remaining(offset);
+                    download(&io, address, data, |n| count.advance(n))
+                        .context("could not write firmware to the device")?;
+                }
+
+                if verifying {
+                    let mut count = Counter::new(&mut progress, Phase::Verify, total);
+                    for image in images {
+                        verify(&io, image.address, &image.data, |n| count.advance(n))?;
+                    }
+                    progress.message(&format!("Verified {total} bytes"));
+                    verification = Verification::Passed;
+                }
+                progress.finished();
+
+                if after == After::Leave {
+                    match dfuse::leave(&io, images[0].address) {
+                        Ok(()) => (),
+                        Err(e) if usb_failure(&e) => {
+                            println!("Download successful; Device reseted itself");
+                            return Ok(verification);
+                        }
+                        Err(e) => return Err(e).context("could not leave DFU mode"),
+                    }
+                }
+            }
+        }
+
+        match after {
+            After::Reset => {
+                let _reset = tracing::info_span!("reset").entered();
+                let device = DfuSync::new(io);
+                // Detach isn't strictly meant to be sent after a download, however
+                // u-boot in particular will only switch to the
+                // downloaded firmware if it saw a detach before
+                // a usb reset. So send a detach blindly...
+                //
+                // This matches the behaviour of dfu-util so should be safe
+                if device.will_detach() {
+                    println!("Detaching device");
+                    device.detach()?;
+                } else {
+                    println!("Device does not support detach");
+                }
+
+                println!("Resetting device");
+                device.usb_reset()?;
+            }
+            After::Leave if images.is_empty() => {
+                let address = self.address.context("--after leave needs --address")?;
+                println!("Leaving DFU mode");
+                match dfuse::leave(&io, address) {
+                    Ok(()) => (),
+                    Err(e) if usb_failure(&e) => (),
+                    Err(e) => return Err(e).context("could not leave DFU mode"),
+                }
+            }
+            After::Leave | After::Nothing => (),
+            After::Dfu => {
+                dfuse::ensure_idle(&io).context("could not return to dfuIDLE")?;
+                println!("Device stays in DFU mode");
+            }
+        }
+
+        Ok(verification)
+    }
+
+    /// Offset into `image` to continue writing from, rounded down to the
+    /// start of its page since only whole pages can be erased.
+    fn resume_offset<IO>(
+        &self,
+        io: &IO,
+        image: &Image,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<usize>
+    where
+        IO: DfuIo<Read = usize, Write = usize>,
+        IO::Error: Into<BikesafeError>,
+    {
+        let offset = match self.resume_from {
+            Some(offset) => offset as usize,
+            None if self.resume => {
+                let length = image.data.len() as u64;
+                let mut count = Counter::new(&mut *progress, Phase::Verify, length);
+                compare(io, image.address, &image.data, |n| count.advance(n))
+                    .context("could not read firmware back")?
+                    .unwrap_or(image.data.len())
+            }
+            None => return Ok(0),
+        };
+        if offset >= image.data.len() {
+            progress.message("The image is already on the device, nothing to write");
+            return Ok(image.data.len());
+        }
+
+        let address = image.address + offset as u32;
+        let page = dfuse::page_start(io, address)
+            .context("resume offset lies outside the device memory")?;
+        let offset = page.saturating_sub(image.address) as usize;
+        progress.message(&format!(
+            "Resuming at offset {offset:#X} ({:#010X})",
+            image.address + offset as u32
+        ));
+        Ok(offset)
+    }
+}
+
+/// Whether `error` is a failed USB request, as when the device drops off
+/// the bus to reset itself instead of answering.
+fn usb_failure(error: &BikesafeError) -> bool {
+    matches!(
+        error,
+        BikesafeError::Disconnected | BikesafeError::PermissionDenied | BikesafeError::UsbIo(_)
+    )
+}
+
+/// Erase, write and read back `firmware` at `address` with raw DfuSe
+/// requests, reporting each phase to `progress`.
+pub fn write_verified<IO>(
+    io: &IO,
+    address: u32,
+    firmware: &[u8],
+    progress: &mut dyn ProgressSink,
+) -> Result<()>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    ensure_upload(io)?;
+    let length = firmware.len() as u64;
+    let mut count = Counter::new(&mut *progress, Phase::Erase, length);
+    erase(io, address, firmware, |n| count.advance(n)).context("could not erase flash")?;
+    let mut count = Counter::new(&mut *progress, Phase::Download, length);
+    download(io, address, firmware, |n| count.advance(n))
+        .context("could not write firmware to the device")?;
+    let mut count = Counter::new(&mut *progress, Phase::Verify, length);
+    verify(io, address, firmware, |n| count.advance(n))?;
+    progress.finished();
+    Ok(())
+}
+
+/// Run `command` through the shell with `env` set, failing on a non-zero
+/// exit status.
+fn run_hook(command: &str, env: &[(&str, &str)]) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .envs(env.iter().copied())
+        .status()
+        .with_context(|| format!("could not run `{command}`"))?;
+    anyhow::ensure!(status.success(), "`{command}` exited with {status}");
+    Ok(())
+}
+
+/// Append one row to the production log, starting a new file with a header.
+fn append_log(path: &Path, row: &[&str]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("could not open log file `{}`", path.display()))?;
+    let mut lines = String::new();
+    if file.metadata()?.len() == 0 {
+        lines += &LOG_HEADER.join(",");
+        lines.push('\n');
+    }
+    let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+    lines += &row.join(",");
+    lines.push('\n');
+    file.write_all(lines.as_bytes())
+        .with_context(|| format!("could not write log file `{}`", path.display()))
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn after_values() {
+        assert_eq!(After::parse("flash=reset").unwrap(), After::Reset);
+        assert_eq!(After::parse("flash=none").unwrap(), After::Nothing);
+        assert_eq!(After::parse("dfu").unwrap(), After::Dfu);
+        assert!(After::parse("boot=reset").is_err());
+        assert!(After::parse("flash=jump").is_err());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("BB-2025-000123"), "BB-2025-000123");
+        assert_eq!(csv_field("line 3, left"), "\"line 3, left\"");
+        assert_eq!(csv_field("the \"fast\" one"), "\"the \"\"fast\"\" one\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn log_has_one_header() {
+        let path = std::env::temp_dir().join(format!("bikesafe-log-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        append_log(&path, &["t1", "A,1"]).unwrap();
+        append_log(&path, &["t2", "B"]).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log, format!("{}\nt1,\"A,1\"\nt2,B\n", LOG_HEADER.join(",")));
+    }
+}
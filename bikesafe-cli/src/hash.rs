@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::Suffix;
+use sha2::{Digest, Sha256};
+
+#[derive(clap::Args)]
+pub struct HashArgs {
+    /// Firmware artifact (.bin, .dfu, ...) to hash.
+    file: PathBuf,
+}
+
+impl HashArgs {
+    /// Print size and checksums of the file, and check the suffix of .dfu
+    /// files.
+    pub fn run(self) -> Result<()> {
+        let data = std::fs::read(&self.file)
+            .with_context(|| format!("could not open `{}`", self.file.display()))?;
+
+        println!("File     {}", self.file.display());
+        println!("Size     {} bytes", data.len());
+        println!("CRC32    {:#010X}", crc32fast::hash(&data));
+        println!("SHA-256  {:x}", Sha256::digest(&data));
+
+        let is_dfu = self
+            .file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dfu"));
+        if is_dfu {
+            check_suffix(&data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print the DFU suffix fields and fail if its CRC does not match.
+fn check_suffix(data: &[u8]) -> Result<()> {
+    let suffix = Suffix::parse(data)?;
+    println!(
+        "Suffix   {:04x}:{:04x}, bcdDevice {:#06x}, bcdDFU {:#06x}",
+        suffix.vid, suffix.pid, suffix.bcd_device, suffix.bcd_dfu
+    );
+    if suffix.crc_valid() {
+        println!("DFU CRC  {:#010X} (valid)", suffix.crc);
+        Ok(())
+    } else {
+        println!(
+            "DFU CRC  {:#010X} (invalid, expected {:#010X})",
+            suffix.crc, suffix.computed_crc
+        );
+        anyhow::bail!("DFU suffix CRC mismatch")
+    }
+}
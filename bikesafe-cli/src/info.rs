@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_file::MemoryLayout;
+use serde::Serialize;
+
+use crate::device::Device;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(clap::Args)]
+pub struct InfoArgs {
+    /// Print the dump as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+/// Everything the host can learn about the device without talking DFU.
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: String,
+    pub usb_version: String,
+    pub bus: u8,
+    pub address: u8,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    pub dfu: Option<DfuAttributes>,
+    pub configurations: Vec<ConfigurationInfo>,
+}
+
+/// Contents of the DFU functional descriptor.
+#[derive(Serialize)]
+pub struct DfuAttributes {
+    pub can_download: bool,
+    pub can_upload: bool,
+    pub manifestation_tolerant: bool,
+    pub will_detach: bool,
+    pub detach_timeout: u16,
+    pub transfer_size: u16,
+    pub dfu_version: String,
+}
+
+#[derive(Serialize)]
+pub struct ConfigurationInfo {
+    pub number: u8,
+    pub name: Option<String>,
+    pub interfaces: Vec<InterfaceInfo>,
+}
+
+/// One alternate setting of an interface.
+#[derive(Serialize)]
+pub struct InterfaceInfo {
+    pub number: u8,
+    pub alt_setting: u8,
+    pub class: u8,
+    pub sub_class: u8,
+    pub protocol: u8,
+    pub name: Option<String>,
+    pub memory_layout: Option<MemoryLayout>,
+}
+
+impl From<&FunctionalDescriptor> for DfuAttributes {
+    fn from(desc: &FunctionalDescriptor) -> Self {
+        let (major, minor) = desc.dfu_version;
+        Self {
+            can_download: desc.can_download,
+            can_upload: desc.can_upload,
+            manifestation_tolerant: desc.manifestation_tolerant,
+            will_detach: desc.will_detach,
+            detach_timeout: desc.detach_timeout,
+            transfer_size: desc.transfer_size,
+            dfu_version: format!("{major:#04X}{minor:02X}"),
+        }
+    }
+}
+
+impl InfoArgs {
+    pub fn run(self, device: &Device) -> Result<()> {
+        let info = DeviceInfo::read(device)?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        } else {
+            info.print();
+        }
+        Ok(())
+    }
+}
+
+impl DeviceInfo {
+    /// Collect descriptors and strings of the selected device.
+    pub fn read(device: &Device) -> Result<Self> {
+        let device = device.usb_device()?;
+        let desc = device.device_descriptor()?;
+        let handle = device.open().context("could not open device")?;
+        let lang = handle.read_languages(TIMEOUT)?.first().copied();
+        let string = |index: Option<u8>| {
+            let (lang, index) = (lang?, index?);
+            handle.read_string_descriptor(lang, index, TIMEOUT).ok()
+        };
+
+        let mut dfu = None;
+        let mut configurations = Vec::new();
+        for index in 0..desc.num_configurations() {
+            let config = device.config_descriptor(index)?;
+            let mut interfaces = Vec::new();
+            for interface in config.interfaces() {
+                for alt in interface.descriptors() {
+                    if dfu.is_none() {
+                        dfu = FunctionalDescriptor::from_bytes(alt.extra())
+                            .and_then(Result::ok)
+                            .map(|d| DfuAttributes::from(&d));
+                    }
+                    let name = string(alt.description_string_index());
+                    let memory_layout = match name.as_deref().and_then(MemoryLayout::parse) {
+                        Some(Ok(layout)) => Some(layout),
+                        Some(Err(e)) => {
+                            tracing::warn!("Could not parse memory layout `{name:?}`: {e:#}");
+                            None
+                        }
+                        None => None,
+                    };
+                    interfaces.push(InterfaceInfo {
+                        number: alt.interface_number(),
+                        alt_setting: alt.setting_number(),
+                        class: alt.class_code(),
+                        sub_class: alt.sub_class_code(),
+                        protocol: alt.protocol_code(),
+                        name,
+                        memory_layout,
+                    });
+                }
+            }
+            if dfu.is_none() {
+                dfu = FunctionalDescriptor::from_bytes(config.extra())
+                    .and_then(Result::ok)
+                    .map(|d| DfuAttributes::from(&d));
+            }
+            configurations.push(ConfigurationInfo {
+                number: config.number(),
+                name: string(config.description_string_index()),
+                interfaces,
+            });
+        }
+
+        Ok(Self {
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            device_version: version(desc.device_version()),
+            usb_version: version(desc.usb_version()),
+            bus: device.bus_number(),
+            address: device.address(),
+            manufacturer: string(desc.manufacturer_string_index()),
+            product: string(desc.product_string_index()),
+            serial_number: string(desc.serial_number_string_index()),
+            dfu,
+            configurations,
+        })
+    }
+
+    fn print(&self) {
+        println!(
+            "Device {:04x}:{:04x} (bus {}, address {}), version {}, USB {}",
+            self.vendor_id,
+            self.product_id,
+            self.bus,
+            self.address,
+            self.device_version,
+            self.usb_version
+        );
+        for (label, value) in [
+            ("Manufacturer", &self.manufacturer),
+            ("Product", &self.product),
+            ("Serial", &self.serial_number),
+        ] {
+            println!("  {label:<13} {}", value.as_deref().unwrap_or("-"));
+        }
+
+        if let Some(dfu) = &self.dfu {
+            let capabilities: Vec<_> = [
+                (dfu.can_download, "download"),
+                (dfu.can_upload, "upload"),
+                (dfu.manifestation_tolerant, "manifestation tolerant"),
+                (dfu.will_detach, "will detach"),
+            ]
+            .into_iter()
+            .filter_map(|(set, name)| set.then_some(name))
+            .collect();
+            println!(
+                "DFU {}: transfer size {} bytes, detach timeout {} ms, {}",
+                dfu.dfu_version,
+                dfu.transfer_size,
+                dfu.detach_timeout,
+                capabilities.join(", ")
+            );
+        }
+
+        for config in &self.configurations {
+            println!(
+                "Configuration {}{}",
+                config.number,
+                config
+                    .name
+                    .as_deref()
+                    .map(|n| format!(": {n}"))
+                    .unwrap_or_default()
+            );
+            for intf in &config.interfaces {
+                println!(
+                    "  Interface {} alt {} (class {:#04x}/{:#04x}/{:#04x}): {}",
+                    intf.number,
+                    intf.alt_setting,
+                    intf.class,
+                    intf.sub_class,
+                    intf.protocol,
+                    intf.name.as_deref().unwrap_or("-")
+                );
+                let Some(layout) = &intf.memory_layout else {
+                    continue;
+                };
+                for segment in &layout.segments {
+                    // `None` once the sectors reach the end of the address space.
+                    let mut address = Some(segment.address);
+                    for sectors in &segment.sectors {
+                        let Some(start) = address else {
+                            tracing::warn!(
+                                "Memory layout `{}` continues past 4 GiB",
+                                intf.name.as_deref().unwrap_or_default()
+                            );
+                            break;
+                        };
+                        println!(
+                            "    {start:#010X}  {:>4} x {:>6} bytes  {}",
+                            sectors.count,
+                            sectors.size,
+                            sectors.access()
+                        );
+                        address = sectors
+                            .count
+                            .checked_mul(sectors.size)
+                            .and_then(|length| start.checked_add(length));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Format a BCD version as `major.minor.sub`.
+pub fn version(version: rusb::Version) -> String {
+    format!(
+        "{}.{}.{}",
+        version.major(),
+        version.minor(),
+        version.sub_minor()
+    )
+}
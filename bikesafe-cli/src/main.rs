@@ -1,154 +1,260 @@
-use std::io::{self, Seek};
-use std::path::PathBuf;
+mod app;
+mod batch;
+mod benchmark;
+mod bundle;
+mod crc;
+mod doctor;
+mod fetch;
+#[cfg(feature = "fixture")]
+mod fixture;
+mod flash;
+mod hash;
+mod info;
+mod monitor;
+mod option_bytes;
+mod progress;
+mod protect;
+mod provision;
+mod recover;
+mod recover_rom;
+mod slot;
+mod udev;
+mod update;
+mod upload;
+mod verify_file;
+
+use std::io::{self, IsTerminal, Write};
+use std::process::ExitCode;
+use std::sync::OnceLock;
 
 use anyhow::{Context, Result};
+use bikesafe_core::cancel::Cancel;
+use bikesafe_core::family::{self, Family};
+use bikesafe_core::{BikesafeError, device, dfuse};
 use dfu_core::DfuIo; /* Import the Dfu trait to bring
  * functional_descriptor into scope */
 use dfu_libusb::*;
+use localization::tr;
+use logging::LogFormat;
+
+use crate::device::Device;
 
 #[derive(clap::Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 pub struct Cli {
-    /// Path to the firmware file to write to the device.
-    #[clap(long, short)]
-    path: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    flash: flash::FlashArgs,
+
+    #[cfg(feature = "fixture")]
+    #[clap(flatten)]
+    fixture: fixture::FixtureArgs,
 
     /// Specify Vendor/Product ID(s) of DFU device.
-    /// i.e. 1209:2444
+    /// i.e. 1209:2444. Defaults to the first connected device of a supported
+    /// product, or else a BrakeBright.
     #[clap(
         long,
         short,
         value_parser = Self::parse_vid_pid, name = "VID>:<PID",
-        default_value = "0x1209:0x2444"
+        env = "BIKESAFE_DEVICE",
+        global = true
     )]
-    device: (u16, u16),
-
-    /// target address to flash the firmware
-    #[clap(long, short, default_value = "0x08004000", value_parser = Self::parse_address)]
-    address: Option<u32>,
+    device: Option<(u16, u16)>,
 
     /// Specify the DFU Interface number.
-    #[clap(long, short, default_value = "0")]
+    #[clap(long, short, default_value = "0", env = "BIKESAFE_INTF", global = true)]
     intf: u8,
 
     /// Specify the Altsetting of the DFU Interface by number.
-    #[clap(long, default_value = "0")]
+    #[clap(long, default_value = "0", env = "BIKESAFE_ALT", global = true)]
     alt: u8,
 
-    /// Reset after download.
-    #[clap(short, long)]
-    reset: bool,
+    /// Specify the Altsetting of the DFU Interface by its string descriptor,
+    /// e.g. "Internal Flash". Takes precedence over `--alt`.
+    #[clap(long, env = "BIKESAFE_ALT_NAME", global = true)]
+    alt_name: Option<String>,
 
-    /// Enable verbose logs.
-    #[clap(long, short)]
+    /// Enable verbose logs, including the time spent in each phase.
+    #[clap(long, short, global = true)]
     verbose: bool,
 
+    /// Disable ANSI colors in logs and progress output.
+    #[clap(long, env = "NO_COLOR", global = true)]
+    no_color: bool,
+
+    /// Replace the progress bar with periodic plain-text progress lines.
+    /// Implied when stdout or stderr is not a terminal.
+    #[clap(long, env = "BIKESAFE_NO_PROGRESS", global = true)]
+    no_progress: bool,
+
+    /// Log output format.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        env = "BIKESAFE_LOG_FORMAT",
+        global = true
+    )]
+    log_format: LogFormat,
+
     #[clap(long)]
     /// print info and exit
     info: bool,
+
+    /// With `--info`, print the DFU functional descriptor as JSON.
+    #[clap(long, requires = "info")]
+    json: bool,
+}
+
+/// How progress and colors are rendered, decided once at startup.
+#[derive(Debug, Clone, Copy)]
+struct Output {
+    color: bool,
+    progress_bar: bool,
+    /// Progress as JSON lines on stderr, with the logs.
+    json_progress: bool,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Talk to the running application: version, battery, settings,
+    /// self-test, reboot into DFU mode.
+    #[clap(subcommand)]
+    App(app::Command),
+    /// Measure upload/download throughput at several transfer sizes.
+    Benchmark(benchmark::BenchmarkArgs),
+    /// Read a memory region back and print its CRC32 and SHA-256.
+    Crc(crc::CrcArgs),
+    /// Check libusb, drivers, permissions and device presence.
+    Doctor,
+    /// Download the newest published firmware bundle and print its path.
+    Fetch(fetch::FetchArgs),
+    /// Write firmware to the device, optionally verifying and logging it.
+    Flash(flash::FlashArgs),
+    /// Print size, CRC32 and SHA-256 of a firmware file (and check .dfu
+    /// suffixes).
+    Hash(hash::HashArgs),
+    /// Dump device descriptors, DFU attributes and the DfuSe memory layout.
+    Info(info::InfoArgs),
+    /// Read or write the STM32 option bytes.
+    #[clap(subcommand)]
+    OptionBytes(option_bytes::Command),
+    /// Enable flash read protection (RDP level 1).
+    Protect(protect::ProtectArgs),
+    /// Write per-unit provisioning data (serial, date, hardware revision).
+    Provision(provision::ProvisionArgs),
+    /// Print (or install) the Linux udev rule for the selected device.
+    UdevRule(udev::UdevRuleArgs),
+    /// Clear a stuck DFU error or transfer state, returning to dfuIDLE.
+    Recover,
+    /// Reinstall the bootloader through the STM32 ROM DFU bootloader.
+    RecoverRom(recover_rom::RecoverRomArgs),
+    /// Remove flash read protection. This mass-erases the device!
+    Unprotect(protect::UnprotectArgs),
+    /// Detach, flash, verify and start new firmware, then confirm its version.
+    Update(update::UpdateArgs),
+    /// Read a memory region from the device into a file or stdout.
+    Upload(upload::UploadArgs),
+    /// Run all static checks on a firmware file, without a device.
+    VerifyFile(verify_file::VerifyFileArgs),
 }
 
 impl Cli {
     pub fn run(self) -> Result<()> {
         let Cli {
+            command,
             device,
             intf,
             alt,
+            alt_name,
             verbose,
-            path,
-            reset,
+            no_color,
+            no_progress,
+            log_format,
+            flash,
+            #[cfg(feature = "fixture")]
+            fixture,
             info,
-            address,
+            json,
         } = self;
-        let log_level = if verbose {
-            simplelog::LevelFilter::Trace
-        } else {
-            simplelog::LevelFilter::Info
+        let tty = io::stdout().is_terminal() && io::stderr().is_terminal();
+        let json_progress = matches!(log_format, LogFormat::Json);
+        let output = OUTPUT.get_or_init(|| Output {
+            color: !no_color && tty,
+            progress_bar: !no_progress && tty && !json_progress,
+            json_progress,
+        });
+        logging::init(verbose, log_format, output.color).map_err(|e| anyhow::anyhow!(e))?;
+        let (family, device) = select_family(device);
+        tracing::debug!("{} at {:04x}:{:04x}", family.name, device.0, device.1);
+        let memory = family.memory_map(device, None);
+        let command = match command {
+            Some(Command::Doctor) => return doctor::run(device, intf),
+            Some(Command::Fetch(args)) => return args.run(),
+            Some(Command::Hash(args)) => return args.run(),
+            Some(Command::VerifyFile(args)) => return args.run(memory),
+            Some(Command::UdevRule(args)) => return args.run(device),
+            command => command,
         };
-        simplelog::SimpleLogger::init(log_level, Default::default())?;
         let (vid, pid) = device;
-        let context = rusb::Context::new()?;
-
-        let device: Dfu<rusb::Context> =
-            DfuLibusb::open(&context, vid, pid, intf, alt).context("could not open device")?;
-
-        println!("{:?}", device.into_inner().functional_descriptor());
-        if info {
-            return Ok(());
+        let mut selected = Device {
+            context: rusb::Context::new()?,
+            vid,
+            pid,
+            intf,
+            alt,
+            port: None,
+        };
+        #[cfg(feature = "fixture")]
+        fixture.enter_dfu(&selected)?;
+        let _lock = selected.lock()?;
+        if let Some(name) = alt_name {
+            selected.alt = selected.find_alt(&name)?;
         }
-        let mut device: Dfu<rusb::Context> =
-            DfuLibusb::open(&context, vid, pid, intf, alt).context("could not open device")?;
-
-        if let Some(path) = path {
-            let mut file = std::fs::File::open(&path)
-                .with_context(|| format!("could not open firmware file `{}`", path.display()))?;
-            let file_size = u32::try_from(file.seek(io::SeekFrom::End(0))?)
-                .context("The firmware file is too big")?;
-            file.seek(io::SeekFrom::Start(0))?;
-
-            let bar = indicatif::ProgressBar::new(file_size as u64);
-            bar.set_style(
-                indicatif::ProgressStyle::default_bar()
-                    .template(
-                        "{spinner:.green} [{elapsed_precise}] [{bar:27.cyan/blue}] \
-                    {bytes}/{total_bytes} ({bytes_per_sec}) ({eta}) {msg:10}",
-                    )?
-                    .progress_chars("#>-"),
-            );
-
-            device.with_progress({
-                let bar = bar.clone();
-                move |count| {
-                    bar.inc(count as u64);
-                    if bar.position() == file_size as u64 {
-                        bar.finish();
-                    }
-                }
-            });
-
-            if let Some(address) = address {
-                device.override_address(address);
-            }
 
-            match device.download(file, file_size) {
-                Ok(_) => (),
-                Err(Error::LibUsb(e)) => {
-                    if bar.is_finished() {
-                        // Some devices reset themselves after a successful
-                        // download, causing a LIBUSB_ERROR_NO_DEVICE error
-                        // when we try to communicate further.
-                        eprintln!("{e:#?}");
-                        println!("Download successful; Device reseted itself");
-                        return Ok(());
-                    } else {
-                        eprintln!("Firmware download failed: {e:#?}");
-                    }
-                    return Ok(());
-                }
-                e => {
-                    return e.context("could not write firmware to the device");
+        if let Some(command) = command {
+            return match command {
+                Command::App(command) => command.run(&selected),
+                Command::Benchmark(args) => args.run(&selected, memory),
+                Command::Crc(args) => args.run(&selected, memory),
+                Command::Doctor
+                | Command::Fetch(_)
+                | Command::Hash(_)
+                | Command::UdevRule(_)
+                | Command::VerifyFile(_) => {
+                    unreachable!("handled before opening USB")
                 }
-            }
+                Command::Flash(args) => args.run(&selected, memory),
+                Command::Info(args) => args.run(&selected),
+                Command::OptionBytes(command) => command.run(&selected),
+                Command::Protect(args) => args.run(&selected),
+                Command::Provision(args) => args.run(&selected, memory),
+                Command::Recover => recover::run(&selected),
+                Command::RecoverRom(args) => args.run(&selected),
+                Command::Unprotect(args) => args.run(&selected),
+                Command::Update(args) => args.run(&selected, family),
+                Command::Upload(args) => args.run(&selected, memory),
+            };
         }
 
-        if reset {
-            // Detach isn't strictly meant to be sent after a download, however
-            // u-boot in particular will only switch to the
-            // downloaded firmware if it saw a detach before
-            // a usb reset. So send a detach blindly...
-            //
-            // This matches the behaviour of dfu-util so should be safe
-            if device.will_detach() {
-                println!("Detaching device");
-                device.detach()?;
-            } else {
-                println!("Device does not support detach");
-            }
+        let device: Dfu<rusb::Context> = selected.open()?;
 
-            println!("Resetting device");
-            device.usb_reset()?;
+        let descriptor = *device.into_inner().functional_descriptor();
+        if json {
+            let attributes = info::DfuAttributes::from(&descriptor);
+            println!("{}", serde_json::to_string_pretty(&attributes)?);
+            return Ok(());
         }
-
-        Ok(())
+        println!("{descriptor:?}");
+        if info {
+            return Ok(());
+        }
+        flash.run(&selected, memory)
     }
 
     pub fn parse_vid_pid(s: &str) -> Result<(u16, u16)> {
@@ -173,8 +279,143 @@ impl Cli {
         let address = u32::from_str_radix(s, 16).context("could not parse address")?;
         Ok(address)
     }
+
+    /// Parse a byte count: decimal, `0x` hex, or with a `K`/`M` suffix.
+    pub fn parse_size(s: &str) -> Result<u32> {
+        let (s, multiplier) = match s.strip_suffix(['K', 'k']) {
+            Some(s) => (s, 1024),
+            None => match s.strip_suffix('M') {
+                Some(s) => (s, 1024 * 1024),
+                None => (s, 1),
+            },
+        };
+        let size = match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .context("could not parse size")?;
+        size.checked_mul(multiplier).context("size too large")
+    }
+}
+
+/// The product family and DFU VID:PID to work with: those of `--device`,
+/// or else of the first connected device of a known family. Falls back to
+/// the BrakeBright for unknown IDs or when nothing is connected.
+fn select_family(device: Option<(u16, u16)>) -> (&'static Family, (u16, u16)) {
+    use rusb::UsbContext;
+
+    if let Some((vid, pid)) = device {
+        return (
+            family::find(vid, pid).unwrap_or(&family::BRAKEBRIGHT),
+            (vid, pid),
+        );
+    }
+    let connected = rusb::Context::new()
+        .and_then(|context| context.devices())
+        .ok()
+        .and_then(|devices| {
+            devices.iter().find_map(|usb| {
+                let desc = usb.device_descriptor().ok()?;
+                family::find(desc.vendor_id(), desc.product_id())
+            })
+        });
+    let family = connected.unwrap_or(&family::BRAKEBRIGHT);
+    (family, family.dfu_ids[0])
+}
+
+static CANCEL: OnceLock<Cancel> = OnceLock::new();
+
+/// Cancellation of device transfers by Ctrl-C: the first press stops the
+/// transfer before its next block, leaving the device in DFU mode, and a
+/// second one exits right away. The handler is installed on first use, so
+/// Ctrl-C still ends the program at once before any transfer started.
+pub fn ctrl_c() -> Result<Cancel> {
+    if let Some(cancel) = CANCEL.get() {
+        return Ok(cancel.clone());
+    }
+    let cancel = CANCEL.get_or_init(Cancel::new);
+    ctrlc::set_handler({
+        let cancel = cancel.clone();
+        move || {
+            if cancel.is_cancelled() {
+                std::process::exit(130);
+            }
+            eprintln!("{}", tr!("cli-cancelling"));
+            cancel.cancel();
+        }
+    })
+    .context("could not install Ctrl-C handler")?;
+    Ok(cancel.clone())
+}
+
+/// Ask the user to type `yes` before doing something irreversible.
+pub fn confirm(question: &str, assume_yes: bool) -> Result<()> {
+    if assume_yes {
+        return Ok(());
+    }
+    print!("{question} Type `yes` to continue: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    anyhow::ensure!(answer.trim() == "yes", "aborted by user");
+    Ok(())
+}
+
+/// Exit code for a failed run, by the kind of the first [`BikesafeError`]
+/// behind it, so that scripts can tell a missing device from a bad file.
+fn exit_code(error: &anyhow::Error) -> ExitCode {
+    let Some(error) = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<BikesafeError>())
+    else {
+        return ExitCode::FAILURE;
+    };
+    ExitCode::from(match error {
+        BikesafeError::DeviceNotFound { .. } | BikesafeError::Disconnected => 3,
+        BikesafeError::PermissionDenied | BikesafeError::Busy(_) => 4,
+        BikesafeError::ReadFirmware { .. } | BikesafeError::ValidationFailed(_) => 5,
+        BikesafeError::VerifyFailed { .. } => 6,
+        BikesafeError::Dfu(_)
+        | BikesafeError::Protocol(_)
+        | BikesafeError::UsbIo(_)
+        | BikesafeError::Backend(_) => 7,
+        BikesafeError::Cancelled => 130,
+        _ => 1,
+    })
 }
 
-fn main() -> Result<()> {
-    <Cli as clap::Parser>::parse().run()
+fn main() -> ExitCode {
+    match <Cli as clap::Parser>::parse().run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", tr!("cli-error", error = format!("{e:?}")));
+            if let Some(hint) = e
+                .chain()
+                .find_map(|e| e.downcast_ref::<BikesafeError>())
+                .and_then(localization::hint)
+            {
+                eprintln!("{}", tr!("cli-hint", hint = hint));
+            }
+            exit_code(&e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cli;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Cli::parse_size("2048").unwrap(), 2048);
+        assert_eq!(Cli::parse_size("0x800").unwrap(), 0x800);
+        assert_eq!(Cli::parse_size("16K").unwrap(), 16 * 1024);
+        assert_eq!(Cli::parse_size("16k").unwrap(), 16 * 1024);
+        assert_eq!(Cli::parse_size("0x10K").unwrap(), 16 * 1024);
+        assert_eq!(Cli::parse_size("1M").unwrap(), 1024 * 1024);
+        assert!(Cli::parse_size("4096M").is_err());
+        assert!(Cli::parse_size("1m").is_err());
+        assert!(Cli::parse_size("-1").is_err());
+        assert!(Cli::parse_size("").is_err());
+    }
 }
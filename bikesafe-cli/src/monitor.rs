@@ -0,0 +1,133 @@
+//! Pass-through [`DfuIo`] that prints DFU state transitions, poll timeouts
+//! and DfuSe commands as they happen.
+
+use std::cell::Cell;
+use std::time::Instant;
+
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::{DfuIo, DfuProtocol, State};
+
+const DFU_DNLOAD: u8 = 1;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_ABORT: u8 = 6;
+
+pub struct Monitor<IO> {
+    io: IO,
+    enabled: bool,
+    bar: indicatif::ProgressBar,
+    start: Instant,
+    last: Cell<Option<(State, u64)>>,
+}
+
+impl<IO> Monitor<IO> {
+    /// Wrap `io`; when `enabled`, events are printed above `bar`.
+    pub fn new(io: IO, enabled: bool, bar: indicatif::ProgressBar) -> Self {
+        Self {
+            io,
+            enabled,
+            bar,
+            start: Instant::now(),
+            last: Cell::new(None),
+        }
+    }
+
+    fn print(&self, event: std::fmt::Arguments) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let line = format!("[{elapsed:>9.3}s] {event}");
+        if self.bar.is_hidden() {
+            eprintln!("{line}");
+        } else {
+            self.bar.println(line);
+        }
+    }
+
+    fn on_status(&self, buffer: &[u8]) {
+        let [status, t0, t1, t2, state, ..] = *buffer else {
+            return;
+        };
+        let status = dfu_core::Status::from(status);
+        let poll_timeout = u32::from_le_bytes([t0, t1, t2, 0]) as u64;
+        let state = State::from(state);
+        if self.last.replace(Some((state, poll_timeout))) == Some((state, poll_timeout)) {
+            return;
+        }
+        self.print(format_args!(
+            "{:<22} status {:<14} poll {poll_timeout} ms",
+            format!("{state:?}"),
+            format!("{status:?}")
+        ));
+    }
+
+    fn on_command(&self, request: u8, value: u16, buffer: &[u8]) {
+        match (request, value, buffer) {
+            (DFU_DNLOAD, 0, [0x21, a @ ..]) if a.len() == 4 => {
+                let address = u32::from_le_bytes([a[0], a[1], a[2], a[3]]);
+                self.print(format_args!("set address {address:#010X}"));
+            }
+            (DFU_DNLOAD, 0, [0x41, a @ ..]) if a.len() == 4 => {
+                let address = u32::from_le_bytes([a[0], a[1], a[2], a[3]]);
+                self.print(format_args!("erase page {address:#010X}"));
+            }
+            (DFU_DNLOAD, 0, [0x41]) => self.print(format_args!("mass erase")),
+            (DFU_DNLOAD, 0, [0x92]) => self.print(format_args!("read unprotect")),
+            (DFU_DNLOAD, _, []) => self.print(format_args!("zero-length download (leave)")),
+            (DFU_CLRSTATUS, ..) => self.print(format_args!("clear status")),
+            (DFU_ABORT, ..) => self.print(format_args!("abort")),
+            _ => {}
+        }
+    }
+}
+
+impl<IO> DfuIo for Monitor<IO>
+where
+    IO: DfuIo<Read = usize>,
+{
+    type Read = IO::Read;
+    type Write = IO::Write;
+    type Reset = IO::Reset;
+    type Error = IO::Error;
+    type MemoryLayout = IO::MemoryLayout;
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> Result<Self::Read, Self::Error> {
+        let n = self.io.read_control(request_type, request, value, buffer)?;
+        if self.enabled && request == DFU_GETSTATUS {
+            self.on_status(&buffer[..n.min(buffer.len())]);
+        }
+        Ok(n)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> Result<Self::Write, Self::Error> {
+        if self.enabled {
+            self.on_command(request, value, buffer);
+        }
+        self.io.write_control(request_type, request, value, buffer)
+    }
+
+    fn usb_reset(&self) -> Result<Self::Reset, Self::Error> {
+        if self.enabled {
+            self.print(format_args!("USB reset"));
+        }
+        self.io.usb_reset()
+    }
+
+    fn protocol(&self) -> &DfuProtocol<Self::MemoryLayout> {
+        self.io.protocol()
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        self.io.functional_descriptor()
+    }
+}
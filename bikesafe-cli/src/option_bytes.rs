@@ -0,0 +1,326 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use dfu_core::{DfuIo, DfuProtocol};
+use dfu_libusb::DfuLibusb;
+
+use crate::device::Device;
+use crate::dfuse;
+
+/// Interface string prefix of the DfuSe option-byte alternate setting.
+const ALT_NAME: &str = "@Option Bytes";
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Read and decode the option bytes.
+    Read,
+    /// Program new option-byte values. Fields that are not given keep their
+    /// current value.
+    Write(WriteArgs),
+}
+
+#[derive(clap::Args)]
+pub struct WriteArgs {
+    /// Read protection byte (0xAA: level 0, 0xCC: level 2 - PERMANENT, other:
+    /// level 1).
+    #[clap(long, value_parser = parse_byte)]
+    rdp: Option<u8>,
+
+    /// USER byte (watchdog, reset-on-stop/standby, brown-out ...).
+    #[clap(long, value_parser = parse_byte)]
+    user: Option<u8>,
+
+    /// DATA0 user data byte.
+    #[clap(long, value_parser = parse_byte)]
+    data0: Option<u8>,
+
+    /// DATA1 user data byte.
+    #[clap(long, value_parser = parse_byte)]
+    data1: Option<u8>,
+
+    /// Write protection bits WRP3..WRP0 (a cleared bit protects its sector).
+    #[clap(long, value_parser = crate::Cli::parse_address)]
+    wrp: Option<u32>,
+
+    /// Do not ask for confirmation.
+    #[clap(long)]
+    yes: bool,
+
+    /// Allow setting RDP level 2, which disables DFU and debug access forever.
+    #[clap(long)]
+    allow_level_2: bool,
+}
+
+/// Level of flash read protection encoded in the RDP byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadProtection {
+    Level0,
+    Level1,
+    Level2,
+}
+
+impl ReadProtection {
+    pub fn from_rdp(rdp: u8) -> Self {
+        match rdp {
+            // 0xA5 is the level 0 key on STM32F1 parts.
+            0xAA | 0xA5 => ReadProtection::Level0,
+            0xCC => ReadProtection::Level2,
+            _ => ReadProtection::Level1,
+        }
+    }
+}
+
+impl fmt::Display for ReadProtection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadProtection::Level0 => write!(f, "level 0, no protection"),
+            ReadProtection::Level1 => write!(f, "level 1, flash read protected"),
+            ReadProtection::Level2 => write!(f, "level 2, chip permanently locked"),
+        }
+    }
+}
+
+/// The STM32 option-byte block: each value byte is followed by its
+/// complement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionBytes {
+    pub rdp: u8,
+    pub user: u8,
+    pub data: [u8; 2],
+    pub wrp: [u8; 4],
+}
+
+impl OptionBytes {
+    pub const LEN: usize = 16;
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Self> {
+        anyhow::ensure!(
+            raw.len() >= Self::LEN,
+            "option-byte block too short: {} < {} bytes",
+            raw.len(),
+            Self::LEN
+        );
+        for (i, pair) in raw[..Self::LEN].chunks(2).enumerate() {
+            if pair[0] != !pair[1] {
+                tracing::warn!(
+                    "Option byte {i} ({:#04X}) does not match its complement ({:#04X})",
+                    pair[0],
+                    pair[1]
+                );
+            }
+        }
+        Ok(Self {
+            rdp: raw[0],
+            user: raw[2],
+            data: [raw[4], raw[6]],
+            wrp: [raw[8], raw[10], raw[12], raw[14]],
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let values = [
+            self.rdp,
+            self.user,
+            self.data[0],
+            self.data[1],
+            self.wrp[0],
+            self.wrp[1],
+            self.wrp[2],
+            self.wrp[3],
+        ];
+        let mut raw = [0; Self::LEN];
+        for (pair, value) in raw.chunks_mut(2).zip(values) {
+            pair[0] = value;
+            pair[1] = !value;
+        }
+        raw
+    }
+
+    pub fn protection(&self) -> ReadProtection {
+        ReadProtection::from_rdp(self.rdp)
+    }
+
+    pub fn wrp(&self) -> u32 {
+        u32::from_le_bytes(self.wrp)
+    }
+}
+
+impl fmt::Display for OptionBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "RDP    {:#04X} ({})", self.rdp, self.protection())?;
+        writeln!(f, "USER   {:#04X}", self.user)?;
+        writeln!(f, "DATA0  {:#04X}", self.data[0])?;
+        writeln!(f, "DATA1  {:#04X}", self.data[1])?;
+        let protected = self.wrp().count_zeros();
+        write!(
+            f,
+            "WRP    {:#010X} ({protected} sectors write protected)",
+            self.wrp()
+        )
+    }
+}
+
+impl Command {
+    pub fn run(self, device: &Device) -> Result<()> {
+        let (io, address) = open(device)?;
+        let current = read(&io, address)?;
+
+        let args = match self {
+            Command::Read => {
+                println!("{current}");
+                return Ok(());
+            }
+            Command::Write(args) => args,
+        };
+
+        let mut new = current;
+        if let Some(rdp) = args.rdp {
+            new.rdp = rdp;
+        }
+        if let Some(user) = args.user {
+            new.user = user;
+        }
+        if let Some(data0) = args.data0 {
+            new.data[0] = data0;
+        }
+        if let Some(data1) = args.data1 {
+            new.data[1] = data1;
+        }
+        if let Some(wrp) = args.wrp {
+            new.wrp = wrp.to_le_bytes();
+        }
+        if new == current {
+            println!("Option bytes already up to date");
+            return Ok(());
+        }
+
+        println!("Current option bytes:\n{current}\n");
+        println!("New option bytes:\n{new}\n");
+        match (current.protection(), new.protection()) {
+            (_, ReadProtection::Level2) => {
+                anyhow::ensure!(
+                    args.allow_level_2,
+                    "refusing to set RDP level 2 without --allow-level-2"
+                );
+                println!(
+                    "WARNING: RDP level 2 is PERMANENT. The device can never be updated again."
+                );
+            }
+            (ReadProtection::Level1, ReadProtection::Level0) => {
+                println!("WARNING: Leaving RDP level 1 mass-erases the whole flash.");
+            }
+            _ => {}
+        }
+        crate::confirm("Program these option bytes?", args.yes)?;
+
+        write(&io, address, &new)
+    }
+}
+
+/// Open the option-byte alternate setting, returning the interface and the
+/// option-byte base address.
+pub fn open(device: &Device) -> Result<(DfuLibusb<rusb::Context>, u32)> {
+    let alt = device.find_alt(ALT_NAME)?;
+    let io = device.open_alt(alt)?.into_inner();
+    let DfuProtocol::Dfuse { address, .. } = io.protocol() else {
+        anyhow::bail!("option bytes need a DfuSe device");
+    };
+    let address = *address;
+    Ok((io, address))
+}
+
+/// Read and decode the option bytes at `address`.
+pub fn read<IO>(io: &IO, address: u32) -> Result<OptionBytes>
+where
+    IO: DfuIo<Read = usize, Write = usize, Error = dfu_libusb::Error>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    let raw = dfuse::upload(io, address, OptionBytes::LEN, transfer_size, |_| ())
+        .context("could not read option bytes")?;
+    OptionBytes::from_bytes(&raw)
+}
+
+/// Program `option_bytes` at `address`, tolerating the reset the device
+/// performs to load them.
+pub fn write<IO>(io: &IO, address: u32, option_bytes: &OptionBytes) -> Result<()>
+where
+    IO: DfuIo<Read = usize, Write = usize, Error = dfu_libusb::Error>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    match dfuse::download(io, address, &option_bytes.to_bytes(), transfer_size, |_| ()) {
+        Ok(()) => println!("Option bytes written; power-cycle the device to apply them"),
+        // The device resets itself to reload the option bytes.
+        Err(e) if is_disconnect(&e) => {
+            tracing::debug!("{e:#?}");
+            println!("Option bytes written; device reset itself");
+        }
+        Err(e) => return Err(e).context("could not write option bytes"),
+    }
+    Ok(())
+}
+
+/// Whether `error` is the device dropping off the bus, as it does when it
+/// resets itself to load new option bytes. Stalls, timeouts and access
+/// errors are failures.
+pub fn is_disconnect(error: &dfu_libusb::Error) -> bool {
+    matches!(
+        error,
+        dfu_libusb::Error::LibUsb(rusb::Error::NoDevice | rusb::Error::Io)
+    )
+}
+
+fn parse_byte(s: &str) -> Result<u8> {
+    // remove leading 0x if present
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    let byte = u8::from_str_radix(s, 16).context("could not parse byte")?;
+    Ok(byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTES: OptionBytes = OptionBytes {
+        rdp: 0xAA,
+        user: 0xFF,
+        data: [0x12, 0x34],
+        wrp: [0xFF, 0xFE, 0xFF, 0x7F],
+    };
+
+    #[test]
+    fn round_trip() {
+        let raw = BYTES.to_bytes();
+        assert_eq!(&raw[..4], [0xAA, 0x55, 0xFF, 0x00]);
+        assert_eq!(OptionBytes::from_bytes(&raw).unwrap(), BYTES);
+        assert_eq!(BYTES.wrp(), 0x7FFF_FEFF);
+        assert_eq!(BYTES.wrp().count_zeros(), 2);
+    }
+
+    #[test]
+    fn short_block_is_rejected() {
+        let raw = BYTES.to_bytes();
+        assert!(OptionBytes::from_bytes(&raw[..OptionBytes::LEN - 1]).is_err());
+        // Reads may return more than the block; the rest is ignored.
+        let mut longer = raw.to_vec();
+        longer.extend([0; 4]);
+        assert_eq!(OptionBytes::from_bytes(&longer).unwrap(), BYTES);
+    }
+
+    #[test]
+    fn read_protection_levels() {
+        assert_eq!(ReadProtection::from_rdp(0xAA), ReadProtection::Level0);
+        assert_eq!(ReadProtection::from_rdp(0xA5), ReadProtection::Level0);
+        assert_eq!(ReadProtection::from_rdp(0xCC), ReadProtection::Level2);
+        for rdp in [0x00, 0x55, 0xBB, 0xFF] {
+            assert_eq!(ReadProtection::from_rdp(rdp), ReadProtection::Level1);
+        }
+    }
+
+    #[test]
+    fn byte_values() {
+        assert_eq!(parse_byte("0xCC").unwrap(), 0xCC);
+        assert_eq!(parse_byte("aa").unwrap(), 0xAA);
+        assert!(parse_byte("0x100").is_err());
+        assert!(parse_byte("level0").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Progress of transfers on the terminal: a bar, a plain line every few
+//! seconds without a terminal (or with `--no-progress`), or JSON lines on
+//! stderr with `--log-format json`.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use bikesafe_core::progress::JsonLines;
+use bikesafe_core::{Phase, ProgressSink};
+
+use crate::{OUTPUT, Output};
+
+/// Interval between plain-text progress lines.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where an operation reports its progress. Clones report to the same bar.
+#[derive(Clone)]
+pub struct Progress {
+    bar: indicatif::ProgressBar,
+    json: Option<Arc<Mutex<JsonLines<io::Stderr>>>>,
+}
+
+impl Progress {
+    pub fn new() -> Result<Self> {
+        let output = OUTPUT.get().copied().unwrap_or(Output {
+            color: true,
+            progress_bar: true,
+            json_progress: false,
+        });
+        let json = output
+            .json_progress
+            .then(|| Arc::new(Mutex::new(JsonLines::new(io::stderr()))));
+        if !output.progress_bar {
+            let bar = indicatif::ProgressBar::hidden();
+            if json.is_none() {
+                report_progress(bar.downgrade());
+            }
+            return Ok(Self { bar, json });
+        }
+
+        let template = if output.color {
+            "{spinner:.green} [{elapsed_precise}] [{bar:27.cyan/blue}] \
+                {bytes}/{total_bytes} ({bytes_per_sec}) ({eta}) {msg:10}"
+        } else {
+            "{spinner} [{elapsed_precise}] [{bar:27}] \
+                {bytes}/{total_bytes} ({bytes_per_sec}) ({eta}) {msg:10}"
+        };
+        let bar = indicatif::ProgressBar::new(0);
+        bar.set_style(
+            indicatif::ProgressStyle::default_bar()
+                .template(template)?
+                .progress_chars("#>-"),
+        );
+        Ok(Self { bar, json })
+    }
+
+    /// The bar, hidden if there is none, to print lines above it.
+    pub fn bar(&self) -> &indicatif::ProgressBar {
+        &self.bar
+    }
+
+    fn json(&self, report: impl FnOnce(&mut JsonLines<io::Stderr>)) {
+        if let Some(json) = &self.json {
+            report(&mut json.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+}
+
+impl ProgressSink for Progress {
+    fn phase(&mut self, phase: Phase, total: u64) {
+        self.bar.set_message(phase.name());
+        self.bar.set_length(total);
+        self.bar.set_position(0);
+        self.json(|json| json.phase(phase, total));
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        self.bar.set_length(total);
+        self.bar.set_position(done);
+        self.json(|json| json.bytes(done, total));
+    }
+
+    fn message(&mut self, message: &str) {
+        self.bar.suspend(|| println!("{message}"));
+        self.json(|json| json.message(message));
+    }
+
+    fn finished(&mut self) {
+        self.bar.finish();
+        self.json(|json| json.finished());
+    }
+}
+
+/// Print the position of `bar` whenever it moved, until it finishes or is
+/// dropped.
+fn report_progress(bar: indicatif::WeakProgressBar) {
+    thread::spawn(move || {
+        let mut last = None;
+        loop {
+            thread::sleep(PROGRESS_INTERVAL);
+            let Some(bar) = bar.upgrade() else {
+                return;
+            };
+            let position = bar.position();
+            if last != Some(position) {
+                last = Some(position);
+                let length = bar.length().unwrap_or(0);
+                let percent = (position * 100).checked_div(length).unwrap_or(0);
+                let message = bar.message();
+                let label = match message.trim() {
+                    "" => "progress",
+                    message => message,
+                };
+                eprintln!("{label} {position}/{length} bytes ({percent}%)");
+            }
+            if bar.is_finished() {
+                return;
+            }
+        }
+    });
+}
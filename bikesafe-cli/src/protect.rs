@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+
+use crate::device::Device;
+use crate::dfuse;
+use crate::option_bytes::{self, ReadProtection};
+
+/// RDP value used to enter level 1; anything but the level 0/2 keys works.
+const RDP_LEVEL_1: u8 = 0x00;
+
+#[derive(clap::Args)]
+pub struct ProtectArgs {
+    /// Do not ask for confirmation.
+    #[clap(long)]
+    yes: bool,
+}
+
+#[derive(clap::Args)]
+pub struct UnprotectArgs {
+    /// Do not ask for confirmation.
+    #[clap(long)]
+    yes: bool,
+}
+
+impl ProtectArgs {
+    /// Raise read protection to level 1.
+    pub fn run(self, device: &Device) -> Result<()> {
+        let (io, address) = option_bytes::open(device)?;
+        let current = option_bytes::read(&io, address)?;
+        if current.protection() != ReadProtection::Level0 {
+            println!("Device already protected ({})", current.protection());
+            return Ok(());
+        }
+
+        println!(
+            "Enabling RDP level 1 blocks reading the flash over DFU and SWD. Removing it again \
+             mass-erases the device."
+        );
+        crate::confirm("Protect the device?", self.yes)?;
+
+        let protected = option_bytes::OptionBytes {
+            rdp: RDP_LEVEL_1,
+            ..current
+        };
+        option_bytes::write(&io, address, &protected)
+    }
+}
+
+impl UnprotectArgs {
+    /// Clear read protection, mass-erasing the flash.
+    pub fn run(self, device: &Device) -> Result<()> {
+        println!(
+            "WARNING: Removing read protection MASS-ERASES the whole flash: the firmware and all \
+             stored settings are lost and the device has to be flashed again afterwards."
+        );
+        crate::confirm("Unprotect and erase the device?", self.yes)?;
+
+        let io = device.open()?.into_inner();
+        dfuse::ensure_idle(&io)?;
+        match dfuse::read_unprotect(&io) {
+            Ok(()) => println!("Read protection removed; power-cycle the device"),
+            // The device resets itself once the mass erase is done.
+            Err(e) if option_bytes::is_disconnect(&e) => {
+                tracing::debug!("{e:#?}");
+                println!("Read protection removed; device reset itself");
+            }
+            Err(e) => return Err(e).context("could not remove read protection"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Per-unit provisioning data written to a reserved flash page during
+//! production.
+//!
+//! Blob layout (version 1, 48 bytes, little-endian):
+//!
+//! | Offset | Size | Field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0x00   | 4    | magic `BBPV`                                   |
+//! | 0x04   | 2    | format version (1)                             |
+//! | 0x06   | 2    | hardware revision, major in the high byte      |
+//! | 0x08   | 4    | manufacture date as decimal `YYYYMMDD`         |
+//! | 0x0C   | 32   | serial number, ASCII, NUL-padded               |
+//! | 0x2C   | 4    | CRC32 of bytes 0x00..0x2C                      |
+//!
+//! The firmware reads this page as-is, so any change to the layout needs a
+//! new format version.
+
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use bikesafe_core::family::MemoryMap;
+
+use crate::device::Device;
+use crate::progress::Progress;
+
+const MAGIC: &[u8; 4] = b"BBPV";
+const VERSION: u16 = 1;
+const SERIAL_LEN: usize = 32;
+
+#[derive(clap::Args)]
+pub struct ProvisionArgs {
+    /// Serial number to store, e.g. BB-2025-000123.
+    #[clap(long, env = "BIKESAFE_SERIAL")]
+    serial_number: String,
+
+    /// Address of the reserved provisioning page. Defaults to the one in the
+    /// device's memory map.
+    #[clap(
+        long,
+        short,
+        env = "BIKESAFE_PROVISION_ADDRESS",
+        value_parser = crate::Cli::parse_address
+    )]
+    address: Option<u32>,
+
+    /// Hardware revision as MAJOR.MINOR.
+    #[clap(
+        long,
+        default_value = "1.0",
+        env = "BIKESAFE_HARDWARE_REV",
+        value_parser = parse_revision
+    )]
+    hardware_rev: (u8, u8),
+
+    /// Manufacture date as YYYY-MM-DD [default: today]
+    #[clap(long, value_parser = parse_date)]
+    date: Option<u32>,
+}
+
+/// Contents of the provisioning page.
+pub struct Provisioning {
+    pub serial_number: String,
+    pub hardware_rev: (u8, u8),
+    pub date: u32,
+}
+
+impl Provisioning {
+    pub const LEN: usize = 48;
+
+    /// Encode the blob, failing if the serial number does not fit.
+    pub fn to_bytes(&self) -> Result<[u8; Self::LEN]> {
+        let serial = self.serial_number.as_bytes();
+        anyhow::ensure!(
+            self.serial_number.is_ascii() && serial.len() <= SERIAL_LEN,
+            "serial number must be at most {SERIAL_LEN} ASCII characters"
+        );
+
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..4].copy_from_slice(MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[6..8].copy_from_slice(&u16::from_be_bytes(self.hardware_rev.into()).to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.date.to_le_bytes());
+        bytes[12..12 + serial.len()].copy_from_slice(serial);
+        let crc = crc32fast::hash(&bytes[..Self::LEN - 4]);
+        bytes[Self::LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        Ok(bytes)
+    }
+}
+
+impl ProvisionArgs {
+    pub fn run(self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let address = self
+            .address
+            .or(memory.reserved("provisioning").map(|page| page.origin))
+            .context("the device has no provisioning page; pass --address")?;
+        let date = match self.date {
+            Some(date) => date,
+            None => today()?,
+        };
+        let provisioning = Provisioning {
+            serial_number: self.serial_number,
+            hardware_rev: self.hardware_rev,
+            date,
+        };
+        let blob = provisioning.to_bytes()?;
+
+        println!(
+            "Provisioning {} (hardware {}.{}, manufactured {}) at {:#010X}",
+            provisioning.serial_number,
+            provisioning.hardware_rev.0,
+            provisioning.hardware_rev.1,
+            provisioning.date,
+            address
+        );
+        let io = device.open()?.into_inner();
+        crate::flash::write_verified(&io, address, &blob, &mut Progress::new()?)?;
+        println!("Provisioning data written and verified");
+        Ok(())
+    }
+}
+
+fn parse_revision(s: &str) -> Result<(u8, u8)> {
+    let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+    let major = major.parse().context("could not parse major revision")?;
+    let minor = minor.parse().context("could not parse minor revision")?;
+    Ok((major, minor))
+}
+
+/// Parse `YYYY-MM-DD` into the decimal `YYYYMMDD` stored in the blob.
+fn parse_date(s: &str) -> Result<u32> {
+    let mut parts = s.splitn(3, '-').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("date must look like YYYY-MM-DD");
+    };
+    anyhow::ensure!(
+        (2000..=9999).contains(&year) && (1..=12).contains(&month) && (1..=31).contains(&day),
+        "date out of range"
+    );
+    Ok(year * 10000 + month * 100 + day)
+}
+
+fn today() -> Result<u32> {
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    parse_date(&now[..10])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_layout() {
+        let blob = Provisioning {
+            serial_number: "BB-2025-000123".into(),
+            hardware_rev: (2, 1),
+            date: 20250314,
+        }
+        .to_bytes()
+        .unwrap();
+        assert_eq!(&blob[0..4], MAGIC);
+        assert_eq!(blob[4..6], VERSION.to_le_bytes());
+        assert_eq!(blob[6..8], [1, 2]);
+        assert_eq!(blob[8..12], 20250314u32.to_le_bytes());
+        assert_eq!(&blob[12..26], b"BB-2025-000123");
+        assert!(blob[26..44].iter().all(|&b| b == 0));
+        assert_eq!(blob[44..], crc32fast::hash(&blob[..44]).to_le_bytes());
+    }
+
+    #[test]
+    fn serial_must_fit() {
+        let provisioning = |serial: &str| Provisioning {
+            serial_number: serial.into(),
+            hardware_rev: (1, 0),
+            date: 20250314,
+        };
+        provisioning(&"X".repeat(SERIAL_LEN)).to_bytes().unwrap();
+        assert!(
+            provisioning(&"X".repeat(SERIAL_LEN + 1))
+                .to_bytes()
+                .is_err()
+        );
+        assert!(provisioning("BB-ü").to_bytes().is_err());
+    }
+
+    #[test]
+    fn revisions_and_dates() {
+        assert_eq!(parse_revision("2.1").unwrap(), (2, 1));
+        assert_eq!(parse_revision("3").unwrap(), (3, 0));
+        assert!(parse_revision("256.0").is_err());
+        assert_eq!(parse_date("2025-03-14").unwrap(), 20250314);
+        assert!(parse_date("2025-13-01").is_err());
+        assert!(parse_date("1999-12-31").is_err());
+        assert!(parse_date("14.03.2025").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! Bring a wedged device back to dfuIDLE without unplugging it.
+
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use dfu_core::{DfuIo, State};
+
+use crate::device::Device;
+use crate::dfuse::{self, DeviceStatus};
+
+/// Requests to try before giving up.
+const ATTEMPTS: usize = 5;
+
+/// Issue DFU_CLRSTATUS / DFU_ABORT until the device reports dfuIDLE,
+/// printing the state before and after.
+pub fn run(device: &Device) -> Result<()> {
+    let io = device.open()?.into_inner();
+    let before = dfuse::get_status(&io).context("could not read device status")?;
+    print_status("Before", &before);
+
+    let mut status = before;
+    for _ in 0..ATTEMPTS {
+        match status.state {
+            State::DfuIdle => break,
+            State::AppIdle | State::AppDetach => {
+                anyhow::bail!("device is running its application; put it into DFU mode first")
+            }
+            State::DfuManifestWaitReset => {
+                println!("Device waits for a reset after manifestation, resetting");
+                io.usb_reset()?;
+                return Ok(());
+            }
+            State::DfuDnbusy | State::DfuManifest => {
+                // Busy states cannot be interrupted; let the operation finish.
+                thread::sleep(Duration::from_millis(status.poll_timeout.max(10)));
+            }
+            State::DfuError => {
+                println!("Clearing error status {:?}", status.status);
+                dfuse::clear_status(&io)?;
+            }
+            state => {
+                println!("Aborting from {state:?}");
+                dfuse::abort(&io)?;
+            }
+        }
+        status = dfuse::get_status(&io).context("could not read device status")?;
+    }
+
+    print_status("After", &status);
+    anyhow::ensure!(
+        status.state == State::DfuIdle,
+        "device is still in {:?}; unplug and replug it",
+        status.state
+    );
+    Ok(())
+}
+
+fn print_status(label: &str, status: &DeviceStatus) {
+    println!(
+        "{label:<7} state {:?}, status {:?}",
+        status.state, status.status
+    );
+}
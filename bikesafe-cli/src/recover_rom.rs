@@ -0,0 +1,95 @@
+//! Reinstalling the BrakeBright bootloader through the STM32's built-in ROM
+//! DFU bootloader, for units whose own bootloader no longer starts.
+//!
+//! The ROM bootloader runs when BOOT0 is held high during reset and
+//! enumerates as `0483:df11` with one alternate setting per memory area.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_libusb::Error;
+
+use crate::device::Device;
+use crate::progress::Progress;
+use crate::{dfuse, flash};
+
+#[derive(clap::Args)]
+pub struct RecoverRomArgs {
+    /// Bootloader image to install.
+    #[clap(long, short)]
+    path: PathBuf,
+
+    /// Address to write the bootloader to.
+    #[clap(long, short, default_value = "0x08000000", value_parser = crate::Cli::parse_address)]
+    address: u32,
+
+    /// Erase the whole flash before writing (firmware and settings too).
+    #[clap(long)]
+    mass_erase: bool,
+
+    /// VID:PID of the ROM bootloader.
+    #[clap(
+        long,
+        value_name = "VID:PID",
+        default_value = "0483:df11",
+        value_parser = crate::Cli::parse_vid_pid
+    )]
+    rom_device: (u16, u16),
+
+    /// Alternate setting of the ROM bootloader holding the flash.
+    #[clap(long, default_value = "@Internal Flash")]
+    rom_alt: String,
+
+    /// Do not ask for confirmation.
+    #[clap(long)]
+    yes: bool,
+}
+
+impl RecoverRomArgs {
+    pub fn run(self, device: &Device) -> Result<()> {
+        let bootloader = std::fs::read(&self.path)
+            .with_context(|| format!("could not open bootloader file `{}`", self.path.display()))?;
+
+        let (vid, pid) = self.rom_device;
+        let mut rom = Device {
+            vid,
+            pid,
+            intf: 0,
+            alt: 0,
+            port: None,
+            ..device.clone()
+        };
+        rom.usb_device().with_context(|| {
+            format!(
+                "no ROM bootloader {vid:04x}:{pid:04x} found; hold BOOT0 high while resetting \
+                 the board"
+            )
+        })?;
+        rom.alt = rom.find_alt(&self.rom_alt)?;
+
+        let io = rom.open()?.into_inner();
+        if self.mass_erase {
+            println!(
+                "WARNING: a mass erase removes the firmware and all stored settings along with \
+                 the bootloader."
+            );
+            crate::confirm("Mass-erase the device?", self.yes)?;
+            println!("Mass-erasing flash");
+            dfuse::mass_erase(&io).context("could not mass-erase the flash")?;
+        }
+
+        flash::write_verified(&io, self.address, &bootloader, &mut Progress::new()?)?;
+        println!(
+            "Bootloader written and verified ({} bytes at {:#010X})",
+            bootloader.len(),
+            self.address
+        );
+
+        match dfuse::leave(&io, self.address) {
+            Ok(()) | Err(Error::LibUsb(_)) => (),
+            Err(e) => return Err(e).context("could not leave the ROM bootloader"),
+        }
+        println!("Release BOOT0; the BrakeBright bootloader starts on the next reset");
+        Ok(())
+    }
+}
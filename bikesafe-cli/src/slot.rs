@@ -0,0 +1,93 @@
+//! A/B slot selection for dual-bank firmware builds.
+//!
+//! The bootloader describes its slots as DfuSe alternate settings named
+//! `@Slot A` and `@Slot B`, e.g. `@Slot A (active) /0x08004000/24*001Kg`.
+//! The first segment address is the slot's base, and the slot the
+//! bootloader will start is marked `(active)`.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+
+use crate::device::Device;
+use crate::info::DeviceInfo;
+
+/// `--slot` values.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SlotArg {
+    A,
+    B,
+    /// Whichever slot is not currently active.
+    Inactive,
+}
+
+/// One slot of the device's slot map.
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    pub name: char,
+    pub address: u32,
+    pub active: bool,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slot {} at {:#010X}", self.name, self.address)?;
+        if self.active {
+            write!(f, " (active)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the slot map from the interface strings of `device`.
+pub fn slots(device: &Device) -> Result<Vec<Slot>> {
+    let info = DeviceInfo::read(device)?;
+    let slots = info
+        .configurations
+        .iter()
+        .flat_map(|config| &config.interfaces)
+        .filter(|intf| intf.number == device.intf)
+        .filter_map(|intf| {
+            let layout = intf.memory_layout.as_ref()?;
+            let label = layout.name.strip_prefix("Slot ")?;
+            let name = label.chars().next()?.to_ascii_uppercase();
+            Some(Slot {
+                name,
+                address: layout.segments.first()?.address,
+                active: label.contains("(active)"),
+            })
+        })
+        .collect();
+    Ok(slots)
+}
+
+/// Resolve `slot` to the slot it names on `device`.
+pub fn resolve(device: &Device, slot: SlotArg) -> Result<Slot> {
+    let slots = slots(device)?;
+    anyhow::ensure!(
+        !slots.is_empty(),
+        "device does not report a slot map (no `@Slot A`/`@Slot B` alternate settings)"
+    );
+    let wanted = |name| {
+        slots
+            .iter()
+            .find(|s| s.name == name)
+            .copied()
+            .with_context(|| format!("device has no slot {name}"))
+    };
+    match slot {
+        SlotArg::A => wanted('A'),
+        SlotArg::B => wanted('B'),
+        SlotArg::Inactive => {
+            let active = slots
+                .iter()
+                .find(|s| s.active)
+                .context("bootloader does not report an active slot")?;
+            slots
+                .iter()
+                .find(|s| !s.active)
+                .copied()
+                .with_context(|| format!("no slot besides the active {active}"))
+        }
+    }
+}
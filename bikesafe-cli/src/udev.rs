@@ -0,0 +1,54 @@
+//! Linux udev rule giving logged-in users access to the bootloader.
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+pub const RULES_PATH: &str = "/etc/udev/rules.d/70-bootloader.rules";
+
+#[derive(clap::Args)]
+pub struct UdevRuleArgs {
+    /// Write the rule to /etc/udev/rules.d and reload udev (needs root).
+    #[clap(long)]
+    install: bool,
+}
+
+/// Rule line for device `vid:pid`.
+pub fn rule(vid: u16, pid: u16) -> String {
+    format!(r#"ATTRS{{idVendor}}=="{vid:04x}", ATTRS{{idProduct}}=="{pid:04x}", TAG+="uaccess""#)
+}
+
+impl UdevRuleArgs {
+    pub fn run(self, (vid, pid): (u16, u16)) -> Result<()> {
+        let rule = rule(vid, pid);
+        if !self.install {
+            println!("{rule}");
+            return Ok(());
+        }
+
+        let path = Path::new(RULES_PATH);
+        std::fs::write(path, format!("{rule}\n")).with_context(|| {
+            format!("could not write `{}` (try again with sudo)", path.display())
+        })?;
+        println!("Wrote {}", path.display());
+
+        udevadm(&["control", "--reload"])?;
+        udevadm(&["trigger"])?;
+        println!("udev rules reloaded; replug the device if it was already connected");
+        Ok(())
+    }
+}
+
+fn udevadm(args: &[&str]) -> Result<()> {
+    let status = Command::new("udevadm")
+        .args(args)
+        .status()
+        .context("could not run udevadm")?;
+    anyhow::ensure!(
+        status.success(),
+        "udevadm {} failed: {status}",
+        args.join(" ")
+    );
+    Ok(())
+}
@@ -0,0 +1,280 @@
+//! One-shot update for end users: get the device into DFU mode, write and
+//! verify the firmware, start it and check the version it reports (and,
+//! optionally, its self-test).
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bikesafe_core::cancel::Cancellable;
+use bikesafe_core::family::{Family, MemoryMap, PostFlashTest};
+use device_protocol::Runtime;
+use dfu_libusb::{DfuLibusb, Error};
+use telemetry::{Outcome, Telemetry};
+
+use crate::bundle::{self, Bundle, KeyArgs};
+use crate::device::{self, Device, PROTOCOL_DFU, PROTOCOL_RUNTIME};
+use crate::progress::Progress;
+use crate::{dfuse, flash, info};
+
+/// Longest time between device scans while waiting for re-enumeration.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(clap::Args)]
+pub struct UpdateArgs {
+    /// Path to the firmware file to write to the device.
+    #[clap(long, short, required_unless_present = "bundle")]
+    path: Option<PathBuf>,
+
+    /// target address to flash the firmware. Defaults to the start of the
+    /// device's application flash.
+    #[clap(
+        long,
+        short,
+        env = "BIKESAFE_ADDRESS",
+        value_parser = crate::Cli::parse_address
+    )]
+    address: Option<u32>,
+
+    /// Release bundle to write instead of `--path`. The address and the
+    /// version to expect come from the manifest.
+    #[clap(long, conflicts_with = "path")]
+    bundle: Option<PathBuf>,
+
+    #[clap(flatten)]
+    keys: KeyArgs,
+
+    /// Version the application must report (USB bcdDevice) after the
+    /// update, e.g. 1.4.0.
+    #[clap(long, conflicts_with = "bundle")]
+    expect_version: Option<String>,
+
+    /// VID:PID of the running application, if it differs from `--device`.
+    #[clap(long, value_name = "VID:PID", value_parser = crate::Cli::parse_vid_pid)]
+    runtime_device: Option<(u16, u16)>,
+
+    /// How long to wait for the device to re-enumerate after switching
+    /// between application and bootloader.
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    timeout: Duration,
+
+    /// Run the device's self-test once the new firmware is running. Always
+    /// done for products that require it.
+    #[clap(long)]
+    self_test: bool,
+
+    /// Send an anonymous report of the outcome (OS, versions, failure class,
+    /// duration) to this URL. Nothing is sent without it.
+    #[clap(long, value_name = "URL", env = telemetry::ENDPOINT_ENV)]
+    telemetry: Option<String>,
+}
+
+/// The firmware to install, checked against the memory map before the
+/// device is touched.
+struct Image {
+    bundle: Option<Bundle>,
+    address: u32,
+    firmware: Vec<u8>,
+    expected: Option<String>,
+}
+
+impl UpdateArgs {
+    pub fn run(self, device: &Device, family: &'static Family) -> Result<()> {
+        let start = Instant::now();
+        let memory = family.memory_map((device.vid, device.pid), None);
+        let (result, outcome, version) = match self.load(memory) {
+            Ok(image) => {
+                let result = self.install(device, family, &image);
+                let outcome = match &result {
+                    Ok(_) => Outcome::Success,
+                    Err(e) => Outcome::of(e.as_ref()),
+                };
+                let version = result.as_ref().ok().cloned().or(image.expected);
+                (result.map(drop), outcome, version)
+            }
+            Err(e) => (Err(e), Outcome::ValidationError, None),
+        };
+        if let Some(endpoint) = self.telemetry {
+            let telemetry = Telemetry::new(endpoint, "bikesafe-cli", env!("CARGO_PKG_VERSION"));
+            if let Err(e) = telemetry.report(version.as_deref(), outcome, start.elapsed()) {
+                tracing::debug!("{:#}", anyhow::Error::from(e));
+            }
+        }
+        result
+    }
+
+    fn load(&self, memory: &MemoryMap) -> Result<Image> {
+        let bundle = match &self.bundle {
+            Some(path) => Some(Bundle::open(path, self.keys.key()?.as_ref())?),
+            None => None,
+        };
+        let (address, firmware, expected) = match &bundle {
+            Some(bundle) => (
+                bundle.manifest.address,
+                bundle.firmware.clone(),
+                Some(bundle.manifest.version.clone()),
+            ),
+            None => {
+                let path = self.path.as_ref().context("--path or --bundle is needed")?;
+                let firmware = bikesafe_core::read_firmware(path)?;
+                let address = self.address.unwrap_or(memory.flash.origin);
+                (address, firmware, self.expect_version.clone())
+            }
+        };
+        bikesafe_core::validate_at(memory, address, &firmware)?;
+        Ok(Image {
+            bundle,
+            address,
+            firmware,
+            expected,
+        })
+    }
+
+    /// Install `image` and return the version the device then reports.
+    fn install(&self, device: &Device, family: &Family, image: &Image) -> Result<String> {
+        let Image {
+            bundle,
+            address,
+            firmware,
+            expected,
+        } = image;
+        let runtime = self
+            .runtime_device
+            .unwrap_or_else(|| family.runtime_id((device.vid, device.pid)));
+
+        if find(device, (device.vid, device.pid), true)?.is_some() {
+            println!("Device is in DFU mode");
+        } else {
+            let app = find(device, runtime, false)?.with_context(|| {
+                format!(
+                    "no device {:04x}:{:04x} found; connect it, or hold the boot button while \
+                     plugging it in (`bikesafe-cli doctor` helps with the rest)",
+                    runtime.0, runtime.1
+                )
+            })?;
+            detach(app)?;
+            let dfu = (device.vid, device.pid);
+            wait(dfu, self.timeout, || find(device, dfu, true))?
+                .context("device did not enter DFU mode after detach")?;
+            println!("Device is in DFU mode");
+        }
+
+        if let Some(bundle) = &bundle {
+            bundle::check_compatible(
+                bundle,
+                device,
+                family.memory_map((device.vid, device.pid), None),
+            )?;
+        }
+
+        let io = Cancellable::new(device.open()?.into_inner(), crate::ctrl_c()?);
+        flash::write_verified(&io, *address, firmware, &mut Progress::new()?)?;
+        println!("Verified {} bytes", firmware.len());
+        let io = io.into_inner();
+
+        println!("Starting application");
+        match dfuse::leave(&io, *address) {
+            // The device may drop off the bus before answering.
+            Ok(()) | Err(Error::LibUsb(_)) => (),
+            Err(e) => return Err(e).context("could not leave DFU mode"),
+        }
+        drop(io);
+
+        let app = wait(runtime, self.timeout, || find(device, runtime, false))?
+            .context("application did not start after the update")?;
+        // Applications speaking the runtime protocol report their version
+        // there; others only through bcdDevice.
+        let protocol = match device_protocol::interface(&app) {
+            Some(_) => {
+                Some(Runtime::open(&app).context("could not open the application interface")?)
+            }
+            None => None,
+        };
+        let version = match &protocol {
+            Some(protocol) => protocol.version()?.to_string(),
+            None => info::version(app.device_descriptor()?.device_version()),
+        };
+        println!("Running version {version}");
+        if let Some(expected) = expected {
+            anyhow::ensure!(
+                &version == expected,
+                "device reports version {version}, expected {expected}"
+            );
+        }
+        if self.self_test || family.post_flash_test == PostFlashTest::SelfTest {
+            let protocol =
+                protocol.context("the application does not support the runtime protocol")?;
+            crate::app::self_test(&protocol, self.timeout)?;
+        }
+        println!("Update complete");
+        Ok(version)
+    }
+}
+
+/// First device `vid:pid` that is in DFU mode (`dfu`) or running its
+/// application (`!dfu`).
+pub fn find(
+    device: &Device,
+    (vid, pid): (u16, u16),
+    dfu: bool,
+) -> Result<Option<rusb::Device<rusb::Context>>> {
+    use rusb::UsbContext;
+
+    Ok(device.context.devices()?.iter().find(|usb| {
+        usb.device_descriptor()
+            .is_ok_and(|desc| (desc.vendor_id(), desc.product_id()) == (vid, pid))
+            && (device::dfu_interface(usb).map(|(_, protocol)| protocol) == Some(PROTOCOL_DFU))
+                == dfu
+    }))
+}
+
+/// Ask the running application to restart into its bootloader, through
+/// its DFU runtime interface or else the runtime protocol.
+fn detach(app: rusb::Device<rusb::Context>) -> Result<()> {
+    let Some((intf, PROTOCOL_RUNTIME)) = device::dfu_interface(&app) else {
+        anyhow::ensure!(
+            device_protocol::interface(&app).is_some(),
+            "the application has no DFU runtime interface; \
+             hold the boot button while plugging the device in instead"
+        );
+        println!("Restarting device into DFU mode");
+        return Runtime::open(&app)
+            .and_then(Runtime::reboot_to_dfu)
+            .context("could not restart the device into DFU mode");
+    };
+    let handle = app.open().context("could not open device")?;
+    let dfu = DfuLibusb::from_usb_device(app, handle, intf, 0)
+        .context("could not open the DFU runtime interface")?;
+
+    println!("Detaching device");
+    dfu.detach().context("could not detach device")?;
+    if !dfu.will_detach() {
+        match dfu.usb_reset() {
+            Ok(_) | Err(Error::LibUsb(_)) => (),
+            Err(e) => return Err(e).context("could not reset device"),
+        }
+    }
+    Ok(())
+}
+
+/// Call `find` until it returns a device or `timeout` passes, trying again
+/// as soon as a `vid:pid` device arrives.
+pub fn wait<T>(
+    (vid, pid): (u16, u16),
+    timeout: Duration,
+    mut find: impl FnMut() -> Result<Option<T>>,
+) -> Result<Option<T>> {
+    let watcher = device_watch::watch_devices(vid, pid)?;
+    let start = Instant::now();
+    loop {
+        if let Some(found) = find()? {
+            return Ok(Some(found));
+        }
+        let Some(left) = timeout.checked_sub(start.elapsed()) else {
+            return Ok(None);
+        };
+        // The descriptors may not be readable right at arrival, so look
+        // again after a while even without an event.
+        watcher.next_timeout(left.min(POLL_INTERVAL));
+    }
+}
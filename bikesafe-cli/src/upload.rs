@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bikesafe_core::cancel::Cancellable;
+use bikesafe_core::family::MemoryMap;
+use bikesafe_core::progress::Counter;
+use bikesafe_core::{Phase, ProgressSink};
+use dfu_core::DfuIo;
+
+use crate::device::Device;
+use crate::dfuse;
+use crate::progress::Progress;
+
+/// Blocks read per request batch; each batch is written out before the
+/// next one is read.
+const BLOCKS_PER_CHUNK: usize = 32;
+
+#[derive(clap::Args)]
+pub struct UploadArgs {
+    /// Start address of the region. Defaults to the start of the device's
+    /// application flash.
+    #[clap(long, short, value_parser = crate::Cli::parse_address)]
+    address: Option<u32>,
+
+    /// Number of bytes to read, e.g. 49152, 0xC000 or 48K.
+    #[clap(long, short, value_parser = crate::Cli::parse_size)]
+    length: u32,
+
+    /// File to write the image to, or `-` for stdout.
+    #[clap(long, short)]
+    output: PathBuf,
+}
+
+impl UploadArgs {
+    /// Read the region from the device and write it to the output as it
+    /// arrives.
+    pub fn run(self, device: &Device, memory: &MemoryMap) -> Result<()> {
+        let address = self.address.unwrap_or(memory.flash.origin);
+        let to_stdout = self.output.as_os_str() == "-";
+        let mut output: Box<dyn Write> = if to_stdout {
+            Box::new(io::stdout().lock())
+        } else {
+            Box::new(
+                File::create(&self.output)
+                    .with_context(|| format!("could not create `{}`", self.output.display()))?,
+            )
+        };
+
+        let io = Cancellable::new(device.open()?.into_inner(), crate::ctrl_c()?);
+        let descriptor = *io.functional_descriptor();
+        anyhow::ensure!(descriptor.can_upload, "device does not support upload");
+        let transfer_size = descriptor.transfer_size as usize;
+        let chunk = transfer_size * BLOCKS_PER_CHUNK;
+
+        let mut progress = Progress::new()?;
+        let mut count = Counter::new(&mut progress, Phase::Upload, self.length as u64);
+        let mut done = 0;
+        while done < self.length as usize {
+            let length = chunk.min(self.length as usize - done);
+            let data = dfuse::upload(&io, address + done as u32, length, transfer_size, |n| {
+                count.advance(n)
+            })
+            .context("could not read memory")?;
+            match output.write_all(&data) {
+                // The reader (e.g. `head`) went away; nothing left to do.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result.context("could not write output")?,
+            }
+            done += data.len();
+            if data.len() < length {
+                break;
+            }
+        }
+        output.flush().context("could not write output")?;
+        progress.finished();
+
+        anyhow::ensure!(
+            done == self.length as usize,
+            "device returned {done} of {} bytes",
+            self.length
+        );
+        if !to_stdout {
+            println!("Wrote {done} bytes to {}", self.output.display());
+        }
+        Ok(())
+    }
+}
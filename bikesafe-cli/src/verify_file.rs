@@ -0,0 +1,240 @@
+//! Static checks on a firmware artifact, without a device: a CI gate for
+//! the firmware repository.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bikesafe_core::family::MemoryMap;
+use device_memory::VectorTable;
+use dfu_file::{DfuElement, DfuFile, MemoryLayout, Suffix};
+use ed25519_dalek::Signature;
+use firmware_metadata::Metadata;
+
+use crate::bundle::{Bundle, KeyArgs};
+
+#[derive(clap::Args)]
+pub struct VerifyFileArgs {
+    /// Firmware artifact: a .bin, a .dfu or a release bundle (.zip).
+    file: PathBuf,
+
+    /// Address a .bin is linked for; .dfu files and bundles carry their own.
+    /// Defaults to the start of the device's application flash.
+    #[clap(long, short, value_parser = crate::Cli::parse_address)]
+    address: Option<u32>,
+
+    /// DfuSe memory layout to check the image against, as listed by `info`,
+    /// e.g. "@Internal Flash /0x08000000/16*001Ka,48*001Kg". Defaults to the
+    /// device's application flash.
+    #[clap(long)]
+    layout: Option<String>,
+
+    /// Detached ed25519 signature over the file (64 raw bytes), checked
+    /// with `--public-key`.
+    #[clap(long, value_name = "FILE")]
+    signature: Option<PathBuf>,
+
+    #[clap(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&mut self, message: &str) {
+        println!("[PASS] {message}");
+    }
+
+    fn warn(&mut self, message: &str) {
+        println!("[WARN] {message}");
+    }
+
+    fn fail(&mut self, message: &str) {
+        self.failures += 1;
+        println!("[FAIL] {message}");
+    }
+
+    fn check(&mut self, result: Result<String>) {
+        match result {
+            Ok(message) => self.pass(&message),
+            Err(e) => self.fail(&format!("{e:#}")),
+        }
+    }
+}
+
+impl VerifyFileArgs {
+    /// Check the file against `memory`, the memory map of the device it is
+    /// for.
+    pub fn run(self, memory: &MemoryMap) -> Result<()> {
+        let file = std::fs::read(&self.file)
+            .with_context(|| format!("could not open `{}`", self.file.display()))?;
+        let mut report = Report::default();
+
+        if let Some(path) = &self.signature {
+            report.check(self.check_signature(&file, path));
+        }
+
+        let extension = self
+            .file
+            .extension()
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        let elements = if extension == "zip" || extension == "bbfw" {
+            match Bundle::open(&self.file, self.keys.key()?.as_ref()) {
+                Ok(bundle) => {
+                    report.pass(&format!(
+                        "bundle {} for {:04x}:{:04x}: manifest and firmware hash check out",
+                        bundle.manifest.version,
+                        bundle.manifest.compatible.vid,
+                        bundle.manifest.compatible.pid
+                    ));
+                    vec![DfuElement {
+                        address: bundle.manifest.address,
+                        data: bundle.firmware,
+                    }]
+                }
+                Err(e) => {
+                    report.fail(&format!("{e:#}"));
+                    Vec::new()
+                }
+            }
+        } else if extension == "dfu" {
+            match Suffix::parse(&file)
+                .and_then(|suffix| DfuFile::from_bytes_unchecked(&file).map(|dfu| (suffix, dfu)))
+            {
+                Ok((suffix, dfu)) => {
+                    if suffix.crc_valid() {
+                        report.pass(&format!(
+                            "DfuSe file for {:04x}:{:04x}, suffix CRC {:#010X}",
+                            suffix.vid, suffix.pid, suffix.crc
+                        ));
+                    } else {
+                        report.fail(&format!(
+                            "DFU suffix CRC {:#010X} does not match the file ({:#010X})",
+                            suffix.crc, suffix.computed_crc
+                        ));
+                    }
+                    dfu.targets
+                        .into_iter()
+                        .flat_map(|target| target.elements)
+                        .collect()
+                }
+                Err(e) => {
+                    report.fail(&format!("{e:#}"));
+                    Vec::new()
+                }
+            }
+        } else {
+            vec![DfuElement {
+                address: self.address.unwrap_or(memory.flash.origin),
+                data: file,
+            }]
+        };
+
+        // The first element holds the application and its vector table.
+        if let Some(app) = elements.first() {
+            report.check(check_vector_table(app, memory));
+            match Metadata::find(&app.data) {
+                Some(metadata) => report.check(
+                    metadata
+                        .map_err(Into::into)
+                        .and_then(|m| check_metadata(&m, app)),
+                ),
+                None => report.warn("no metadata block (`BBFW` magic) in the image"),
+            }
+        }
+        let layout = self.layout()?;
+        for element in &elements {
+            report.check(check_fits(element, layout.as_ref(), memory));
+        }
+
+        match report.failures {
+            0 => {
+                println!("All checks passed");
+                Ok(())
+            }
+            1 => anyhow::bail!("1 check failed"),
+            n => anyhow::bail!("{n} checks failed"),
+        }
+    }
+
+    fn layout(&self) -> Result<Option<MemoryLayout>> {
+        let Some(layout) = &self.layout else {
+            return Ok(None);
+        };
+        let layout = MemoryLayout::parse(layout).context("--layout must start with `@`")??;
+        Ok(Some(layout))
+    }
+
+    fn check_signature(&self, file: &[u8], path: &PathBuf) -> Result<String> {
+        let key = self.keys.key()?.context("--signature needs --public-key")?;
+        let signature = std::fs::read(path)
+            .with_context(|| format!("could not read signature `{}`", path.display()))?;
+        let signature = Signature::from_slice(&signature).context("malformed signature")?;
+        key.verify_strict(file, &signature)
+            .context("signature is not valid for this key")?;
+        Ok("detached signature is valid".into())
+    }
+}
+
+/// The initial stack pointer must point into the RAM of `memory` and the
+/// reset vector into the image, as a Thumb address.
+fn check_vector_table(app: &DfuElement, memory: &MemoryMap) -> Result<String> {
+    let VectorTable { sp, reset } = VectorTable::check(&app.data, app.address, memory.ram)?;
+    Ok(format!("vector table: SP {sp:#010X}, reset {reset:#010X}"))
+}
+
+fn check_metadata(metadata: &Metadata, app: &DfuElement) -> Result<String> {
+    let mut message = format!(
+        "metadata block at {:#X}: version {}, build `{}`",
+        metadata.offset,
+        metadata.version_string(),
+        metadata.build_id
+    );
+    if !metadata.has_crc() {
+        message += ", CRC not filled in";
+        return Ok(message);
+    }
+    let computed = metadata.compute_crc(&app.data).with_context(|| {
+        format!(
+            "metadata covers {} bytes, image has {}",
+            metadata.length,
+            app.data.len()
+        )
+    })?;
+    anyhow::ensure!(
+        computed == metadata.crc,
+        "metadata CRC {:#010X} does not match the image ({computed:#010X})",
+        metadata.crc
+    );
+    message += &format!(", CRC {computed:#010X}");
+    Ok(message)
+}
+
+/// The element must lie in writable flash: the application flash of
+/// `memory`, or the writable sectors of `layout`.
+fn check_fits(
+    element: &DfuElement,
+    layout: Option<&MemoryLayout>,
+    memory: &MemoryMap,
+) -> Result<String> {
+    let start = element.address as u64;
+    let end = start + element.data.len() as u64;
+    let regions: Vec<(u64, u64)> = match layout {
+        None => vec![(memory.flash.origin as u64, memory.flash.end())],
+        Some(layout) => layout
+            .pages()
+            .filter(|(_, sectors)| sectors.writable)
+            .map(|(address, sectors)| (address, address + sectors.size as u64))
+            .collect(),
+    };
+
+    device_memory::check_fits(start, end, &regions)
+        .with_context(|| format!("{} bytes at {start:#010X}", element.data.len()))?;
+    Ok(format!(
+        "{} bytes at {start:#010X}..{end:#010X} fit the memory map",
+        element.data.len()
+    ))
+}
@@ -0,0 +1,383 @@
+//! A DFU gadget exported over USB/IP: a minimal usbipd serving one device
+//! whose class requests go to a [`MockDfu`], so the kernel's vhci-hcd driver
+//! attaches it like a real bootloader and libusb talks to it unchanged.
+//!
+//! Only the control endpoint exists. Standard requests are answered from
+//! the descriptors built here; every setup packet is logged, and the
+//! `MockDfu` keeps its own log of the DFU class requests and their data.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bikesafe_core::mock::{MockDfu, Transfer};
+use dfu_core::DfuIo;
+
+pub const BUS_ID: &str = "1-1";
+const BUS_NUM: u32 = 1;
+const DEV_NUM: u32 = 2;
+/// `USB_SPEED_FULL` in the kernel's `enum usb_device_speed`.
+const SPEED_FULL: u32 = 2;
+
+const USBIP_VERSION: u16 = 0x0111;
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+const USBIP_CMD_SUBMIT: u32 = 1;
+const USBIP_CMD_UNLINK: u32 = 2;
+const USBIP_RET_SUBMIT: u32 = 3;
+const USBIP_RET_UNLINK: u32 = 4;
+const USBIP_DIR_IN: u32 = 1;
+const EPIPE: i32 = -32;
+
+const GET_STATUS: u8 = 0;
+const CLEAR_FEATURE: u8 = 1;
+const GET_DESCRIPTOR: u8 = 6;
+const GET_CONFIGURATION: u8 = 8;
+const SET_CONFIGURATION: u8 = 9;
+const SET_INTERFACE: u8 = 11;
+pub const DT_DEVICE: u8 = 1;
+const DT_CONFIG: u8 = 2;
+const DT_STRING: u8 = 3;
+const DT_DFU_FUNCTIONAL: u8 = 0x21;
+
+pub const VID: u16 = 0x1209;
+pub const PID: u16 = 0x2444;
+const STRINGS: [&str; 3] = ["bikesafe.me", "BrakeBright DFU (simulated)", "SIM0001"];
+
+/// A setup packet as the gadget received it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Setup {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl Setup {
+    fn parse(bytes: [u8; 8]) -> Self {
+        Setup {
+            request_type: bytes[0],
+            request: bytes[1],
+            value: u16::from_le_bytes([bytes[2], bytes[3]]),
+            index: u16::from_le_bytes([bytes[4], bytes[5]]),
+            length: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+
+    fn is_class(&self) -> bool {
+        self.request_type & 0x60 == 0x20
+    }
+
+    /// The kernel reading the device descriptor, as it does on every
+    /// enumeration (and so after a port reset).
+    pub fn is_enumeration(&self) -> bool {
+        self.request_type == 0x80
+            && self.request == GET_DESCRIPTOR
+            && self.value == (DT_DEVICE as u16) << 8
+    }
+}
+
+struct State {
+    mock: MockDfu,
+    setups: Vec<Setup>,
+}
+
+/// A running USB/IP server exporting one simulated DFU device as
+/// [`BUS_ID`].
+pub struct Gadget {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl Gadget {
+    /// Serve `mock` on a free localhost port; `interface` is the DfuSe
+    /// interface string reported for alternate setting 0.
+    pub fn start(mock: MockDfu, interface: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let descriptors = Descriptors::new(&mock, interface);
+        let state = Arc::new(Mutex::new(State {
+            mock,
+            setups: Vec::new(),
+        }));
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                if let Err(e) = serve(stream, &descriptors, &shared) {
+                    eprintln!("usbip gadget: {e}");
+                }
+            }
+        });
+        Ok(Gadget { port, state })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The DFU class requests so far, as the mock logged them.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        self.state.lock().unwrap().mock.transfers()
+    }
+
+    /// `len` bytes of the simulated flash at `address`.
+    pub fn read(&self, address: u32, len: usize) -> Vec<u8> {
+        self.state.lock().unwrap().mock.read(address, len)
+    }
+
+    /// Every setup packet received so far.
+    pub fn setups(&self) -> Vec<Setup> {
+        self.state.lock().unwrap().setups.clone()
+    }
+}
+
+/// Descriptors of the simulated device, built from the mock's functional
+/// descriptor.
+struct Descriptors {
+    device: Vec<u8>,
+    config: Vec<u8>,
+    /// String descriptors 1.., after the language IDs at index 0.
+    strings: Vec<String>,
+}
+
+impl Descriptors {
+    fn new(mock: &MockDfu, interface: &str) -> Self {
+        let functional = mock.functional_descriptor();
+        let [vid_lo, vid_hi] = VID.to_le_bytes();
+        let [pid_lo, pid_hi] = PID.to_le_bytes();
+        #[rustfmt::skip]
+        let device = vec![
+            18, DT_DEVICE, 0x00, 0x02, // bcdUSB 2.0
+            0, 0, 0, 64, // class from the interface, EP0 max packet 64
+            vid_lo, vid_hi, pid_lo, pid_hi, 0x00, 0x02, // bcdDevice 2.00
+            1, 2, 3, 1, // manufacturer, product, serial, one configuration
+        ];
+
+        let attributes = functional.can_download as u8
+            | (functional.can_upload as u8) << 1
+            | (functional.manifestation_tolerant as u8) << 2
+            | (functional.will_detach as u8) << 3;
+        let [detach_lo, detach_hi] = functional.detach_timeout.to_le_bytes();
+        let [transfer_lo, transfer_hi] = functional.transfer_size.to_le_bytes();
+        let (major, minor) = functional.dfu_version;
+        #[rustfmt::skip]
+        let mut config = vec![
+            9, DT_CONFIG, 0, 0, // wTotalLength patched below
+            1, 1, 0, 0x80, 50, // one interface, configuration 1, bus powered, 100 mA
+            9, 4, 0, 0, 0, // interface 0, alternate setting 0, no endpoints
+            0xFE, 0x01, 0x02, 4, // DFU class, DFU mode protocol, interface string
+            9, DT_DFU_FUNCTIONAL, attributes, detach_lo, detach_hi, transfer_lo, transfer_hi,
+            minor, major,
+        ];
+        let total = (config.len() as u16).to_le_bytes();
+        config[2..4].copy_from_slice(&total);
+
+        let mut strings: Vec<String> = STRINGS.iter().map(|s| s.to_string()).collect();
+        strings.push(interface.to_string());
+        Descriptors {
+            device,
+            config,
+            strings,
+        }
+    }
+
+    fn string(&self, index: u8) -> Option<Vec<u8>> {
+        let utf16: Vec<u16> = match index {
+            0 => vec![0x0409],
+            n => self.strings.get(n as usize - 1)?.encode_utf16().collect(),
+        };
+        let mut descriptor = vec![(2 + 2 * utf16.len()) as u8, DT_STRING];
+        descriptor.extend(utf16.iter().flat_map(|c| c.to_le_bytes()));
+        Some(descriptor)
+    }
+}
+
+fn read_u16(stream: &mut TcpStream) -> io::Result<u16> {
+    let mut buffer = [0; 2];
+    stream.read_exact(&mut buffer)?;
+    Ok(u16::from_be_bytes(buffer))
+}
+
+fn read_u32(stream: &mut TcpStream) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    stream.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+/// `struct usbip_usb_device`, followed by the interfaces for a device list.
+fn device_info(descriptors: &Descriptors, with_interfaces: bool) -> Vec<u8> {
+    let mut info = Vec::new();
+    let mut path = b"/sys/devices/simulated/usb1/1-1".to_vec();
+    path.resize(256, 0);
+    info.extend(path);
+    let mut bus_id = BUS_ID.as_bytes().to_vec();
+    bus_id.resize(32, 0);
+    info.extend(bus_id);
+    info.extend(BUS_NUM.to_be_bytes());
+    info.extend(DEV_NUM.to_be_bytes());
+    info.extend(SPEED_FULL.to_be_bytes());
+    info.extend(VID.to_be_bytes());
+    info.extend(PID.to_be_bytes());
+    info.extend(0x0200u16.to_be_bytes());
+    // Class, subclass, protocol, configuration value, configurations,
+    // interfaces.
+    info.extend([0, 0, 0, 1, 1, 1]);
+    if with_interfaces {
+        let interface = &descriptors.config[9..18];
+        info.extend([interface[5], interface[6], interface[7], 0]);
+    }
+    info
+}
+
+fn op_header(code: u16) -> Vec<u8> {
+    let mut header = USBIP_VERSION.to_be_bytes().to_vec();
+    header.extend(code.to_be_bytes());
+    header.extend(0u32.to_be_bytes());
+    header
+}
+
+/// Handle one connection: a device list request, or an import followed by
+/// URBs until the host detaches or the device leaves DFU mode.
+fn serve(mut stream: TcpStream, descriptors: &Descriptors, state: &Mutex<State>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let _version = read_u16(&mut stream)?;
+    let code = read_u16(&mut stream)?;
+    let _status = read_u32(&mut stream)?;
+    match code {
+        OP_REQ_DEVLIST => {
+            let mut reply = op_header(OP_REP_DEVLIST);
+            reply.extend(1u32.to_be_bytes());
+            reply.extend(device_info(descriptors, true));
+            stream.write_all(&reply)
+        }
+        OP_REQ_IMPORT => {
+            let mut bus_id = [0; 32];
+            stream.read_exact(&mut bus_id)?;
+            let requested = bus_id.split(|&b| b == 0).next().unwrap_or_default();
+            if requested != BUS_ID.as_bytes() {
+                let mut reply = op_header(OP_REP_IMPORT);
+                reply[4..8].copy_from_slice(&1u32.to_be_bytes());
+                return stream.write_all(&reply);
+            }
+            let mut reply = op_header(OP_REP_IMPORT);
+            reply.extend(device_info(descriptors, false));
+            stream.write_all(&reply)?;
+            urbs(stream, descriptors, state)
+        }
+        code => Err(io::Error::other(format!("unknown operation {code:#06x}"))),
+    }
+}
+
+fn urbs(mut stream: TcpStream, descriptors: &Descriptors, state: &Mutex<State>) -> io::Result<()> {
+    loop {
+        let mut header = [0; 48];
+        match stream.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let word = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+        let (command, seqnum, direction, endpoint) = (word(0), word(4), word(12), word(16));
+        match command {
+            USBIP_CMD_SUBMIT => {
+                let length = word(24) as usize;
+                let setup = Setup::parse(header[40..48].try_into().unwrap());
+                let mut out = vec![0; if direction == USBIP_DIR_IN { 0 } else { length }];
+                stream.read_exact(&mut out)?;
+
+                let (status, data, gone) = match endpoint {
+                    0 => control(state, descriptors, setup, &out, length),
+                    _ => (EPIPE, Vec::new(), false),
+                };
+                let mut reply = Vec::with_capacity(48 + data.len());
+                reply.extend(USBIP_RET_SUBMIT.to_be_bytes());
+                reply.extend(seqnum.to_be_bytes());
+                reply.extend([0; 12]);
+                reply.extend(status.to_be_bytes());
+                let actual = match direction {
+                    USBIP_DIR_IN => data.len(),
+                    _ if status == 0 => out.len(),
+                    _ => 0,
+                };
+                reply.extend((actual as u32).to_be_bytes());
+                reply.extend([0; 20]);
+                reply.extend(&data);
+                stream.write_all(&reply)?;
+                if gone {
+                    // The application starts: drop off the bus.
+                    return Ok(());
+                }
+            }
+            USBIP_CMD_UNLINK => {
+                // URBs complete before the next one is read, so there is
+                // never anything left to unlink.
+                let mut reply = Vec::with_capacity(48);
+                reply.extend(USBIP_RET_UNLINK.to_be_bytes());
+                reply.extend(seqnum.to_be_bytes());
+                reply.extend([0; 40]);
+                stream.write_all(&reply)?;
+            }
+            command => {
+                return Err(io::Error::other(format!("unknown command {command}")));
+            }
+        }
+    }
+}
+
+/// Answer one control transfer: the status, the IN data and whether the
+/// device just left DFU mode.
+fn control(
+    state: &Mutex<State>,
+    descriptors: &Descriptors,
+    setup: Setup,
+    out: &[u8],
+    length: usize,
+) -> (i32, Vec<u8>, bool) {
+    let mut state = state.lock().unwrap();
+    state.setups.push(setup);
+    if setup.is_class() {
+        let mock = &state.mock;
+        let result = match setup.request_type & 0x80 {
+            0 => mock
+                .write_control(setup.request_type, setup.request, setup.value, out)
+                .map(|_| Vec::new()),
+            _ => {
+                let mut buffer = vec![0; length];
+                mock.read_control(setup.request_type, setup.request, setup.value, &mut buffer)
+                    .map(|n| buffer[..n].to_vec())
+            }
+        };
+        let gone = mock.started().is_some();
+        return match result {
+            Ok(data) => (0, data, false),
+            Err(_) => (EPIPE, Vec::new(), gone),
+        };
+    }
+
+    let reply = match (setup.request_type, setup.request) {
+        (0x80, GET_DESCRIPTOR) => match (setup.value >> 8) as u8 {
+            DT_DEVICE => Some(descriptors.device.clone()),
+            DT_CONFIG => Some(descriptors.config.clone()),
+            DT_STRING => descriptors.string(setup.value as u8),
+            _ => None,
+        },
+        (0x80..=0x82, GET_STATUS) => Some(vec![0, 0]),
+        (0x80, GET_CONFIGURATION) => Some(vec![1]),
+        (0x00, SET_CONFIGURATION) | (0x01, SET_INTERFACE) | (0x00..=0x02, CLEAR_FEATURE) => {
+            Some(Vec::new())
+        }
+        _ => None,
+    };
+    match reply {
+        Some(mut data) => {
+            data.truncate(length);
+            (0, data, false)
+        }
+        None => (EPIPE, Vec::new(), false),
+    }
+}
@@ -0,0 +1,368 @@
+//! The CLI's flash flows end to end, against a simulated DFU device attached
+//! through USB/IP, checking the exact DFU requests the device receives.
+//!
+//! Needs the `usbip` tool, the `vhci-hcd` kernel module and root (to
+//! attach), so the tests only build with `--features usbip-tests`:
+//!
+//! ```text
+//! sudo modprobe vhci-hcd
+//! sudo -E cargo test -p bikesafe-cli --features usbip-tests --test usbip -- --test-threads 1
+//! ```
+
+mod gadget;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bikesafe_core::mock::{
+    DFU_ABORT, DFU_CLRSTATUS, DFU_DNLOAD, DFU_GETSTATE, DFU_GETSTATUS, DFU_UPLOAD, MockDfu,
+    Transfer,
+};
+use bikesafe_core::{dfuse, transfer};
+use dfu_core::sync::DfuSync;
+
+use crate::gadget::{BUS_ID, Gadget, PID, VID};
+
+const LAYOUT: &str = "@Internal Flash  /0x08000000/16*1Ka,48*1Kg";
+const ADDRESS: u32 = 0x0800_4000;
+/// 2 full blocks and a short one at the mock's 2 KiB transfer size, over
+/// five 1 KiB pages.
+const SIZE: usize = 5000;
+
+/// Only one simulated device may be attached at a time: the CLI picks the
+/// first one with the VID:PID.
+static USB: Mutex<()> = Mutex::new(());
+
+/// A gadget attached to the local vhci-hcd, detached again on drop.
+struct Attached {
+    gadget: Gadget,
+    vhci_port: String,
+}
+
+impl Attached {
+    fn new(mock: MockDfu) -> Self {
+        let gadget = Gadget::start(mock, LAYOUT).unwrap();
+        let tcp_port = gadget.port().to_string();
+        let status = Command::new("usbip")
+            .args([
+                "--tcp-port",
+                &tcp_port,
+                "attach",
+                "-r",
+                "127.0.0.1",
+                "-b",
+                BUS_ID,
+            ])
+            .status()
+            .expect("could not run `usbip`; is usbip installed?");
+        assert!(status.success(), "usbip attach failed; is vhci-hcd loaded?");
+        let vhci_port = vhci_port(gadget.port()).expect("attached device not in `usbip port`");
+        wait_for_device();
+        Attached { gadget, vhci_port }
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        // Fails harmlessly when the device already dropped off the bus.
+        let _ = Command::new("usbip")
+            .args(["detach", "-p", &self.vhci_port])
+            .output();
+    }
+}
+
+/// The vhci port `usbip port` lists the import from `tcp_port` under.
+fn vhci_port(tcp_port: u16) -> Option<String> {
+    let output = Command::new("usbip").arg("port").output().ok()?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let url = format!("127.0.0.1:{tcp_port}/{BUS_ID}");
+    let mut port = None;
+    for line in listing.lines() {
+        if let Some(rest) = line.trim().strip_prefix("Port ") {
+            port = rest.split(':').next().map(str::to_string);
+        }
+        if line.contains(&url) {
+            return port;
+        }
+    }
+    None
+}
+
+fn wait_for_device() {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        let found = rusb::devices().is_ok_and(|devices| {
+            devices.iter().any(|device| {
+                device
+                    .device_descriptor()
+                    .is_ok_and(|desc| (desc.vendor_id(), desc.product_id()) == (VID, PID))
+            })
+        });
+        if found {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("simulated device did not enumerate");
+}
+
+fn firmware() -> PathBuf {
+    let data: Vec<u8> = (0..SIZE).map(|i| (i * 7 + i / 256) as u8).collect();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("usbip-firmware.bin");
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+fn cli(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_bikesafe-cli"))
+        .args(["--device", "1209:2444", "--no-progress"])
+        .args(args)
+        .env_remove("BIKESAFE_ADDRESS")
+        .output()
+        .unwrap();
+    eprintln!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// One line per DFU request, for comparing whole sequences.
+fn describe(transfer: &Transfer) -> String {
+    let address = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap_or_default());
+    let line = match (transfer.request, transfer.value, &transfer.data[..]) {
+        (DFU_DNLOAD, 0, [0x21, a @ ..]) => format!("SET_ADDRESS {:#010X}", address(a)),
+        (DFU_DNLOAD, 0, [0x41, a @ ..]) => format!("ERASE {:#010X}", address(a)),
+        (DFU_DNLOAD, block, data) => format!("DNLOAD {block} {}", data.len()),
+        (DFU_UPLOAD, block, data) => format!("UPLOAD {block} {}", data.len()),
+        (DFU_GETSTATUS, ..) => "GETSTATUS".into(),
+        (DFU_CLRSTATUS, ..) => "CLRSTATUS".into(),
+        (DFU_GETSTATE, ..) => "GETSTATE".into(),
+        (DFU_ABORT, ..) => "ABORT".into(),
+        (request, value, _) => format!("REQUEST {request} {value}"),
+    };
+    match transfer.failed {
+        true => line + " (failed)",
+        false => line,
+    }
+}
+
+fn sequence(gadget: &Gadget) -> Vec<String> {
+    gadget.transfers().iter().map(describe).collect()
+}
+
+/// A DfuSe command followed by the two status polls the mock answers it
+/// with: dfuDNBUSY, then dfuDNLOAD-IDLE.
+fn command(line: String) -> [String; 3] {
+    [line, "GETSTATUS".into(), "GETSTATUS".into()]
+}
+
+/// Erasing the image's pages one by one.
+fn erase_pages() -> Vec<String> {
+    (0..SIZE.div_ceil(1024) as u32)
+        .flat_map(|page| command(format!("ERASE {:#010X}", ADDRESS + page * 1024)))
+        .collect()
+}
+
+/// `bikesafe_core::transfer::erase`.
+fn erase() -> Vec<String> {
+    let mut lines = vec!["GETSTATUS".to_string()];
+    lines.extend(erase_pages());
+    lines
+}
+
+/// `bikesafe_core::transfer::download` after an erase, which left the
+/// device in dfuDNLOAD-IDLE: each block at wBlockNum 2 after its own
+/// address.
+fn download() -> Vec<String> {
+    let mut lines = vec!["GETSTATUS".to_string(), "ABORT".to_string()];
+    for (i, len) in [2048, 2048, 904].into_iter().enumerate() {
+        lines.extend(command(format!(
+            "SET_ADDRESS {:#010X}",
+            ADDRESS + i as u32 * 2048
+        )));
+        lines.extend(command(format!("DNLOAD 2 {len}")));
+    }
+    lines.push("ABORT".into());
+    lines
+}
+
+/// `bikesafe_core::transfer::verify`: full-size uploads from wBlockNum 2.
+fn verify() -> Vec<String> {
+    let mut lines = vec!["GETSTATUS".to_string()];
+    lines.extend(command(format!("SET_ADDRESS {ADDRESS:#010X}")));
+    lines.push("ABORT".into());
+    lines.extend((2..5).map(|block| format!("UPLOAD {block} 2048")));
+    lines.push("ABORT".into());
+    lines
+}
+
+/// `dfuse::leave`: the device starts the application instead of answering
+/// the last status request.
+fn leave() -> Vec<String> {
+    let mut lines = vec!["GETSTATUS".to_string()];
+    lines.extend(command(format!("SET_ADDRESS {ADDRESS:#010X}")));
+    lines.push("DNLOAD 2 0".into());
+    lines.push("GETSTATUS (failed)".into());
+    lines
+}
+
+/// dfu-core's download of a single image, which the CLI uses when it does
+/// not verify: status, then erase everything first and write the blocks at
+/// consecutive block numbers after one address, then leave.
+fn dfu_core_download() -> Vec<String> {
+    let mut lines = vec!["GETSTATUS".to_string(), "GETSTATUS".to_string()];
+    lines.extend(erase_pages());
+    lines.extend(command(format!("SET_ADDRESS {ADDRESS:#010X}")));
+    for (block, len) in [(2, 2048), (3, 2048), (4, 904)] {
+        lines.extend(command(format!("DNLOAD {block} {len}")));
+    }
+    lines.push("DNLOAD 5 0".into());
+    lines.push("GETSTATUS (failed)".into());
+    lines
+}
+
+#[test]
+fn download_and_leave() {
+    let _usb = USB.lock().unwrap_or_else(|e| e.into_inner());
+    let device = Attached::new(MockDfu::dfuse(LAYOUT).unwrap());
+    let firmware = firmware();
+    assert!(
+        cli(&["--path", firmware.to_str().unwrap()])
+            .status
+            .success()
+    );
+
+    assert_eq!(sequence(&device.gadget), dfu_core_download());
+    assert_eq!(
+        device.gadget.read(ADDRESS, SIZE),
+        std::fs::read(&firmware).unwrap()
+    );
+}
+
+#[test]
+fn erase_write_verify_leave() {
+    let _usb = USB.lock().unwrap_or_else(|e| e.into_inner());
+    let device = Attached::new(MockDfu::dfuse(LAYOUT).unwrap());
+    let firmware = firmware();
+    let output = cli(&["--path", firmware.to_str().unwrap(), "--verify"]);
+    assert!(output.status.success());
+
+    let expected = [erase(), download(), verify(), leave()].concat();
+    assert_eq!(sequence(&device.gadget), expected);
+}
+
+#[test]
+fn verify_and_reset() {
+    let _usb = USB.lock().unwrap_or_else(|e| e.into_inner());
+    let device = Attached::new(MockDfu::dfuse(LAYOUT).unwrap());
+    let firmware = firmware();
+    let output = cli(&[
+        "--path",
+        firmware.to_str().unwrap(),
+        "--verify",
+        "--after",
+        "flash=reset",
+    ]);
+    assert!(output.status.success());
+
+    // No leave request and no DFU_DETACH (the device does not set
+    // bitWillDetach): the port reset makes the kernel enumerate the device
+    // again after the last DFU request.
+    let expected = [erase(), download(), verify()].concat();
+    assert_eq!(sequence(&device.gadget), expected);
+    let setups = device.gadget.setups();
+    let last_class = setups
+        .iter()
+        .rposition(|setup| setup.request_type & 0x60 == 0x20)
+        .unwrap();
+    assert!(setups[last_class..].iter().any(|s| s.is_enumeration()));
+}
+
+#[test]
+fn stay_in_dfu() {
+    let _usb = USB.lock().unwrap_or_else(|e| e.into_inner());
+    let device = Attached::new(MockDfu::dfuse(LAYOUT).unwrap());
+    let firmware = firmware();
+    let output = cli(&[
+        "--path",
+        firmware.to_str().unwrap(),
+        "--verify",
+        "--after",
+        "dfu",
+    ]);
+    assert!(output.status.success());
+
+    let mut expected = [erase(), download(), verify()].concat();
+    expected.push("GETSTATUS".into());
+    assert_eq!(sequence(&device.gadget), expected);
+}
+
+#[test]
+fn failed_write_stops() {
+    let _usb = USB.lock().unwrap_or_else(|e| e.into_inner());
+    // Stall the second data block.
+    let mock = MockDfu::dfuse(LAYOUT).unwrap().fail(DFU_DNLOAD, 5 + 3);
+    let device = Attached::new(mock);
+    let firmware = firmware();
+    let output = cli(&["--path", firmware.to_str().unwrap(), "--verify"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not write firmware"));
+
+    let mut expected = erase();
+    let download = download();
+    expected.extend_from_slice(&download[..11]);
+    expected.push("DNLOAD 2 2048 (failed)".into());
+    assert_eq!(sequence(&device.gadget), expected);
+}
+
+/// The USB/IP side alone, without attaching: the device list `usbip list
+/// -r` would show.
+#[test]
+fn device_list() {
+    let gadget = Gadget::start(MockDfu::dfuse(LAYOUT).unwrap(), LAYOUT).unwrap();
+    let mut stream = TcpStream::connect(("127.0.0.1", gadget.port())).unwrap();
+    stream
+        .write_all(&[0x01, 0x11, 0x80, 0x05, 0, 0, 0, 0])
+        .unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply.len(), 8 + 4 + 312 + 4);
+    assert_eq!(
+        reply[..12],
+        [0x01, 0x11, 0x00, 0x05, 0, 0, 0, 0, 0, 0, 0, 1]
+    );
+    assert_eq!(&reply[12 + 256..12 + 256 + 3], BUS_ID.as_bytes());
+    assert_eq!(reply[12 + 300..12 + 304], [0x12, 0x09, 0x24, 0x44]);
+    assert_eq!(reply[12 + 312..], [0xFE, 0x01, 0x02, 0]);
+}
+
+/// The expected sequences against the library calls the CLI makes, on the
+/// mock directly: a USB-level failure above then points at the USB stack or
+/// the CLI, not at the expectations.
+#[test]
+fn library_sequences() {
+    let data = std::fs::read(firmware()).unwrap();
+    let transfers = |io: &MockDfu| io.transfers().iter().map(describe).collect::<Vec<_>>();
+
+    let io = MockDfu::dfuse(LAYOUT).unwrap();
+    transfer::erase(&io, ADDRESS, &data, |_| ()).unwrap();
+    transfer::download(&io, ADDRESS, &data, |_| ()).unwrap();
+    transfer::verify(&io, ADDRESS, &data, |_| ()).unwrap();
+    let _ = dfuse::leave(&io, ADDRESS);
+    let expected = [erase(), download(), verify(), leave()].concat();
+    assert_eq!(transfers(&io), expected);
+
+    let mut dfu = DfuSync::new(MockDfu::dfuse(LAYOUT).unwrap());
+    dfu.override_address(ADDRESS);
+    assert!(dfu.download_from_slice(&data).is_err());
+    let io = dfu.into_inner();
+    assert_eq!(transfers(&io), dfu_core_download());
+    assert_eq!(io.read(ADDRESS, SIZE), data);
+}
@@ -0,0 +1,178 @@
+//! Stopping an operation from another thread, such as a Ctrl-C handler or
+//! a Cancel button. [`Cancellable`] wraps a [`DfuIo`] and fails its
+//! requests with [`BikesafeError::Cancelled`] once its [`Cancel`] is set,
+//! so the transfer steps stop before their next block and the device is
+//! left in DFU mode, to be written again.
+//!
+//! ```
+//! use bikesafe_core::cancel::{Cancel, Cancellable};
+//! use bikesafe_core::mock::MockDfu;
+//! use bikesafe_core::{BikesafeError, transfer};
+//!
+//! let cancel = Cancel::new();
+//! let io = Cancellable::new(MockDfu::dfuse("@Flash /0x08000000/64*1Kg")?, cancel.clone());
+//! let firmware = [0; 4096];
+//! let result = transfer::erase(&io, 0x0800_0000, &firmware, |_| cancel.cancel());
+//! assert!(matches!(result, Err(BikesafeError::Cancelled)));
+//! # Ok::<(), BikesafeError>(())
+//! ```
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::{DfuIo, DfuProtocol};
+
+use crate::BikesafeError;
+
+/// Shared flag asking an operation to stop. Clones set the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the operations watching this flag to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// [`BikesafeError::Cancelled`] once the flag is set.
+    pub fn check(&self) -> Result<(), BikesafeError> {
+        match self.is_cancelled() {
+            true => Err(BikesafeError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// A [`DfuIo`] whose control requests fail once `cancel` is set. USB
+/// resets still go through.
+pub struct Cancellable<IO> {
+    io: IO,
+    cancel: Cancel,
+}
+
+impl<IO> Cancellable<IO> {
+    pub fn new(io: IO, cancel: Cancel) -> Self {
+        Self { io, cancel }
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.io
+    }
+}
+
+impl<IO> DfuIo for Cancellable<IO>
+where
+    IO: DfuIo,
+    IO::Error: Into<BikesafeError>,
+{
+    type Read = IO::Read;
+    type Write = IO::Write;
+    type Reset = IO::Reset;
+    type Error = BikesafeError;
+    type MemoryLayout = IO::MemoryLayout;
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> Result<Self::Read, Self::Error> {
+        self.cancel.check()?;
+        self.io
+            .read_control(request_type, request, value, buffer)
+            .map_err(Into::into)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> Result<Self::Write, Self::Error> {
+        self.cancel.check()?;
+        self.io
+            .write_control(request_type, request, value, buffer)
+            .map_err(Into::into)
+    }
+
+    fn usb_reset(&self) -> Result<Self::Reset, Self::Error> {
+        self.io.usb_reset().map_err(Into::into)
+    }
+
+    fn protocol(&self) -> &DfuProtocol<Self::MemoryLayout> {
+        self.io.protocol()
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        self.io.functional_descriptor()
+    }
+}
+
+/// The same for the steps of [`nonblocking`](crate::nonblocking).
+#[cfg(feature = "async")]
+impl<IO> dfu_core::asynchronous::DfuAsyncIo for Cancellable<IO>
+where
+    IO: dfu_core::asynchronous::DfuAsyncIo + Sync,
+    IO::Error: Into<BikesafeError>,
+{
+    type Read = IO::Read;
+    type Write = IO::Write;
+    type Reset = IO::Reset;
+    type Error = BikesafeError;
+    type MemoryLayout = IO::MemoryLayout;
+
+    async fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> Result<Self::Read, Self::Error> {
+        self.cancel.check()?;
+        self.io
+            .read_control(request_type, request, value, buffer)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> Result<Self::Write, Self::Error> {
+        self.cancel.check()?;
+        self.io
+            .write_control(request_type, request, value, buffer)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn usb_reset(&self) -> Result<Self::Reset, Self::Error> {
+        self.io.usb_reset().await.map_err(Into::into)
+    }
+
+    async fn sleep(&self, duration: std::time::Duration) {
+        self.io.sleep(duration).await
+    }
+
+    fn protocol(&self) -> &DfuProtocol<Self::MemoryLayout> {
+        self.io.protocol()
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        self.io.functional_descriptor()
+    }
+}
@@ -0,0 +1,81 @@
+//! Identifying the MCU behind a DfuSe bootloader from what the factory
+//! programmed into its system memory: the unique ID, which tells units apart
+//! in production records, and the flash size register, which tells boards
+//! with the same USB IDs apart and bounds what fits on them.
+
+use std::fmt;
+
+use dfu_core::{DfuIo, DfuProtocol};
+
+use crate::family::{Hardware, MemoryMap};
+use crate::{BikesafeError, dfuse};
+
+/// Identification of one MCU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChipId {
+    /// The 96-bit unique ID, as stored (little-endian words).
+    pub uid: [u8; 12],
+    /// Flash of the MCU in bytes, bootloader included.
+    pub flash_size: u32,
+}
+
+/// Read the unique ID and flash size register at the addresses of `memory`.
+/// Needs a DfuSe bootloader that allows uploads from system memory.
+pub fn read_chip_id<IO>(io: &IO, memory: &MemoryMap) -> Result<ChipId, BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    // A plain DFU device would take the DfuSe address command for data.
+    if !matches!(io.protocol(), DfuProtocol::Dfuse { .. }) {
+        return Err(BikesafeError::DfuseNotSupported);
+    }
+    let flash_size = read(io, memory.flash_size_register, 2)?;
+    let uid = read(io, memory.unique_id, 12)?;
+    Ok(ChipId {
+        uid: uid.try_into().unwrap(),
+        flash_size: u16::from_le_bytes([flash_size[0], flash_size[1]]) as u32 * 1024,
+    })
+}
+
+/// Upload exactly `length` bytes from `address`.
+fn read<IO>(io: &IO, address: u32, length: usize) -> Result<Vec<u8>, BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    let data = dfuse::upload(io, address, length, length, |_| ()).map_err(Into::into)?;
+    if data.len() < length {
+        return Err(dfu_core::Error::ResponseTooShort {
+            got: data.len(),
+            expected: length,
+        }
+        .into());
+    }
+    Ok(data)
+}
+
+impl ChipId {
+    /// The board this MCU sits on, if the bootloader `vid:pid` has an entry
+    /// in [`device_memory::HARDWARE`] for its flash size.
+    pub fn hardware(&self, vid: u16, pid: u16) -> Option<&'static Hardware> {
+        device_memory::identify(vid, pid, self.flash_size)
+    }
+
+    /// End of the MCU's flash, for a chip whose flash starts where the
+    /// bootloader of `memory` does.
+    pub fn flash_end(&self, memory: &MemoryMap) -> u64 {
+        memory.bootloader.origin as u64 + self.flash_size as u64
+    }
+}
+
+/// The unique ID as hex, most significant byte first, as ST's tools show
+/// it.
+impl fmt::Display for ChipId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.uid
+            .iter()
+            .rev()
+            .try_for_each(|byte| write!(f, "{byte:02X}"))
+    }
+}
@@ -0,0 +1,214 @@
+//! Finding, opening and locking the DFU device.
+
+use std::time::Duration;
+
+use dfu_core::DfuIo;
+use dfu_libusb::{Dfu, DfuLibusb};
+use rusb::UsbContext;
+
+use crate::family::MemoryMap;
+use crate::{BikesafeError, dfuse, read_chip_id};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Interface class/subclass of DFU interfaces.
+pub const DFU_CLASS: (u8, u8) = (0xFE, 0x01);
+pub const PROTOCOL_RUNTIME: u8 = 1;
+pub const PROTOCOL_DFU: u8 = 2;
+
+/// Number and protocol of the first DFU interface in the active
+/// configuration of `device`.
+pub fn dfu_interface<C: UsbContext>(device: &rusb::Device<C>) -> Option<(u8, u8)> {
+    let config = device.active_config_descriptor().ok()?;
+    config
+        .interfaces()
+        .flat_map(|i| i.descriptors())
+        .find(|d| (d.class_code(), d.sub_class_code()) == DFU_CLASS)
+        .map(|d| (d.interface_number(), d.protocol_code()))
+}
+
+/// What a release needs to know to tell whether it suits a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub vid: u16,
+    pub pid: u16,
+    /// Hardware revisions the device may be, as `major.minor.sub`: those of
+    /// the board its chip identifies, or else its bcdDevice.
+    pub revisions: Vec<String>,
+    /// End of the chip's flash, if it could be read.
+    pub flash_end: Option<u64>,
+}
+
+/// The device and DFU interface selected on the command line.
+#[derive(Clone)]
+pub struct Device {
+    pub context: rusb::Context,
+    pub vid: u16,
+    pub pid: u16,
+    pub intf: u8,
+    pub alt: u8,
+    /// Bus number and address, to tell apart several devices with the same
+    /// `vid:pid`. `None` selects the first one found.
+    pub port: Option<(u8, u8)>,
+}
+
+impl Device {
+    /// Open the selected interface and alternate setting.
+    pub fn open(&self) -> Result<Dfu<rusb::Context>, BikesafeError> {
+        self.open_alt(self.alt)
+    }
+
+    /// Open the selected interface with alternate setting `alt`.
+    #[tracing::instrument(name = "open", skip(self), fields(intf = self.intf))]
+    pub fn open_alt(&self, alt: u8) -> Result<Dfu<rusb::Context>, BikesafeError> {
+        let device = self.usb_device()?;
+        let handle = device.open()?;
+        let io = DfuLibusb::from_usb_device(device, handle, self.intf, alt)?.into_inner();
+        dfuse::check_transfer_size(io.functional_descriptor())?;
+        Ok(Dfu::new(io))
+    }
+
+    /// Every connected device matching `vid:pid` that is in DFU mode, each
+    /// selected by its port.
+    pub fn all(&self) -> Result<Vec<Device>, BikesafeError> {
+        let devices = self
+            .context
+            .devices()?
+            .iter()
+            .filter(|device| {
+                device
+                    .device_descriptor()
+                    .is_ok_and(|desc| desc.vendor_id() == self.vid && desc.product_id() == self.pid)
+                    && dfu_interface(device).is_some_and(|(_, protocol)| protocol == PROTOCOL_DFU)
+            })
+            .map(|device| Device {
+                port: Some((device.bus_number(), device.address())),
+                ..self.clone()
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    /// Find the first USB device matching `vid:pid` (and the port, if set).
+    #[tracing::instrument(
+        name = "enumerate",
+        skip(self),
+        fields(device = format_args!("{:04x}:{:04x}", self.vid, self.pid))
+    )]
+    pub fn usb_device(&self) -> Result<rusb::Device<rusb::Context>, BikesafeError> {
+        self.context
+            .devices()?
+            .iter()
+            .find(|device| {
+                device
+                    .device_descriptor()
+                    .is_ok_and(|desc| desc.vendor_id() == self.vid && desc.product_id() == self.pid)
+                    && self
+                        .port
+                        .is_none_or(|port| port == (device.bus_number(), device.address()))
+            })
+            .ok_or(BikesafeError::DeviceNotFound {
+                vid: self.vid,
+                pid: self.pid,
+            })
+    }
+
+    /// Take the advisory lock on the selected device, so that no other
+    /// flasher uses it until the lock is dropped. `None` if the device is
+    /// not connected (yet).
+    pub fn lock(&self) -> Result<Option<device_lock::DeviceLock>, BikesafeError> {
+        let Ok(device) = self.usb_device() else {
+            return Ok(None);
+        };
+        Ok(Some(device_lock::DeviceLock::acquire(
+            device.bus_number(),
+            device.address(),
+        )?))
+    }
+
+    /// Read the USB serial number string, if the device has one.
+    pub fn serial_number(&self) -> Result<Option<String>, BikesafeError> {
+        let device = self.usb_device()?;
+        let desc = device.device_descriptor()?;
+        if desc.serial_number_string_index().is_none() {
+            return Ok(None);
+        }
+        let handle = device.open()?;
+        Ok(Some(handle.read_serial_number_string_ascii(&desc)?))
+    }
+
+    /// Identify the device for checking a release against it. The chip is
+    /// read through the bootloader (see [`crate::chip`]); a bootloader that
+    /// does not allow it leaves only the bcdDevice.
+    pub fn identify(&self, memory: &MemoryMap) -> Result<Identity, BikesafeError> {
+        let desc = self.usb_device()?.device_descriptor()?;
+        let (vid, pid) = (desc.vendor_id(), desc.product_id());
+        let mut identity = Identity {
+            vid,
+            pid,
+            revisions: Vec::new(),
+            flash_end: None,
+        };
+        match read_chip_id(&self.open()?.into_inner(), memory) {
+            Ok(chip) => {
+                identity.flash_end = Some(chip.flash_end(memory));
+                if let Some(hardware) = chip.hardware(vid, pid) {
+                    let revisions = hardware.revisions.iter().map(|r| r.to_string());
+                    identity.revisions.extend(revisions);
+                }
+            }
+            Err(e) => tracing::warn!("Could not identify the chip: {e}"),
+        }
+        if identity.revisions.is_empty() {
+            let version = desc.device_version();
+            identity.revisions.push(format!(
+                "{}.{}.{}",
+                version.major(),
+                version.minor(),
+                version.sub_minor()
+            ));
+        }
+        Ok(identity)
+    }
+
+    /// Find the alternate setting of the selected interface whose string
+    /// descriptor starts with `name`, e.g. `@Option Bytes` for the DfuSe
+    /// option-byte area, or whose DfuSe memory name is `name`, e.g.
+    /// `Internal Flash` for `@Internal Flash  /0x08000000/64*002Kg`.
+    pub fn find_alt(&self, name: &str) -> Result<u8, BikesafeError> {
+        let device = self.usb_device()?;
+        let handle = device.open()?;
+        let mut seen = Vec::new();
+        // Without string descriptors no setting has a name.
+        let Some(&lang) = handle.read_languages(TIMEOUT)?.first() else {
+            return Err(BikesafeError::AltNotFound {
+                name: name.into(),
+                intf: self.intf,
+                found: seen,
+            });
+        };
+
+        let config = device.active_config_descriptor()?;
+        for interface in config.interfaces().filter(|i| i.number() == self.intf) {
+            for desc in interface.descriptors() {
+                let Ok(label) = handle.read_interface_string(lang, &desc, TIMEOUT) else {
+                    continue;
+                };
+                let dfuse_name = label
+                    .strip_prefix('@')
+                    .and_then(|label| label.split('/').next())
+                    .map(str::trim);
+                if label.starts_with(name) || dfuse_name == Some(name) {
+                    return Ok(desc.setting_number());
+                }
+                seen.push(format!("{}: {label}", desc.setting_number()));
+            }
+        }
+
+        Err(BikesafeError::AltNotFound {
+            name: name.into(),
+            intf: self.intf,
+            found: seen,
+        })
+    }
+}
@@ -0,0 +1,427 @@
+//! Raw DFU / DfuSe requests that `dfu-core` does not expose (upload, abort,
+//! clear-status and the ST-specific DNLOAD commands), plus plain DFU 1.1
+//! transfers for devices without ST's extensions.
+//!
+//! Everything here works on top of a [`DfuIo`], so the same code drives the
+//! libusb backend and anything else implementing the trait.
+
+use std::thread;
+use std::time::Duration;
+
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::{DfuIo, DfuProtocol, State, Status};
+
+use crate::BikesafeError;
+
+pub(crate) const REQUEST_TYPE: u8 = 0b0010_0001;
+pub(crate) const DFU_DNLOAD: u8 = 1;
+pub(crate) const DFU_UPLOAD: u8 = 2;
+pub(crate) const DFU_GETSTATUS: u8 = 3;
+pub(crate) const DFU_CLRSTATUS: u8 = 4;
+pub(crate) const DFU_ABORT: u8 = 6;
+
+/// DfuSe commands, sent as DFU_DNLOAD with wBlockNum = 0 (AN3156).
+pub(crate) const CMD_SET_ADDRESS: u8 = 0x21;
+pub(crate) const CMD_ERASE: u8 = 0x41;
+const CMD_READ_UNPROTECT: u8 = 0x92;
+
+/// Block number of the first data block after the address pointer was set.
+pub(crate) const FIRST_DATA_BLOCK: u16 = 2;
+
+/// Response to DFU_GETSTATUS.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceStatus {
+    pub status: Status,
+    pub poll_timeout: u64,
+    pub state: State,
+}
+
+/// Reject a device whose functional descriptor gives a wTransferSize of 0,
+/// which no block could be sent or read with. Backends check it when they
+/// open the device, so the transfers here can rely on it.
+pub fn check_transfer_size(descriptor: &FunctionalDescriptor) -> Result<(), BikesafeError> {
+    match descriptor.transfer_size {
+        0 => Err(BikesafeError::ZeroTransferSize),
+        _ => Ok(()),
+    }
+}
+
+/// Issue DFU_GETSTATUS and decode the answer.
+pub fn get_status<IO>(io: &IO) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let mut buffer = [0u8; 6];
+    let n = io.read_control(REQUEST_TYPE, DFU_GETSTATUS, 0, &mut buffer)?;
+    Ok(DeviceStatus::decode(&buffer, n)?)
+}
+
+impl DeviceStatus {
+    /// Decode the `n` bytes of `buffer` the device answered DFU_GETSTATUS
+    /// with.
+    pub(crate) fn decode(buffer: &[u8; 6], n: usize) -> Result<Self, dfu_core::Error> {
+        if n < buffer.len() {
+            return Err(dfu_core::Error::ResponseTooShort {
+                got: n,
+                expected: buffer.len(),
+            });
+        }
+        let poll_timeout = u32::from_le_bytes([buffer[1], buffer[2], buffer[3], 0]) as u64;
+        Ok(DeviceStatus {
+            status: buffer[0].into(),
+            poll_timeout,
+            state: buffer[4].into(),
+        })
+    }
+}
+
+/// Issue DFU_CLRSTATUS, leaving dfuERROR for dfuIDLE.
+pub fn clear_status<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_CLRSTATUS, 0, &[])?;
+    Ok(())
+}
+
+/// Issue DFU_ABORT, returning the device to dfuIDLE.
+pub fn abort<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_ABORT, 0, &[])?;
+    Ok(())
+}
+
+/// Poll the status until the device leaves dfuDNBUSY, failing on dfuERROR.
+pub fn wait_while_busy<IO>(io: &IO) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    loop {
+        let status = get_status(io)?;
+        match status.state {
+            State::DfuDnbusy | State::DfuDnloadSync => {
+                thread::sleep(Duration::from_millis(status.poll_timeout));
+            }
+            State::DfuError => {
+                return Err(dfu_core::Error::StatusError(status.status).into());
+            }
+            _ => return Ok(status),
+        }
+    }
+}
+
+/// Make sure the device sits in dfuIDLE, clearing a pending error first.
+pub fn ensure_idle<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let status = get_status(io)?;
+    match status.state {
+        State::DfuIdle => Ok(()),
+        State::DfuError => {
+            tracing::debug!("Device in dfuERROR ({:?}), clearing status", status.status);
+            clear_status(io)
+        }
+        _ => {
+            tracing::debug!("Device in {:?}, aborting", status.state);
+            abort(io)
+        }
+    }
+}
+
+fn command<IO>(io: &IO, command: u8, argument: &[u8]) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let mut buffer = Vec::with_capacity(1 + argument.len());
+    buffer.push(command);
+    buffer.extend_from_slice(argument);
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, 0, &buffer)?;
+    wait_while_busy(io)
+}
+
+/// Point the DfuSe address pointer at `address`.
+pub fn set_address<IO>(io: &IO, address: u32) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    command(io, CMD_SET_ADDRESS, &address.to_le_bytes())?;
+    Ok(())
+}
+
+/// Erase the flash page containing `address`.
+pub fn erase_page<IO>(io: &IO, address: u32) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    command(io, CMD_ERASE, &address.to_le_bytes())?;
+    Ok(())
+}
+
+/// Erase every page overlapping `address..address + length`, walking the page
+/// layout the device reported. `progress` receives the size of each erased
+/// page.
+#[tracing::instrument(skip_all, fields(address = format_args!("{address:#010X}"), length))]
+pub fn erase<IO>(
+    io: &IO,
+    address: u32,
+    length: u32,
+    mut progress: impl FnMut(u32),
+) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let DfuProtocol::Dfuse {
+        address: base,
+        memory_layout,
+    } = io.protocol()
+    else {
+        return Err(dfu_core::Error::UnknownProtocol.into());
+    };
+    let end = address
+        .checked_add(length)
+        .ok_or(dfu_core::Error::NoSpaceLeft)?;
+
+    ensure_idle(io)?;
+    let mut page = *base;
+    for &size in memory_layout.as_ref() {
+        if page >= end {
+            return Ok(());
+        }
+        let next = page.checked_add(size).ok_or(dfu_core::Error::NoSpaceLeft)?;
+        if next > address {
+            erase_page(io, page)?;
+            progress(size);
+        }
+        page = next;
+    }
+    if page < end {
+        return Err(dfu_core::Error::NoSpaceLeft.into());
+    }
+    Ok(())
+}
+
+/// Erase the whole flash with the DfuSe erase command without an address.
+///
+/// Supported by the STM32 system-memory bootloader; it can take several
+/// seconds, which the device reports through the poll timeout.
+pub fn mass_erase<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    command(io, CMD_ERASE, &[])?;
+    Ok(())
+}
+
+/// Leave DFU mode and start the application at `address`: set the address
+/// pointer, then send a zero-length DNLOAD and poll the status once.
+///
+/// The device resets while answering, so the final request usually fails.
+#[tracing::instrument(name = "reset", skip_all, fields(address = format_args!("{address:#010X}")))]
+pub fn leave<IO>(io: &IO, address: u32) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    set_address(io, address)?;
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, FIRST_DATA_BLOCK, &[])?;
+    get_status(io)?;
+    Ok(())
+}
+
+/// Start of the flash page containing `address`, according to the DfuSe
+/// memory layout. `None` for plain DFU devices and addresses outside the
+/// layout.
+pub fn page_start<IO>(io: &IO, address: u32) -> Option<u32>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let DfuProtocol::Dfuse {
+        address: base,
+        memory_layout,
+    } = io.protocol()
+    else {
+        return None;
+    };
+    let mut page = *base;
+    for &size in memory_layout.as_ref() {
+        let next = page.checked_add(size)?;
+        if address < next {
+            return (address >= page).then_some(page);
+        }
+        page = next;
+    }
+    None
+}
+
+/// Ask the device to clear read protection.
+///
+/// On STM32 parts this mass-erases the flash and resets the device, so the
+/// USB transfers following this command usually fail.
+pub fn read_unprotect<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, 0, &[CMD_READ_UNPROTECT])?;
+    wait_while_busy(io)?;
+    Ok(())
+}
+
+/// Write `data` to `address` in blocks of `transfer_size` bytes.
+///
+/// The target pages must already be erased.
+#[tracing::instrument(
+    skip_all,
+    fields(address = format_args!("{address:#010X}"), length = data.len())
+)]
+pub fn download<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    transfer_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    // The device derives the write address from wBlockNum and wLength, which
+    // breaks for a short final block. Re-setting the pointer per chunk (as
+    // dfu-util does) keeps every block at wBlockNum = 2.
+    for (offset, chunk) in (0..).step_by(transfer_size).zip(data.chunks(transfer_size)) {
+        set_address(io, block_address(address, offset)?)?;
+        io.write_control(REQUEST_TYPE, DFU_DNLOAD, FIRST_DATA_BLOCK, chunk)?;
+        wait_while_busy(io)?;
+        progress(chunk.len());
+    }
+    abort(io)
+}
+
+/// The address of the block `offset` bytes after `address`; an error if it
+/// is past the end of the address space.
+pub(crate) fn block_address(address: u32, offset: usize) -> Result<u32, dfu_core::Error> {
+    u32::try_from(offset)
+        .ok()
+        .and_then(|offset| address.checked_add(offset))
+        .ok_or(dfu_core::Error::NoSpaceLeft)
+}
+
+/// Read `length` bytes starting at `address`, in blocks of `transfer_size`.
+#[tracing::instrument(skip_all, fields(address = format_args!("{address:#010X}"), length))]
+pub fn upload<IO>(
+    io: &IO,
+    address: u32,
+    length: usize,
+    transfer_size: usize,
+    progress: impl FnMut(usize),
+) -> Result<Vec<u8>, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    set_address(io, address)?;
+    // Uploads are only accepted from dfuIDLE.
+    abort(io)?;
+
+    let data = upload_blocks(io, FIRST_DATA_BLOCK, length, transfer_size, progress)?;
+    abort(io)?;
+    Ok(data)
+}
+
+/// Read up to `length` bytes from the start of the alternate setting's
+/// memory with plain DFU 1.1 uploads.
+#[tracing::instrument(skip_all, fields(length))]
+pub fn upload_plain<IO>(
+    io: &IO,
+    length: usize,
+    transfer_size: usize,
+    progress: impl FnMut(usize),
+) -> Result<Vec<u8>, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    let data = upload_blocks(io, 0, length, transfer_size, progress)?;
+    abort(io)?;
+    Ok(data)
+}
+
+/// Issue DFU_UPLOAD for consecutive blocks from `first_block` until
+/// `length` bytes arrived or the device sends a short packet.
+fn upload_blocks<IO>(
+    io: &IO,
+    first_block: u16,
+    length: usize,
+    transfer_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<u8>, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    let mut data = Vec::with_capacity(length);
+    let mut buffer = vec![0u8; transfer_size];
+    let mut block = first_block;
+    while data.len() < length {
+        // Always ask for a full block: the device derives the address from
+        // wBlockNum and wLength.
+        let n = io.read_control(REQUEST_TYPE, DFU_UPLOAD, block, &mut buffer)?;
+        let n = n.min(length - data.len());
+        data.extend_from_slice(&buffer[..n]);
+        progress(n);
+        if n < transfer_size && data.len() < length {
+            // Short packet: the device has nothing more to give.
+            break;
+        }
+        block = block.wrapping_add(1);
+    }
+    Ok(data)
+}
+
+/// Write `data` with plain DFU 1.1 requests: consecutive blocks from
+/// wBlockNum 0, without DfuSe addressing or erase commands. Finish with
+/// [`manifest`].
+#[tracing::instrument(skip_all, fields(length = data.len()))]
+pub fn download_plain<IO>(
+    io: &IO,
+    data: &[u8],
+    transfer_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<(), IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io)?;
+    let mut block: u16 = 0;
+    for chunk in data.chunks(transfer_size) {
+        io.write_control(REQUEST_TYPE, DFU_DNLOAD, block, chunk)?;
+        wait_while_busy(io)?;
+        progress(chunk.len());
+        block = block.wrapping_add(1);
+    }
+    Ok(())
+}
+
+/// End a plain DFU download with a zero-length DNLOAD and poll until the
+/// device finished manifestation. Returns the state it ends in: dfuIDLE for
+/// manifestation-tolerant devices, dfuMANIFEST-WAIT-RESET otherwise.
+pub fn manifest<IO>(io: &IO) -> Result<State, IO::Error>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, 0, &[])?;
+    loop {
+        let status = get_status(io)?;
+        match status.state {
+            State::DfuManifestSync | State::DfuManifest => {
+                thread::sleep(Duration::from_millis(status.poll_timeout));
+            }
+            State::DfuError => {
+                return Err(dfu_core::Error::StatusError(status.status).into());
+            }
+            state => return Ok(state),
+        }
+    }
+}
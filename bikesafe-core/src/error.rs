@@ -0,0 +1,118 @@
+//! Errors of the shared device code, typed so that front-ends can branch
+//! on them: retry a flaky transfer, pick an exit code, or point the user
+//! at the udev rule rather than the firmware file.
+
+use std::path::PathBuf;
+
+use dfu_core::Status;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BikesafeError {
+    #[error("no device {vid:04x}:{pid:04x} found")]
+    DeviceNotFound { vid: u16, pid: u16 },
+    /// The OS refused to open the device: a missing udev rule on Linux, or
+    /// no WinUSB driver on Windows.
+    #[error("permission denied opening the device")]
+    PermissionDenied,
+    /// The device went away mid-operation.
+    #[error("device disconnected")]
+    Disconnected,
+    /// Another flasher holds the device, or its lock file is unusable.
+    #[cfg(feature = "libusb")]
+    #[error(transparent)]
+    Busy(#[from] device_lock::Error),
+    #[error("no alternate setting named `{name}` on interface {intf} (found: {})", found.join(", "))]
+    AltNotFound {
+        name: String,
+        intf: u8,
+        found: Vec<String>,
+    },
+    #[error("could not open firmware file `{}`", path.display())]
+    ReadFirmware {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The image is not an application for the device; nothing was written.
+    #[error(transparent)]
+    ValidationFailed(#[from] ValidationError),
+    /// The device answered with a DFU error status.
+    #[error("device reported {0:?}")]
+    Dfu(Status),
+    /// The device's answers do not follow the DFU protocol.
+    #[error("DFU protocol error")]
+    Protocol(#[source] dfu_core::Error),
+    /// A USB transfer failed for another reason: a stall, a timeout, an I/O
+    /// error.
+    #[cfg(feature = "libusb")]
+    #[error("USB transfer failed")]
+    UsbIo(#[source] rusb::Error),
+    /// Anything else a [`DfuIo`](dfu_core::DfuIo) backend reported.
+    #[error("DFU backend error")]
+    Backend(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("device does not support upload, cannot read the image back")]
+    UploadNotSupported,
+    /// The device only speaks plain DFU, without addressed reads and writes.
+    #[error("device does not support DfuSe")]
+    DfuseNotSupported,
+    /// The functional descriptor gives a wTransferSize of 0, so no block
+    /// could be sent or read.
+    #[error("device reports a transfer size of 0")]
+    ZeroTransferSize,
+    /// The firmware read back from the device differs from the file.
+    #[error("verification failed: first difference at {address:#010X}")]
+    VerifyFailed { address: u32 },
+    /// The user stopped the operation.
+    #[error("cancelled")]
+    Cancelled,
+}
+
+/// Why a firmware image was rejected.
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    #[error("firmware file is too big")]
+    FileTooBig,
+    /// The image does not fit the flash or its vector table does not suit
+    /// the memory map.
+    #[error(transparent)]
+    Image(#[from] device_memory::ImageError),
+}
+
+#[cfg(feature = "libusb")]
+impl From<rusb::Error> for BikesafeError {
+    fn from(error: rusb::Error) -> Self {
+        match error {
+            rusb::Error::Access => BikesafeError::PermissionDenied,
+            rusb::Error::NoDevice => BikesafeError::Disconnected,
+            error => BikesafeError::UsbIo(error),
+        }
+    }
+}
+
+/// An I/O error of a [`DfuIo`](dfu_core::DfuIo) backend, or of the reader
+/// dfu-core's `DfuSync` streams an image from.
+impl From<std::io::Error> for BikesafeError {
+    fn from(error: std::io::Error) -> Self {
+        BikesafeError::Backend(Box::new(error))
+    }
+}
+
+impl From<dfu_core::Error> for BikesafeError {
+    fn from(error: dfu_core::Error) -> Self {
+        match error {
+            dfu_core::Error::StatusError(status) => BikesafeError::Dfu(status),
+            error => BikesafeError::Protocol(error),
+        }
+    }
+}
+
+#[cfg(feature = "libusb")]
+impl From<dfu_libusb::Error> for BikesafeError {
+    fn from(error: dfu_libusb::Error) -> Self {
+        match error {
+            dfu_libusb::Error::LibUsb(error) => error.into(),
+            dfu_libusb::Error::Dfu(error) => error.into(),
+            error => BikesafeError::Backend(Box::new(error)),
+        }
+    }
+}
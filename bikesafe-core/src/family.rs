@@ -0,0 +1,89 @@
+//! The products the tools support, and what differs between them: USB IDs,
+//! memory map and what to check once new firmware runs. Front-ends pick the
+//! family of the connected device with [`find`] instead of assuming a
+//! BrakeBright; adding a model means adding an entry to [`FAMILIES`].
+
+pub use device_memory::{Hardware, MemoryMap, Region};
+
+use crate::ValidationError;
+
+/// A product line sharing a bootloader and application layout.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Family {
+    pub name: &'static str,
+    /// VID:PIDs of the bootloader, i.e. in DFU mode. The first is the
+    /// default.
+    pub dfu_ids: &'static [(u16, u16)],
+    /// VID:PIDs of the running application.
+    pub runtime_ids: &'static [(u16, u16)],
+    /// Memory map of boards whose IDs have no entry in
+    /// [`device_memory::HARDWARE`].
+    pub memory: MemoryMap,
+    /// Check to run once the new firmware has started.
+    pub post_flash_test: PostFlashTest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostFlashTest {
+    /// Only check that the application starts (and reports the expected
+    /// version).
+    None,
+    /// Also run the self-test of the runtime protocol.
+    SelfTest,
+}
+
+pub const BRAKEBRIGHT: Family = Family {
+    name: "BrakeBright",
+    dfu_ids: &[(0x1209, 0x2444)],
+    runtime_ids: &[(0x1209, 0x2444)],
+    memory: device_memory::BRAKEBRIGHT,
+    // Firmware older than the runtime protocol has no self-test; `update
+    // --self-test` asks for it where available.
+    post_flash_test: PostFlashTest::None,
+};
+
+/// Every supported family. Devices outside all of them are treated as
+/// [`BRAKEBRIGHT`]s, so development boards with other IDs keep working.
+pub static FAMILIES: &[Family] = &[BRAKEBRIGHT];
+
+/// Family of a device with `vid:pid`, in DFU mode or running its
+/// application.
+pub fn find(vid: u16, pid: u16) -> Option<&'static Family> {
+    FAMILIES.iter().find(|family| family.claims(vid, pid))
+}
+
+impl Family {
+    /// Whether `vid:pid` is one of the family's bootloader or application
+    /// IDs.
+    pub fn claims(&self, vid: u16, pid: u16) -> bool {
+        self.dfu_ids
+            .iter()
+            .chain(self.runtime_ids)
+            .any(|&id| id == (vid, pid))
+    }
+
+    /// VID:PID of the application started from the bootloader `dfu`: the
+    /// family's first runtime ID, or `dfu` itself for devices outside the
+    /// family.
+    pub fn runtime_id(&self, dfu: (u16, u16)) -> (u16, u16) {
+        if self.dfu_ids.contains(&dfu) {
+            self.runtime_ids[0]
+        } else {
+            dfu
+        }
+    }
+
+    /// Memory map of the family's device `id` on hardware revision
+    /// `revision`: its entry in [`device_memory::HARDWARE`], or else the
+    /// family's.
+    pub fn memory_map(&self, id: (u16, u16), revision: Option<&str>) -> &MemoryMap {
+        device_memory::find(id.0, id.1, revision).unwrap_or(&self.memory)
+    }
+
+    /// Check that a raw application image fits the family's flash and
+    /// starts with a vector table whose initial SP points into RAM and whose
+    /// reset vector points into the image.
+    pub fn validate(&self, data: &[u8]) -> Result<(), ValidationError> {
+        crate::validate_for(&self.memory, data)
+    }
+}
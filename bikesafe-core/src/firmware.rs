@@ -0,0 +1,57 @@
+//! Checks of firmware images before they are written.
+
+use std::path::Path;
+
+use device_memory::VectorTable;
+
+use crate::family::{self, MemoryMap};
+use crate::{BikesafeError, ValidationError};
+
+/// Read a firmware image, failing if it is too big to address.
+pub fn read_firmware(path: &Path) -> Result<Vec<u8>, BikesafeError> {
+    let data = std::fs::read(path).map_err(|source| BikesafeError::ReadFirmware {
+        path: path.to_owned(),
+        source,
+    })?;
+    u32::try_from(data.len()).map_err(|_| ValidationError::FileTooBig)?;
+    Ok(data)
+}
+
+/// Check that a raw application image is one for a BrakeBright; see
+/// [`Family::validate`](crate::family::Family::validate) for other
+/// families.
+pub fn validate(data: &[u8]) -> Result<(), ValidationError> {
+    family::BRAKEBRIGHT.validate(data)
+}
+
+/// Check that a raw application image fits the flash of `memory` and starts
+/// with a vector table whose initial SP points into its RAM and whose reset
+/// vector points into the image.
+pub fn validate_for(memory: &MemoryMap, data: &[u8]) -> Result<(), ValidationError> {
+    validate_at(memory, memory.flash.origin, data)
+}
+
+/// The same for an image written at `address` rather than the start of the
+/// application flash.
+///
+/// ```
+/// use bikesafe_core::family::BRAKEBRIGHT;
+///
+/// let mut image = vec![0; 256];
+/// image[..4].copy_from_slice(&0x2000_5000u32.to_le_bytes());
+/// image[4..8].copy_from_slice(&0x0800_8021u32.to_le_bytes());
+/// assert!(bikesafe_core::validate_at(&BRAKEBRIGHT.memory, 0x0800_8000, &image).is_ok());
+/// // Linked for 0x08008000, so its reset vector misses the image elsewhere.
+/// assert!(bikesafe_core::validate_for(&BRAKEBRIGHT.memory, &image).is_err());
+/// ```
+pub fn validate_at(memory: &MemoryMap, address: u32, data: &[u8]) -> Result<(), ValidationError> {
+    let flash = memory.flash;
+    let start = address as u64;
+    device_memory::check_fits(
+        start,
+        start + data.len() as u64,
+        &[(flash.origin as u64, flash.end())],
+    )?;
+    VectorTable::check(data, address, memory.ram)?;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Device access shared by the graphical and command-line updaters: finding
+//! and opening the DFU device, checking firmware images, and writing,
+//! verifying and starting them, so a feature only has to be implemented
+//! once. [`FirmwareUpdater`] is the high-level entry point; [`transfer`]
+//! and [`dfuse`] are the building blocks for front-ends that need finer
+//! control.
+//!
+//! Without the default `libusb` feature only the firmware checks and the
+//! building blocks are built; they work over any [`DfuIo`](dfu_core::DfuIo)
+//! transport, such as WebUSB, which the `webusb` feature provides.
+
+pub mod cancel;
+pub mod chip;
+#[cfg(feature = "libusb")]
+pub mod device;
+pub mod dfuse;
+mod error;
+pub mod family;
+mod firmware;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod progress;
+pub mod transfer;
+#[cfg(feature = "libusb")]
+mod updater;
+#[cfg(feature = "webusb")]
+pub mod webusb;
+
+pub use chip::{ChipId, read_chip_id};
+#[cfg(feature = "libusb")]
+pub use device::{Device, Identity};
+pub use error::{BikesafeError, ValidationError};
+pub use firmware::{read_firmware, validate, validate_at, validate_for};
+pub use progress::{Phase, ProgressSink};
+#[cfg(feature = "libusb")]
+pub use updater::FirmwareUpdater;
+
+/// VID:PID of the BrakeBright bootloader.
+pub const DEFAULT_DEVICE: (u16, u16) = family::BRAKEBRIGHT.dfu_ids[0];
+
+/// Start of the BrakeBright application image, after the bootloader.
+pub const APPLICATION_ADDRESS: u32 = family::BRAKEBRIGHT.memory.flash.origin;
@@ -0,0 +1,577 @@
+//! A simulated DfuSe (or plain DFU) device implementing [`DfuIo`], to run
+//! the transfer code without hardware. It keeps a flash image, follows the
+//! DFU state machine closely enough for [`dfuse`](crate::dfuse), records
+//! every control transfer and can be told to fail a given request.
+//!
+//! ```
+//! use bikesafe_core::mock::MockDfu;
+//!
+//! let io = MockDfu::dfuse("@Internal Flash  /0x08000000/16*1Ka,48*1Kg").unwrap();
+//! bikesafe_core::transfer::erase(&io, 0x0800_4000, b"firmware", |_| ()).unwrap();
+//! bikesafe_core::transfer::download(&io, 0x0800_4000, b"firmware", |_| ()).unwrap();
+//! assert_eq!(io.read(0x0800_4000, 8), b"firmware");
+//! ```
+
+use std::cell::RefCell;
+
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::memory_layout::MemoryLayout;
+use dfu_core::{DfuIo, DfuProtocol, State, Status};
+
+pub const DFU_DNLOAD: u8 = 1;
+pub const DFU_UPLOAD: u8 = 2;
+pub const DFU_GETSTATUS: u8 = 3;
+pub const DFU_CLRSTATUS: u8 = 4;
+pub const DFU_GETSTATE: u8 = 5;
+pub const DFU_ABORT: u8 = 6;
+
+const CMD_SET_ADDRESS: u8 = 0x21;
+const CMD_ERASE: u8 = 0x41;
+const CMD_READ_UNPROTECT: u8 = 0x92;
+
+/// Flash size of a plain DFU mock.
+const PLAIN_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MockError {
+    #[error(transparent)]
+    Dfu(#[from] dfu_core::Error),
+    /// Needed to drive the mock with `dfu_core::sync::DfuSync`.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A failure set up with [`MockDfu::fail`] or
+    /// [`MockDfu::disconnect_after`].
+    #[error("injected failure of request {request} (transfer {index})")]
+    Injected { request: u8, index: usize },
+}
+
+impl From<MockError> for crate::BikesafeError {
+    fn from(error: MockError) -> Self {
+        match error {
+            MockError::Dfu(error) => error.into(),
+            // What a failed request looks like on a real bus: a stall.
+            MockError::Injected { .. } => crate::BikesafeError::UsbIo(rusb::Error::Pipe),
+            error => crate::BikesafeError::Backend(Box::new(error)),
+        }
+    }
+}
+
+/// One control transfer as the device saw it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    /// Data sent to the device, or returned by it for reads.
+    pub data: Vec<u8>,
+    /// Whether the transfer failed (injected failures are recorded too).
+    pub failed: bool,
+}
+
+#[derive(Default)]
+struct Failures {
+    /// `(request, occurrence)`: fail the nth (from 0) transfer of `request`.
+    requests: Vec<(u8, usize)>,
+    /// Fail every transfer from this index on.
+    disconnect_after: Option<usize>,
+}
+
+struct Inner {
+    state: State,
+    status: Status,
+    address: u32,
+    flash: Vec<u8>,
+    /// Read-only regions outside the flash, as `(address, contents)`.
+    system: Vec<(u32, Vec<u8>)>,
+    protected: bool,
+    transfers: Vec<Transfer>,
+    failures: Failures,
+    resets: usize,
+    started: Option<u32>,
+}
+
+/// A simulated device; see the [module documentation](self).
+pub struct MockDfu {
+    protocol: DfuProtocol<MemoryLayout>,
+    descriptor: FunctionalDescriptor,
+    base: u32,
+    inner: RefCell<Inner>,
+}
+
+impl MockDfu {
+    /// A DfuSe device described by its interface string, such as
+    /// `@Internal Flash  /0x08000000/16*1Ka,48*1Kg`, with erased flash, a
+    /// 2 KiB transfer size and upload support.
+    pub fn dfuse(interface_string: &str) -> Result<Self, dfu_core::Error> {
+        let protocol = DfuProtocol::new(interface_string, (1, 0x1a))?;
+        let DfuProtocol::Dfuse {
+            address,
+            memory_layout,
+        } = &protocol
+        else {
+            unreachable!("DfuSe version");
+        };
+        let size = memory_layout.iter().sum::<u32>() as usize;
+        let base = *address;
+        Ok(Self::new(protocol, descriptor((1, 0x1a)), base, size))
+    }
+
+    /// A plain DFU 1.1 device with 64 KiB of erased memory.
+    pub fn plain() -> Self {
+        Self::new(DfuProtocol::Dfu, descriptor((1, 0x10)), 0, PLAIN_SIZE)
+    }
+
+    fn new(
+        protocol: DfuProtocol<MemoryLayout>,
+        descriptor: FunctionalDescriptor,
+        base: u32,
+        size: usize,
+    ) -> Self {
+        Self {
+            protocol,
+            descriptor,
+            base,
+            inner: RefCell::new(Inner {
+                state: State::DfuIdle,
+                status: Status::Ok,
+                address: base,
+                flash: vec![0xFF; size],
+                system: Vec::new(),
+                protected: false,
+                transfers: Vec::new(),
+                failures: Failures::default(),
+                resets: 0,
+                started: None,
+            }),
+        }
+    }
+
+    /// Replace the functional descriptor, e.g. to change the transfer size
+    /// or drop upload support.
+    pub fn with_descriptor(mut self, edit: impl FnOnce(&mut FunctionalDescriptor)) -> Self {
+        edit(&mut self.descriptor);
+        self
+    }
+
+    /// Start in `state`, e.g. dfuERROR left over from an earlier session.
+    pub fn with_state(self, state: State, status: Status) -> Self {
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.state = state;
+            inner.status = status;
+        }
+        self
+    }
+
+    /// Refuse uploads and writes until read protection is removed.
+    pub fn read_protected(self) -> Self {
+        self.inner.borrow_mut().protected = true;
+        self
+    }
+
+    /// Preload the flash at `address`.
+    pub fn with_flash(self, address: u32, data: &[u8]) -> Self {
+        {
+            let mut inner = self.inner.borrow_mut();
+            let offset = (address - self.base) as usize;
+            inner.flash[offset..offset + data.len()].copy_from_slice(data);
+        }
+        self
+    }
+
+    /// Make `data` readable at `address` outside the flash, like the
+    /// identification registers in an STM32's system memory.
+    pub fn with_system_memory(self, address: u32, data: &[u8]) -> Self {
+        self.inner
+            .borrow_mut()
+            .system
+            .push((address, data.to_vec()));
+        self
+    }
+
+    /// Fail the `occurrence`th (from 0) transfer of `request`.
+    pub fn fail(self, request: u8, occurrence: usize) -> Self {
+        self.inner
+            .borrow_mut()
+            .failures
+            .requests
+            .push((request, occurrence));
+        self
+    }
+
+    /// Fail every transfer after the first `count`, as if the device was
+    /// unplugged.
+    pub fn disconnect_after(self, count: usize) -> Self {
+        self.inner.borrow_mut().failures.disconnect_after = Some(count);
+        self
+    }
+
+    /// Plug the device back in: clear the injected failures, keeping the
+    /// flash contents.
+    pub fn reconnect(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.failures = Failures::default();
+        inner.state = State::DfuIdle;
+        inner.status = Status::Ok;
+    }
+
+    /// `len` bytes of flash at `address`.
+    pub fn read(&self, address: u32, len: usize) -> Vec<u8> {
+        let offset = (address - self.base) as usize;
+        self.inner.borrow().flash[offset..offset + len].to_vec()
+    }
+
+    /// Every control transfer so far.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        self.inner.borrow().transfers.clone()
+    }
+
+    /// The request codes of every transfer so far, for asserting on the
+    /// sequence.
+    pub fn requests(&self) -> Vec<u8> {
+        self.inner
+            .borrow()
+            .transfers
+            .iter()
+            .map(|t| t.request)
+            .collect()
+    }
+
+    /// Data blocks written with DFU_DNLOAD, with their block numbers;
+    /// DfuSe commands are left out.
+    pub fn writes(&self) -> Vec<(u16, Vec<u8>)> {
+        let dfuse = matches!(self.protocol, DfuProtocol::Dfuse { .. });
+        self.inner
+            .borrow()
+            .transfers
+            .iter()
+            .filter(|t| t.request == DFU_DNLOAD && !t.failed && !t.data.is_empty())
+            .filter(|t| !dfuse || t.value != 0)
+            .map(|t| (t.value, t.data.clone()))
+            .collect()
+    }
+
+    pub fn state(&self) -> State {
+        self.inner.borrow().state
+    }
+
+    /// Number of USB resets issued.
+    pub fn resets(&self) -> usize {
+        self.inner.borrow().resets
+    }
+
+    /// Address the device was told to start the application at, once it
+    /// left DFU mode.
+    pub fn started(&self) -> Option<u32> {
+        self.inner.borrow().started
+    }
+
+    fn transfer(&self, transfer: Transfer) -> Result<(), MockError> {
+        let mut inner = self.inner.borrow_mut();
+        let index = inner.transfers.len();
+        let occurrence = inner
+            .transfers
+            .iter()
+            .filter(|t| t.request == transfer.request)
+            .count();
+        let failed = inner.started.is_some()
+            || inner.failures.disconnect_after.is_some_and(|n| index >= n)
+            || inner
+                .failures
+                .requests
+                .contains(&(transfer.request, occurrence));
+        let request = transfer.request;
+        inner.transfers.push(Transfer { failed, ..transfer });
+        match failed {
+            true => Err(MockError::Injected { request, index }),
+            false => Ok(()),
+        }
+    }
+
+    fn record_read(&self, data: &[u8]) {
+        if let Some(last) = self.inner.borrow_mut().transfers.last_mut() {
+            last.data = data.to_vec();
+        }
+    }
+}
+
+fn descriptor(dfu_version: (u8, u8)) -> FunctionalDescriptor {
+    FunctionalDescriptor {
+        can_download: true,
+        can_upload: true,
+        manifestation_tolerant: true,
+        will_detach: false,
+        detach_timeout: 255,
+        transfer_size: 2048,
+        dfu_version,
+    }
+}
+
+impl Inner {
+    fn error(&mut self, status: Status) {
+        self.state = State::DfuError;
+        self.status = status;
+    }
+
+    /// Up to `len` bytes of system memory at `address`, if it is in one of
+    /// the regions.
+    fn read_system(&self, address: u32, len: usize) -> Option<Vec<u8>> {
+        self.system.iter().find_map(|(origin, memory)| {
+            let start = address.checked_sub(*origin)? as usize;
+            let end = (start + len).min(memory.len());
+            memory
+                .get(start..end)
+                .filter(|data| !data.is_empty())
+                .map(<[u8]>::to_vec)
+        })
+    }
+
+    fn write(&mut self, base: u32, address: u32, data: &[u8]) {
+        let offset = address.wrapping_sub(base) as usize;
+        let Some(flash) = self.flash.get_mut(offset..offset + data.len()) else {
+            return self.error(Status::ErrAddress);
+        };
+        if self.protected {
+            return self.error(Status::ErrWrite);
+        }
+        // Flash bits only go from 1 to 0 without an erase.
+        if flash.iter().zip(data).any(|(&old, &new)| old & new != new) {
+            return self.error(Status::ErrCheckErased);
+        }
+        flash.copy_from_slice(data);
+        self.state = State::DfuDnbusy;
+    }
+}
+
+impl DfuIo for MockDfu {
+    type Read = usize;
+    type Write = usize;
+    type Reset = ();
+    type Error = MockError;
+    type MemoryLayout = MemoryLayout;
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, MockError> {
+        self.transfer(Transfer {
+            request_type,
+            request,
+            value,
+            data: Vec::new(),
+            failed: false,
+        })?;
+        let mut inner = self.inner.borrow_mut();
+        let data = match request {
+            DFU_GETSTATUS => {
+                let answer = [inner.status.into(), 0, 0, 0, inner.state.into(), 0];
+                inner.state = match inner.state {
+                    State::DfuDnbusy | State::DfuDnloadSync => State::DfuDnloadIdle,
+                    State::DfuManifestSync if self.descriptor.manifestation_tolerant => {
+                        State::DfuIdle
+                    }
+                    State::DfuManifestSync => State::DfuManifestWaitReset,
+                    state => state,
+                };
+                answer.to_vec()
+            }
+            DFU_GETSTATE => vec![inner.state.into()],
+            DFU_UPLOAD => {
+                if !matches!(inner.state, State::DfuIdle | State::DfuUploadIdle)
+                    || !self.descriptor.can_upload
+                {
+                    inner.error(Status::ErrStalledpkt);
+                    return Err(dfu_core::Error::StateError(State::DfuError).into());
+                }
+                if inner.protected {
+                    inner.error(Status::ErrVendor);
+                    return Err(dfu_core::Error::StatusError(Status::ErrVendor).into());
+                }
+                let system = match self.protocol {
+                    DfuProtocol::Dfuse { .. } => inner.read_system(
+                        inner
+                            .address
+                            .wrapping_add((value as u32).saturating_sub(2) * buffer.len() as u32),
+                        buffer.len(),
+                    ),
+                    DfuProtocol::Dfu => None,
+                };
+                let start = match self.protocol {
+                    DfuProtocol::Dfuse { .. } => {
+                        inner.address.wrapping_sub(self.base)
+                            + (value as u32).saturating_sub(2) * buffer.len() as u32
+                    }
+                    DfuProtocol::Dfu => value as u32 * buffer.len() as u32,
+                } as usize;
+                let end = (start + buffer.len()).min(inner.flash.len());
+                inner.state = State::DfuUploadIdle;
+                system.unwrap_or_else(|| inner.flash.get(start..end).unwrap_or_default().to_vec())
+            }
+            _ => {
+                inner.error(Status::ErrStalledpkt);
+                return Err(dfu_core::Error::StateError(State::DfuError).into());
+            }
+        };
+        drop(inner);
+        let n = data.len().min(buffer.len());
+        buffer[..n].copy_from_slice(&data[..n]);
+        self.record_read(&data[..n]);
+        Ok(n)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> Result<usize, MockError> {
+        self.transfer(Transfer {
+            request_type,
+            request,
+            value,
+            data: buffer.to_vec(),
+            failed: false,
+        })?;
+        let mut inner = self.inner.borrow_mut();
+        match request {
+            DFU_CLRSTATUS | DFU_ABORT => {
+                inner.state = State::DfuIdle;
+                inner.status = Status::Ok;
+            }
+            DFU_DNLOAD if !matches!(inner.state, State::DfuIdle | State::DfuDnloadIdle) => {
+                inner.error(Status::ErrStalledpkt);
+            }
+            DFU_DNLOAD => match (&self.protocol, value, buffer) {
+                (DfuProtocol::Dfuse { .. }, 0, [CMD_SET_ADDRESS, address @ ..]) => {
+                    inner.address = u32::from_le_bytes(address.try_into().unwrap_or_default());
+                    inner.state = State::DfuDnbusy;
+                }
+                (DfuProtocol::Dfuse { .. }, 0, [CMD_ERASE]) => {
+                    inner.flash.fill(0xFF);
+                    inner.state = State::DfuDnbusy;
+                }
+                (DfuProtocol::Dfuse { memory_layout, .. }, 0, [CMD_ERASE, address @ ..]) => {
+                    let address = u32::from_le_bytes(address.try_into().unwrap_or_default());
+                    let mut page = self.base;
+                    let mut erased = false;
+                    for &size in memory_layout.iter() {
+                        if address >= page && address < page + size {
+                            let offset = (page - self.base) as usize;
+                            inner.flash[offset..offset + size as usize].fill(0xFF);
+                            erased = true;
+                        }
+                        page += size;
+                    }
+                    match erased {
+                        true => inner.state = State::DfuDnbusy,
+                        false => inner.error(Status::ErrAddress),
+                    }
+                }
+                (DfuProtocol::Dfuse { .. }, 0, [CMD_READ_UNPROTECT]) => {
+                    inner.flash.fill(0xFF);
+                    inner.protected = false;
+                    inner.state = State::DfuDnbusy;
+                }
+                (DfuProtocol::Dfuse { .. }, 0, _) => inner.error(Status::ErrStalledpkt),
+                (DfuProtocol::Dfuse { .. }, _, []) => {
+                    // Leave DFU mode: the device resets, failing every
+                    // transfer from now on.
+                    inner.started = Some(inner.address);
+                    inner.state = State::DfuManifest;
+                }
+                (DfuProtocol::Dfuse { .. }, block, data) => {
+                    let address =
+                        inner.address + (block as u32 - 2) * self.descriptor.transfer_size as u32;
+                    inner.write(self.base, address, data);
+                }
+                (DfuProtocol::Dfu, _, []) => inner.state = State::DfuManifestSync,
+                (DfuProtocol::Dfu, block, data) => {
+                    let address = block as u32 * self.descriptor.transfer_size as u32;
+                    inner.write(self.base, address, data);
+                }
+            },
+            _ => inner.error(Status::ErrStalledpkt),
+        }
+        Ok(buffer.len())
+    }
+
+    fn usb_reset(&self) -> Result<(), MockError> {
+        let mut inner = self.inner.borrow_mut();
+        inner.resets += 1;
+        inner.state = State::DfuIdle;
+        inner.status = Status::Ok;
+        Ok(())
+    }
+
+    fn protocol(&self) -> &DfuProtocol<MemoryLayout> {
+        &self.protocol
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        &self.descriptor
+    }
+}
+
+/// The same device for the steps of [`nonblocking`](crate::nonblocking):
+/// every request is done by the time its future is created, and sleeps
+/// return at once.
+#[cfg(feature = "async")]
+impl dfu_core::asynchronous::DfuAsyncIo for MockDfu {
+    type Read = usize;
+    type Write = usize;
+    type Reset = ();
+    type Error = MockError;
+    type MemoryLayout = MemoryLayout;
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<usize, MockError>> + Send {
+        std::future::ready(DfuIo::read_control(
+            self,
+            request_type,
+            request,
+            value,
+            buffer,
+        ))
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> impl Future<Output = Result<usize, MockError>> + Send {
+        std::future::ready(DfuIo::write_control(
+            self,
+            request_type,
+            request,
+            value,
+            buffer,
+        ))
+    }
+
+    fn usb_reset(&self) -> impl Future<Output = Result<(), MockError>> + Send {
+        std::future::ready(DfuIo::usb_reset(self))
+    }
+
+    fn sleep(&self, _: std::time::Duration) -> impl Future<Output = ()> + Send {
+        std::future::ready(())
+    }
+
+    fn protocol(&self) -> &DfuProtocol<MemoryLayout> {
+        &self.protocol
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        &self.descriptor
+    }
+}
@@ -0,0 +1,228 @@
+//! The update steps as futures, for callers that run an executor or an
+//! event loop rather than a thread per device. [`transfer`] and [`dfuse`]
+//! erase, write and read back images over any [`DfuAsyncIo`], such as
+//! WebUSB; [`AsyncFirmwareUpdater`] runs them on a libusb device.
+//!
+//! [`AsyncFirmwareUpdater`] is not yet the asynchronous USB backend it is
+//! meant to become: that needs `nusb`'s asynchronous transfers (and
+//! `dfu-nusb`), which the tools do not depend on yet. Until then,
+//! [`Blocking`] is an interim shim over the libusb `DfuIo`: each request,
+//! and each of dfu-core's sleeps, blocks a thread of the `blocking` crate's
+//! pool while the caller awaits. The steps themselves only await, so they
+//! will run unchanged over a real asynchronous transport such as WebUSB.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), bikesafe_core::BikesafeError> {
+//! use bikesafe_core::nonblocking::AsyncFirmwareUpdater;
+//!
+//! let firmware = bikesafe_core::read_firmware("firmware.bin".as_ref())?;
+//! let updater = AsyncFirmwareUpdater::find_device(0x1209, 0x2444).await?;
+//! updater.update(&firmware, &mut ()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod dfuse;
+pub mod transfer;
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use blocking::unblock;
+use dfu_core::asynchronous::DfuAsyncIo;
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::memory_layout::MemoryLayout;
+use dfu_core::{DfuIo, DfuProtocol};
+
+#[cfg(feature = "libusb")]
+use crate::cancel::Cancellable;
+#[cfg(feature = "libusb")]
+use crate::progress::{Counter, Phase};
+#[cfg(feature = "libusb")]
+use crate::{BikesafeError, FirmwareUpdater, ProgressSink};
+
+/// A [`DfuIo`] as a [`DfuAsyncIo`]: each request runs on the thread pool,
+/// and its future resolves when it is done. An interim shim until there is
+/// an asynchronous libusb-free backend; see the module documentation.
+pub struct Blocking<IO> {
+    io: Arc<Mutex<IO>>,
+    protocol: DfuProtocol<MemoryLayout>,
+    functional_descriptor: FunctionalDescriptor,
+}
+
+impl<IO: DfuIo + Send + 'static> Blocking<IO> {
+    pub fn new(io: IO) -> Self {
+        let protocol = match io.protocol() {
+            DfuProtocol::Dfu => DfuProtocol::Dfu,
+            DfuProtocol::Dfuse {
+                address,
+                memory_layout,
+            } => DfuProtocol::Dfuse {
+                address: *address,
+                memory_layout: memory_layout.as_ref().to_vec().into(),
+            },
+        };
+        let functional_descriptor = *io.functional_descriptor();
+        Self {
+            io: Arc::new(Mutex::new(io)),
+            protocol,
+            functional_descriptor,
+        }
+    }
+
+    async fn run<T: Send + 'static>(&self, request: impl FnOnce(&IO) -> T + Send + 'static) -> T {
+        let io = self.io.clone();
+        unblock(move || request(&io.lock().unwrap_or_else(PoisonError::into_inner))).await
+    }
+}
+
+impl<IO> DfuAsyncIo for Blocking<IO>
+where
+    IO: DfuIo + Send + 'static,
+    IO::Read: Send + 'static,
+    IO::Write: Send + 'static,
+    IO::Reset: Send + 'static,
+    IO::Error: Send + 'static,
+{
+    type Read = IO::Read;
+    type Write = IO::Write;
+    type Reset = IO::Reset;
+    type Error = IO::Error;
+    type MemoryLayout = MemoryLayout;
+
+    async fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> Result<Self::Read, Self::Error> {
+        let mut owned = vec![0; buffer.len()];
+        let (result, owned) = self
+            .run(move |io| {
+                let result = io.read_control(request_type, request, value, &mut owned);
+                (result, owned)
+            })
+            .await;
+        buffer.copy_from_slice(&owned);
+        result
+    }
+
+    async fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> Result<Self::Write, Self::Error> {
+        let buffer = buffer.to_vec();
+        self.run(move |io| io.write_control(request_type, request, value, &buffer))
+            .await
+    }
+
+    async fn usb_reset(&self) -> Result<Self::Reset, Self::Error> {
+        self.run(|io| io.usb_reset()).await
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        unblock(move || thread::sleep(duration)).await
+    }
+
+    fn protocol(&self) -> &DfuProtocol<Self::MemoryLayout> {
+        &self.protocol
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        &self.functional_descriptor
+    }
+}
+
+/// A [`FirmwareUpdater`] whose steps are futures. Cloning it shares the
+/// device and its lock.
+#[cfg(feature = "libusb")]
+#[derive(Clone)]
+pub struct AsyncFirmwareUpdater {
+    inner: Arc<FirmwareUpdater>,
+}
+
+#[cfg(feature = "libusb")]
+impl From<FirmwareUpdater> for AsyncFirmwareUpdater {
+    fn from(updater: FirmwareUpdater) -> Self {
+        Self {
+            inner: Arc::new(updater),
+        }
+    }
+}
+
+#[cfg(feature = "libusb")]
+impl AsyncFirmwareUpdater {
+    /// See [`FirmwareUpdater::find_device`].
+    pub async fn find_device(vid: u16, pid: u16) -> Result<Self, BikesafeError> {
+        Ok(unblock(move || FirmwareUpdater::find_device(vid, pid))
+            .await?
+            .into())
+    }
+
+    /// The updater the steps run on, for its device, memory map and
+    /// address, and to validate images.
+    pub fn updater(&self) -> &FirmwareUpdater {
+        &self.inner
+    }
+
+    async fn open(
+        &self,
+    ) -> Result<Blocking<Cancellable<dfu_libusb::DfuLibusb<rusb::Context>>>, BikesafeError> {
+        let inner = self.inner.clone();
+        Ok(Blocking::new(unblock(move || inner.open()).await?))
+    }
+
+    /// See [`FirmwareUpdater::flash`].
+    pub async fn flash<S: ProgressSink + ?Sized>(
+        &self,
+        firmware: &[u8],
+        progress: &mut S,
+    ) -> Result<(), BikesafeError> {
+        let io = self.open().await?;
+        let address = self.inner.address();
+        let total = firmware.len() as u64;
+        let mut erase = Counter::new(&mut *progress, Phase::Erase, total);
+        transfer::erase(&io, address, firmware, |n| erase.advance(n)).await?;
+        let mut write = Counter::new(progress, Phase::Download, total);
+        transfer::download(&io, address, firmware, |n| write.advance(n)).await
+    }
+
+    /// See [`FirmwareUpdater::verify`].
+    pub async fn verify<S: ProgressSink + ?Sized>(
+        &self,
+        firmware: &[u8],
+        progress: &mut S,
+    ) -> Result<(), BikesafeError> {
+        let io = self.open().await?;
+        transfer::ensure_upload(&io)?;
+        let mut verify = Counter::new(progress, Phase::Verify, firmware.len() as u64);
+        transfer::verify(&io, self.inner.address(), firmware, |n| verify.advance(n)).await
+    }
+
+    /// See [`FirmwareUpdater::reset`].
+    pub async fn reset(&self) -> Result<(), BikesafeError> {
+        let io = self.open().await?;
+        match dfuse::leave(&io, self.inner.address()).await {
+            // The device may drop off the bus before answering.
+            Ok(()) | Err(BikesafeError::Disconnected | BikesafeError::UsbIo(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Validate, write and start `firmware`, reporting the erase and write
+    /// phases.
+    pub async fn update<S: ProgressSink + ?Sized>(
+        &self,
+        firmware: &[u8],
+        progress: &mut S,
+    ) -> Result<(), BikesafeError> {
+        self.inner.validate(firmware)?;
+        self.flash(firmware, progress).await?;
+        self.reset().await
+    }
+}
@@ -0,0 +1,222 @@
+//! The DfuSe requests of [`crate::dfuse`] that writing, reading back and
+//! starting an image need, over a [`DfuAsyncIo`]. They behave the same; see
+//! there for the details.
+
+use std::time::Duration;
+
+use dfu_core::asynchronous::DfuAsyncIo;
+use dfu_core::{DfuProtocol, State};
+
+use crate::dfuse::{
+    CMD_ERASE, CMD_SET_ADDRESS, DFU_ABORT, DFU_CLRSTATUS, DFU_DNLOAD, DFU_GETSTATUS, DFU_UPLOAD,
+    DeviceStatus, FIRST_DATA_BLOCK, REQUEST_TYPE, block_address,
+};
+
+/// Issue DFU_GETSTATUS and decode the answer.
+pub async fn get_status<IO>(io: &IO) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    let mut buffer = [0u8; 6];
+    let n = io
+        .read_control(REQUEST_TYPE, DFU_GETSTATUS, 0, &mut buffer)
+        .await?;
+    Ok(DeviceStatus::decode(&buffer, n)?)
+}
+
+/// Issue DFU_CLRSTATUS, leaving dfuERROR for dfuIDLE.
+pub async fn clear_status<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_CLRSTATUS, 0, &[])
+        .await?;
+    Ok(())
+}
+
+/// Issue DFU_ABORT, returning the device to dfuIDLE.
+pub async fn abort<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    io.write_control(REQUEST_TYPE, DFU_ABORT, 0, &[]).await?;
+    Ok(())
+}
+
+/// Poll the status until the device leaves dfuDNBUSY, failing on dfuERROR.
+pub async fn wait_while_busy<IO>(io: &IO) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    loop {
+        let status = get_status(io).await?;
+        match status.state {
+            State::DfuDnbusy | State::DfuDnloadSync => {
+                io.sleep(Duration::from_millis(status.poll_timeout)).await;
+            }
+            State::DfuError => {
+                return Err(dfu_core::Error::StatusError(status.status).into());
+            }
+            _ => return Ok(status),
+        }
+    }
+}
+
+/// Make sure the device sits in dfuIDLE, clearing a pending error first.
+pub async fn ensure_idle<IO>(io: &IO) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    let status = get_status(io).await?;
+    match status.state {
+        State::DfuIdle => Ok(()),
+        State::DfuError => {
+            tracing::debug!("Device in dfuERROR ({:?}), clearing status", status.status);
+            clear_status(io).await
+        }
+        _ => {
+            tracing::debug!("Device in {:?}, aborting", status.state);
+            abort(io).await
+        }
+    }
+}
+
+async fn command<IO>(io: &IO, command: u8, argument: &[u8]) -> Result<DeviceStatus, IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    let mut buffer = Vec::with_capacity(1 + argument.len());
+    buffer.push(command);
+    buffer.extend_from_slice(argument);
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, 0, &buffer)
+        .await?;
+    wait_while_busy(io).await
+}
+
+/// Point the DfuSe address pointer at `address`.
+pub async fn set_address<IO>(io: &IO, address: u32) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    command(io, CMD_SET_ADDRESS, &address.to_le_bytes()).await?;
+    Ok(())
+}
+
+/// Erase every page overlapping `address..address + length`. `progress`
+/// receives the size of each erased page.
+#[tracing::instrument(skip_all, fields(address = format_args!("{address:#010X}"), length))]
+pub async fn erase<IO>(
+    io: &IO,
+    address: u32,
+    length: u32,
+    mut progress: impl FnMut(u32),
+) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    let DfuProtocol::Dfuse {
+        address: base,
+        memory_layout,
+    } = io.protocol()
+    else {
+        return Err(dfu_core::Error::UnknownProtocol.into());
+    };
+    let end = address
+        .checked_add(length)
+        .ok_or(dfu_core::Error::NoSpaceLeft)?;
+
+    ensure_idle(io).await?;
+    let mut page = *base;
+    for &size in memory_layout.as_ref() {
+        if page >= end {
+            return Ok(());
+        }
+        let next = page.checked_add(size).ok_or(dfu_core::Error::NoSpaceLeft)?;
+        if next > address {
+            command(io, CMD_ERASE, &page.to_le_bytes()).await?;
+            progress(size);
+        }
+        page = next;
+    }
+    if page < end {
+        return Err(dfu_core::Error::NoSpaceLeft.into());
+    }
+    Ok(())
+}
+
+/// Leave DFU mode and start the application at `address`. The device
+/// resets while answering, so the final request usually fails.
+#[tracing::instrument(name = "reset", skip_all, fields(address = format_args!("{address:#010X}")))]
+pub async fn leave<IO>(io: &IO, address: u32) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io).await?;
+    set_address(io, address).await?;
+    io.write_control(REQUEST_TYPE, DFU_DNLOAD, FIRST_DATA_BLOCK, &[])
+        .await?;
+    get_status(io).await?;
+    Ok(())
+}
+
+/// Write `data` to already erased pages at `address` in blocks of
+/// `transfer_size` bytes.
+#[tracing::instrument(
+    skip_all,
+    fields(address = format_args!("{address:#010X}"), length = data.len())
+)]
+pub async fn download<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    transfer_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<(), IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io).await?;
+    for (offset, chunk) in (0..).step_by(transfer_size).zip(data.chunks(transfer_size)) {
+        set_address(io, block_address(address, offset)?).await?;
+        io.write_control(REQUEST_TYPE, DFU_DNLOAD, FIRST_DATA_BLOCK, chunk)
+            .await?;
+        wait_while_busy(io).await?;
+        progress(chunk.len());
+    }
+    abort(io).await
+}
+
+/// Read `length` bytes starting at `address`, in blocks of `transfer_size`.
+#[tracing::instrument(skip_all, fields(address = format_args!("{address:#010X}"), length))]
+pub async fn upload<IO>(
+    io: &IO,
+    address: u32,
+    length: usize,
+    transfer_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<u8>, IO::Error>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+{
+    ensure_idle(io).await?;
+    set_address(io, address).await?;
+    abort(io).await?;
+
+    let mut data = Vec::with_capacity(length);
+    let mut buffer = vec![0u8; transfer_size];
+    let mut block = FIRST_DATA_BLOCK;
+    while data.len() < length {
+        let n = io
+            .read_control(REQUEST_TYPE, DFU_UPLOAD, block, &mut buffer)
+            .await?;
+        let n = n.min(length - data.len());
+        data.extend_from_slice(&buffer[..n]);
+        progress(n);
+        if n < transfer_size && data.len() < length {
+            break;
+        }
+        block = block.wrapping_add(1);
+    }
+    abort(io).await?;
+    Ok(data)
+}
@@ -0,0 +1,81 @@
+//! The steps of [`crate::transfer`] over a [`DfuAsyncIo`].
+//!
+//! `progress` callbacks get the number of bytes transferred since the last
+//! call.
+
+use dfu_core::asynchronous::DfuAsyncIo;
+
+use super::dfuse;
+use crate::BikesafeError;
+use crate::transfer::first_difference;
+
+/// Erase the pages that will hold `data` at `address`. `progress` gets the
+/// size of each erased page, which may add up to more than `data.len()`.
+pub async fn erase<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    mut progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    if data.is_empty() {
+        return Ok(());
+    }
+    dfuse::erase(io, address, data.len() as u32, |page| {
+        progress(page as usize)
+    })
+    .await
+    .map_err(Into::into)
+}
+
+/// Write `data` to already erased pages at `address`.
+pub async fn download<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    dfuse::download(io, address, data, transfer_size, progress)
+        .await
+        .map_err(Into::into)
+}
+
+/// Read `data.len()` bytes back from `address` and fail on the first byte
+/// that differs from `data`.
+pub async fn verify<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuAsyncIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    let read_back = dfuse::upload(io, address, data.len(), transfer_size, progress)
+        .await
+        .map_err(Into::into)?;
+    if let Some(offset) = first_difference(data, &read_back) {
+        return Err(BikesafeError::VerifyFailed {
+            address: address + offset as u32,
+        });
+    }
+    Ok(())
+}
+
+/// Fail unless the device can read its memory back.
+pub fn ensure_upload<IO: DfuAsyncIo>(io: &IO) -> Result<(), BikesafeError> {
+    if !io.functional_descriptor().can_upload {
+        return Err(BikesafeError::UploadNotSupported);
+    }
+    Ok(())
+}
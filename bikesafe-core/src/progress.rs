@@ -0,0 +1,208 @@
+//! Progress reporting shared by the front-ends. Operations report to a
+//! [`ProgressSink`]: the phase they are in, how many of its bytes are done,
+//! messages for the user, and that they finished. The CLI shows it as a
+//! progress bar, the GUI in its window, and [`JsonLines`] writes it for
+//! scripts, so each front-end reports every operation the same way.
+//!
+//! The [`transfer`](crate::transfer) and [`dfuse`](crate::dfuse) steps
+//! report the bytes transferred since their last call; a [`Counter`] turns
+//! those into a phase's progress.
+
+use std::fmt;
+use std::io::Write;
+
+/// Step of an operation, with bytes to count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Erasing the flash pages an image needs.
+    Erase,
+    /// Writing an image to the device.
+    Download,
+    /// Reading memory from the device.
+    Upload,
+    /// Reading an image back and comparing it.
+    Verify,
+}
+
+impl Phase {
+    /// Lower-case name, as shown and logged.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Erase => "erase",
+            Phase::Download => "download",
+            Phase::Upload => "upload",
+            Phase::Verify => "verify",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where an operation reports how far it got.
+pub trait ProgressSink {
+    /// `phase` starts, covering `total` bytes.
+    fn phase(&mut self, phase: Phase, total: u64);
+
+    /// `done` of the current phase's `total` bytes are through.
+    fn bytes(&mut self, done: u64, total: u64);
+
+    /// A line for the user, such as where a resumed write continues.
+    fn message(&mut self, message: &str);
+
+    /// The operation completed.
+    fn finished(&mut self);
+}
+
+/// Reports nothing.
+impl ProgressSink for () {
+    fn phase(&mut self, _: Phase, _: u64) {}
+
+    fn bytes(&mut self, _: u64, _: u64) {}
+
+    fn message(&mut self, _: &str) {}
+
+    fn finished(&mut self) {}
+}
+
+impl<S: ProgressSink + ?Sized> ProgressSink for &mut S {
+    fn phase(&mut self, phase: Phase, total: u64) {
+        (**self).phase(phase, total);
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        (**self).bytes(done, total);
+    }
+
+    fn message(&mut self, message: &str) {
+        (**self).message(message);
+    }
+
+    fn finished(&mut self) {
+        (**self).finished();
+    }
+}
+
+/// One phase in progress, counting the bytes the transfer steps report.
+///
+/// ```
+/// use bikesafe_core::progress::{Counter, Phase};
+///
+/// let mut sink = ();
+/// let mut write = Counter::new(&mut sink, Phase::Download, 4096);
+/// for _ in 0..2 {
+///     write.advance(2048);
+/// }
+/// assert_eq!(write.done(), 4096);
+/// ```
+pub struct Counter<'a, S: ProgressSink + ?Sized = dyn ProgressSink> {
+    sink: &'a mut S,
+    done: u64,
+    total: u64,
+}
+
+impl<'a, S: ProgressSink + ?Sized> Counter<'a, S> {
+    /// Start `phase` of `total` bytes on `sink`.
+    pub fn new(sink: &'a mut S, phase: Phase, total: u64) -> Self {
+        sink.phase(phase, total);
+        Self {
+            sink,
+            done: 0,
+            total,
+        }
+    }
+
+    /// Count `count` more bytes. Erasing whole pages may count more than
+    /// the phase's total, which is where it stops.
+    pub fn advance(&mut self, count: usize) {
+        self.done = (self.done + count as u64).min(self.total);
+        self.sink.bytes(self.done, self.total);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+}
+
+/// Writes progress as one JSON object per line:
+///
+/// ```text
+/// {"phase":"download","done":0,"total":49152}
+/// {"phase":"download","done":24576,"total":49152}
+/// {"message":"Verified 49152 bytes"}
+/// {"finished":true}
+/// ```
+///
+/// Bytes are written when the phase's whole percentage changes, so a phase
+/// takes at most 101 lines however small the transfer blocks are. Write
+/// errors are ignored: progress is not worth failing an update over.
+pub struct JsonLines<W> {
+    writer: W,
+    phase: Option<Phase>,
+    percent: Option<u64>,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            phase: None,
+            percent: None,
+        }
+    }
+
+    fn line(&mut self, json: fmt::Arguments) {
+        let _ = writeln!(self.writer, "{json}");
+        let _ = self.writer.flush();
+    }
+}
+
+impl<W: Write> ProgressSink for JsonLines<W> {
+    fn phase(&mut self, phase: Phase, total: u64) {
+        self.phase = Some(phase);
+        self.percent = None;
+        self.bytes(0, total);
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        let percent = (done * 100).checked_div(total).unwrap_or(100);
+        if self.percent.replace(percent) == Some(percent) {
+            return;
+        }
+        let phase = self.phase.map_or("", Phase::name);
+        self.line(format_args!(
+            r#"{{"phase":"{phase}","done":{done},"total":{total}}}"#
+        ));
+    }
+
+    fn message(&mut self, message: &str) {
+        self.line(format_args!(r#"{{"message":"{}"}}"#, JsonEscaped(message)));
+    }
+
+    fn finished(&mut self) {
+        self.line(format_args!(r#"{{"finished":true}}"#));
+    }
+}
+
+/// A string's contents as a JSON string literal's.
+struct JsonEscaped<'a>(&'a str);
+
+impl fmt::Display for JsonEscaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str(r#"\""#)?,
+                '\\' => f.write_str(r"\\")?,
+                '\n' => f.write_str(r"\n")?,
+                '\r' => f.write_str(r"\r")?,
+                '\t' => f.write_str(r"\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Erasing, writing and reading back images with raw DfuSe requests.
+//! `dfu-core` leaves DFU mode right after the last block, which would make
+//! reading the image back impossible.
+//!
+//! `progress` callbacks get the number of bytes transferred since the last
+//! call.
+
+use dfu_core::DfuIo;
+
+use crate::{BikesafeError, dfuse};
+
+/// Erase the pages that will hold `data` at `address`. `progress` gets the
+/// size of each erased page, which may add up to more than `data.len()`.
+pub fn erase<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    mut progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    if data.is_empty() {
+        return Ok(());
+    }
+    dfuse::erase(io, address, data.len() as u32, |page| {
+        progress(page as usize)
+    })
+    .map_err(Into::into)
+}
+
+/// Write `data` to already erased pages at `address`.
+pub fn download<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    dfuse::download(io, address, data, transfer_size, progress).map_err(Into::into)
+}
+
+/// Read `data.len()` bytes back from `address` and fail on the first byte
+/// that differs from `data`.
+#[tracing::instrument(
+    skip_all,
+    fields(address = format_args!("{address:#010X}"), length = data.len())
+)]
+pub fn verify<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    progress: impl FnMut(usize),
+) -> Result<(), BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    if let Some(offset) = compare(io, address, data, progress)? {
+        return Err(BikesafeError::VerifyFailed {
+            address: address + offset as u32,
+        });
+    }
+    Ok(())
+}
+
+/// Read `data.len()` bytes from `address` and return the offset of the
+/// first byte differing from `data`.
+pub fn compare<IO>(
+    io: &IO,
+    address: u32,
+    data: &[u8],
+    progress: impl FnMut(usize),
+) -> Result<Option<usize>, BikesafeError>
+where
+    IO: DfuIo<Read = usize, Write = usize>,
+    IO::Error: Into<BikesafeError>,
+{
+    let transfer_size = io.functional_descriptor().transfer_size as usize;
+    let read_back =
+        dfuse::upload(io, address, data.len(), transfer_size, progress).map_err(Into::into)?;
+    Ok(first_difference(data, &read_back))
+}
+
+/// Offset of the first byte of `read_back` differing from `data`, counting
+/// missing bytes as different.
+pub fn first_difference(data: &[u8], read_back: &[u8]) -> Option<usize> {
+    data.iter()
+        .zip(read_back)
+        .position(|(a, b)| a != b)
+        .or((read_back.len() < data.len()).then_some(read_back.len()))
+}
+
+/// Fail unless the device can read its memory back.
+pub fn ensure_upload<IO: DfuIo>(io: &IO) -> Result<(), BikesafeError> {
+    if !io.functional_descriptor().can_upload {
+        return Err(BikesafeError::UploadNotSupported);
+    }
+    Ok(())
+}
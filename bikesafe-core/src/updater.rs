@@ -0,0 +1,166 @@
+//! The update steps every front-end goes through, on one locked device.
+
+use dfu_core::DfuIo;
+use dfu_libusb::{DfuLibusb, Error};
+
+use crate::cancel::{Cancel, Cancellable};
+use crate::device::{Device, PROTOCOL_DFU};
+use crate::family::{self, Family, MemoryMap};
+use crate::progress::{Counter, Phase, ProgressSink};
+use crate::{BikesafeError, dfuse, transfer};
+
+/// A DFU device held for an update: find it, check the image, write it,
+/// read it back and start it.
+///
+/// ```no_run
+/// # fn main() -> Result<(), bikesafe_core::BikesafeError> {
+/// use bikesafe_core::FirmwareUpdater;
+///
+/// let firmware = bikesafe_core::read_firmware("firmware.bin".as_ref())?;
+/// let updater = FirmwareUpdater::find_device(0x1209, 0x2444)?;
+/// updater.validate(&firmware)?;
+/// updater.flash(&firmware, &mut ())?;
+/// updater.verify(&firmware, &mut ())?;
+/// updater.reset()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FirmwareUpdater {
+    device: Device,
+    family: &'static Family,
+    memory: &'static MemoryMap,
+    address: u32,
+    cancel: Cancel,
+    _lock: Option<device_lock::DeviceLock>,
+}
+
+impl FirmwareUpdater {
+    /// Take the first `vid:pid` device in DFU mode, interface 0 and
+    /// alternate setting 0.
+    pub fn find_device(vid: u16, pid: u16) -> Result<Self, BikesafeError> {
+        let device = Device {
+            context: rusb::Context::new()?,
+            vid,
+            pid,
+            intf: 0,
+            alt: 0,
+            port: None,
+        };
+        let found = device
+            .all()?
+            .into_iter()
+            .next()
+            .ok_or(BikesafeError::DeviceNotFound { vid, pid })?;
+        Self::new(found)
+    }
+
+    /// Use `device`, locking it against other flashers until the updater
+    /// is dropped. Images are checked against and written to the flash of
+    /// the device's memory map.
+    pub fn new(device: Device) -> Result<Self, BikesafeError> {
+        let lock = device.lock()?;
+        let family = family::find(device.vid, device.pid).unwrap_or(&family::BRAKEBRIGHT);
+        let memory = family.memory_map((device.vid, device.pid), None);
+        Ok(Self {
+            device,
+            family,
+            memory,
+            address: memory.flash.origin,
+            cancel: Cancel::new(),
+            _lock: lock,
+        })
+    }
+
+    /// Write images to `address` instead of the start of the application
+    /// flash.
+    pub fn with_address(mut self, address: u32) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Stop [`flash`](Self::flash) and [`verify`](Self::verify) with
+    /// [`BikesafeError::Cancelled`] once `cancel` is set.
+    pub fn with_cancel(mut self, cancel: Cancel) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn family(&self) -> &'static Family {
+        self.family
+    }
+
+    pub fn memory_map(&self) -> &'static MemoryMap {
+        self.memory
+    }
+
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Whether the device is (still) connected in DFU mode.
+    pub fn is_connected(&self) -> bool {
+        self.device.usb_device().is_ok_and(|usb| {
+            crate::device::dfu_interface(&usb).is_some_and(|(_, protocol)| protocol == PROTOCOL_DFU)
+        })
+    }
+
+    /// Check that `firmware` is an application image for the device.
+    pub fn validate(&self, firmware: &[u8]) -> Result<(), BikesafeError> {
+        Ok(crate::validate_at(self.memory, self.address, firmware)?)
+    }
+
+    /// Open the device for a step that stops once cancelled.
+    pub(crate) fn open(&self) -> Result<Cancellable<DfuLibusb<rusb::Context>>, BikesafeError> {
+        Ok(Cancellable::new(
+            self.device.open()?.into_inner(),
+            self.cancel.clone(),
+        ))
+    }
+
+    /// Erase the pages `firmware` needs and write it, staying in DFU mode.
+    pub fn flash(
+        &self,
+        firmware: &[u8],
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(), BikesafeError> {
+        let io = self.open()?;
+        let total = firmware.len() as u64;
+        let mut erase = Counter::new(&mut *progress, Phase::Erase, total);
+        transfer::erase(&io, self.address, firmware, |n| erase.advance(n))?;
+        let mut write = Counter::new(progress, Phase::Download, total);
+        transfer::download(&io, self.address, firmware, |n| write.advance(n))
+    }
+
+    /// Read the image back and compare it with `firmware`.
+    pub fn verify(
+        &self,
+        firmware: &[u8],
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(), BikesafeError> {
+        let io = self.open()?;
+        transfer::ensure_upload(&io)?;
+        let mut verify = Counter::new(progress, Phase::Verify, firmware.len() as u64);
+        transfer::verify(&io, self.address, firmware, |n| verify.advance(n))
+    }
+
+    /// Whether the device can read its memory back for
+    /// [`verify`](Self::verify).
+    pub fn can_verify(&self) -> Result<bool, BikesafeError> {
+        let io = self.device.open()?.into_inner();
+        Ok(io.functional_descriptor().can_upload)
+    }
+
+    /// Leave DFU mode and start the application.
+    pub fn reset(&self) -> Result<(), BikesafeError> {
+        let io = self.device.open()?.into_inner();
+        match dfuse::leave(&io, self.address) {
+            // The device may drop off the bus before answering.
+            Ok(()) | Err(Error::LibUsb(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! The browser's WebUSB as a [`DfuAsyncIo`], so the [`nonblocking`]
+//! steps update a device from a web page. The user picks the device in the
+//! browser's chooser; [`WebUsbDfu::request`] then opens its DFU interface
+//! the way `dfu-libusb` does, reading the functional descriptor from the
+//! configuration and the DfuSe memory layout from the interface name.
+//!
+//! JavaScript values cannot leave the thread they were made on, while
+//! `DfuAsyncIo` asks for `Send` futures. A wasm32 page runs on one thread,
+//! so the device and the futures are wrapped in a [`SendWrapper`].
+//!
+//! ```ignore
+//! use bikesafe_core::family::BRAKEBRIGHT;
+//! use bikesafe_core::nonblocking::transfer;
+//! use bikesafe_core::webusb::WebUsbDfu;
+//!
+//! let io = WebUsbDfu::request(BRAKEBRIGHT.dfu_ids).await?;
+//! let address = BRAKEBRIGHT.memory_map(io.id(), None).flash.origin;
+//! transfer::erase(&io, address, &firmware, |_| ()).await?;
+//! transfer::download(&io, address, &firmware, |_| ()).await?;
+//! ```
+//!
+//! [`nonblocking`]: crate::nonblocking
+
+use std::time::Duration;
+
+use dfu_core::DfuProtocol;
+use dfu_core::asynchronous::DfuAsyncIo;
+use dfu_core::functional_descriptor::FunctionalDescriptor;
+use dfu_core::memory_layout::MemoryLayout;
+use js_sys::{Array, Promise, Uint8Array};
+use send_wrapper::SendWrapper;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    DomException, UsbAlternateInterface, UsbConfiguration, UsbControlTransferParameters, UsbDevice,
+    UsbDeviceFilter, UsbDeviceRequestOptions, UsbInTransferResult, UsbInterface,
+    UsbOutTransferResult, UsbRecipient, UsbRequestType, UsbTransferStatus,
+};
+
+use crate::{BikesafeError, dfuse};
+
+/// Interface class/subclass of DFU interfaces.
+const DFU_CLASS: (u8, u8) = (0xFE, 0x01);
+/// Descriptor type of the DFU functional descriptor.
+const FUNCTIONAL_DESCRIPTOR: u8 = 0x21;
+const GET_DESCRIPTOR: u8 = 0x06;
+const CONFIGURATION_DESCRIPTOR: u16 = 0x0200;
+
+/// What the browser reported for a failed WebUSB call.
+#[derive(Debug, thiserror::Error)]
+#[error("WebUSB: {0}")]
+pub struct WebUsbError(String);
+
+/// Whether the browser has WebUSB: Chromium-based ones do, on pages served
+/// over HTTPS or from localhost.
+pub fn is_supported() -> bool {
+    web_sys::window().is_some_and(|window| {
+        js_sys::Reflect::has(&window.navigator(), &JsValue::from_str("usb")).unwrap_or(false)
+    })
+}
+
+/// An opened device in DFU mode, its DFU interface claimed with alternate
+/// setting 0.
+pub struct WebUsbDfu {
+    device: SendWrapper<UsbDevice>,
+    interface: u8,
+    protocol: DfuProtocol<MemoryLayout>,
+    functional_descriptor: FunctionalDescriptor,
+}
+
+impl WebUsbDfu {
+    /// Show the browser's chooser for devices with one of the VID:PIDs
+    /// `ids`, such as a family's `dfu_ids`, and open the one picked.
+    /// Closing the chooser fails with [`BikesafeError::Cancelled`].
+    pub async fn request(ids: &[(u16, u16)]) -> Result<Self, BikesafeError> {
+        let filters: Array = ids
+            .iter()
+            .map(|&(vid, pid)| {
+                let filter = UsbDeviceFilter::new();
+                filter.set_vendor_id(vid);
+                filter.set_product_id(pid);
+                filter
+            })
+            .collect();
+        let usb = web_sys::window()
+            .ok_or_else(|| backend("not running in a browser window"))?
+            .navigator()
+            .usb();
+        let device = JsFuture::from(usb.request_device(&UsbDeviceRequestOptions::new(&filters)))
+            .await
+            .map_err(|error| match dom_error(&error).as_deref() {
+                // No device chosen.
+                Some("NotFoundError") => BikesafeError::Cancelled,
+                _ => js_error(error),
+            })?;
+        Self::open(device.unchecked_into()).await
+    }
+
+    /// Open `device`, e.g. one of the paired devices of
+    /// `navigator.usb.getDevices()`, and claim its first DFU interface.
+    pub async fn open(device: UsbDevice) -> Result<Self, BikesafeError> {
+        JsFuture::from(device.open()).await.map_err(js_error)?;
+        if device.configuration().is_none() {
+            JsFuture::from(device.select_configuration(1))
+                .await
+                .map_err(js_error)?;
+        }
+        let configuration: UsbConfiguration = device
+            .configuration()
+            .ok_or_else(|| backend("device has no configuration"))?;
+        let (interface, alternate) = configuration
+            .interfaces()
+            .iter()
+            .map(JsCast::unchecked_into::<UsbInterface>)
+            .find_map(|interface| {
+                let alternate = interface
+                    .alternates()
+                    .iter()
+                    .map(JsCast::unchecked_into::<UsbAlternateInterface>)
+                    .find(|alternate| {
+                        alternate.alternate_setting() == 0
+                            && (alternate.interface_class(), alternate.interface_subclass())
+                                == DFU_CLASS
+                    })?;
+                Some((interface.interface_number(), alternate))
+            })
+            .ok_or_else(|| backend("device has no DFU interface"))?;
+        JsFuture::from(device.claim_interface(interface))
+            .await
+            .map_err(js_error)?;
+        JsFuture::from(device.select_alternate_interface(interface, 0))
+            .await
+            .map_err(js_error)?;
+
+        // WebUSB only describes the standard descriptors, so read the
+        // configuration descriptor for the functional one.
+        let mut header = [0; 9];
+        let read = control_in(
+            &device,
+            0x80,
+            GET_DESCRIPTOR,
+            CONFIGURATION_DESCRIPTOR,
+            0,
+            &mut header,
+        )
+        .await?;
+        let total = match header.get(2..4).filter(|_| read >= 4) {
+            Some(total) => u16::from_le_bytes([total[0], total[1]]),
+            None => return Err(backend("short configuration descriptor")),
+        };
+        let mut config = vec![0; total as usize];
+        let read = control_in(
+            &device,
+            0x80,
+            GET_DESCRIPTOR,
+            CONFIGURATION_DESCRIPTOR,
+            0,
+            &mut config,
+        )
+        .await?;
+        let functional_descriptor = find_functional_descriptor(&config[..read])
+            .ok_or_else(|| backend("device has no DFU functional descriptor"))?
+            .map_err(|e| BikesafeError::Backend(Box::new(e)))?;
+        dfuse::check_transfer_size(&functional_descriptor)?;
+        let protocol = DfuProtocol::new(
+            &alternate.interface_name().unwrap_or_default(),
+            functional_descriptor.dfu_version,
+        )?;
+        Ok(Self {
+            device: SendWrapper::new(device),
+            interface,
+            protocol,
+            functional_descriptor,
+        })
+    }
+
+    /// VID:PID of the device, to pick its family and memory map.
+    pub fn id(&self) -> (u16, u16) {
+        (self.device.vendor_id(), self.device.product_id())
+    }
+
+    /// Release the device for another page or a later [`open`](Self::open).
+    pub async fn close(self) {
+        let _ = JsFuture::from(self.device.close()).await;
+    }
+}
+
+impl DfuAsyncIo for WebUsbDfu {
+    type Read = usize;
+    type Write = usize;
+    type Reset = ();
+    type Error = BikesafeError;
+    type MemoryLayout = MemoryLayout;
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<Self::Read, Self::Error>> + Send {
+        SendWrapper::new(control_in(
+            &self.device,
+            request_type,
+            request,
+            value,
+            self.interface as u16,
+            buffer,
+        ))
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        buffer: &[u8],
+    ) -> impl Future<Output = Result<Self::Write, Self::Error>> + Send {
+        let setup = setup(request_type, request, value, self.interface as u16);
+        SendWrapper::new(async move {
+            let promise = self
+                .device
+                .control_transfer_out_with_u8_array(&setup, &Uint8Array::from(buffer))
+                .map_err(js_error)?;
+            let result: UsbOutTransferResult = JsFuture::from(promise)
+                .await
+                .map_err(js_error)?
+                .unchecked_into();
+            check_status(result.status())?;
+            Ok(result.bytes_written() as usize)
+        })
+    }
+
+    fn usb_reset(&self) -> impl Future<Output = Result<Self::Reset, Self::Error>> + Send {
+        SendWrapper::new(async move {
+            JsFuture::from(self.device.reset())
+                .await
+                .map_err(js_error)?;
+            Ok(())
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        SendWrapper::new(async move {
+            let timeout = Promise::new(&mut |resolve, _| {
+                if let Some(window) = web_sys::window() {
+                    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                        &resolve,
+                        duration.as_millis().try_into().unwrap_or(i32::MAX),
+                    );
+                }
+            });
+            let _ = JsFuture::from(timeout).await;
+        })
+    }
+
+    fn protocol(&self) -> &DfuProtocol<Self::MemoryLayout> {
+        &self.protocol
+    }
+
+    fn functional_descriptor(&self) -> &FunctionalDescriptor {
+        &self.functional_descriptor
+    }
+}
+
+/// The WebUSB form of a control request's `bmRequestType`, `bRequest`,
+/// `wValue` and `wIndex`.
+fn setup(request_type: u8, request: u8, value: u16, index: u16) -> UsbControlTransferParameters {
+    let kind = match (request_type >> 5) & 0b11 {
+        0 => UsbRequestType::Standard,
+        1 => UsbRequestType::Class,
+        _ => UsbRequestType::Vendor,
+    };
+    let recipient = match request_type & 0b1_1111 {
+        0 => UsbRecipient::Device,
+        1 => UsbRecipient::Interface,
+        2 => UsbRecipient::Endpoint,
+        _ => UsbRecipient::Other,
+    };
+    UsbControlTransferParameters::new(index, recipient, request, kind, value)
+}
+
+/// Read up to `buffer.len()` bytes with a control request, returning how
+/// many came.
+async fn control_in(
+    device: &UsbDevice,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+) -> Result<usize, BikesafeError> {
+    let length = buffer.len().try_into().unwrap_or(u16::MAX);
+    let promise = device.control_transfer_in(&setup(request_type, request, value, index), length);
+    let result: UsbInTransferResult = JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .unchecked_into();
+    check_status(result.status())?;
+    let Some(data) = result.data() else {
+        return Ok(0);
+    };
+    let data = Uint8Array::new_with_byte_offset_and_length(
+        &data.buffer(),
+        data.byte_offset() as u32,
+        data.byte_length() as u32,
+    );
+    let read = (data.length() as usize).min(buffer.len());
+    data.subarray(0, read as u32).copy_to(&mut buffer[..read]);
+    Ok(read)
+}
+
+fn check_status(status: UsbTransferStatus) -> Result<(), BikesafeError> {
+    match status {
+        UsbTransferStatus::Ok => Ok(()),
+        UsbTransferStatus::Stall => Err(backend("control transfer stalled")),
+        _ => Err(backend("control transfer failed")),
+    }
+}
+
+/// The first DFU functional descriptor in the configuration descriptor
+/// `config`.
+fn find_functional_descriptor(
+    mut config: &[u8],
+) -> Option<Result<FunctionalDescriptor, dfu_core::functional_descriptor::Error>> {
+    while let [length, kind, ..] = *config {
+        if length < 2 {
+            break;
+        }
+        let length = (length as usize).min(config.len());
+        if kind == FUNCTIONAL_DESCRIPTOR {
+            return FunctionalDescriptor::from_bytes(&config[..length]);
+        }
+        config = &config[length..];
+    }
+    None
+}
+
+/// The `name` of a `DOMException`, which tells WebUSB's errors apart.
+fn dom_error(error: &JsValue) -> Option<String> {
+    error.dyn_ref::<DomException>().map(DomException::name)
+}
+
+fn js_error(error: JsValue) -> BikesafeError {
+    match dom_error(&error).as_deref() {
+        Some("NotFoundError") => BikesafeError::Disconnected,
+        Some("SecurityError" | "NotAllowedError") => BikesafeError::PermissionDenied,
+        _ => {
+            let message = match error.dyn_ref::<DomException>() {
+                Some(exception) => exception.message(),
+                None => format!("{error:?}"),
+            };
+            backend(&message)
+        }
+    }
+}
+
+fn backend(message: &str) -> BikesafeError {
+    BikesafeError::Backend(Box::new(WebUsbError(message.to_owned())))
+}
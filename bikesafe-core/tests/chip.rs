@@ -0,0 +1,56 @@
+//! Reading the chip identification from [`MockDfu`]'s system memory.
+
+use bikesafe_core::family::BRAKEBRIGHT;
+use bikesafe_core::mock::MockDfu;
+use bikesafe_core::{BikesafeError, read_chip_id};
+
+const LAYOUT: &str = "@Internal Flash  /0x08000000/16*1Ka,48*1Kg";
+
+#[test]
+fn reads_unique_id_and_flash_size() {
+    let memory = &BRAKEBRIGHT.memory;
+    let uid = [
+        0x34, 0xFF, 0xD8, 0x05, 0x4E, 0x50, 0x35, 0x31, 0x18, 0x59, 0x17, 0x43,
+    ];
+    let io = MockDfu::dfuse(LAYOUT)
+        .unwrap()
+        .with_system_memory(memory.flash_size_register, &64u16.to_le_bytes())
+        .with_system_memory(memory.unique_id, &uid);
+
+    let chip = read_chip_id(&io, memory).unwrap();
+    assert_eq!(chip.uid, uid);
+    assert_eq!(chip.flash_size, 64 * 1024);
+    assert_eq!(chip.to_string(), "431759183135504E05D8FF34");
+    assert_eq!(chip.flash_end(memory), 0x0801_0000);
+    let hardware = chip.hardware(0x1209, 0x2444).unwrap();
+    assert_eq!(hardware.memory, *memory);
+}
+
+#[test]
+fn unknown_flash_size_matches_no_hardware() {
+    let memory = &BRAKEBRIGHT.memory;
+    let io = MockDfu::dfuse(LAYOUT)
+        .unwrap()
+        .with_system_memory(memory.flash_size_register, &128u16.to_le_bytes())
+        .with_system_memory(memory.unique_id, &[0; 12]);
+
+    let chip = read_chip_id(&io, memory).unwrap();
+    assert_eq!(chip.flash_size, 128 * 1024);
+    assert!(chip.hardware(0x1209, 0x2444).is_none());
+}
+
+#[test]
+fn fails_without_system_memory() {
+    let io = MockDfu::dfuse(LAYOUT).unwrap();
+    assert!(read_chip_id(&io, &BRAKEBRIGHT.memory).is_err());
+}
+
+#[test]
+fn refuses_plain_dfu_devices() {
+    let io = MockDfu::plain();
+    assert!(matches!(
+        read_chip_id(&io, &BRAKEBRIGHT.memory),
+        Err(BikesafeError::DfuseNotSupported)
+    ));
+    assert!(io.transfers().is_empty());
+}
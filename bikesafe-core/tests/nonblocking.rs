@@ -0,0 +1,88 @@
+//! The async transfer steps against [`MockDfu`], directly and through the
+//! thread pool.
+
+use bikesafe_core::mock::MockDfu;
+use bikesafe_core::nonblocking::{AsyncFirmwareUpdater, Blocking, dfuse, transfer};
+use bikesafe_core::progress::JsonLines;
+use bikesafe_core::{APPLICATION_ADDRESS, BikesafeError};
+use dfu_core::State;
+use futures_lite::future::block_on;
+
+const LAYOUT: &str = "@Internal Flash  /0x08000000/16*1Ka,48*1Kg";
+
+fn device() -> MockDfu {
+    MockDfu::dfuse(LAYOUT).unwrap()
+}
+
+fn firmware(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn flash_verify_and_leave() {
+    let io = device();
+    let data = firmware(5000);
+    let mut erased = 0;
+    let mut written = 0;
+    block_on(async {
+        transfer::erase(&io, APPLICATION_ADDRESS, &data, |n| erased += n).await?;
+        transfer::download(&io, APPLICATION_ADDRESS, &data, |n| written += n).await?;
+        transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ()).await?;
+        // The device resets while answering the final GETSTATUS.
+        assert!(dfuse::leave(&io, APPLICATION_ADDRESS).await.is_err());
+        Ok::<_, BikesafeError>(())
+    })
+    .unwrap();
+    assert_eq!((erased, written), (5 * 1024, data.len()));
+    assert_eq!(io.read(APPLICATION_ADDRESS, data.len()), data);
+    assert_eq!(io.started(), Some(APPLICATION_ADDRESS));
+}
+
+#[test]
+fn same_requests_as_the_blocking_steps() {
+    let data = firmware(3000);
+    let sync = device();
+    bikesafe_core::transfer::erase(&sync, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+    bikesafe_core::transfer::download(&sync, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+    let io = device();
+    block_on(async {
+        transfer::erase(&io, APPLICATION_ADDRESS, &data, |_| ()).await?;
+        transfer::download(&io, APPLICATION_ADDRESS, &data, |_| ()).await
+    })
+    .unwrap();
+    assert_eq!(io.transfers(), sync.transfers());
+}
+
+#[test]
+fn verify_reports_the_first_difference() {
+    let mut data = firmware(3000);
+    let io = device().with_flash(APPLICATION_ADDRESS, &data);
+    data[2500] ^= 1;
+    assert!(matches!(
+        block_on(transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ())),
+        Err(BikesafeError::VerifyFailed { address }) if address == APPLICATION_ADDRESS + 2500
+    ));
+}
+
+#[test]
+fn blocking_io_runs_on_the_pool() {
+    let data = firmware(5000);
+    let io = Blocking::new(device());
+    block_on(async {
+        transfer::erase(&io, APPLICATION_ADDRESS, &data, |_| ()).await?;
+        transfer::download(&io, APPLICATION_ADDRESS, &data, |_| ()).await?;
+        transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ()).await?;
+        assert_eq!(dfuse::get_status(&io).await?.state, State::DfuIdle);
+        Ok::<_, BikesafeError>(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn updater_steps_are_send() {
+    fn send<T: Send>(_: T) {}
+    let _ = |updater: &AsyncFirmwareUpdater, progress: &mut JsonLines<Vec<u8>>| {
+        send(updater.update(&[], progress));
+        send(updater.verify(&[], progress));
+    };
+}
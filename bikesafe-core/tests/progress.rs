@@ -0,0 +1,46 @@
+//! Progress as the JSON sink writes it.
+
+use bikesafe_core::progress::{Counter, JsonLines, Phase};
+use bikesafe_core::{ProgressSink, transfer};
+
+fn lines(output: &[u8]) -> Vec<&str> {
+    std::str::from_utf8(output).unwrap().lines().collect()
+}
+
+#[test]
+fn json_lines_per_percent() {
+    let mut output = Vec::new();
+    let mut json = JsonLines::new(&mut output);
+    let mut write = Counter::new(&mut json, Phase::Download, 400);
+    for _ in 0..400 {
+        write.advance(1);
+    }
+    json.message("Verified \"firmware.bin\"\n");
+    json.finished();
+
+    let lines = lines(&output);
+    assert_eq!(lines.len(), 1 + 100 + 2);
+    assert_eq!(lines[0], r#"{"phase":"download","done":0,"total":400}"#);
+    assert_eq!(lines[1], r#"{"phase":"download","done":4,"total":400}"#);
+    assert_eq!(lines[100], r#"{"phase":"download","done":400,"total":400}"#);
+    assert_eq!(lines[101], r#"{"message":"Verified \"firmware.bin\"\n"}"#);
+    assert_eq!(lines[102], r#"{"finished":true}"#);
+}
+
+#[test]
+fn erased_pages_count_up_to_the_total() {
+    let io = bikesafe_core::mock::MockDfu::dfuse("@Internal Flash  /0x08000000/64*1Kg").unwrap();
+    let mut output = Vec::new();
+    let mut json = JsonLines::new(&mut output);
+    let mut erase = Counter::new(&mut json, Phase::Erase, 1500);
+    transfer::erase(&io, 0x0800_0200, &[0; 1500], |n| erase.advance(n)).unwrap();
+    assert_eq!(erase.done(), 1500);
+    assert_eq!(
+        lines(&output),
+        [
+            r#"{"phase":"erase","done":0,"total":1500}"#,
+            r#"{"phase":"erase","done":1024,"total":1500}"#,
+            r#"{"phase":"erase","done":1500,"total":1500}"#,
+        ]
+    );
+}
@@ -0,0 +1,210 @@
+//! The transfer steps against [`MockDfu`], a simulated STM32F1 bootloader.
+
+use bikesafe_core::cancel::{Cancel, Cancellable};
+use bikesafe_core::mock::{DFU_ABORT, DFU_CLRSTATUS, DFU_DNLOAD, DFU_GETSTATUS, MockDfu};
+use bikesafe_core::{APPLICATION_ADDRESS, BikesafeError, dfuse, transfer};
+use dfu_core::{DfuIo, State, Status};
+
+const LAYOUT: &str = "@Internal Flash  /0x08000000/16*1Ka,48*1Kg";
+
+fn device() -> MockDfu {
+    MockDfu::dfuse(LAYOUT).unwrap()
+}
+
+fn firmware(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+fn flash(io: &MockDfu, data: &[u8]) -> Result<(), BikesafeError> {
+    transfer::erase(io, APPLICATION_ADDRESS, data, |_| ())?;
+    transfer::download(io, APPLICATION_ADDRESS, data, |_| ())
+}
+
+#[test]
+fn flash_and_verify() {
+    let io = device();
+    let data = firmware(5000);
+    let mut written = 0;
+    transfer::erase(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+    transfer::download(&io, APPLICATION_ADDRESS, &data, |n| written += n).unwrap();
+    assert_eq!(written, data.len());
+    assert_eq!(io.read(APPLICATION_ADDRESS, data.len()), data);
+    transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+    assert_eq!(io.state(), State::DfuIdle);
+
+    // Three blocks of at most 2 KiB, each at wBlockNum 2 after its own
+    // address.
+    let writes = io.writes();
+    assert_eq!(
+        writes.iter().map(|(_, d)| d.len()).collect::<Vec<_>>(),
+        [2048, 2048, 904]
+    );
+    assert!(writes.iter().all(|&(block, _)| block == 2));
+}
+
+#[test]
+fn cancel_stops_at_the_next_block() {
+    let cancel = Cancel::new();
+    let io = Cancellable::new(device(), cancel.clone());
+    let data = firmware(5000);
+    transfer::erase(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+    let mut written = 0;
+    let error = transfer::download(&io, APPLICATION_ADDRESS, &data, |n| {
+        written += n;
+        cancel.cancel();
+    })
+    .unwrap_err();
+    assert!(matches!(error, BikesafeError::Cancelled));
+    assert_eq!(written, 2048);
+
+    // The device stays in DFU mode and takes the image again.
+    let io = io.into_inner();
+    assert_eq!(io.writes().len(), 1);
+    flash(&io, &data).unwrap();
+    assert_eq!(io.read(APPLICATION_ADDRESS, data.len()), data);
+}
+
+#[test]
+fn erase_only_touches_the_image_pages() {
+    let io = device().with_flash(0x0800_0000, &[0x42; 0x4000]);
+    let data = firmware(1500);
+    flash(&io, &data).unwrap();
+    // The bootloader pages stay, and the second 1 KiB page is erased
+    // beyond the image.
+    assert_eq!(io.read(0x0800_0000, 0x4000), [0x42; 0x4000]);
+    assert_eq!(io.read(APPLICATION_ADDRESS + 1500, 548), [0xFF; 548]);
+}
+
+#[test]
+fn erase_past_the_address_space_fails() {
+    // The last page would end at 4 GiB.
+    let io = MockDfu::dfuse("@Internal Flash  /0xFFFFF000/4*1Ka").unwrap();
+    let error = transfer::erase(&io, 0xFFFF_FF00, &[0; 16], |_| ()).unwrap_err();
+    assert!(matches!(
+        error,
+        BikesafeError::Protocol(dfu_core::Error::NoSpaceLeft)
+    ));
+    assert!(io.writes().is_empty());
+}
+
+#[test]
+fn zero_transfer_size_is_refused() {
+    let io = device().with_descriptor(|descriptor| descriptor.transfer_size = 0);
+    assert!(matches!(
+        dfuse::check_transfer_size(io.functional_descriptor()),
+        Err(BikesafeError::ZeroTransferSize)
+    ));
+    dfuse::check_transfer_size(device().functional_descriptor()).unwrap();
+}
+
+#[test]
+fn writing_without_erase_fails() {
+    let io = device().with_flash(APPLICATION_ADDRESS, &[0; 16]);
+    let data = firmware(16);
+    let error = transfer::download(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap_err();
+    assert!(matches!(error, BikesafeError::Dfu(Status::ErrCheckErased)));
+    assert_eq!(io.state(), State::DfuError);
+}
+
+#[test]
+fn verify_reports_the_first_difference() {
+    let data = firmware(3000);
+    let mut corrupted = data.clone();
+    corrupted[2100] ^= 0xFF;
+    let io = device().with_flash(APPLICATION_ADDRESS, &corrupted);
+    let error = transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap_err();
+    let BikesafeError::VerifyFailed { address } = error else {
+        panic!("expected a verify failure, got {error:?}");
+    };
+    assert_eq!(address, APPLICATION_ADDRESS + 2100);
+}
+
+#[test]
+fn resume_after_disconnect() {
+    let data = firmware(6000);
+    // Erasing the six pages takes 19 transfers, each block 6 more after
+    // the initial GETSTATUS: unplug after the first block.
+    let io = device().disconnect_after(19 + 1 + 6);
+    assert!(flash(&io, &data).is_err());
+    io.reconnect();
+
+    let offset = transfer::compare(&io, APPLICATION_ADDRESS, &data, |_| ())
+        .unwrap()
+        .unwrap();
+    assert_eq!(offset, 2048);
+    transfer::download(
+        &io,
+        APPLICATION_ADDRESS + offset as u32,
+        &data[offset..],
+        |_| (),
+    )
+    .unwrap();
+    transfer::verify(&io, APPLICATION_ADDRESS, &data, |_| ()).unwrap();
+}
+
+#[test]
+fn failed_request_surfaces() {
+    // The second GETSTATUS is the one after setting the address.
+    let io = device().fail(DFU_GETSTATUS, 1);
+    assert!(matches!(
+        flash(&io, &firmware(100)),
+        Err(BikesafeError::UsbIo(rusb::Error::Pipe))
+    ));
+    assert!(io.transfers().last().unwrap().failed);
+}
+
+#[test]
+fn pending_error_is_cleared() {
+    let io = device().with_state(State::DfuError, Status::ErrProg);
+    flash(&io, &firmware(100)).unwrap();
+    assert_eq!(io.requests()[..2], [DFU_GETSTATUS, DFU_CLRSTATUS]);
+}
+
+#[test]
+fn leave_starts_the_application() {
+    let io = device();
+    // The device resets while answering the final GETSTATUS.
+    assert!(dfuse::leave(&io, APPLICATION_ADDRESS).is_err());
+    assert_eq!(io.started(), Some(APPLICATION_ADDRESS));
+    let last = io.transfers().into_iter().rev().nth(1).unwrap();
+    assert_eq!(
+        (last.request, last.value, last.data),
+        (DFU_DNLOAD, 2, vec![])
+    );
+}
+
+#[test]
+fn upload_needs_support() {
+    let io = device().with_descriptor(|descriptor| descriptor.can_upload = false);
+    assert!(matches!(
+        transfer::ensure_upload(&io),
+        Err(BikesafeError::UploadNotSupported)
+    ));
+}
+
+#[test]
+fn read_protection() {
+    let io = device().read_protected();
+    assert!(transfer::compare(&io, APPLICATION_ADDRESS, &[0; 4], |_| ()).is_err());
+    io.reconnect();
+    dfuse::read_unprotect(&io).unwrap();
+    transfer::verify(&io, APPLICATION_ADDRESS, &[0xFF; 4], |_| ()).unwrap();
+}
+
+#[test]
+fn plain_dfu() {
+    let io = MockDfu::plain().with_descriptor(|descriptor| descriptor.transfer_size = 1024);
+    let data = firmware(2500);
+    dfuse::download_plain(&io, &data, 1024, |_| ()).unwrap();
+    assert_eq!(dfuse::manifest(&io).unwrap(), State::DfuIdle);
+    assert_eq!(
+        io.writes()
+            .iter()
+            .map(|&(block, _)| block)
+            .collect::<Vec<_>>(),
+        [0, 1, 2]
+    );
+    let read_back = dfuse::upload_plain(&io, data.len(), 1024, |_| ()).unwrap();
+    assert_eq!(read_back, data);
+    assert_eq!(io.requests().last(), Some(&DFU_ABORT));
+}
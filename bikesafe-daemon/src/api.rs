@@ -0,0 +1,392 @@
+//! The HTTP routes. Requests and responses are JSON; errors are
+//! `{"error": "..."}` with a 4xx or 5xx status.
+//!
+//! With a token, every request must carry it as `Authorization: Bearer`.
+//! Without one, the `Host` and any `Origin` must be loopback, so that a web
+//! page the daemon's user opens cannot reach it, directly or by rebinding
+//! its own host name to 127.0.0.1.
+
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bikesafe_core::{Device, family};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, ResponseBox};
+
+use crate::jobs::{Job, Jobs, State, Status};
+
+pub struct Api {
+    jobs: Jobs,
+    token: Option<String>,
+}
+
+/// A connected device in DFU mode.
+#[derive(Serialize)]
+struct DeviceInfo {
+    bus: u8,
+    address: u8,
+    vid: u16,
+    pid: u16,
+    family: &'static str,
+    serial: Option<String>,
+}
+
+/// Body of `POST /updates`.
+#[derive(Deserialize)]
+struct UpdateRequest {
+    bus: u8,
+    address: u8,
+    /// Path of the firmware file on the host running the daemon.
+    firmware: PathBuf,
+    #[serde(default = "default_verify")]
+    verify: bool,
+}
+
+fn default_verify() -> bool {
+    true
+}
+
+/// What a route answers with.
+enum Reply {
+    Json(ResponseBox),
+    /// Stream the status of the job as it changes.
+    Progress(Arc<Job>),
+}
+
+/// A failed request, as its status code and message.
+struct ApiError(u16, String);
+
+impl<E: std::fmt::Display> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError(500, e.to_string())
+    }
+}
+
+impl Api {
+    /// Requests must carry `token`, if there is one.
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            jobs: Jobs::default(),
+            token,
+        }
+    }
+
+    pub fn handle(&self, mut request: Request) {
+        tracing::debug!("{} {}", request.method(), request.url());
+        let result = match self.route(&mut request) {
+            Ok(Reply::Json(response)) => request.respond(response),
+            Ok(Reply::Progress(job)) => stream_progress(request.into_writer(), &job),
+            Err(ApiError(status, message)) => request
+                .respond(json(&serde_json::json!({ "error": message })).with_status_code(status)),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Could not send response: {e}");
+        }
+    }
+
+    fn route(&self, request: &mut Request) -> Result<Reply, ApiError> {
+        let url = request.url().to_owned();
+        let path: Vec<&str> = url
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect();
+        self.authorize(request)?;
+        match (request.method(), path.as_slice()) {
+            (Method::Get, ["devices"]) => Ok(Reply::Json(json(&devices()?))),
+            (Method::Get, ["updates"]) => Ok(Reply::Json(json(&self.jobs.list()))),
+            (Method::Post, ["updates"]) => {
+                let json_body = header(request, "Content-Type")
+                    .and_then(|value| value.split(';').next())
+                    .is_some_and(|media| media.trim().eq_ignore_ascii_case("application/json"));
+                if !json_body {
+                    return Err(ApiError(
+                        415,
+                        "expected Content-Type: application/json".into(),
+                    ));
+                }
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body)?;
+                let update = serde_json::from_str(&body)
+                    .map_err(|e| ApiError(400, format!("invalid request: {e}")))?;
+                let status = self.start(update)?;
+                Ok(Reply::Json(json(&status).with_status_code(202)))
+            }
+            (Method::Get, ["updates", id]) => Ok(Reply::Json(json(&self.job(id)?.status()))),
+            (Method::Get, ["updates", id, "progress"]) => Ok(Reply::Progress(self.job(id)?)),
+            _ => Err(ApiError(404, format!("no route for {url}"))),
+        }
+    }
+
+    fn start(&self, update: UpdateRequest) -> Result<Status, ApiError> {
+        let port = (update.bus, update.address);
+        let device = dfu_devices()?
+            .into_iter()
+            .find(|(device, _)| device.port == Some(port))
+            .map(|(device, _)| device)
+            .ok_or_else(|| {
+                ApiError(
+                    404,
+                    format!("no device in DFU mode at {}:{}", port.0, port.1),
+                )
+            })?;
+        let firmware = update.firmware.display().to_string();
+        let job = self
+            .jobs
+            .start(device, update.firmware, update.verify)
+            .ok_or_else(|| {
+                ApiError(
+                    409,
+                    format!("device at {}:{} is already being updated", port.0, port.1),
+                )
+            })?;
+        tracing::info!("Updating {}:{} with {firmware}", port.0, port.1);
+        Ok(job.status())
+    }
+
+    /// Reject requests without the token, or, without a token, those a web
+    /// page could have sent.
+    fn authorize(&self, request: &Request) -> Result<(), ApiError> {
+        if let Some(token) = &self.token {
+            let given =
+                header(request, "Authorization").and_then(|value| value.strip_prefix("Bearer "));
+            return match given.is_some_and(|given| same(given.as_bytes(), token.as_bytes())) {
+                true => Ok(()),
+                false => Err(ApiError(401, "missing or wrong token".into())),
+            };
+        }
+        if let Some(host) = header(request, "Host")
+            && !is_loopback(host)
+        {
+            return Err(ApiError(403, format!("host {host} is not loopback")));
+        }
+        if let Some(origin) = header(request, "Origin")
+            && !origin
+                .split_once("://")
+                .is_some_and(|(_, host)| is_loopback(host))
+        {
+            return Err(ApiError(
+                403,
+                format!("requests from {origin} are not allowed"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn job(&self, id: &str) -> Result<Arc<Job>, ApiError> {
+        id.parse()
+            .ok()
+            .and_then(|id| self.jobs.get(id))
+            .ok_or_else(|| ApiError(404, format!("no update {id}")))
+    }
+}
+
+/// Every connected device in DFU mode with the bootloader IDs of a known
+/// family, and that family's name.
+fn dfu_devices() -> Result<Vec<(Device, &'static str)>, ApiError> {
+    let context = rusb::Context::new()?;
+    let mut devices = Vec::new();
+    for family in family::FAMILIES {
+        for &(vid, pid) in family.dfu_ids {
+            let template = Device {
+                context: context.clone(),
+                vid,
+                pid,
+                intf: 0,
+                alt: 0,
+                port: None,
+            };
+            devices.extend(
+                template
+                    .all()?
+                    .into_iter()
+                    .map(|device| (device, family.name)),
+            );
+        }
+    }
+    Ok(devices)
+}
+
+fn devices() -> Result<Vec<DeviceInfo>, ApiError> {
+    dfu_devices()?
+        .into_iter()
+        .map(|(device, family)| {
+            let (bus, address) = device.port.unwrap_or_default();
+            Ok(DeviceInfo {
+                bus,
+                address,
+                vid: device.vid,
+                pid: device.pid,
+                family,
+                serial: device.serial_number()?,
+            })
+        })
+        .collect()
+}
+
+/// Write the status of `job` as newline-delimited JSON, one line per
+/// change, until the job has finished. The response has no length and ends
+/// with the connection, so that each line reaches the client as soon as it
+/// is written.
+fn stream_progress(mut writer: Box<dyn Write + Send>, job: &Job) -> io::Result<()> {
+    writer.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n",
+    )?;
+    let mut status = job.status();
+    loop {
+        serde_json::to_writer(&mut writer, &status)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        if status.state != State::Running {
+            return Ok(());
+        }
+        status = job.wait_for_change(&status);
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Whether `host`, with or without a port, names this machine.
+fn is_loopback(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']').map_or(bracketed, |(ip, _)| ip),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// `a == b`, in a time that does not depend on where they differ.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn json_header() -> Header {
+    "Content-Type: application/json".parse().unwrap()
+}
+
+fn json(value: &impl Serialize) -> ResponseBox {
+    Response::from_data(serde_json::to_vec(value).unwrap())
+        .with_header(json_header())
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::Api;
+
+    /// A daemon on a free loopback port.
+    fn serve(token: Option<&str>) -> SocketAddr {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let api = Arc::new(Api::new(token.map(str::to_owned)));
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                api.handle(request);
+            }
+        });
+        address
+    }
+
+    /// Send `request` with `headers` and return the status and body.
+    fn send(address: SocketAddr, request: &str, headers: &[&str], body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut head = format!("{request} HTTP/1.1\r\nConnection: close\r\n");
+        for header in headers {
+            head += &format!("{header}\r\n");
+        }
+        head += &format!("Content-Length: {}\r\n\r\n{body}", body.len());
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (status, body)
+    }
+
+    const LOCAL: &str = "Host: localhost:7645";
+    const JSON: &str = "Content-Type: application/json";
+
+    #[test]
+    fn routes() {
+        let daemon = serve(None);
+        assert_eq!(
+            send(daemon, "GET /updates", &[LOCAL], ""),
+            (200, "[]".into())
+        );
+        assert_eq!(send(daemon, "GET /updates/1", &[LOCAL], "").0, 404);
+        assert_eq!(send(daemon, "GET /updates/x/progress", &[LOCAL], "").0, 404);
+        assert_eq!(send(daemon, "DELETE /updates", &[LOCAL], "").0, 404);
+        let (status, body) = send(daemon, "GET /firmware", &[LOCAL], "");
+        assert_eq!(status, 404);
+        assert_eq!(body, r#"{"error":"no route for /firmware"}"#);
+    }
+
+    #[test]
+    fn update_needs_a_json_body() {
+        let daemon = serve(None);
+        let update = r#"{"bus": 1, "address": 7, "firmware": "/tmp/firmware.dfu"}"#;
+        let form = "Content-Type: application/x-www-form-urlencoded";
+        assert_eq!(send(daemon, "POST /updates", &[LOCAL, form], update).0, 415);
+        assert_eq!(send(daemon, "POST /updates", &[LOCAL], update).0, 415);
+        assert_eq!(send(daemon, "POST /updates", &[LOCAL, JSON], "{}").0, 400);
+        let json = "content-type: Application/JSON; charset=utf-8";
+        assert_eq!(send(daemon, "POST /updates", &[LOCAL, json], "{").0, 400);
+    }
+
+    #[test]
+    fn web_pages_are_kept_out() {
+        let daemon = serve(None);
+        for host in ["127.0.0.1:7645", "[::1]:7645", "localhost", "LOCALHOST:80"] {
+            let host = format!("Host: {host}");
+            assert_eq!(send(daemon, "GET /updates", &[&host], "").0, 200, "{host}");
+        }
+        for host in [
+            "bikesafe.example:7645",
+            "192.168.1.20:7645",
+            "[fe80::1]:7645",
+        ] {
+            let host = format!("Host: {host}");
+            assert_eq!(send(daemon, "GET /updates", &[&host], "").0, 403, "{host}");
+        }
+        let local = "Origin: http://localhost:7645";
+        assert_eq!(send(daemon, "GET /updates", &[LOCAL, local], "").0, 200);
+        for origin in ["https://bikesafe.example", "null"] {
+            let origin = format!("Origin: {origin}");
+            let update = send(daemon, "POST /updates", &[LOCAL, JSON, &origin], "{}");
+            assert_eq!(update.0, 403, "{origin}");
+        }
+    }
+
+    #[test]
+    fn token_is_required() {
+        let daemon = serve(Some("s3cret"));
+        assert_eq!(send(daemon, "GET /updates", &[LOCAL], "").0, 401);
+        let wrong = "Authorization: Bearer s3cre7";
+        assert_eq!(send(daemon, "GET /updates", &[LOCAL, wrong], "").0, 401);
+        let token = "Authorization: Bearer s3cret";
+        assert_eq!(send(daemon, "GET /updates", &[LOCAL, token], "").0, 200);
+        // The token is enough for a daemon on another address.
+        let remote = "Host: 192.168.1.20:7645";
+        assert_eq!(send(daemon, "GET /updates", &[remote, token], "").0, 200);
+        assert_eq!(
+            send(daemon, "POST /updates", &[remote, token, JSON], "{}").0,
+            400
+        );
+    }
+}
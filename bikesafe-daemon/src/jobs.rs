@@ -0,0 +1,210 @@
+//! Updates started through the API. Each runs on its own thread with the
+//! same steps as the GUI's update, and publishes its status for the API to
+//! report and stream.
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use bikesafe_core::{BikesafeError, Device, FirmwareUpdater, ProgressSink};
+use serde::Serialize;
+
+/// Every update since the daemon started, by ID.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+}
+
+/// One update and what it last reported.
+pub struct Job {
+    status: Mutex<Status>,
+    changed: Condvar,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Status {
+    pub id: u64,
+    /// Bus number and address of the device.
+    pub port: (u8, u8),
+    pub firmware: PathBuf,
+    pub state: State,
+    pub phase: Option<Phase>,
+    /// Bytes of the current phase done, and its total.
+    pub done: u64,
+    pub total: u64,
+    pub error: Option<String>,
+    /// Run time once finished.
+    pub duration_s: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Validate,
+    Erase,
+    Write,
+    Verify,
+    Reset,
+}
+
+impl Jobs {
+    /// Start writing `firmware` to `device` (and reading it back, if
+    /// `verify` and the device can), and return the new job, or `None` if
+    /// a job is still running on the device's port. Both happen under the
+    /// same lock, so two requests cannot start on one device.
+    pub fn start(&self, device: Device, firmware: PathBuf, verify: bool) -> Option<Arc<Job>> {
+        let port = device.port.unwrap_or_default();
+        let mut jobs = self.lock();
+        let busy = jobs.values().any(|job| {
+            let status = job.lock();
+            status.port == port && status.state == State::Running
+        });
+        if busy {
+            return None;
+        }
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        let job = Arc::new(Job {
+            status: Mutex::new(Status {
+                id,
+                port,
+                firmware: firmware.clone(),
+                state: State::Running,
+                phase: None,
+                done: 0,
+                total: 0,
+                error: None,
+                duration_s: None,
+            }),
+            changed: Condvar::new(),
+        });
+        jobs.insert(id, job.clone());
+
+        let running = job.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            // A panicking update must still end, or its port stays busy.
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| running.run(device, &firmware, verify)));
+            let result = match result {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(payload) => {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    Err(format!("internal error: {message}"))
+                }
+            };
+            running.update(|status| {
+                status.duration_s = Some(start.elapsed().as_secs_f64());
+                match result {
+                    Ok(()) => status.state = State::Succeeded,
+                    Err(e) => {
+                        tracing::warn!("Update {} failed: {e}", status.id);
+                        status.state = State::Failed;
+                        status.error = Some(e);
+                    }
+                }
+            });
+        });
+        Some(job)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.lock().get(&id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Status> {
+        self.lock().values().map(|job| job.status()).collect()
+    }
+
+    /// The jobs, even if a thread panicked holding them: the map stays
+    /// consistent, as each change to it is a single insert.
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Job>>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Job {
+    pub fn status(&self) -> Status {
+        self.lock().clone()
+    }
+
+    /// Block until the status differs from `seen`, and return it.
+    pub fn wait_for_change(&self, seen: &Status) -> Status {
+        self.changed
+            .wait_while(self.lock(), |status| status == seen)
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The status, even if a thread panicked while updating it: every
+    /// field is valid on its own, and the job thread still records the
+    /// outcome.
+    fn lock(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update(&self, edit: impl FnOnce(&mut Status)) {
+        edit(&mut self.lock());
+        self.changed.notify_all();
+    }
+
+    fn enter(&self, phase: Phase, total: u64) {
+        tracing::info!("Update {}: {phase:?}", self.lock().id);
+        self.update(|status| {
+            status.phase = Some(phase);
+            status.done = 0;
+            status.total = total;
+        });
+    }
+
+    fn run(&self, device: Device, path: &Path, verify: bool) -> Result<(), BikesafeError> {
+        let firmware = bikesafe_core::read_firmware(path)?;
+        let updater = FirmwareUpdater::new(device)?;
+        self.enter(Phase::Validate, 0);
+        updater.validate(&firmware)?;
+        let mut progress = self;
+        updater.flash(&firmware, &mut progress)?;
+        if verify && updater.can_verify()? {
+            updater.verify(&firmware, &mut progress)?;
+        }
+        self.enter(Phase::Reset, 0);
+        updater.reset()
+    }
+}
+
+/// The update's steps report straight into its status.
+impl ProgressSink for &Job {
+    fn phase(&mut self, phase: bikesafe_core::Phase, total: u64) {
+        let phase = match phase {
+            bikesafe_core::Phase::Erase => Phase::Erase,
+            bikesafe_core::Phase::Download => Phase::Write,
+            bikesafe_core::Phase::Upload | bikesafe_core::Phase::Verify => Phase::Verify,
+        };
+        self.enter(phase, total);
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        self.update(|status| (status.done, status.total) = (done, total));
+    }
+
+    fn message(&mut self, message: &str) {
+        tracing::info!("Update {}: {message}", self.lock().id);
+    }
+
+    // The thread running the job records the outcome.
+    fn finished(&mut self) {}
+}
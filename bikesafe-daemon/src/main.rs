@@ -0,0 +1,76 @@
+//! Local service for fleet and kiosk integrations: lists connected devices
+//! and runs firmware updates on request, over HTTP on a local port or a
+//! Unix socket.
+
+mod api;
+mod jobs;
+
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::Result;
+use clap::Parser;
+use logging::LogFormat;
+
+use crate::api::Api;
+
+#[derive(clap::Parser)]
+struct Args {
+    /// Address to listen on. An address other than loopback needs a
+    /// `--token`.
+    #[clap(long, default_value = "127.0.0.1:7645", env = "BIKESAFE_LISTEN")]
+    listen: SocketAddr,
+
+    /// Secret that every request must send as `Authorization: Bearer`.
+    #[clap(long, env = "BIKESAFE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Listen on this Unix socket instead of `--listen`.
+    #[cfg(unix)]
+    #[clap(long, env = "BIKESAFE_SOCKET")]
+    socket: Option<PathBuf>,
+
+    /// Enable verbose logs, including every request.
+    #[clap(long, short)]
+    verbose: bool,
+
+    /// Log format on stderr.
+    #[clap(long, value_enum, default_value = "text", env = "BIKESAFE_LOG_FORMAT")]
+    log_format: LogFormat,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let color = std::io::stderr().is_terminal();
+    logging::init(args.verbose, args.log_format, color).map_err(|e| anyhow::anyhow!(e))?;
+
+    let server = listen(&args)?;
+    let api = Arc::new(Api::new(args.token.clone()));
+    for request in server.incoming_requests() {
+        let api = api.clone();
+        // Progress streams stay open for the whole update.
+        thread::spawn(move || api.handle(request));
+    }
+    Ok(())
+}
+
+fn listen(args: &Args) -> Result<tiny_http::Server> {
+    #[cfg(unix)]
+    if let Some(socket) = &args.socket {
+        // A socket left behind by an earlier run would fail the bind.
+        let _ = std::fs::remove_file(socket);
+        let server = tiny_http::Server::http_unix(socket).map_err(|e| anyhow::anyhow!(e))?;
+        tracing::info!("Listening on {}", socket.display());
+        return Ok(server);
+    }
+    if !args.listen.ip().is_loopback() && args.token.is_none() {
+        anyhow::bail!("listening on {} needs a --token", args.listen);
+    }
+    let server = tiny_http::Server::http(args.listen).map_err(|e| anyhow::anyhow!(e))?;
+    tracing::info!("Listening on http://{}", args.listen);
+    Ok(server)
+}
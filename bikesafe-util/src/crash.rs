@@ -0,0 +1,161 @@
+//! Crash reports: a panic on any thread writes its message, backtrace and
+//! the most recent log lines to `crash.txt` in the state directory, and the
+//! next launch offers to put it into a diagnostic bundle. Without this a
+//! panicking worker thread goes unnoticed in release builds, which have no
+//! console.
+
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use logging::LogFormat;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::{self, format::FmtSpan};
+use tracing_subscriber::prelude::*;
+use zip::write::SimpleFileOptions;
+
+/// Log lines kept for the report.
+const RECENT_LINES: usize = 200;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Appends each formatted line to [`RECENT`].
+struct Recent;
+
+impl io::Write for Recent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(String::from_utf8_lossy(buf).trim_end().to_owned());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Set up logging and the panic hook.
+///
+/// Logs go to stderr as filtered by `RUST_LOG` (`level` or
+/// `target=level,...`), as JSON if `BIKESAFE_LOG_FORMAT` is `json`. The
+/// last info and higher lines, with the time each update phase took, are
+/// kept for crash reports whatever the filter.
+pub fn init() {
+    let filter = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|filter| filter.parse::<Targets>().ok())
+        .unwrap_or_else(|| Targets::new().with_default(LevelFilter::ERROR));
+    let stderr = logging::stderr(LogFormat::from_env(), true, io::stderr().is_terminal());
+    let recent = fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(|| Recent);
+    // Span fields are formatted once, by the first layer; the crash log
+    // must not get the escape codes of a coloured stderr.
+    let _ = tracing_subscriber::registry()
+        .with(recent.with_filter(LevelFilter::INFO))
+        .with(stderr.with_filter(filter))
+        .try_init();
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = write_report(info);
+        previous(info);
+    }));
+}
+
+fn write_report(info: &std::panic::PanicHookInfo) -> Result<()> {
+    let thread = std::thread::current();
+    let mut report = format!(
+        "bikesafe-util {} on {} {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    writeln!(
+        report,
+        "thread '{}' panicked at {}:\n{}\n",
+        thread.name().unwrap_or("<unnamed>"),
+        info.location()
+            .map_or_else(|| "unknown location".into(), ToString::to_string),
+        info.payload_as_str().unwrap_or("Box<dyn Any>")
+    )?;
+    writeln!(report, "Backtrace:\n{}\n", Backtrace::force_capture())?;
+    writeln!(report, "Recent log:")?;
+    for line in RECENT.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        writeln!(report, "{line}")?;
+    }
+
+    let path = crash_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, report)?;
+    Ok(())
+}
+
+/// `bikesafe/crash.txt` in the user's state directory (`$XDG_STATE_HOME`,
+/// `~/.local/state` or `%LOCALAPPDATA%`), or in the temporary directory.
+pub fn crash_file() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .unwrap_or_else(std::env::temp_dir)
+        .join("bikesafe")
+        .join("crash.txt")
+}
+
+/// The report left by a crash of an earlier run, if any.
+pub fn last_crash() -> Option<String> {
+    fs::read_to_string(crash_file()).ok()
+}
+
+/// Forget the report of the last crash.
+pub fn discard() {
+    let _ = fs::remove_file(crash_file());
+}
+
+/// Write a diagnostic bundle to `path`: a zip with the tool and system
+/// versions, the recent log and, if `crash` is given, a crash report.
+pub fn save_bundle(path: &Path, crash: Option<&str>) -> Result<()> {
+    let libusb = rusb::version();
+    let system = format!(
+        "bikesafe-util {}\nOS: {} {}\nlibusb: {}.{}.{}\nhotplug: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        libusb.major(),
+        libusb.minor(),
+        libusb.micro(),
+        rusb::has_hotplug()
+    );
+    let log = RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .fold(String::new(), |log, line| log + line + "\n");
+
+    let file =
+        File::create(path).with_context(|| format!("could not create {}", path.display()))?;
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut archive = zip::ZipWriter::new(file);
+    let mut entries = vec![("system.txt", system.as_str()), ("log.txt", log.as_str())];
+    entries.extend(crash.map(|crash| ("crash.txt", crash)));
+    for (name, data) in entries {
+        archive.start_file(name, options)?;
+        archive.write_all(data.as_bytes())?;
+    }
+    archive.finish()?;
+    Ok(())
+}
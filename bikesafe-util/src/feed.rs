@@ -0,0 +1,55 @@
+//! Checking the update feed for new firmware, as `bikesafe-cli fetch --feed`
+//! does: the stable channel of `BIKESAFE_FEED`, whose index must be signed
+//! with the key in `BIKESAFE_PUBLIC_KEY`. The newest .dfu it lists is
+//! downloaded into the cache through `update-client`, so an unchanged index
+//! costs one request and an interrupted download resumes.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use ed25519_dalek::VerifyingKey;
+use localization::tr;
+use update_client::Client;
+
+#[derive(Clone)]
+pub struct Feed {
+    index_url: String,
+    key: VerifyingKey,
+}
+
+impl Feed {
+    /// The feed the user configured, `None` if there is none.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(feed) = std::env::var("BIKESAFE_FEED")
+            .ok()
+            .filter(|feed| !feed.is_empty())
+        else {
+            return Ok(None);
+        };
+        let key = std::env::var_os("BIKESAFE_PUBLIC_KEY").context(tr!("gui-feed-needs-key"))?;
+        Ok(Some(Self {
+            index_url: format!(
+                "{}/stable/{}",
+                feed.trim_end_matches('/'),
+                firmware_manifest::INDEX_FILE
+            ),
+            key: firmware_manifest::read_public_key(key.as_ref())?,
+        }))
+    }
+
+    /// Download the newest .dfu of the feed, checked against the size and
+    /// SHA-256 of the index. Returns its path and version.
+    pub fn fetch(&self) -> Result<(PathBuf, String)> {
+        let client = Client::new(concat!("bikesafe-util/", env!("CARGO_PKG_VERSION")));
+        let index = client.index(&self.index_url, Some(&self.key))?;
+        let entry = index
+            .newest(|entry| entry.file.ends_with(".dfu"))
+            .context(tr!("gui-feed-empty"))?;
+        let dir = update_client::cache_dir().join("firmware");
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("could not create `{}`", dir.display()))?;
+        let path = client.download_entry(&self.index_url, entry, &dir)?;
+        tracing::info!("Downloaded {} from the update feed", entry.file);
+        Ok((path, entry.version.clone().unwrap_or_default()))
+    }
+}
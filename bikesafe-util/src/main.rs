@@ -1,29 +1,45 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::fs::File;
-use std::io::{self, Seek};
+mod crash;
+mod feed;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
-use std::thread;
-use std::time::Duration;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Poll, Wake, Waker};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use dfu_libusb::*;
+use bikesafe_core::cancel::Cancel;
+use bikesafe_core::family::{self, MemoryMap};
+use bikesafe_core::nonblocking::AsyncFirmwareUpdater;
+use bikesafe_core::{BikesafeError, Device, FirmwareUpdater, Phase, ProgressSink};
+use blocking::{Task, unblock};
+use device_protocol::{Battery, Runtime, Version};
+use device_watch::{DeviceEvent, DeviceInfo, DeviceWatcher};
+use dfu_file::DfuFile;
 use eframe::egui::{self, ProgressBar};
+use firmware_manifest::bundle::{self, Bundle};
+use localization::tr;
+use rusb::UsbContext;
+use telemetry::{Outcome, Telemetry};
 
 fn main() -> eframe::Result {
-    env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
+    // Errors go to stderr, more as `RUST_LOG` asks; a panic writes a crash report.
+    crash::init();
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([640.0, 240.0]) // wide enough for the drag-drop overlay text
+            .with_inner_size([640.0, 320.0]) // wide enough for the drag-drop overlay text
             .with_resizable(false)
             .with_drag_and_drop(true),
         persist_window: true,
         ..Default::default()
     };
     eframe::run_native(
-        "BrakeBright Firmware Update Util",
+        &tr!("gui-title"),
         options,
         Box::new(|_cc| Ok(Box::new(MyApp::new()))),
     )
@@ -31,203 +47,609 @@ fn main() -> eframe::Result {
 
 const PROGRESS_INIT: f32 = 0.000001; // avoid 0% progress bar
 
+/// Writes an image and starts it.
+type UpdateFuture = Pin<Box<dyn Future<Output = Result<(), BikesafeError>>>>;
+
+/// An update the window drives: its future is polled on every frame until
+/// it completes, and reports into `shown`.
+struct Update {
+    future: Option<UpdateFuture>,
+    shown: Rc<RefCell<Shown>>,
+    /// Stops the update.
+    cancel: Cancel,
+}
+
+/// What the update reported so far.
+struct Shown {
+    phase: Option<Phase>,
+    /// Fraction of the current phase done.
+    progress: f32,
+    finished: bool,
+}
+
+/// Reports the update's progress to the window.
+struct Report(Rc<RefCell<Shown>>);
+
+impl ProgressSink for Report {
+    fn phase(&mut self, phase: Phase, _: u64) {
+        let mut shown = self.0.borrow_mut();
+        shown.phase = Some(phase);
+        shown.progress = PROGRESS_INIT;
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        let fraction = match total {
+            0 => 1.0,
+            total => done as f32 / total as f32,
+        };
+        self.0.borrow_mut().progress = fraction.max(PROGRESS_INIT);
+    }
+
+    fn message(&mut self, message: &str) {
+        tracing::info!("{message}");
+    }
+
+    fn finished(&mut self) {
+        self.0.borrow_mut().finished = true;
+    }
+}
+
+/// Repaints the window, to poll the futures it drives again.
+struct Repaint(egui::Context);
+
+impl Wake for Repaint {
+    fn wake(self: Arc<Self>) {
+        self.0.request_repaint();
+    }
+}
+
+/// Poll `future` once, repainting the window when it can go on.
+fn poll<F: Future + ?Sized>(future: Pin<&mut F>, ctx: &egui::Context) -> Poll<F::Output> {
+    let waker = Waker::from(Arc::new(Repaint(ctx.clone())));
+    future.poll(&mut std::task::Context::from_waker(&waker))
+}
+
+/// Longest the self-test may take.
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A device running its application, with what it reported on arrival.
+struct App {
+    device: DeviceInfo,
+    version: Option<Version>,
+    battery: Option<Battery>,
+}
+
 #[derive(Default)]
 struct MyApp {
     picked_path: Option<PathBuf>,
-    progress: f32,
-    receiver: Option<Receiver<f32>>,
+    /// The running or finished update, until it fails or another starts.
+    update: Option<Update>,
     file_valid: Option<bool>,
     error: Option<String>,
+    /// One per VID:PID of the supported families.
+    watchers: Vec<DeviceWatcher>,
+    /// Devices in DFU mode, by port.
+    devices: HashMap<(u8, u8), DeviceInfo>,
+    /// Devices running their application, by port.
+    apps: HashMap<(u8, u8), App>,
+    self_test: Option<Task<String>>,
+    self_test_result: Option<String>,
+    /// Update feed to check for new firmware, if the user configured one.
+    feed: Option<feed::Feed>,
+    feed_check: Option<Task<Result<(PathBuf, String)>>>,
+    feed_result: Option<String>,
+    /// Set when the user configured a telemetry endpoint; reports are only
+    /// sent once they also ticked the box.
+    telemetry: Option<Telemetry>,
+    share_telemetry: bool,
+    /// Report of a crash of the previous run, until saved or discarded.
+    last_crash: Option<String>,
+    include_crash: bool,
+    /// Bench fixture that can restart the unit into DFU mode, on production
+    /// stations that configure one.
+    #[cfg(feature = "fixture")]
+    fixture: Option<fixture_gpio::Config>,
 }
 
 impl MyApp {
     fn new() -> Self {
+        let mut ids: Vec<_> = family::FAMILIES
+            .iter()
+            .flat_map(|family| family.dfu_ids.iter().chain(family.runtime_ids))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        let (watchers, error) = match ids
+            .into_iter()
+            .map(|&(vid, pid)| device_watch::watch_devices(vid, pid))
+            .collect()
+        {
+            Ok(watchers) => (watchers, None),
+            Err(e) => (Vec::new(), Some(format!("{e}"))),
+        };
+        #[cfg(feature = "fixture")]
+        let (fixture, error) = match fixture_gpio::Config::from_env() {
+            Ok(fixture) => (fixture, error),
+            Err(e) => (None, Some(format!("{e}"))),
+        };
+        let (feed, error) = match feed::Feed::from_env() {
+            Ok(feed) => (feed, error),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
         Self {
             picked_path: None,
-            progress: PROGRESS_INIT,
+            update: None,
             file_valid: None,
-            error: None,
-            receiver: None,
+            error,
+            watchers,
+            devices: HashMap::new(),
+            apps: HashMap::new(),
+            self_test: None,
+            self_test_result: None,
+            feed,
+            feed_check: None,
+            feed_result: None,
+            telemetry: Telemetry::from_env("bikesafe-util", env!("CARGO_PKG_VERSION")),
+            share_telemetry: false,
+            last_crash: crash::last_crash(),
+            include_crash: true,
+            #[cfg(feature = "fixture")]
+            fixture,
+        }
+    }
+
+    /// Memory map of the connected device; a BrakeBright's if there is
+    /// none.
+    fn memory_map(&self) -> &'static MemoryMap {
+        self.devices
+            .values()
+            .chain(self.apps.values().map(|app| &app.device))
+            .find_map(|device| {
+                let id = (device.vid, device.pid);
+                family::find(id.0, id.1).map(|family| family.memory_map(id, None))
+            })
+            .unwrap_or(&family::BRAKEBRIGHT.memory)
+    }
+
+    /// Take in the devices plugged in or removed since the last frame.
+    fn update_devices(&mut self) {
+        let events: Vec<_> = self.watchers.iter().flat_map(|w| w.try_iter()).collect();
+        for event in events {
+            match event {
+                DeviceEvent::Arrived(device) if device.dfu => {
+                    self.devices.insert(device.port(), device);
+                }
+                DeviceEvent::Arrived(device) => {
+                    let runtime = open_app(&device);
+                    let app = App {
+                        device,
+                        version: runtime.as_ref().ok().and_then(|r| r.version().ok()),
+                        battery: runtime.as_ref().ok().and_then(|r| r.battery().ok()),
+                    };
+                    self.apps.insert(device.port(), app);
+                }
+                DeviceEvent::Left(device) => {
+                    self.devices.remove(&device.port());
+                    self.apps.remove(&device.port());
+                }
+            }
         }
     }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.update_devices();
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("BrakeBright Firmware Update Util");
+            ui.heading(tr!("gui-title"));
 
             if let Some(path) = &self.picked_path {
                 let path_str = path.display().to_string();
                 ui.horizontal(|ui| {
-                    ui.label("Firmware Path:");
+                    ui.label(tr!("gui-firmware-path"));
                     ui.monospace(path_str);
                 });
             } else {
-                ui.label("Select a firmware file to update your BrakeBright device.");
+                ui.label(tr!("gui-select-firmware"));
             }
 
             if let Some(error) = &self.error {
                 ui.label(error).highlight();
             }
 
-            if ui.button("Open file…").clicked() {
-                if let Some(path) = rfd::FileDialog::new()
-                    .add_filter("firmware", &["bin"])
-                    .pick_file()
+            if self.last_crash.is_some() {
+                ui.label(tr!("gui-crashed")).highlight();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.include_crash, tr!("gui-include-crash"));
+                    if ui.button(tr!("gui-discard-crash")).clicked() {
+                        crash::discard();
+                        self.last_crash = None;
+                    }
+                });
+            }
+            if ui.button(tr!("gui-save-diagnostics")).clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("zip", &["zip"])
+                    .set_file_name("bikesafe-diagnostics.zip")
+                    .save_file()
+            {
+                let crash = self.last_crash.as_deref().filter(|_| self.include_crash);
+                match crash::save_bundle(&path, crash) {
+                    Ok(()) if crash.is_some() => {
+                        crash::discard();
+                        self.last_crash = None;
+                    }
+                    Ok(()) => (),
+                    Err(e) => self.error = Some(format!("{e:#}")),
+                }
+            }
+
+            if let Some(task) = &mut self.self_test
+                && let Poll::Ready(result) = poll(Pin::new(task), ctx)
+            {
+                self.self_test_result = Some(result);
+                self.self_test = None;
+            }
+            if let Some(app) = self.apps.values().next() {
+                ui.horizontal(|ui| {
+                    match &app.version {
+                        Some(version) => {
+                            ui.label(tr!("gui-app-version", version = version.to_string()))
+                        }
+                        None => ui.label(tr!("gui-app-running")),
+                    };
+                    if let Some(battery) = &app.battery {
+                        ui.label(tr!("gui-battery", battery = battery.to_string()));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button(tr!("gui-switch-to-dfu")).clicked()
+                        && let Err(e) = open_app(&app.device).and_then(|runtime| {
+                            runtime.reboot_to_dfu().context(tr!("gui-restart-failed"))
+                        })
+                    {
+                        self.error = Some(format!("{e:#}"));
+                    }
+                    if self.self_test.is_none() && ui.button(tr!("gui-run-self-test")).clicked() {
+                        self.self_test_result = None;
+                        let device = app.device;
+                        self.self_test = Some(unblock(move || {
+                            let result = open_app(&device)
+                                .and_then(|runtime| Ok(runtime.self_test(SELF_TEST_TIMEOUT)?));
+                            match result {
+                                Ok(result) => {
+                                    tr!("gui-self-test-result", result = result.to_string())
+                                }
+                                Err(e) => tr!("gui-self-test-error", error = format!("{e:#}")),
+                            }
+                        }));
+                    }
+                    if self.self_test.is_some() {
+                        ui.label(tr!("gui-self-test-running"));
+                    } else if let Some(result) = &self.self_test_result {
+                        ui.label(result);
+                    }
+                });
+            }
+
+            if let Some(task) = &mut self.feed_check
+                && let Poll::Ready(result) = poll(Pin::new(task), ctx)
+            {
+                self.feed_check = None;
+                match result {
+                    Ok((path, version)) => {
+                        self.feed_result = Some(tr!("gui-update-downloaded", version = version));
+                        self.picked_path = Some(path);
+                        self.file_valid = None;
+                    }
+                    Err(e) => self.error = Some(format!("{e:#}")),
+                }
+            }
+            ui.horizontal(|ui| {
+                if ui.button(tr!("gui-open-file")).clicked()
+                    && let Some(path) = rfd::FileDialog::new()
+                        .add_filter("firmware", &["bin", "dfu", bundle::EXTENSION])
+                        .pick_file()
                 {
                     self.picked_path = Some(path);
                     self.file_valid = None;
                 }
-            }
+                if let Some(feed) = &self.feed {
+                    if self.feed_check.is_some() {
+                        ui.label(tr!("gui-checking-updates"));
+                    } else if ui.button(tr!("gui-check-updates")).clicked() {
+                        self.feed_result = None;
+                        let feed = feed.clone();
+                        self.feed_check = Some(unblock(move || feed.fetch()));
+                    } else if let Some(result) = &self.feed_result {
+                        ui.label(result);
+                    }
+                }
+            });
 
             if let Some(path) = &self.picked_path {
                 if self.file_valid.is_none() {
-                    // Check if the file is valid (e.g., check the extension)
-                    if path.extension().and_then(|s| s.to_str()) == Some("bin") {
-                        match validate_firmware(path) {
+                    if matches!(
+                        path.extension().and_then(|s| s.to_str()),
+                        Some("bin" | "dfu" | bundle::EXTENSION)
+                    ) {
+                        match validate_firmware(path, self.memory_map()) {
                             Ok(_) => {
                                 self.file_valid = Some(true);
                                 self.error = None;
                             }
                             Err(e) => {
                                 self.file_valid = Some(false);
-                                self.error = Some(format!("Invalid firmware file: {}", e));
+                                self.error = Some(user_message(&e));
                             }
                         }
                     } else {
                         self.file_valid = Some(false);
-                        self.error =
-                            Some("Invalid file type. Please select a .bin file.".to_string());
+                        self.error = Some(tr!("gui-invalid-file-type"));
                     }
                 }
 
                 if self.file_valid.unwrap_or(false) {
                     ui.label("_____________________________________________________");
-                    // CLI logic adapted
-                    let vid = 0x1209;
-                    let pid = 0x2444;
-                    let intf = 0;
-                    let alt = 0;
-                    let context = rusb::Context::new().expect("Failed to create USB context");
-                    if DfuLibusb::open(&context, 0x1209, 0x2444, 0, 0).is_ok()
-                    {
-                        if ui.button("Update Firmware").clicked() {
-                            ui.label("Updating firmware...");
-                            let (tx, rx) = mpsc::channel();
-                            self.receiver = Some(rx);
-
+                    if self.telemetry.is_some() {
+                        ui.checkbox(&mut self.share_telemetry, tr!("gui-share-telemetry"));
+                    }
+                    if let Some(found) = self.devices.values().next() {
+                        if ui.button(tr!("gui-update")).clicked() {
+                            let device = Device {
+                                context: rusb::Context::new()
+                                    .expect("Failed to create USB context"),
+                                vid: found.vid,
+                                pid: found.pid,
+                                intf: 0,
+                                alt: 0,
+                                port: Some(found.port()),
+                            };
+                            // Fail here rather than mid-download if another
+                            // flasher already uses the device.
+                            let cancel = Cancel::new();
+                            let updater = match FirmwareUpdater::new(device) {
+                                Ok(updater) => updater.with_cancel(cancel.clone()),
+                                Err(e) => {
+                                    self.error = Some(user_message(&e));
+                                    return;
+                                }
+                            };
+                            ui.label(tr!("gui-updating"));
+                            let shown = Rc::new(RefCell::new(Shown {
+                                phase: None,
+                                progress: PROGRESS_INIT,
+                                finished: false,
+                            }));
+                            let mut progress = Report(shown.clone());
+                            let updater = AsyncFirmwareUpdater::from(updater);
                             let path = path.clone();
-                            thread::spawn(move || {
-                                let mut device = DfuLibusb::open(&context, vid, pid, intf, alt)
-                                    .context("could not open device")
-                                    .unwrap();
-
-                                let mut file = File::open(&path)
-                                    .with_context(|| {
-                                        format!("could not open firmware file `{}`", path.display())
-                                    })
-                                    .unwrap();
-                                let file_size =
-                                    u32::try_from(file.seek(io::SeekFrom::End(0)).unwrap())
-                                        .context("The firmware file is too big")
-                                        .unwrap();
-                                file.seek(io::SeekFrom::Start(0)).unwrap();
-
-                                // Progress via DFU core
-                                device.with_progress({
-                                    let tx = tx.clone();
-                                    move |count| {
-                                        // count is bytes since last callback
-                                        let prog = count as f32 / file_size as f32;
-                                        let _ = tx.send(prog);
+                            let telemetry = self.telemetry.clone().filter(|_| self.share_telemetry);
+                            let future = async move {
+                                let start = Instant::now();
+                                let result = update(&updater, &path, &mut progress).await;
+                                if let Err(e) = &result {
+                                    tracing::error!("Download error: {}", chain(e));
+                                }
+                                if let Some(telemetry) = telemetry {
+                                    let outcome = match &result {
+                                        Ok(()) => Outcome::Success,
+                                        Err(e) => Outcome::of(e),
+                                    };
+                                    let elapsed = start.elapsed();
+                                    let report =
+                                        unblock(move || telemetry.report(None, outcome, elapsed));
+                                    if let Err(e) = report.await {
+                                        tracing::debug!("{e}");
                                     }
-                                });
-
-                                // Optionally override start address
-                                device.override_address(0x08004000);
-
-                                // Perform download
-                                match device.download(file, file_size) {
-                                    Ok(_) => (),
-                                    Err(e) => log::error!("Download error: {e:?}"),
-                                };
+                                }
+                                result
+                            };
+                            self.update = Some(Update {
+                                future: Some(Box::pin(future)),
+                                shown,
+                                cancel,
                             });
                         }
-                    } else if self.receiver.is_none() {
-                        ui.label(
-                            "Please make sure the USB is connected and the device is in DFU mode. (LED blinking constantly)",
-                        );
+                    } else if self.update.is_none() {
+                        ui.label(tr!("gui-connect-dfu"));
+                        #[cfg(feature = "fixture")]
+                        if let Some(config) = &self.fixture
+                            && ui.button(tr!("gui-fixture-restart")).clicked()
+                            && let Err(e) = fixture_gpio::Fixture::open(config)
+                                .and_then(|fixture| fixture.enter_dfu())
+                        {
+                            self.error = Some(chain(&e));
+                        }
                         ctx.request_repaint_after(Duration::from_millis(100));
                     }
 
-                    if let Some(rx) = &self.receiver {
-                        for p in rx.try_iter() {
-                            self.progress += p;
+                    if let Some(update) = &mut self.update {
+                        if let Some(future) = &mut update.future
+                            && let Poll::Ready(result) = poll(future.as_mut(), ctx)
+                        {
+                            update.future = None;
+                            if let Err(e) = result {
+                                self.error = Some(user_message(&e));
+                                self.update = None;
+                                return;
+                            }
+                        }
+                        let shown = update.shown.borrow();
+                        tracing::debug!("Progress: {}", shown.progress);
+                        if let Some(phase) = shown.phase.filter(|_| !shown.finished) {
+                            ui.label(phase_label(phase));
                         }
-                        log::error!("Progress: {}", self.progress);
-                        ui.add(ProgressBar::new(self.progress).show_percentage());
-                        if self.progress >= 1.0 {
-                            ui.label("Flash complete! Please test the device function by tilting it.");
-                        } else {
-                            ctx.request_repaint();
+                        ui.add(ProgressBar::new(shown.progress).show_percentage());
+                        if shown.finished {
+                            ui.label(tr!("gui-flash-complete"));
+                        } else if ui
+                            .add_enabled(
+                                !update.cancel.is_cancelled(),
+                                egui::Button::new(tr!("gui-cancel")),
+                            )
+                            .clicked()
+                        {
+                            update.cancel.cancel();
                         }
                     }
                 } else {
-                    ui.label("Please select a valid firmware file.");
+                    ui.label(tr!("gui-select-valid-firmware"));
                 }
             } else {
-                ui.label("No firmware file selected.");
+                ui.label(tr!("gui-no-firmware"));
             }
         });
     }
 }
 
-fn validate_firmware(path: &Path) -> Result<()> {
-    const FLASH_ORIGIN: u32 = 0x0800_4000;
-    const FLASH_LEN: u32 = 48 * 1024;
-    const RAM_ORIGIN: u32 = 0x2000_0000 + 0x10;
-    const RAM_LEN: u32 = 20 * 1024 - 0x10;
-
-    let data = std::fs::read(path)?;
-    let len = data.len() as u32;
-    anyhow::ensure!(
-        len <= FLASH_LEN,
-        "Firmware too large: {} > {} bytes",
-        len,
-        FLASH_LEN
-    );
-
-    // Vector table:
-    let sp = u32::from_le_bytes(data[0..4].try_into()?);
-    let reset = u32::from_le_bytes(data[4..8].try_into()?);
-
-    let ram_end = RAM_ORIGIN + RAM_LEN;
-    anyhow::ensure!(
-        sp >= RAM_ORIGIN && sp <= ram_end,
-        "Invalid initial SP: {:#010X}, expected between {:#010X} and {:#010X}",
-        sp,
-        RAM_ORIGIN,
-        ram_end
-    );
-
-    let flash_end = FLASH_ORIGIN + FLASH_LEN;
-    anyhow::ensure!(
-        reset >= FLASH_ORIGIN && reset < flash_end,
-        "Invalid reset vector: {:#010X}, expected between {:#010X} and {:#010X}",
-        reset,
-        FLASH_ORIGIN,
-        flash_end
-    );
-
-    let offset = reset - FLASH_ORIGIN;
-    anyhow::ensure!(
-        offset < len,
-        "Reset vector at {:#X} points past end of file (offset {:#X}, len {:#X})",
-        reset,
-        offset,
-        len
-    );
+/// What to tell the user about `error`: the remedy where there is an
+/// obvious one, and else the error itself.
+fn user_message(error: &BikesafeError) -> String {
+    match error {
+        BikesafeError::ValidationFailed(e) => tr!("gui-invalid-firmware", reason = e.to_string()),
+        BikesafeError::Cancelled => tr!("gui-update-cancelled"),
+        error => localization::hint(error).unwrap_or_else(|| chain(error)),
+    }
+}
 
+/// `error` and its sources, like anyhow's `{:#}`.
+fn chain(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+/// Open the runtime protocol of a device running its application.
+fn open_app(device: &DeviceInfo) -> Result<Runtime<rusb::Context>> {
+    let usb = rusb::Context::new()?
+        .devices()?
+        .iter()
+        .find(|usb| (usb.bus_number(), usb.address()) == device.port())
+        .context("device is gone")?;
+    Runtime::open(&usb).context("could not open the device")
+}
+
+fn validate_firmware(path: &Path, memory: &MemoryMap) -> Result<(), BikesafeError> {
+    if is_bundle(path) {
+        let bundle = open_bundle(path)?;
+        return Ok(bikesafe_core::validate_at(
+            memory,
+            bundle.manifest.address,
+            &bundle.firmware,
+        )?);
+    }
+    Ok(bikesafe_core::validate_for(
+        memory,
+        &read_image(path, memory.flash.origin)?,
+    )?)
+}
+
+fn is_bundle(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some(bundle::EXTENSION)
+}
+
+/// `path` is not a firmware file that can be written, for `error`.
+fn invalid(path: &Path, error: Box<dyn std::error::Error + Send + Sync>) -> BikesafeError {
+    BikesafeError::ReadFirmware {
+        path: path.to_owned(),
+        source: std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+    }
+}
+
+/// The release bundle at `path`, opened and checked as `bikesafe-cli`
+/// does, against the key in `BIKESAFE_PUBLIC_KEY`.
+fn open_bundle(path: &Path) -> Result<Bundle, BikesafeError> {
+    let key = std::env::var_os("BIKESAFE_PUBLIC_KEY")
+        .ok_or_else(|| invalid(path, tr!("gui-bundle-needs-key").into()))?;
+    let key =
+        firmware_manifest::read_public_key(key.as_ref()).map_err(|e| invalid(path, e.into()))?;
+    Bundle::open(path, Some(&key)).map_err(|e| invalid(path, e.into()))
+}
+
+/// The application image in `path`: a .bin as it is, or the one element of
+/// alternate setting 0 of a .dfu, which has to be linked for `address`.
+fn read_image(path: &Path, address: u32) -> Result<Vec<u8>, BikesafeError> {
+    let data = bikesafe_core::read_firmware(path)?;
+    if path.extension().and_then(|s| s.to_str()) != Some("dfu") {
+        return Ok(data);
+    }
+    let invalid = |error| invalid(path, error);
+    let dfu = DfuFile::from_bytes(&data).map_err(|e| invalid(e.into()))?;
+    let elements = dfu
+        .target(0)
+        .map_or(&[][..], |target| target.elements.as_slice());
+    let [element] = elements else {
+        return Err(invalid(
+            format!(
+                "alternate setting 0 has {} images, only one can be written",
+                elements.len()
+            )
+            .into(),
+        ));
+    };
+    if element.address != address {
+        return Err(invalid(
+            format!(
+                "image is at {:#010X}, the application starts at {address:#010X}",
+                element.address
+            )
+            .into(),
+        ));
+    }
+    Ok(element.data.clone())
+}
+
+/// Write the firmware and start it, reporting to `progress`. A bundle is
+/// first checked against the connected device.
+async fn update(
+    updater: &AsyncFirmwareUpdater,
+    path: &Path,
+    progress: &mut Report,
+) -> Result<(), BikesafeError> {
+    let address = updater.updater().address();
+    let firmware = if is_bundle(path) {
+        let bundle = open_bundle(path)?;
+        let device = updater.updater().device().clone();
+        let memory = updater.updater().memory_map();
+        let identity = unblock(move || device.identify(memory)).await?;
+        bundle
+            .check_compatible(
+                identity.vid,
+                identity.pid,
+                &identity.revisions,
+                identity.flash_end,
+            )
+            .map_err(|e| invalid(path, e.into()))?;
+        if bundle.manifest.address != address {
+            return Err(invalid(
+                path,
+                format!(
+                    "image is at {:#010X}, the application starts at {address:#010X}",
+                    bundle.manifest.address
+                )
+                .into(),
+            ));
+        }
+        bundle.firmware
+    } else {
+        read_image(path, address)?
+    };
+    updater.update(&firmware, progress).await?;
+    progress.finished();
     Ok(())
 }
+
+/// What the window says the update is doing.
+fn phase_label(phase: Phase) -> String {
+    match phase {
+        Phase::Erase => tr!("gui-phase-erase"),
+        Phase::Download => tr!("gui-phase-download"),
+        Phase::Upload => tr!("gui-phase-upload"),
+        Phase::Verify => tr!("gui-phase-verify"),
+    }
+}
@@ -0,0 +1,321 @@
+//! The page: pick a firmware file, choose the device in the browser's
+//! list, and watch it being written. Laid out like the desktop window,
+//! whose messages it shares.
+
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Poll, Wake, Waker};
+
+use bikesafe_core::cancel::{Cancel, Cancellable};
+use bikesafe_core::family::{self, FAMILIES};
+use bikesafe_core::nonblocking::{dfuse, transfer};
+use bikesafe_core::progress::Counter;
+use bikesafe_core::webusb::{self, WebUsbDfu};
+use bikesafe_core::{BikesafeError, Phase, ProgressSink};
+use dfu_file::DfuFile;
+use eframe::egui::{self, ProgressBar};
+use localization::tr;
+
+const PROGRESS_INIT: f32 = 0.000001; // avoid 0% progress bar
+
+/// A firmware file, read in the browser.
+struct Firmware {
+    name: String,
+    data: Vec<u8>,
+}
+
+type PickFuture = Pin<Box<dyn Future<Output = Option<Firmware>>>>;
+type UpdateFuture = Pin<Box<dyn Future<Output = Result<(), BikesafeError>>>>;
+
+/// An update the page drives: its future is polled on every frame until it
+/// completes, and reports into `shown`.
+struct Update {
+    future: Option<UpdateFuture>,
+    shown: Rc<RefCell<Shown>>,
+    /// Stops the update.
+    cancel: Cancel,
+}
+
+/// What the update reported so far.
+struct Shown {
+    phase: Option<Phase>,
+    /// Fraction of the current phase done.
+    progress: f32,
+    finished: bool,
+}
+
+/// Reports the update's progress to the page.
+struct Report(Rc<RefCell<Shown>>);
+
+impl ProgressSink for Report {
+    fn phase(&mut self, phase: Phase, _: u64) {
+        let mut shown = self.0.borrow_mut();
+        shown.phase = Some(phase);
+        shown.progress = PROGRESS_INIT;
+    }
+
+    fn bytes(&mut self, done: u64, total: u64) {
+        let fraction = match total {
+            0 => 1.0,
+            total => done as f32 / total as f32,
+        };
+        self.0.borrow_mut().progress = fraction.max(PROGRESS_INIT);
+    }
+
+    fn message(&mut self, _: &str) {}
+
+    fn finished(&mut self) {
+        self.0.borrow_mut().finished = true;
+    }
+}
+
+/// Repaints the page, to poll the futures it drives again.
+struct Repaint(egui::Context);
+
+impl Wake for Repaint {
+    fn wake(self: Arc<Self>) {
+        self.0.request_repaint();
+    }
+}
+
+/// Poll `future` once, repainting the page when it can go on.
+fn poll<F: Future + ?Sized>(future: Pin<&mut F>, ctx: &egui::Context) -> Poll<F::Output> {
+    let waker = Waker::from(Arc::new(Repaint(ctx.clone())));
+    future.poll(&mut std::task::Context::from_waker(&waker))
+}
+
+#[derive(Default)]
+pub struct WebApp {
+    firmware: Option<Rc<Firmware>>,
+    /// The file dialog, while it is open.
+    pick: Option<PickFuture>,
+    /// The running or finished update, until it fails or another starts.
+    update: Option<Update>,
+    error: Option<String>,
+}
+
+impl eframe::App for WebApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(tr!("gui-title"));
+
+            if !webusb::is_supported() {
+                ui.label(tr!("web-no-webusb")).highlight();
+                return;
+            }
+
+            match &self.firmware {
+                Some(firmware) => {
+                    ui.horizontal(|ui| {
+                        ui.label(tr!("gui-firmware-path"));
+                        ui.monospace(&firmware.name);
+                    });
+                }
+                None => {
+                    ui.label(tr!("gui-select-firmware"));
+                }
+            }
+
+            if let Some(error) = &self.error {
+                ui.label(error).highlight();
+            }
+
+            let running = self.update.as_ref().is_some_and(|u| u.future.is_some());
+            if ui
+                .add_enabled(
+                    self.pick.is_none() && !running,
+                    egui::Button::new(tr!("gui-open-file")),
+                )
+                .clicked()
+            {
+                self.pick = Some(Box::pin(async {
+                    let file = rfd::AsyncFileDialog::new()
+                        .add_filter("firmware", &["bin", "dfu"])
+                        .pick_file()
+                        .await?;
+                    Some(Firmware {
+                        name: file.file_name(),
+                        data: file.read().await,
+                    })
+                }));
+            }
+            if let Some(pick) = &mut self.pick
+                && let Poll::Ready(picked) = poll(pick.as_mut(), ctx)
+            {
+                self.pick = None;
+                if let Some(firmware) = picked {
+                    self.firmware = Some(Rc::new(firmware));
+                    self.update = None;
+                    self.error = None;
+                }
+            }
+
+            let Some(firmware) = &self.firmware else {
+                ui.label(tr!("gui-no-firmware"));
+                return;
+            };
+
+            ui.separator();
+            if !running {
+                ui.label(tr!("web-connect"));
+                if ui.button(tr!("gui-update")).clicked() {
+                    self.error = None;
+                    let shown = Rc::new(RefCell::new(Shown {
+                        phase: None,
+                        progress: PROGRESS_INIT,
+                        finished: false,
+                    }));
+                    let mut progress = Report(shown.clone());
+                    let cancel = Cancel::new();
+                    let firmware = firmware.clone();
+                    let future = {
+                        let cancel = cancel.clone();
+                        async move { update(&firmware, cancel, &mut progress).await }
+                    };
+                    self.update = Some(Update {
+                        future: Some(Box::pin(future)),
+                        shown,
+                        cancel,
+                    });
+                }
+            }
+
+            if let Some(update) = &mut self.update {
+                if let Some(future) = &mut update.future
+                    && let Poll::Ready(result) = poll(future.as_mut(), ctx)
+                {
+                    update.future = None;
+                    if let Err(e) = result {
+                        // Closing the browser's device list writes nothing.
+                        let started = update.shown.borrow().phase.is_some();
+                        if started || !matches!(e, BikesafeError::Cancelled) {
+                            self.error = Some(user_message(&e));
+                        }
+                        self.update = None;
+                        return;
+                    }
+                }
+                let shown = update.shown.borrow();
+                if let Some(phase) = shown.phase.filter(|_| !shown.finished) {
+                    ui.label(phase_label(phase));
+                }
+                ui.add(ProgressBar::new(shown.progress).show_percentage());
+                if shown.finished {
+                    ui.label(tr!("gui-flash-complete"));
+                } else if ui
+                    .add_enabled(
+                        !update.cancel.is_cancelled(),
+                        egui::Button::new(tr!("gui-cancel")),
+                    )
+                    .clicked()
+                {
+                    update.cancel.cancel();
+                }
+            }
+        });
+    }
+}
+
+/// Ask for the device, then check, write and start `firmware` on it,
+/// reporting to `progress`.
+async fn update(
+    firmware: &Firmware,
+    cancel: Cancel,
+    progress: &mut Report,
+) -> Result<(), BikesafeError> {
+    let ids: Vec<_> = FAMILIES
+        .iter()
+        .flat_map(|family| family.dfu_ids)
+        .copied()
+        .collect();
+    let io = WebUsbDfu::request(&ids).await?;
+    let id = io.id();
+    let family = family::find(id.0, id.1).unwrap_or(&family::BRAKEBRIGHT);
+    let memory = family.memory_map(id, None);
+    let address = memory.flash.origin;
+    let image = read_image(firmware, address)?;
+    bikesafe_core::validate_for(memory, &image)?;
+
+    let io = Cancellable::new(io, cancel);
+    let total = image.len() as u64;
+    let mut erase = Counter::new(&mut *progress, Phase::Erase, total);
+    transfer::erase(&io, address, &image, |n| erase.advance(n)).await?;
+    let mut write = Counter::new(&mut *progress, Phase::Download, total);
+    transfer::download(&io, address, &image, |n| write.advance(n)).await?;
+    match dfuse::leave(&io, address).await {
+        // The device may drop off the bus before answering.
+        Ok(()) | Err(BikesafeError::Disconnected | BikesafeError::Backend(_)) => {}
+        Err(e) => return Err(e),
+    }
+    progress.finished();
+    Ok(())
+}
+
+/// The application image in `firmware`: a .bin as it is, or the one
+/// element of alternate setting 0 of a .dfu, which has to be linked for
+/// `address`.
+fn read_image(firmware: &Firmware, address: u32) -> Result<Vec<u8>, BikesafeError> {
+    if !firmware.name.ends_with(".dfu") {
+        return Ok(firmware.data.clone());
+    }
+    let invalid = |error: Box<dyn std::error::Error + Send + Sync>| BikesafeError::ReadFirmware {
+        path: firmware.name.clone().into(),
+        source: std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+    };
+    let dfu = DfuFile::from_bytes(&firmware.data).map_err(|e| invalid(e.into()))?;
+    let elements = dfu
+        .target(0)
+        .map_or(&[][..], |target| target.elements.as_slice());
+    let [element] = elements else {
+        return Err(invalid(
+            format!(
+                "alternate setting 0 has {} images, only one can be written",
+                elements.len()
+            )
+            .into(),
+        ));
+    };
+    if element.address != address {
+        return Err(invalid(
+            format!(
+                "image is at {:#010X}, the application starts at {address:#010X}",
+                element.address
+            )
+            .into(),
+        ));
+    }
+    Ok(element.data.clone())
+}
+
+/// What to tell the user about `error`.
+fn user_message(error: &BikesafeError) -> String {
+    match error {
+        BikesafeError::ValidationFailed(e) => tr!("gui-invalid-firmware", reason = e.to_string()),
+        BikesafeError::Cancelled => tr!("gui-update-cancelled"),
+        BikesafeError::PermissionDenied => tr!("web-permission-denied"),
+        error => chain(error),
+    }
+}
+
+/// `error` and its sources, like anyhow's `{:#}`.
+fn chain(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+/// What the page says the update is doing.
+fn phase_label(phase: Phase) -> String {
+    match phase {
+        Phase::Erase => tr!("gui-phase-erase"),
+        Phase::Download => tr!("gui-phase-download"),
+        Phase::Upload => tr!("gui-phase-upload"),
+        Phase::Verify => tr!("gui-phase-verify"),
+    }
+}
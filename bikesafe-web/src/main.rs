@@ -0,0 +1,46 @@
+//! The updater as a web page, for users who would rather not install
+//! anything: the egui front-end compiled to WebAssembly, flashing over the
+//! browser's WebUSB through the same update steps as the desktop tools.
+//! WebUSB is only in Chromium-based browsers, on pages served over HTTPS or
+//! from localhost.
+//!
+//! Built with [trunk](https://trunkrs.dev) from this directory:
+//!
+//! ```sh
+//! rustup target add wasm32-unknown-unknown
+//! trunk build --release   # or `trunk serve` while working on it
+//! ```
+//!
+//! which leaves the page in `dist/`, ready to be put on the website.
+
+#[cfg(target_arch = "wasm32")]
+mod app;
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use wasm_bindgen::JsCast;
+
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("bikesafe"))
+        .and_then(|canvas| canvas.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+        .expect("index.html has no canvas `bikesafe`");
+    wasm_bindgen_futures::spawn_local(async move {
+        let started = eframe::WebRunner::new()
+            .start(
+                canvas,
+                eframe::WebOptions::default(),
+                Box::new(|_cc| Ok(Box::new(app::WebApp::default()))),
+            )
+            .await;
+        if let Err(e) = started {
+            web_sys::console::error_2(&"could not start the updater:".into(), &e);
+        }
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("bikesafe-web runs in the browser; build it with `trunk build` (see src/main.rs)");
+    std::process::exit(1);
+}
@@ -0,0 +1,97 @@
+//! Advisory lock on a USB device, shared by the command-line and graphical
+//! flashers so that two of them never talk to the same device at once.
+//!
+//! The lock is an OS file lock on `bikesafe/usb-<bus>-<address>.lock` in
+//! `$XDG_RUNTIME_DIR` (or the temporary directory), and the file holds the
+//! owner's PID for the error message. The OS drops the lock when the owner
+//! exits, so a crashed flasher leaves nothing stale behind.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("device busy (locked by PID {0})")]
+    Busy(u32),
+    #[error("device busy (locked by another process)")]
+    BusyUnknown,
+    #[error("could not lock `{}`", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Bus number and address of a device.
+type Port = (u8, u8);
+
+/// Locks held by this process, so that taking the same device twice (e.g.
+/// once for a whole command and once per step) does not conflict with
+/// itself.
+static HELD: Mutex<Vec<(Port, Weak<File>)>> = Mutex::new(Vec::new());
+
+/// Held lock on one device; released when the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct DeviceLock {
+    _file: Arc<File>,
+}
+
+impl DeviceLock {
+    /// Lock the device at `bus` and `address`, failing immediately if
+    /// another process holds it.
+    pub fn acquire(bus: u8, address: u8) -> Result<Self, Error> {
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        held.retain(|(_, file)| file.strong_count() > 0);
+        if let Some(file) = held
+            .iter()
+            .find(|(port, _)| *port == (bus, address))
+            .and_then(|(_, file)| file.upgrade())
+        {
+            return Ok(Self { _file: file });
+        }
+
+        let path = lock_dir().join(format!("usb-{bus:03}-{address:03}.lock"));
+        let io = |source| Error::Io {
+            path: path.clone(),
+            source,
+        };
+        std::fs::create_dir_all(lock_dir()).map_err(io)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                return Err(match file.read_to_string(&mut pid) {
+                    Ok(_) => pid.trim().parse().map_or(Error::BusyUnknown, Error::Busy),
+                    Err(_) => Error::BusyUnknown,
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(io(e)),
+        }
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| write!(file, "{}", std::process::id()))
+            .map_err(io)?;
+
+        let file = Arc::new(file);
+        held.push(((bus, address), Arc::downgrade(&file)));
+        Ok(Self { _file: file })
+    }
+}
+
+/// Directory holding the lock files.
+pub fn lock_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("bikesafe")
+}
@@ -0,0 +1,141 @@
+//! Checks of application images against a memory map, made the same way
+//! before packaging, by `verify-file` and before flashing.
+
+use std::fmt;
+
+use crate::Region;
+
+/// The first two words of an image's vector table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorTable {
+    /// Initial stack pointer.
+    pub sp: u32,
+    /// Reset vector, with the Thumb bit.
+    pub reset: u32,
+}
+
+/// Why an image does not suit a memory map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// Shorter than the initial SP and reset vector.
+    TooShort,
+    /// The initial SP is outside `ram`.
+    StackPointer { sp: u32, ram: Region },
+    /// The reset vector lacks the Thumb bit, so the core would fault.
+    NotThumb { reset: u32 },
+    /// The reset vector points outside the image, at `start..end`.
+    ResetOutside { reset: u32, start: u32, end: u64 },
+    /// The image starts at `start`, outside writable flash.
+    StartsOutside { start: u64 },
+    /// The image runs `overrun` bytes past writable flash, which ends at
+    /// `end`.
+    Overruns { end: u64, overrun: u64 },
+}
+
+impl VectorTable {
+    /// Read the vector table at the start of `image`, written at `address`,
+    /// and check that the initial SP points into `ram` and the reset vector
+    /// is a Thumb address in the image.
+    ///
+    /// ```
+    /// use device_memory::{BRAKEBRIGHT, ImageError, VectorTable};
+    ///
+    /// let mut image = [0; 64];
+    /// image[..4].copy_from_slice(&0x2000_5000u32.to_le_bytes());
+    /// image[4..8].copy_from_slice(&0x0800_4021u32.to_le_bytes());
+    /// let vectors = VectorTable::check(&image, 0x0800_4000, BRAKEBRIGHT.ram).unwrap();
+    /// assert_eq!(vectors.reset, 0x0800_4021);
+    /// assert!(matches!(
+    ///     VectorTable::check(&image, 0x0800_0000, BRAKEBRIGHT.ram),
+    ///     Err(ImageError::ResetOutside { .. })
+    /// ));
+    /// ```
+    pub fn check(image: &[u8], address: u32, ram: Region) -> Result<Self, ImageError> {
+        let word = |offset: usize| -> Result<u32, ImageError> {
+            let bytes = image.get(offset..offset + 4).ok_or(ImageError::TooShort)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let (sp, reset) = (word(0)?, word(4)?);
+        // The stack grows down from the SP, so the end of RAM is valid.
+        if !(ram.origin as u64..=ram.end()).contains(&(sp as u64)) {
+            return Err(ImageError::StackPointer { sp, ram });
+        }
+        if reset & 1 == 0 {
+            return Err(ImageError::NotThumb { reset });
+        }
+        let end = address as u64 + image.len() as u64;
+        if !(address as u64..end).contains(&(reset as u64 & !1)) {
+            return Err(ImageError::ResetOutside {
+                reset,
+                start: address,
+                end,
+            });
+        }
+        Ok(Self { sp, reset })
+    }
+}
+
+/// Check that `start..end` lies in writable flash, given as `(start, end)`
+/// regions such as the application flash of a map or the writable pages
+/// of a DfuSe layout. Adjacent regions count as one.
+///
+/// ```
+/// use device_memory::{BRAKEBRIGHT, ImageError, check_fits};
+///
+/// let flash = [(BRAKEBRIGHT.flash.origin as u64, BRAKEBRIGHT.flash.end())];
+/// assert_eq!(check_fits(0x0800_4000, 0x0801_0000, &flash), Ok(()));
+/// assert_eq!(
+///     check_fits(0x0800_4000, 0x0801_0100, &flash),
+///     Err(ImageError::Overruns { end: 0x0801_0000, overrun: 0x100 })
+/// );
+/// ```
+pub fn check_fits(start: u64, end: u64, writable: &[(u64, u64)]) -> Result<(), ImageError> {
+    let mut covered = start;
+    while covered < end {
+        let Some(&(_, region_end)) = writable
+            .iter()
+            .find(|&&(from, to)| (from..to).contains(&covered))
+        else {
+            if covered == start {
+                return Err(ImageError::StartsOutside { start });
+            }
+            return Err(ImageError::Overruns {
+                end: covered,
+                overrun: end - covered,
+            });
+        };
+        covered = region_end;
+    }
+    Ok(())
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::TooShort => write!(f, "image too short for a vector table"),
+            ImageError::StackPointer { sp, ram } => write!(
+                f,
+                "invalid initial SP {sp:#010X}, expected between {:#010X} and {:#010X}",
+                ram.origin,
+                ram.end()
+            ),
+            ImageError::NotThumb { reset } => {
+                write!(f, "reset vector {reset:#010X} is not a Thumb address")
+            }
+            ImageError::ResetOutside { reset, start, end } => write!(
+                f,
+                "reset vector {reset:#010X} points outside the image ({start:#010X}..{end:#010X}); \
+                 is it linked for another address?"
+            ),
+            ImageError::StartsOutside { start } => {
+                write!(f, "starts outside writable flash at {start:#010X}")
+            }
+            ImageError::Overruns { end, overrun } => write!(
+                f,
+                "runs {overrun} bytes past the end of writable flash at {end:#010X}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
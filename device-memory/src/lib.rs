@@ -0,0 +1,142 @@
+//! Memory maps of the supported hardware: where the bootloader ends and the
+//! application goes, the RAM its stack may use, the erase page size, the
+//! flash the application must leave alone and where the MCU keeps its
+//! identification. The packager's size checks, the
+//! firmware checks of `bikesafe-core` and the CLI's address defaults all
+//! read them from here instead of repeating the numbers.
+//!
+//! Maps are looked up per VID:PID and hardware revision with [`find`];
+//! adding a board means adding an entry to [`HARDWARE`]. Images are
+//! checked against them with [`VectorTable::check`] and [`check_fits`].
+
+mod image;
+
+pub use image::{ImageError, VectorTable, check_fits};
+
+/// A range of addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub origin: u32,
+    pub length: u32,
+}
+
+/// Flash inside the application region that firmware images must not
+/// cover, e.g. data written per unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reserved {
+    pub name: &'static str,
+    pub region: Region,
+}
+
+/// Where the application goes and what it may point to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// Flash taken by the bootloader, directly before `flash`.
+    pub bootloader: Region,
+    /// Flash available to the application.
+    pub flash: Region,
+    /// RAM the initial stack pointer may point into.
+    pub ram: Region,
+    /// Erase page size of the flash.
+    pub page_size: u32,
+    pub reserved: &'static [Reserved],
+    /// Address of the MCU's 96-bit unique ID, in system memory.
+    pub unique_id: u32,
+    /// Address of the 16-bit register holding the MCU's flash size in KiB.
+    pub flash_size_register: u32,
+}
+
+/// The memory map of some revisions of a product.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hardware {
+    /// VID:PID of the bootloader.
+    pub id: (u16, u16),
+    /// Hardware revisions with this map, as provisioned (e.g. `1.0`); empty
+    /// for all of them.
+    pub revisions: &'static [&'static str],
+    /// Flash of the MCU fitted to these revisions, bootloader included, as
+    /// its flash size register reports it. Tells revisions with the same
+    /// IDs apart.
+    pub flash_size: u32,
+    pub memory: MemoryMap,
+}
+
+/// STM32F103 with 64 KiB of flash and 20 KiB of RAM, behind the 16 KiB
+/// BrakeBright bootloader.
+pub const BRAKEBRIGHT: MemoryMap = MemoryMap {
+    bootloader: Region {
+        origin: 0x0800_0000,
+        length: 16 * 1024,
+    },
+    flash: Region {
+        origin: 0x0800_4000,
+        length: 48 * 1024,
+    },
+    // The bootloader keeps the first 16 bytes of RAM across resets.
+    ram: Region {
+        origin: 0x2000_0000 + 0x10,
+        length: 20 * 1024 - 0x10,
+    },
+    page_size: 1024,
+    reserved: &[Reserved {
+        name: "provisioning",
+        region: Region {
+            origin: 0x0800_FC00,
+            length: 1024,
+        },
+    }],
+    unique_id: 0x1FFF_F7E8,
+    flash_size_register: 0x1FFF_F7E0,
+};
+
+/// Every known board.
+pub static HARDWARE: &[Hardware] = &[Hardware {
+    id: (0x1209, 0x2444),
+    revisions: &[],
+    flash_size: 64 * 1024,
+    memory: BRAKEBRIGHT,
+}];
+
+/// Memory map of the bootloader `vid:pid` on hardware revision `revision`,
+/// or on any revision if it is unknown.
+pub fn find(vid: u16, pid: u16, revision: Option<&str>) -> Option<&'static MemoryMap> {
+    HARDWARE
+        .iter()
+        .filter(|hardware| hardware.id == (vid, pid))
+        .find(|hardware| match revision {
+            Some(revision) => {
+                hardware.revisions.is_empty() || hardware.revisions.contains(&revision)
+            }
+            None => true,
+        })
+        .map(|hardware| &hardware.memory)
+}
+
+/// The board behind the bootloader `vid:pid` whose MCU has `flash_size`
+/// bytes of flash.
+pub fn identify(vid: u16, pid: u16, flash_size: u32) -> Option<&'static Hardware> {
+    HARDWARE
+        .iter()
+        .find(|hardware| hardware.id == (vid, pid) && hardware.flash_size == flash_size)
+}
+
+impl Region {
+    /// First address after the region.
+    pub const fn end(&self) -> u64 {
+        self.origin as u64 + self.length as u64
+    }
+
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        (self.origin as u64) < end && start < self.end()
+    }
+}
+
+impl MemoryMap {
+    /// The reserved region called `name`.
+    pub fn reserved(&self, name: &str) -> Option<&Region> {
+        self.reserved
+            .iter()
+            .find(|reserved| reserved.name == name)
+            .map(|reserved| &reserved.region)
+    }
+}
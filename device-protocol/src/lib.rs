@@ -0,0 +1,319 @@
+//! Host side of the BrakeBright runtime protocol: what the running
+//! application answers over USB, as opposed to the bootloader's DFU. The
+//! CLI's `app` commands and `update`, and the GUI's device panel, all talk
+//! to the application through this crate.
+//!
+//! The application has a HID interface whose feature reports carry the
+//! protocol, read with GET_REPORT and written with SET_REPORT. Every report
+//! is 8 bytes, the report ID first; multi-byte fields are little-endian:
+//!
+//! | ID   | Direction | Payload                                                       |
+//! |------|-----------|---------------------------------------------------------------|
+//! | 0x01 | get       | firmware major, minor, patch, hardware revision               |
+//! | 0x02 | get       | battery u16 mV, charge %, flags (bit 0: charging)             |
+//! | 0x03 | get/set   | brightness %, pattern, sensitivity (0.01 g), auto-off u16 min |
+//! | 0x04 | set       | 0x01 starts the self-test                                     |
+//! | 0x04 | get       | state (0 idle, 1 running, 2 passed, 3 failed), failed checks  |
+//! | 0x05 | set       | command: 0xDF reboots into the DFU bootloader                 |
+//!
+//! ```no_run
+//! use rusb::UsbContext;
+//!
+//! let devices = rusb::Context::new()?.devices()?;
+//! let device = devices
+//!     .iter()
+//!     .find(|device| device_protocol::interface(device).is_some())
+//!     .ok_or("no application running")?;
+//! let app = device_protocol::Runtime::open(&device)?;
+//! println!("version {}, battery {}", app.version()?, app.battery()?);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use rusb::{DeviceHandle, UsbContext};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Interval between self-test status reads.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const HID_CLASS: u8 = 0x03;
+const HID_GET_REPORT: u8 = 0x01;
+const HID_SET_REPORT: u8 = 0x09;
+const FEATURE_REPORT: u16 = 0x0300;
+
+const REPORT_LEN: usize = 8;
+const REPORT_VERSION: u8 = 0x01;
+const REPORT_BATTERY: u8 = 0x02;
+const REPORT_SETTINGS: u8 = 0x03;
+const REPORT_SELF_TEST: u8 = 0x04;
+const REPORT_COMMAND: u8 = 0x05;
+
+const SELF_TEST_START: u8 = 0x01;
+const COMMAND_REBOOT_DFU: u8 = 0xDF;
+
+/// Names of the self-test checks, by bit in the failed-checks byte.
+const CHECKS: [&str; 4] = ["LED driver", "accelerometer", "battery", "firmware CRC"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("USB transfer failed")]
+    Usb(#[from] rusb::Error),
+    #[error("device has no HID interface; is it running the BrakeBright application?")]
+    NoInterface,
+    #[error("report {id:#04x} is {len} bytes, expected {REPORT_LEN}")]
+    ShortReport { id: u8, len: usize },
+    #[error("invalid value {value:#04x} in report {id:#04x}")]
+    InvalidReport { id: u8, value: u8 },
+    #[error("brightness must be at most 100%, not {0}%")]
+    InvalidBrightness(u8),
+    #[error("self-test did not finish within {0:?}")]
+    SelfTestTimeout(Duration),
+}
+
+/// Firmware version and hardware revision reported by the application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    pub hardware: u8,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Battery {
+    pub millivolts: u16,
+    pub percent: u8,
+    pub charging: bool,
+}
+
+impl fmt::Display for Battery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}% ({} mV)", self.percent, self.millivolts)?;
+        if self.charging {
+            write!(f, ", charging")?;
+        }
+        Ok(())
+    }
+}
+
+/// The user settings stored on the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// LED brightness, 0-100%.
+    pub brightness: u8,
+    /// Index of the brake-light flash pattern.
+    pub pattern: u8,
+    /// Deceleration that triggers the brake light, in 0.01 g.
+    pub sensitivity: u8,
+    /// Minutes without motion before the device turns off; 0 never does.
+    pub auto_off_minutes: u16,
+}
+
+impl Settings {
+    fn from_report(report: &[u8; REPORT_LEN]) -> Self {
+        Settings {
+            brightness: report[1],
+            pattern: report[2],
+            sensitivity: report[3],
+            auto_off_minutes: u16::from_le_bytes([report[4], report[5]]),
+        }
+    }
+
+    fn to_report(self) -> Result<[u8; REPORT_LEN], Error> {
+        if self.brightness > 100 {
+            return Err(Error::InvalidBrightness(self.brightness));
+        }
+        let [off0, off1] = self.auto_off_minutes.to_le_bytes();
+        Ok([
+            REPORT_SETTINGS,
+            self.brightness,
+            self.pattern,
+            self.sensitivity,
+            off0,
+            off1,
+            0,
+            0,
+        ])
+    }
+}
+
+/// Outcome of a finished self-test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfTest {
+    Passed,
+    /// Failed, with a bit set for each failed check.
+    Failed(u8),
+}
+
+impl SelfTest {
+    /// Names of the failed checks.
+    pub fn failed_checks(&self) -> Vec<&'static str> {
+        let SelfTest::Failed(bits) = *self else {
+            return Vec::new();
+        };
+        (0..8)
+            .filter(|bit| bits & (1 << bit) != 0)
+            .map(|bit| CHECKS.get(bit).copied().unwrap_or("unknown check"))
+            .collect()
+    }
+}
+
+impl fmt::Display for SelfTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelfTest::Passed => write!(f, "passed"),
+            SelfTest::Failed(_) => write!(f, "failed: {}", self.failed_checks().join(", ")),
+        }
+    }
+}
+
+/// Number of the HID interface in the active configuration of `device`;
+/// `None` if the device is not running an application that speaks the
+/// protocol (e.g. it is in DFU mode).
+pub fn interface<T: UsbContext>(device: &rusb::Device<T>) -> Option<u8> {
+    let config = device.active_config_descriptor().ok()?;
+    config
+        .interfaces()
+        .flat_map(|i| i.descriptors())
+        .find(|d| d.class_code() == HID_CLASS)
+        .map(|d| d.interface_number())
+}
+
+/// The application's HID interface, claimed until dropped.
+pub struct Runtime<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    interface: u8,
+}
+
+impl<T: UsbContext> Runtime<T> {
+    /// Open `device` and claim its HID interface, detaching the kernel's
+    /// HID driver meanwhile where the platform allows.
+    pub fn open(device: &rusb::Device<T>) -> Result<Self, Error> {
+        let interface = interface(device).ok_or(Error::NoInterface)?;
+        let handle = device.open()?;
+        match handle.set_auto_detach_kernel_driver(true) {
+            Ok(()) | Err(rusb::Error::NotSupported) => (),
+            Err(e) => return Err(e.into()),
+        }
+        handle.claim_interface(interface)?;
+        Ok(Runtime { handle, interface })
+    }
+
+    fn get_report(&self, id: u8) -> Result<[u8; REPORT_LEN], Error> {
+        let mut report = [0; REPORT_LEN];
+        let len = self.handle.read_control(
+            rusb::request_type(
+                rusb::Direction::In,
+                rusb::RequestType::Class,
+                rusb::Recipient::Interface,
+            ),
+            HID_GET_REPORT,
+            FEATURE_REPORT | id as u16,
+            self.interface as u16,
+            &mut report,
+            TIMEOUT,
+        )?;
+        if len != REPORT_LEN {
+            return Err(Error::ShortReport { id, len });
+        }
+        if report[0] != id {
+            return Err(Error::InvalidReport {
+                id,
+                value: report[0],
+            });
+        }
+        Ok(report)
+    }
+
+    fn set_report(&self, report: &[u8; REPORT_LEN]) -> Result<(), Error> {
+        self.handle.write_control(
+            rusb::request_type(
+                rusb::Direction::Out,
+                rusb::RequestType::Class,
+                rusb::Recipient::Interface,
+            ),
+            HID_SET_REPORT,
+            FEATURE_REPORT | report[0] as u16,
+            self.interface as u16,
+            report,
+            TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    pub fn version(&self) -> Result<Version, Error> {
+        let report = self.get_report(REPORT_VERSION)?;
+        Ok(Version {
+            major: report[1],
+            minor: report[2],
+            patch: report[3],
+            hardware: report[4],
+        })
+    }
+
+    pub fn battery(&self) -> Result<Battery, Error> {
+        let report = self.get_report(REPORT_BATTERY)?;
+        Ok(Battery {
+            millivolts: u16::from_le_bytes([report[1], report[2]]),
+            percent: report[3],
+            charging: report[4] & 1 != 0,
+        })
+    }
+
+    pub fn settings(&self) -> Result<Settings, Error> {
+        Ok(Settings::from_report(&self.get_report(REPORT_SETTINGS)?))
+    }
+
+    /// Store new settings; the device applies them at once.
+    pub fn set_settings(&self, settings: Settings) -> Result<(), Error> {
+        self.set_report(&settings.to_report()?)
+    }
+
+    /// Run the device's self-test and wait up to `timeout` for its result.
+    pub fn self_test(&self, timeout: Duration) -> Result<SelfTest, Error> {
+        let mut start = [0; REPORT_LEN];
+        start[..2].copy_from_slice(&[REPORT_SELF_TEST, SELF_TEST_START]);
+        self.set_report(&start)?;
+
+        let started = Instant::now();
+        loop {
+            let report = self.get_report(REPORT_SELF_TEST)?;
+            match report[1] {
+                // Idle until the application picked up the request.
+                0 | 1 => (),
+                2 => return Ok(SelfTest::Passed),
+                3 => return Ok(SelfTest::Failed(report[2])),
+                value => {
+                    return Err(Error::InvalidReport {
+                        id: REPORT_SELF_TEST,
+                        value,
+                    });
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Err(Error::SelfTestTimeout(timeout));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Restart into the DFU bootloader. The device drops off the bus, so a
+    /// transfer that fails because it is gone counts as success.
+    pub fn reboot_to_dfu(self) -> Result<(), Error> {
+        let mut command = [0; REPORT_LEN];
+        command[..2].copy_from_slice(&[REPORT_COMMAND, COMMAND_REBOOT_DFU]);
+        match self.set_report(&command) {
+            Ok(()) | Err(Error::Usb(rusb::Error::NoDevice | rusb::Error::Io)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Arrival and removal of the devices with one VID:PID, for the front-ends
+//! that wait for a device instead of asking for it: the GUI's device
+//! status, the CLI's `--watch` production mode and its wait for the device
+//! to come back after a detach or an update.
+//!
+//! Events come from libusb's hotplug callbacks where the platform has them
+//! (Linux, macOS) and from scanning the bus every 500 ms elsewhere
+//! (Windows). Either way a background thread delivers them in order, and
+//! devices already connected are reported as arrived first:
+//!
+//! ```no_run
+//! use device_watch::DeviceEvent;
+//!
+//! for event in device_watch::watch_devices(0x1209, 0x2444)? {
+//!     match event {
+//!         DeviceEvent::Arrived(device) if device.dfu => println!("{device} in DFU mode"),
+//!         DeviceEvent::Arrived(device) => println!("{device} running its application"),
+//!         DeviceEvent::Left(device) => println!("{device} unplugged"),
+//!     }
+//! }
+//! # Ok::<(), device_watch::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rusb::{Context, Hotplug, HotplugBuilder, UsbContext};
+
+/// Interval between bus scans without hotplug support, and the longest a
+/// dropped watcher waits for its thread.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Interface class/subclass of DFU interfaces, and the protocol of DFU mode
+/// (as opposed to the runtime interface of an application).
+const DFU_CLASS: (u8, u8) = (0xFE, 0x01);
+const PROTOCOL_DFU: u8 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not watch for USB devices")]
+    Usb(#[from] rusb::Error),
+}
+
+/// A matching device, as it was when it arrived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceInfo {
+    pub bus: u8,
+    pub address: u8,
+    pub vid: u16,
+    pub pid: u16,
+    /// Whether the device has a DFU-mode interface, i.e. sits in its
+    /// bootloader.
+    pub dfu: bool,
+}
+
+impl DeviceInfo {
+    fn new(device: &rusb::Device<Context>) -> Option<Self> {
+        let descriptor = device.device_descriptor().ok()?;
+        let dfu = device.active_config_descriptor().is_ok_and(|config| {
+            config.interfaces().flat_map(|i| i.descriptors()).any(|d| {
+                (d.class_code(), d.sub_class_code()) == DFU_CLASS
+                    && d.protocol_code() == PROTOCOL_DFU
+            })
+        });
+        Some(DeviceInfo {
+            bus: device.bus_number(),
+            address: device.address(),
+            vid: descriptor.vendor_id(),
+            pid: descriptor.product_id(),
+            dfu,
+        })
+    }
+
+    /// Bus number and address, which tell apart devices with the same
+    /// VID:PID.
+    pub fn port(&self) -> (u8, u8) {
+        (self.bus, self.address)
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:04x} at bus {:03} address {:03}",
+            self.vid, self.pid, self.bus, self.address
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Arrived(DeviceInfo),
+    Left(DeviceInfo),
+}
+
+/// Where the events come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Hotplug,
+    Polling,
+}
+
+/// The devices present, to report each arrival and removal once.
+struct Tracker {
+    present: HashMap<(u8, u8), DeviceInfo>,
+    sender: Sender<DeviceEvent>,
+}
+
+impl Tracker {
+    fn arrived(&mut self, device: DeviceInfo) {
+        if self.present.insert(device.port(), device) != Some(device) {
+            let _ = self.sender.send(DeviceEvent::Arrived(device));
+        }
+    }
+
+    fn left(&mut self, port: (u8, u8)) {
+        if let Some(device) = self.present.remove(&port) {
+            let _ = self.sender.send(DeviceEvent::Left(device));
+        }
+    }
+
+    /// Compare a bus scan with the devices seen so far.
+    fn scan(&mut self, devices: Vec<DeviceInfo>) {
+        let gone: Vec<_> = self
+            .present
+            .keys()
+            .filter(|port| !devices.iter().any(|device| device.port() == **port))
+            .copied()
+            .collect();
+        for port in gone {
+            self.left(port);
+        }
+        for device in devices {
+            self.arrived(device);
+        }
+    }
+}
+
+impl Hotplug<Context> for Tracker {
+    fn device_arrived(&mut self, device: rusb::Device<Context>) {
+        if let Some(device) = DeviceInfo::new(&device) {
+            self.arrived(device);
+        }
+    }
+
+    fn device_left(&mut self, device: rusb::Device<Context>) {
+        self.left((device.bus_number(), device.address()));
+    }
+}
+
+/// Events for one VID:PID, until dropped. Iterating blocks for the next
+/// event; [`try_iter`](Self::try_iter) and
+/// [`next_timeout`](Self::next_timeout) suit event loops.
+pub struct DeviceWatcher {
+    receiver: Receiver<DeviceEvent>,
+    backend: Backend,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Start watching for devices `vid:pid`.
+pub fn watch_devices(vid: u16, pid: u16) -> Result<DeviceWatcher, Error> {
+    let context = Context::new()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut tracker = Tracker {
+        present: HashMap::new(),
+        sender,
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+
+    let (backend, thread) = if rusb::has_hotplug() {
+        let registration = HotplugBuilder::new()
+            .vendor_id(vid)
+            .product_id(pid)
+            .enumerate(true)
+            .register(&context, Box::new(tracker))?;
+        let thread = thread::spawn(move || {
+            let _registration = registration;
+            while !stopped.load(Ordering::Relaxed) {
+                if context.handle_events(Some(POLL_INTERVAL)).is_err() {
+                    break;
+                }
+            }
+        });
+        (Backend::Hotplug, thread)
+    } else {
+        let thread = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let Ok(devices) = context.devices() else {
+                    break;
+                };
+                let matching = devices
+                    .iter()
+                    .filter_map(|device| DeviceInfo::new(&device))
+                    .filter(|device| (device.vid, device.pid) == (vid, pid))
+                    .collect();
+                tracker.scan(matching);
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        (Backend::Polling, thread)
+    };
+    Ok(DeviceWatcher {
+        receiver,
+        backend,
+        stop,
+        thread: Some(thread),
+    })
+}
+
+impl DeviceWatcher {
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The events that happened since the last call, without blocking.
+    pub fn try_iter(&self) -> impl Iterator<Item = DeviceEvent> + '_ {
+        self.receiver.try_iter()
+    }
+
+    /// The next event, or `None` if none came within `timeout`.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<DeviceEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for DeviceWatcher {
+    type Item = DeviceEvent;
+
+    /// The next event; `None` once the USB context failed.
+    fn next(&mut self) -> Option<DeviceEvent> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
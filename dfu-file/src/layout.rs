@@ -0,0 +1,238 @@
+//! Parser for DfuSe interface strings such as
+//! `@Internal Flash  /0x08000000/16*001Ka,48*001Kg`.
+//!
+//! `dfu-core` only keeps the page sizes of the first segment; this keeps the
+//! region name, every segment's start address and the sector attributes.
+
+/// Memory described by one DfuSe alternate setting.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MemoryLayout {
+    pub name: String,
+    pub segments: Vec<Segment>,
+}
+
+/// A contiguous run of sectors starting at `address`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Segment {
+    pub address: u32,
+    pub sectors: Vec<Sectors>,
+}
+
+/// `count` sectors of `size` bytes sharing the same attributes.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Sectors {
+    pub count: u32,
+    pub size: u32,
+    pub readable: bool,
+    pub erasable: bool,
+    pub writable: bool,
+}
+
+/// Most sectors one description may declare. DfuSe writes the count with
+/// three digits; this bounds what [`MemoryLayout::pages`] walks for a
+/// malformed string.
+const MAX_SECTOR_COUNT: u32 = 4096;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error("invalid segment address `{0}`")]
+    SegmentAddress(String),
+    #[error("missing sectors for segment {0:#010X}")]
+    MissingSectors(u32),
+    #[error("invalid sector description `{0}`")]
+    Sectors(String),
+    #[error("missing sector type in `{0}`")]
+    SectorType(String),
+    #[error("too many sectors in `{0}`")]
+    SectorCount(String),
+    #[error("segment {0:#010X} extends past 4 GiB")]
+    SegmentEnd(u32),
+}
+
+impl MemoryLayout {
+    /// Parse a DfuSe interface string. Returns `None` for strings that do not
+    /// use the `@name/address/sectors` notation.
+    pub fn parse(interface_string: &str) -> Option<Result<Self, LayoutError>> {
+        let rest = interface_string.strip_prefix('@')?;
+        Some(Self::parse_body(rest))
+    }
+
+    fn parse_body(rest: &str) -> Result<Self, LayoutError> {
+        let mut parts = rest.split('/');
+        let name = parts.next().unwrap_or_default().trim().to_string();
+        let mut segments = Vec::new();
+        while let Some(address) = parts.next() {
+            let address = address.trim();
+            let address = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+                .unwrap_or(address);
+            let address = u32::from_str_radix(address, 16)
+                .map_err(|_| LayoutError::SegmentAddress(address.to_string()))?;
+            let sectors: Vec<Sectors> = parts
+                .next()
+                .ok_or(LayoutError::MissingSectors(address))?
+                .split(',')
+                .map(Sectors::parse)
+                .collect::<Result<_, _>>()?;
+            let length: u64 = sectors
+                .iter()
+                .map(|sectors| sectors.count as u64 * sectors.size as u64)
+                .sum();
+            if address as u64 + length > 1 << 32 {
+                return Err(LayoutError::SegmentEnd(address));
+            }
+            segments.push(Segment { address, sectors });
+        }
+        Ok(Self { name, segments })
+    }
+
+    /// Every page (sector) of the layout with its start address, in order.
+    pub fn pages(&self) -> impl Iterator<Item = (u64, &Sectors)> {
+        self.segments.iter().flat_map(|segment| {
+            segment
+                .sectors
+                .iter()
+                .flat_map(|sectors| std::iter::repeat_n(sectors, sectors.count as usize))
+                .scan(segment.address as u64, |address, sectors| {
+                    let page = *address;
+                    *address += sectors.size as u64;
+                    Some((page, sectors))
+                })
+        })
+    }
+}
+
+impl Sectors {
+    /// Parse `NN*SSSUt` where `U` is ` `, `K` or `M` and `t` is the attribute
+    /// letter (`a`..`g`: bit 0 readable, bit 1 erasable, bit 2 writable).
+    fn parse(s: &str) -> Result<Self, LayoutError> {
+        let s = s.trim();
+        let invalid = || LayoutError::Sectors(s.to_string());
+        let (count, size) = s.split_once('*').ok_or_else(invalid)?;
+        let count = count.trim().parse().map_err(|_| invalid())?;
+        if count > MAX_SECTOR_COUNT {
+            return Err(LayoutError::SectorCount(s.to_string()));
+        }
+
+        let digits = size.trim_end_matches(|c: char| !c.is_ascii_digit());
+        let mut suffix = size[digits.len()..].chars();
+        let size: u32 = digits.parse().map_err(|_| invalid())?;
+        let (multiplier, kind) = match (suffix.next(), suffix.next()) {
+            (Some('K'), Some(kind)) => (1024, kind),
+            (Some('M'), Some(kind)) => (1024 * 1024, kind),
+            (Some(_), Some(kind)) => (1, kind),
+            (Some(kind), None) => (1, kind),
+            (None, _) => return Err(LayoutError::SectorType(s.to_string())),
+        };
+        let size = size.checked_mul(multiplier).ok_or_else(invalid)?;
+        let bits = (kind as u8).wrapping_sub(b'a' - 1);
+
+        Ok(Self {
+            count,
+            size,
+            readable: bits & 0b001 != 0,
+            erasable: bits & 0b010 != 0,
+            writable: bits & 0b100 != 0,
+        })
+    }
+
+    /// Short human-readable form of the attributes, e.g. `read/erase/write`.
+    pub fn access(&self) -> String {
+        let access: Vec<_> = [
+            (self.readable, "read"),
+            (self.erasable, "erase"),
+            (self.writable, "write"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        if access.is_empty() {
+            "no access".to_string()
+        } else {
+            access.join("/")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sectors() {
+        let sectors = Sectors::parse(" 16*001Ka").unwrap();
+        assert_eq!((sectors.count, sectors.size), (16, 1024));
+        assert_eq!(sectors.access(), "read");
+        let sectors = Sectors::parse("2*128Mg").unwrap();
+        assert_eq!((sectors.count, sectors.size), (2, 128 << 20));
+        assert_eq!(sectors.access(), "read/erase/write");
+        let sectors = Sectors::parse("1*16 f").unwrap();
+        assert_eq!((sectors.count, sectors.size), (1, 16));
+        assert_eq!(sectors.access(), "erase/write");
+        let sectors = Sectors::parse("4*512g").unwrap();
+        assert_eq!((sectors.count, sectors.size), (4, 512));
+    }
+
+    #[test]
+    fn invalid_sectors() {
+        for s in ["16001Ka", "x*1Ka", "16*Ka", "16*1"] {
+            assert!(Sectors::parse(s).is_err(), "{s}");
+        }
+        assert!(matches!(
+            Sectors::parse("16*1"),
+            Err(LayoutError::SectorType(_))
+        ));
+        assert!(matches!(
+            Sectors::parse("1*8192Mg"),
+            Err(LayoutError::Sectors(s)) if s == "1*8192Mg"
+        ));
+        assert!(matches!(
+            Sectors::parse("4294967295*1Kg"),
+            Err(LayoutError::SectorCount(_))
+        ));
+    }
+
+    #[test]
+    fn memory_layout() {
+        let layout =
+            MemoryLayout::parse("@Internal Flash  /0x08000000/16*001Ka,48*001Kg/0x1FFF0000/1*2Kf")
+                .unwrap()
+                .unwrap();
+        assert_eq!(layout.name, "Internal Flash");
+        assert_eq!(layout.segments.len(), 2);
+        assert_eq!(layout.segments[0].address, 0x0800_0000);
+        assert_eq!(layout.segments[0].sectors.len(), 2);
+        assert_eq!(layout.segments[1].address, 0x1FFF_0000);
+
+        let pages: Vec<_> = layout.pages().map(|(address, _)| address).collect();
+        assert_eq!(pages.len(), 16 + 48 + 1);
+        assert_eq!(pages[1], 0x0800_0400);
+        assert_eq!(pages[64], 0x1FFF_0000);
+    }
+
+    #[test]
+    fn invalid_memory_layout() {
+        assert!(MemoryLayout::parse("ST...").is_none());
+        assert!(matches!(
+            MemoryLayout::parse("@Flash/0x0800000G/1*1Kg"),
+            Some(Err(LayoutError::SegmentAddress(_)))
+        ));
+        assert!(matches!(
+            MemoryLayout::parse("@Flash/0x08000000"),
+            Some(Err(LayoutError::MissingSectors(0x0800_0000)))
+        ));
+        assert!(matches!(
+            MemoryLayout::parse("@Flash/0xFFFF0000/65*1Kg"),
+            Some(Err(LayoutError::SegmentEnd(0xFFFF_0000)))
+        ));
+        assert!(
+            MemoryLayout::parse("@Flash/0xFFFF0000/64*1Kg")
+                .unwrap()
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Reader and writer for DfuSe files (ST UM0391): a `DfuSe` prefix, one
+//! target per alternate setting with its addressed elements, and the
+//! 16-byte DFU suffix with its CRC.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), dfu_file::Error> {
+//! let dfu = dfu_file::DfuFile::from_reader(std::fs::File::open("firmware.dfu")?)?;
+//! for element in dfu.elements() {
+//!     println!("{} bytes at {:#010X}", element.data.len(), element.address);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod layout;
+mod read;
+mod write;
+
+pub use layout::{LayoutError, MemoryLayout};
+pub use read::Suffix;
+pub use write::append_suffix;
+
+/// Length of the DFU suffix at the end of the file.
+pub const SUFFIX_LEN: usize = 16;
+/// "DfuSe", bVersion, dwSize, bTargets.
+pub const PREFIX_LEN: usize = 11;
+/// "Target", bAlternateSetting, dwNamed, szTargetName, dwTargetSize,
+/// dwNbElements.
+pub const TARGET_PREFIX_LEN: usize = 274;
+/// bcdDFU of DfuSe files (1.1a).
+pub const BCD_DFU: u16 = 0x011A;
+/// bcdDFU of plain DFU 1.1 files, a raw image with just the suffix.
+pub const BCD_DFU_1_1: u16 = 0x0100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("no DFU suffix (`UFD` signature missing)")]
+    NoSuffix,
+    #[error("unsupported DFU suffix length {0}")]
+    SuffixLength(u8),
+    #[error("DFU suffix CRC {crc:#010X} does not match the file ({computed:#010X})")]
+    Crc { crc: u32, computed: u32 },
+    #[error("no DfuSe prefix")]
+    NoPrefix,
+    #[error("unsupported DfuSe version {0}")]
+    Version(u8),
+    #[error("dwSize is {size}, but the file without its suffix is {actual} bytes")]
+    Size { size: u32, actual: usize },
+    #[error("target {0} has no `Target` signature")]
+    TargetSignature(u8),
+    #[error("target {0} is truncated")]
+    TargetTruncated(u8),
+    #[error("element {element} of target {target} is truncated")]
+    ElementTruncated { target: u8, element: u32 },
+    #[error("target name `{0}` is longer than 254 bytes")]
+    NameTooLong(String),
+    #[error("too many targets ({0}, at most 255)")]
+    TooManyTargets(usize),
+    #[error("element at {0:#010X} is larger than 4 GiB")]
+    ElementTooLarge(u32),
+}
+
+/// One contiguous image to flash at `address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuElement {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl DfuElement {
+    /// Address one past the last byte.
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+/// A DFU “Target” (alternate interface), with a 255-byte name (padded).
+/// An empty name is written as an unnamed target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuTarget {
+    pub name: String,
+    pub alternate_setting: u8,
+    pub elements: Vec<DfuElement>,
+}
+
+/// A whole DfuSe file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuFile {
+    pub device_vid: u16,
+    pub device_pid: u16,
+    /// Firmware version from the suffix (`bcdDevice`).
+    pub bcd_device: u16,
+    pub targets: Vec<DfuTarget>,
+}
+
+impl DfuFile {
+    /// Elements of all targets, in file order.
+    pub fn elements(&self) -> impl Iterator<Item = &DfuElement> {
+        self.targets.iter().flat_map(|target| &target.elements)
+    }
+
+    /// Target for alternate setting `alt`, if there is one.
+    pub fn target(&self, alt: u8) -> Option<&DfuTarget> {
+        self.targets
+            .iter()
+            .find(|target| target.alternate_setting == alt)
+    }
+}
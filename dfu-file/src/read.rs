@@ -0,0 +1,156 @@
+use std::io::Read;
+
+use crate::{DfuElement, DfuFile, DfuTarget, Error, PREFIX_LEN, SUFFIX_LEN, TARGET_PREFIX_LEN};
+
+/// The DFU suffix, with the CRC recomputed over the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suffix {
+    pub bcd_device: u16,
+    pub pid: u16,
+    pub vid: u16,
+    pub bcd_dfu: u16,
+    pub crc: u32,
+    pub computed_crc: u32,
+}
+
+impl Suffix {
+    /// Read the suffix at the end of `file`. The CRC is not checked here;
+    /// see [`Suffix::crc_valid`].
+    pub fn parse(file: &[u8]) -> Result<Self, Error> {
+        // bcdDevice, idProduct, idVendor, bcdDFU, "UFD", bLength, dwCRC
+        let suffix = file
+            .len()
+            .checked_sub(SUFFIX_LEN)
+            .map(|start| &file[start..])
+            .filter(|suffix| &suffix[8..11] == b"UFD")
+            .ok_or(Error::NoSuffix)?;
+        if suffix[11] as usize != SUFFIX_LEN {
+            return Err(Error::SuffixLength(suffix[11]));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([suffix[i], suffix[i + 1]]);
+        Ok(Self {
+            bcd_device: u16_at(0),
+            pid: u16_at(2),
+            vid: u16_at(4),
+            bcd_dfu: u16_at(6),
+            crc: u32::from_le_bytes(suffix[12..16].try_into().unwrap()),
+            computed_crc: !crc32fast::hash(&file[..file.len() - 4]),
+        })
+    }
+
+    pub fn crc_valid(&self) -> bool {
+        self.crc == self.computed_crc
+    }
+}
+
+impl DfuFile {
+    /// Read a whole DfuSe file from `reader`; see [`DfuFile::from_bytes`].
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parse a DfuSe file, checking the prefix and suffix signatures, the
+    /// format version, the suffix CRC and the prefix's `dwSize`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let suffix = Suffix::parse(bytes)?;
+        if !suffix.crc_valid() {
+            return Err(Error::Crc {
+                crc: suffix.crc,
+                computed: suffix.computed_crc,
+            });
+        }
+        let dfu = Self::from_bytes_unchecked(bytes)?;
+        let size = u32_at(bytes, 6);
+        let actual = bytes.len() - SUFFIX_LEN;
+        if size as usize != actual {
+            return Err(Error::Size { size, actual });
+        }
+        Ok(dfu)
+    }
+
+    /// Like [`DfuFile::from_bytes`], but without checking the suffix CRC
+    /// and `dwSize`, for tools that report them separately.
+    pub fn from_bytes_unchecked(bytes: &[u8]) -> Result<Self, Error> {
+        let suffix = Suffix::parse(bytes)?;
+        let mut reader = Reader(&bytes[..bytes.len() - SUFFIX_LEN]);
+
+        let prefix = reader.take(PREFIX_LEN).ok_or(Error::NoPrefix)?;
+        if &prefix[..5] != b"DfuSe" {
+            return Err(Error::NoPrefix);
+        }
+        if prefix[5] != 1 {
+            return Err(Error::Version(prefix[5]));
+        }
+        let target_count = prefix[10];
+
+        let mut targets = Vec::new();
+        for index in 0..target_count {
+            let prefix = reader
+                .take(TARGET_PREFIX_LEN)
+                .ok_or(Error::TargetTruncated(index))?;
+            if &prefix[..6] != b"Target" {
+                return Err(Error::TargetSignature(index));
+            }
+            let named = u32_at(prefix, 7) != 0;
+            let name = &prefix[11..266];
+            let name = if named {
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).into_owned()
+            } else {
+                String::new()
+            };
+            let element_count = u32_at(prefix, 270);
+
+            let mut elements = Vec::new();
+            for element in 0..element_count {
+                let truncated = Error::ElementTruncated {
+                    target: index,
+                    element,
+                };
+                let Some(header) = reader.take(8) else {
+                    return Err(truncated);
+                };
+                let address = u32_at(header, 0);
+                let size = u32_at(header, 4) as usize;
+                let Some(data) = reader.take(size) else {
+                    return Err(truncated);
+                };
+                elements.push(DfuElement {
+                    address,
+                    data: data.to_vec(),
+                });
+            }
+            targets.push(DfuTarget {
+                name,
+                alternate_setting: prefix[6],
+                elements,
+            });
+        }
+        Ok(Self {
+            device_vid: suffix.vid,
+            device_pid: suffix.pid,
+            bcd_device: suffix.bcd_device,
+            targets,
+        })
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Cursor over the file bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+}
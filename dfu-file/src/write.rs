@@ -0,0 +1,85 @@
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::{BCD_DFU, DfuFile, Error, PREFIX_LEN, SUFFIX_LEN};
+
+impl DfuFile {
+    /// Write the file to `writer`.
+    pub fn to_writer(&self, mut writer: impl Write) -> Result<(), Error> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Encode the file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        // 1) Build the in-memory DFU body (all Target sections).
+        let mut body = Vec::new();
+        for target in &self.targets {
+            let mut elements_data = Vec::new();
+            for element in &target.elements {
+                let size = u32::try_from(element.data.len())
+                    .map_err(|_| Error::ElementTooLarge(element.address))?;
+                // Element header: address + size (Little-Endian)
+                elements_data.write_u32::<LittleEndian>(element.address)?;
+                elements_data.write_u32::<LittleEndian>(size)?;
+                elements_data.extend(&element.data);
+            }
+            // Pad the target name to exactly 255 bytes
+            if target.name.len() >= 255 {
+                return Err(Error::NameTooLong(target.name.clone()));
+            }
+            let mut name_bytes = target.name.as_bytes().to_vec();
+            name_bytes.resize(255, 0);
+
+            // Target prefix (per dfuse-pack.py):
+            // "Target" (6B), bAlternate (1B), dwNamed (4B), szTargetName
+            // (255B), dwTargetSize (4B), dwNbElements (4B)
+            body.extend(b"Target");
+            body.write_u8(target.alternate_setting)?; // bAlternate
+            // dwNamed = 1 (name present), or 0 with a zeroed name
+            body.write_u32::<LittleEndian>(u32::from(!target.name.is_empty()))?;
+            body.extend(&name_bytes); // szTargetName (255 bytes)
+            body.write_u32::<LittleEndian>(elements_data.len() as u32)?; // dwTargetSize
+            body.write_u32::<LittleEndian>(target.elements.len() as u32)?; // dwNbElements
+
+            // Append element data blocks
+            body.extend(elements_data);
+        }
+        let target_count = u8::try_from(self.targets.len())
+            .map_err(|_| Error::TooManyTargets(self.targets.len()))?;
+
+        // 2) DFU prefix header:
+        // "DfuSe" (5B), bVersion (1B), dwSize (4B), bTargets (1B)
+        let mut dfu = Vec::with_capacity(PREFIX_LEN + body.len() + SUFFIX_LEN);
+        dfu.extend(b"DfuSe");
+        dfu.write_u8(1)?; // bVersion
+        // dwSize = size of this prefix + body, i.e. the file without suffix
+        dfu.write_u32::<LittleEndian>((PREFIX_LEN + body.len()) as u32)?;
+        dfu.write_u8(target_count)?; // bTargets
+        dfu.extend(&body);
+
+        // 3) DFU suffix and CRC
+        append_suffix(
+            &mut dfu,
+            self.bcd_device,
+            self.device_pid,
+            self.device_vid,
+            BCD_DFU,
+        );
+        Ok(dfu)
+    }
+}
+
+/// Append the 16-byte DFU suffix and its CRC to `file`: `bcdDevice`,
+/// `idProduct`, `idVendor`, `bcdDFU`, "UFD", `bLength`, `dwCRC`.
+pub fn append_suffix(file: &mut Vec<u8>, bcd_device: u16, pid: u16, vid: u16, bcd_dfu: u16) {
+    for field in [bcd_device, pid, vid, bcd_dfu] {
+        file.extend(field.to_le_bytes());
+    }
+    file.extend(b"UFD");
+    file.push(SUFFIX_LEN as u8);
+    // CRC32 (bit-inverted) of everything before it
+    let crc = !crc32fast::hash(file);
+    file.extend(crc.to_le_bytes());
+}
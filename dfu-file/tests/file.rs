@@ -0,0 +1,165 @@
+//! Encoding and decoding whole DfuSe files.
+
+use dfu_file::{
+    BCD_DFU, DfuElement, DfuFile, DfuTarget, Error, PREFIX_LEN, SUFFIX_LEN, Suffix,
+    TARGET_PREFIX_LEN,
+};
+
+fn dfu() -> DfuFile {
+    DfuFile {
+        device_vid: 0x1209,
+        device_pid: 0x2444,
+        bcd_device: 0x0142,
+        targets: vec![
+            DfuTarget {
+                name: "Internal Flash".into(),
+                alternate_setting: 0,
+                elements: vec![
+                    DfuElement {
+                        address: 0x0800_4000,
+                        data: (0..1000).map(|i| i as u8).collect(),
+                    },
+                    DfuElement {
+                        address: 0x0800_F800,
+                        data: vec![0xA5; 16],
+                    },
+                ],
+            },
+            DfuTarget {
+                name: String::new(),
+                alternate_setting: 1,
+                elements: vec![DfuElement {
+                    address: 0x1FFF_F800,
+                    data: vec![0xAA, 0x55, 0xFF, 0x00],
+                }],
+            },
+        ],
+    }
+}
+
+/// `bytes` with the suffix written again, so its CRC matches an edit.
+fn resuffix(mut bytes: Vec<u8>) -> Vec<u8> {
+    let suffix = Suffix::parse(&bytes).unwrap();
+    bytes.truncate(bytes.len() - SUFFIX_LEN);
+    dfu_file::append_suffix(
+        &mut bytes,
+        suffix.bcd_device,
+        suffix.pid,
+        suffix.vid,
+        suffix.bcd_dfu,
+    );
+    bytes
+}
+
+/// Offset of the size of the first element of the first target.
+const FIRST_ELEMENT_SIZE: usize = PREFIX_LEN + TARGET_PREFIX_LEN + 4;
+
+#[test]
+fn round_trip() {
+    let dfu = dfu();
+    let bytes = dfu.to_bytes().unwrap();
+    assert_eq!(DfuFile::from_bytes(&bytes).unwrap(), dfu);
+
+    let mut written = Vec::new();
+    dfu.to_writer(&mut written).unwrap();
+    assert_eq!(written, bytes);
+    assert_eq!(DfuFile::from_reader(written.as_slice()).unwrap(), dfu);
+
+    let addresses: Vec<_> = dfu.elements().map(|element| element.address).collect();
+    assert_eq!(addresses, [0x0800_4000, 0x0800_F800, 0x1FFF_F800]);
+    assert_eq!(dfu.target(1).unwrap().elements.len(), 1);
+    assert!(dfu.target(2).is_none());
+}
+
+#[test]
+fn suffix_fields_and_crc() {
+    let bytes = dfu().to_bytes().unwrap();
+    let suffix = Suffix::parse(&bytes).unwrap();
+    assert_eq!((suffix.vid, suffix.pid), (0x1209, 0x2444));
+    assert_eq!(suffix.bcd_device, 0x0142);
+    assert_eq!(suffix.bcd_dfu, BCD_DFU);
+    assert_eq!(&bytes[bytes.len() - 8..bytes.len() - 5], b"UFD");
+    assert_eq!(suffix.crc, !crc32fast::hash(&bytes[..bytes.len() - 4]));
+    assert!(suffix.crc_valid());
+}
+
+#[test]
+fn corrupt_crc_is_rejected() {
+    let mut bytes = dfu().to_bytes().unwrap();
+    bytes[PREFIX_LEN + TARGET_PREFIX_LEN + 8] ^= 0xFF;
+    assert!(matches!(
+        DfuFile::from_bytes(&bytes),
+        Err(Error::Crc { crc, computed }) if crc != computed
+    ));
+    assert!(DfuFile::from_bytes_unchecked(&bytes).is_ok());
+}
+
+#[test]
+fn missing_suffix_is_rejected() {
+    let bytes = dfu().to_bytes().unwrap();
+    assert!(matches!(
+        DfuFile::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(Error::NoSuffix)
+    ));
+    assert!(matches!(
+        DfuFile::from_bytes(&bytes[..SUFFIX_LEN - 1]),
+        Err(Error::NoSuffix)
+    ));
+}
+
+#[test]
+fn truncated_file_is_rejected() {
+    let bytes = dfu().to_bytes().unwrap();
+    // Drop the last byte of the last element.
+    let mut truncated = bytes[..bytes.len() - SUFFIX_LEN - 1].to_vec();
+    truncated.extend(&bytes[bytes.len() - SUFFIX_LEN..]);
+    assert!(matches!(
+        DfuFile::from_bytes(&resuffix(truncated)),
+        Err(Error::ElementTruncated {
+            target: 1,
+            element: 0
+        })
+    ));
+
+    let mut truncated = bytes[..PREFIX_LEN + 100].to_vec();
+    truncated.extend(&bytes[bytes.len() - SUFFIX_LEN..]);
+    assert!(matches!(
+        DfuFile::from_bytes(&resuffix(truncated)),
+        Err(Error::TargetTruncated(0))
+    ));
+}
+
+#[test]
+fn oversize_element_is_rejected() {
+    let mut bytes = dfu().to_bytes().unwrap();
+    bytes[FIRST_ELEMENT_SIZE..FIRST_ELEMENT_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        DfuFile::from_bytes(&resuffix(bytes)),
+        Err(Error::ElementTruncated {
+            target: 0,
+            element: 0
+        })
+    ));
+}
+
+#[test]
+fn wrong_dw_size_is_rejected() {
+    let mut bytes = dfu().to_bytes().unwrap();
+    let actual = bytes.len() - SUFFIX_LEN;
+    for size in [actual as u32 - 1, actual as u32 + 1] {
+        bytes[6..10].copy_from_slice(&size.to_le_bytes());
+        let bytes = resuffix(bytes.clone());
+        assert!(matches!(
+            DfuFile::from_bytes(&bytes),
+            Err(Error::Size { size: s, actual: a }) if s == size && a == actual
+        ));
+        assert!(DfuFile::from_bytes_unchecked(&bytes).is_ok());
+    }
+}
+
+#[test]
+fn long_target_name_is_rejected() {
+    let mut dfu = dfu();
+    dfu.targets[0].name = "x".repeat(255);
+    assert!(matches!(dfu.to_bytes(), Err(Error::NameTooLong(_))));
+}
@@ -0,0 +1,181 @@
+//! `--batch DIR`: package every `.bin` and `.hex` file in a directory, e.g.
+//! the images of several hardware variants from a nightly build. Options
+//! for one file can be given in a sidecar `<stem>.toml` next to it, which
+//! overrides the command line:
+//!
+//! ```toml
+//! address = "08008000"
+//! device = "1209:2444"
+//! fw-version = "1.4.2"
+//! hw-rev = "3.0.0"
+//! target-name = ["Internal Flash"]
+//! ```
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::Cli;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    address: Option<String>,
+    device: Option<String>,
+    fw_version: Option<String>,
+    hw_rev: Option<String>,
+    #[serde(default)]
+    target_name: Vec<String>,
+}
+
+impl Config {
+    /// The sidecar of `input`, if there is one.
+    fn load(input: &Path) -> Result<Option<Self>> {
+        let path = input.with_extension("toml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("could not read `{}`", path.display()))?;
+        let config = toml::from_str(&text)
+            .with_context(|| format!("could not parse `{}`", path.display()))?;
+        tracing::debug!("Options for {} from {}", input.display(), path.display());
+        Ok(Some(config))
+    }
+
+    /// Override the options of `cli` with those given here.
+    fn apply(self, cli: &mut Cli) -> Result<()> {
+        if let Some(address) = &self.address {
+            cli.options.address = Some(Cli::parse_address(address)?);
+        }
+        if let Some(device) = &self.device {
+            cli.options.device = Some(Cli::parse_vid_pid(device)?);
+        }
+        if let Some(version) = &self.fw_version {
+            cli.options.fw_version = Some(Cli::parse_fw_version(version)?);
+        }
+        if let Some(hw_rev) = self.hw_rev {
+            anyhow::ensure!(
+                cli.options.memory_map.is_some(),
+                "hw-rev needs --memory-map"
+            );
+            cli.options.hw_rev = Some(hw_rev);
+        }
+        if !self.target_name.is_empty() {
+            cli.options.target_name = self
+                .target_name
+                .iter()
+                .map(|name| Cli::parse_target_name(name))
+                .collect::<Result<_>>()?;
+        }
+        Ok(())
+    }
+}
+
+/// Package every `.bin` and `.hex` file in `dir` into the directory given
+/// with `--output` (default `dir`), carrying on after failures and failing
+/// at the end if any file failed.
+pub fn run(mut cli: Cli, dir: &Path) -> Result<()> {
+    anyhow::ensure!(
+        !matches!(cli.options.manifest, Some(Some(_))),
+        "--manifest FILE would be overwritten for every file; leave out FILE to write one next to each output"
+    );
+    let inputs = inputs(dir)?;
+    let out_dir = cli
+        .options
+        .output
+        .take()
+        .unwrap_or_else(|| dir.to_path_buf());
+    std::fs::create_dir_all(&out_dir)
+        .with_context(|| format!("could not create `{}`", out_dir.display()))?;
+
+    let defaults = (
+        cli.options.address,
+        cli.options.device,
+        cli.options.fw_version,
+        cli.options.hw_rev.clone(),
+        cli.options.target_name.clone(),
+    );
+    let mut failed = Vec::new();
+    for input in &inputs {
+        (
+            cli.options.address,
+            cli.options.device,
+            cli.options.fw_version,
+            cli.options.hw_rev,
+            cli.options.target_name,
+        ) = defaults.clone();
+        let stem = input.file_stem().unwrap_or_default();
+        cli.options.output = Some(
+            out_dir
+                .join(stem)
+                .with_extension(cli.options.format.extension()),
+        );
+        cli.options.file = Some(input.clone());
+
+        let result = match Config::load(input) {
+            Ok(config) => config
+                .unwrap_or_default()
+                .apply(&mut cli)
+                .and_then(|_| cli.package()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => tracing::info!(
+                "{} -> {}",
+                input.display(),
+                cli.options.output.as_ref().unwrap().display()
+            ),
+            Err(e) => {
+                tracing::error!("{}: {e:#}", input.display());
+                failed.push(input.display().to_string());
+            }
+        }
+    }
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} of {} files failed: {}",
+        failed.len(),
+        inputs.len(),
+        failed.join(", ")
+    );
+    tracing::info!("Packaged {} files", inputs.len());
+    Ok(())
+}
+
+/// The `.bin` and `.hex` files in `dir`, by name.
+fn inputs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("could not read `{}`", dir.display()))?
+    {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        if path.is_file() && (extension == "bin" || extension == "hex") {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    anyhow::ensure!(
+        !inputs.is_empty(),
+        "no .bin or .hex files in `{}`",
+        dir.display()
+    );
+
+    let mut stems = BTreeSet::new();
+    for input in &inputs {
+        let stem = input.file_stem().unwrap_or_default();
+        anyhow::ensure!(
+            stems.insert(stem),
+            "several inputs in `{}` are named `{}`, which would give the same output",
+            dir.display(),
+            stem.display()
+        );
+    }
+    Ok(inputs)
+}
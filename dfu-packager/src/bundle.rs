@@ -0,0 +1,117 @@
+//! `--format bundle`: one `.bbfw` zip archive with everything a release
+//! needs, in the release bundle format `bikesafe-cli` reads (see
+//! `bikesafe-cli/src/bundle.rs`):
+//!
+//! - `<name>.dfu`: the DfuSe file,
+//! - `<name>.bin`: the raw payload of the first target, which the manifest
+//!   describes; with `--compress` it is `<name>.bin.zst`, compressed with zstd,
+//!   and the manifest gives its `compression` and the hash of the decompressed
+//!   payload,
+//! - `manifest.json`: the `--manifest` sidecar,
+//! - `manifest.json.sig`: the ed25519 signature of `manifest.json`, with
+//!   `--signing-key`,
+//! - the `--release-notes` file, under its own name.
+//!
+//! Entries carry a fixed timestamp, so identical inputs give identical
+//! archives.
+
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuFile;
+use ed25519_dalek::{Signer, SigningKey};
+use firmware_manifest::{MANIFEST_FILE, SIGNATURE_FILE};
+use zip::write::SimpleFileOptions;
+
+use crate::manifest::Options;
+
+/// zstd level for `--compress`; bundles are built once and sent often.
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// The bundle for `dfu`, whose entries are named after `path`, with the
+/// manifest made with `options`.
+pub fn to_bytes(
+    path: &Path,
+    dfu: &DfuFile,
+    options: &Options,
+    key: Option<&SigningKey>,
+    release_notes: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let dfu_path = path.with_extension("dfu");
+    let dfu_name = file_name(&dfu_path)?;
+    let dfu_bytes = dfu.to_bytes()?;
+    let target = dfu.targets.first().context("nothing to bundle")?;
+    let (_, payload) = crate::flatten(target, options.fill)?;
+
+    let notes = match release_notes {
+        Some(path) => {
+            let notes = std::fs::read(path)
+                .with_context(|| format!("could not read `{}`", path.display()))?;
+            let name = file_name(path)?;
+            anyhow::ensure!(
+                ![MANIFEST_FILE, SIGNATURE_FILE, dfu_name.as_str()].contains(&name.as_str())
+                    && Path::new(&name).extension() != Some("bin".as_ref()),
+                "release notes `{name}` would clash with another bundle entry"
+            );
+            Some((name, notes))
+        }
+        None => None,
+    };
+    let options = Options {
+        release_notes: notes.as_ref().map(|(name, _)| name.clone()),
+        ..options.clone()
+    };
+    let manifest = crate::manifest::to_bytes(dfu, &dfu_bytes, &dfu_path, &options)?;
+
+    let firmware = match options.compressed {
+        true => {
+            let compressed = zstd::bulk::compress(&payload, COMPRESSION_LEVEL)?;
+            tracing::info!(
+                "Compressed the payload from {} to {} bytes",
+                payload.len(),
+                compressed.len()
+            );
+            (file_name(&dfu_path.with_extension("bin.zst"))?, compressed)
+        }
+        false => (file_name(&dfu_path.with_extension("bin"))?, payload),
+    };
+    let mut entries = vec![
+        (dfu_name, dfu_bytes),
+        firmware,
+        (MANIFEST_FILE.to_string(), manifest),
+    ];
+    match key {
+        Some(key) => {
+            let signature = key.sign(&entries[2].1).to_bytes().to_vec();
+            entries.push((SIGNATURE_FILE.to_string(), signature));
+        }
+        None => tracing::warn!("Bundle is unsigned: give --signing-key to sign its manifest"),
+    }
+    entries.extend(notes);
+
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(0o644);
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in &entries {
+        // Compressing zstd data again gains nothing.
+        let options = match name.ends_with(".zst") {
+            true => options.compression_method(zip::CompressionMethod::Stored),
+            false => options,
+        };
+        archive.start_file(name.as_str(), options)?;
+        archive.write_all(data)?;
+        tracing::debug!("Bundled {name} ({} bytes)", data.len());
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .with_context(|| format!("`{}` has no file name", path.display()))?
+        .to_string_lossy()
+        .into_owned())
+}
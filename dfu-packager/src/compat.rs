@@ -0,0 +1,170 @@
+//! Comparison of the packager's output with a reference file, e.g. one
+//! written by dfu-util's `dfuse-pack.py` for the same inputs.
+
+use dfu_file::{DfuFile, PREFIX_LEN, SUFFIX_LEN, Suffix, TARGET_PREFIX_LEN};
+
+/// Every difference between `ours` and `reference`, structural ones first.
+/// A difference the structure does not explain is reported by the first
+/// differing byte and the field it is in.
+pub fn compare(ours: &[u8], reference: &[u8]) -> Vec<String> {
+    let (suffix, dfu) = match parse(reference) {
+        Ok(parsed) => parsed,
+        Err(e) => return vec![format!("the reference is not a valid DfuSe file: {e}")],
+    };
+    let (our_suffix, our_dfu) = parse(ours).expect("the packager wrote an invalid file");
+
+    let mut differences = Vec::new();
+    field(
+        &mut differences,
+        "idVendor:idProduct",
+        format!("{:04x}:{:04x}", our_suffix.vid, our_suffix.pid),
+        format!("{:04x}:{:04x}", suffix.vid, suffix.pid),
+    );
+    field(
+        &mut differences,
+        "bcdDevice",
+        format!("{:#06x}", our_suffix.bcd_device),
+        format!("{:#06x}", suffix.bcd_device),
+    );
+    field(
+        &mut differences,
+        "bcdDFU",
+        format!("{:#06x}", our_suffix.bcd_dfu),
+        format!("{:#06x}", suffix.bcd_dfu),
+    );
+    field(
+        &mut differences,
+        "bTargets",
+        our_dfu.targets.len().to_string(),
+        dfu.targets.len().to_string(),
+    );
+    for (i, (ours, reference)) in our_dfu.targets.iter().zip(&dfu.targets).enumerate() {
+        let target = format!("target {i}");
+        field(
+            &mut differences,
+            &format!("{target} bAlternateSetting"),
+            ours.alternate_setting.to_string(),
+            reference.alternate_setting.to_string(),
+        );
+        field(
+            &mut differences,
+            &format!("{target} szTargetName"),
+            format!("{:?}", ours.name),
+            format!("{:?}", reference.name),
+        );
+        field(
+            &mut differences,
+            &format!("{target} dwNbElements"),
+            ours.elements.len().to_string(),
+            reference.elements.len().to_string(),
+        );
+        for (j, (ours, reference)) in ours.elements.iter().zip(&reference.elements).enumerate() {
+            let element = format!("{target} element {j}");
+            field(
+                &mut differences,
+                &format!("{element} dwElementAddress"),
+                format!("{:#010X}", ours.address),
+                format!("{:#010X}", reference.address),
+            );
+            field(
+                &mut differences,
+                &format!("{element} dwElementSize"),
+                ours.data.len().to_string(),
+                reference.data.len().to_string(),
+            );
+            if let Some(offset) = ours
+                .data
+                .iter()
+                .zip(&reference.data)
+                .position(|(a, b)| a != b)
+            {
+                differences.push(format!(
+                    "{element} data differs first at offset {offset:#x} ({:#010X})",
+                    ours.address as u64 + offset as u64
+                ));
+            }
+        }
+    }
+
+    if differences.is_empty() && ours != reference {
+        let offset = ours
+            .iter()
+            .zip(reference)
+            .position(|(a, b)| a != b)
+            .unwrap_or(ours.len().min(reference.len()));
+        differences.push(format!(
+            "files differ first at byte {offset:#x} ({}), sizes {} and {}",
+            field_at(reference, offset),
+            ours.len(),
+            reference.len()
+        ));
+    }
+    differences
+}
+
+fn field(differences: &mut Vec<String>, name: &str, ours: String, reference: String) {
+    if ours != reference {
+        differences.push(format!("{name}: {ours}, reference {reference}"));
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<(Suffix, DfuFile), dfu_file::Error> {
+    Ok((Suffix::parse(bytes)?, DfuFile::from_bytes_unchecked(bytes)?))
+}
+
+/// Name of the DfuSe field at `offset` of a well-formed file.
+fn field_at(bytes: &[u8], offset: usize) -> String {
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+    let suffix = bytes.len() - SUFFIX_LEN;
+    if offset >= suffix {
+        return match offset - suffix {
+            0..2 => "bcdDevice",
+            2..4 => "idProduct",
+            4..6 => "idVendor",
+            6..8 => "bcdDFU",
+            8..11 => "ucDfuSignature",
+            11 => "bLength",
+            _ => "dwCRC",
+        }
+        .to_string();
+    }
+    if offset < PREFIX_LEN {
+        return match offset {
+            0..5 => "szSignature",
+            5 => "bVersion",
+            6..10 => "dwImageSize",
+            _ => "bTargets",
+        }
+        .to_string();
+    }
+    let mut at = PREFIX_LEN;
+    for target in 0..bytes[10] {
+        let prefix = at;
+        at += TARGET_PREFIX_LEN;
+        if offset < at {
+            let field = match offset - prefix {
+                0..6 => "szSignature",
+                6 => "bAlternateSetting",
+                7..11 => "bTargetNamed",
+                11..266 => "szTargetName",
+                266..270 => "dwTargetSize",
+                _ => "dwNbElements",
+            };
+            return format!("target {target} {field}");
+        }
+        for element in 0..u32_at(prefix + 270) {
+            let size = u32_at(at + 4);
+            let field = match offset.checked_sub(at) {
+                Some(0..4) => "dwElementAddress",
+                Some(4..8) => "dwElementSize",
+                Some(n) if n < 8 + size => "data",
+                _ => {
+                    at += 8 + size;
+                    continue;
+                }
+            };
+            return format!("target {target} element {element} {field}");
+        }
+    }
+    "trailing data".to_string()
+}
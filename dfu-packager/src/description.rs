@@ -0,0 +1,158 @@
+//! JSON description of a .dfu file, shared by `unpack` and `inspect`, and
+//! read back with `--description` to package from:
+//!
+//! ```json
+//! {
+//!   "vid": "1209",
+//!   "pid": "2444",
+//!   "targets": [
+//!     { "name": "Internal Flash", "alternate_setting": 0,
+//!       "elements": [{ "address": "0x08004000", "file": "app.bin" }] },
+//!     { "name": "Option Bytes", "alternate_setting": 1,
+//!       "elements": [{ "address": "0x1FFFF800", "file": "options.bin" }] }
+//!   ]
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuFile, DfuTarget};
+use serde::{Deserialize, Serialize};
+
+use crate::{Cli, Image, input};
+
+#[derive(Serialize, Deserialize)]
+pub struct Description {
+    /// Hex, without `0x`.
+    pub vid: String,
+    pub pid: String,
+    #[serde(default = "default_bcd_device")]
+    pub bcd_device: String,
+    pub targets: Vec<TargetDescription>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TargetDescription {
+    pub name: String,
+    pub alternate_setting: u8,
+    pub elements: Vec<ElementDescription>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ElementDescription {
+    /// Optional when packaging files that carry their own addresses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Checked against the file when packaging, if given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    /// Where `unpack` wrote the element, or the file to package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl From<&DfuFile> for Description {
+    fn from(dfu: &DfuFile) -> Self {
+        Self {
+            vid: format!("{:04x}", dfu.device_vid),
+            pid: format!("{:04x}", dfu.device_pid),
+            bcd_device: format!("{:#06x}", dfu.bcd_device),
+            targets: dfu
+                .targets
+                .iter()
+                .map(|target| TargetDescription {
+                    name: target.name.clone(),
+                    alternate_setting: target.alternate_setting,
+                    elements: target
+                        .elements
+                        .iter()
+                        .map(|element| ElementDescription {
+                            address: Some(format!("0x{:08X}", element.address)),
+                            size: Some(element.data.len()),
+                            file: None,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Description> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read description `{}`", path.display()))?;
+    serde_json::from_str(&json)
+        .with_context(|| format!("could not parse description `{}`", path.display()))
+}
+
+/// The image files the description at `path` lists.
+pub fn files(path: &Path) -> Result<Vec<PathBuf>> {
+    let base = path.parent().unwrap_or(Path::new("."));
+    Ok(read(path)?
+        .targets
+        .iter()
+        .flat_map(|target| &target.elements)
+        .filter_map(|element| element.file.as_ref())
+        .map(|file| base.join(file))
+        .collect())
+}
+
+/// Read the description at `path` and the images it lists.
+pub fn load(path: &Path) -> Result<DfuFile> {
+    let description = read(path)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let mut targets: Vec<DfuTarget> = Vec::new();
+    for target in description.targets {
+        let alt = target.alternate_setting;
+        anyhow::ensure!(
+            targets.iter().all(|t| t.alternate_setting != alt),
+            "description lists alternate setting {alt} twice"
+        );
+        let mut elements = Vec::new();
+        for element in &target.elements {
+            let file = element
+                .file
+                .as_ref()
+                .with_context(|| format!("an element of target `{}` has no file", target.name))?;
+            let image = Image {
+                file: base.join(file),
+                address: element
+                    .address
+                    .as_deref()
+                    .map(Cli::parse_address)
+                    .transpose()?,
+                alt,
+            };
+            let read = input::read(&image)?;
+            let len: usize = read.iter().map(|element| element.data.len()).sum();
+            if let Some(size) = element.size {
+                anyhow::ensure!(
+                    size == len,
+                    "description gives {size} bytes for `{}`, the file has {len}",
+                    image.file.display()
+                );
+            }
+            crate::add_elements(&mut elements, &image.file, read)?;
+        }
+        targets.push(DfuTarget {
+            name: target.name,
+            alternate_setting: alt,
+            elements,
+        });
+    }
+
+    let hex = |s: &str| u16::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16);
+    Ok(DfuFile {
+        device_vid: hex(&description.vid).context("could not parse description vid")?,
+        device_pid: hex(&description.pid).context("could not parse description pid")?,
+        bcd_device: hex(&description.bcd_device)
+            .context("could not parse description bcd_device")?,
+        targets,
+    })
+}
+
+fn default_bcd_device() -> String {
+    "0x0000".into()
+}
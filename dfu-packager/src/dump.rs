@@ -0,0 +1,122 @@
+//! `--dump`: the written DfuSe file field by field, with the offset and raw
+//! bytes of each field next to its name and decoded value.
+
+use anyhow::{Context, Result};
+use dfu_file::{PREFIX_LEN, SUFFIX_LEN};
+
+/// Raw bytes shown per field; longer fields are cut off.
+const SHOWN: usize = 8;
+
+struct Dump<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Dump<'a> {
+    /// Print the next `len` bytes as the field `name`, with the value
+    /// `describe` gives for them.
+    fn field(
+        &mut self,
+        len: usize,
+        name: &str,
+        describe: impl FnOnce(&[u8]) -> String,
+    ) -> Result<&'a [u8]> {
+        let field = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .with_context(|| {
+                format!("{name} at {:#x} runs past the end of the file", self.offset)
+            })?;
+        let mut hex = field[..len.min(SHOWN)]
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if len > SHOWN {
+            hex.push_str(" ..");
+        }
+        println!(
+            "{:08X}  {hex:<26} {name:<18} {}",
+            self.offset,
+            describe(field)
+        );
+        self.offset += len;
+        Ok(field)
+    }
+
+    fn u8(&mut self, name: &str) -> Result<u8> {
+        Ok(self.field(1, name, |b| b[0].to_string())?[0])
+    }
+
+    fn u16(&mut self, name: &str) -> Result<u16> {
+        let field = self.field(2, name, |b| {
+            format!("{:#06x}", u16::from_le_bytes([b[0], b[1]]))
+        })?;
+        Ok(u16::from_le_bytes([field[0], field[1]]))
+    }
+
+    fn u32(&mut self, name: &str, hex: bool) -> Result<u32> {
+        let value = |b: &[u8]| u32::from_le_bytes(b.try_into().unwrap());
+        let field = self.field(4, name, |b| match hex {
+            true => format!("{:#010X}", value(b)),
+            false => value(b).to_string(),
+        })?;
+        Ok(value(field))
+    }
+
+    fn text(&mut self, len: usize, name: &str) -> Result<()> {
+        self.field(len, name, |b| {
+            let end = b.iter().position(|&b| b == 0).unwrap_or(b.len());
+            format!("\"{}\"", String::from_utf8_lossy(&b[..end]).escape_debug())
+        })?;
+        Ok(())
+    }
+}
+
+/// Print the prefix, target and element headers and suffix of `bytes`,
+/// a DfuSe file.
+pub fn print(bytes: &[u8]) -> Result<()> {
+    anyhow::ensure!(
+        bytes.len() >= PREFIX_LEN + SUFFIX_LEN,
+        "file is too short to dump"
+    );
+    let mut dump = Dump { bytes, offset: 0 };
+
+    println!("DfuSe prefix");
+    dump.text(5, "szSignature")?;
+    dump.u8("bVersion")?;
+    dump.u32("dwImageSize", false)?;
+    let targets = dump.u8("bTargets")?;
+
+    for target in 0..targets {
+        println!("Target {target}");
+        dump.text(6, "szSignature")?;
+        dump.u8("bAlternateSetting")?;
+        dump.u32("bTargetNamed", false)?;
+        dump.text(255, "szTargetName")?;
+        dump.u32("dwTargetSize", false)?;
+        let elements = dump.u32("dwNbElements", false)?;
+        for element in 0..elements {
+            println!("Target {target}, element {element}");
+            dump.u32("dwElementAddress", true)?;
+            let size = dump.u32("dwElementSize", false)?;
+            dump.field(size as usize, "data", |_| format!("{size} bytes"))?;
+        }
+    }
+
+    if dump.offset != bytes.len() - SUFFIX_LEN {
+        println!("Trailing data");
+        dump.field(bytes.len() - SUFFIX_LEN - dump.offset, "data", |b| {
+            format!("{} bytes", b.len())
+        })?;
+    }
+    println!("DFU suffix");
+    dump.u16("bcdDevice")?;
+    dump.u16("idProduct")?;
+    dump.u16("idVendor")?;
+    dump.u16("bcdDFU")?;
+    dump.text(3, "ucDfuSignature")?;
+    dump.u8("bLength")?;
+    dump.u32("dwCRC", true)?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use elf::ElfBytes;
+use elf::abi::PT_LOAD;
+use elf::endian::AnyEndian;
+
+/// Flash of the BrakeBright MCU, bootloader included; loadable segments
+/// elsewhere are packaged with a warning.
+const FLASH_START: u64 = device_memory::BRAKEBRIGHT.bootloader.origin as u64;
+const FLASH_END: u64 = device_memory::BRAKEBRIGHT.flash.end();
+
+/// Contents of each `PT_LOAD` segment with file data, as `(LMA, bytes)`
+/// chunks.
+pub fn parse(bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(bytes).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let segments = file.segments().context("no program headers")?;
+
+    let mut chunks = Vec::new();
+    for segment in segments
+        .iter()
+        .filter(|segment| segment.p_type == PT_LOAD && segment.p_filesz > 0)
+    {
+        let address = u32::try_from(segment.p_paddr)
+            .with_context(|| format!("segment at {:#X} is above 4 GiB", segment.p_paddr))?;
+        let end = segment
+            .p_paddr
+            .checked_add(segment.p_filesz)
+            .filter(|&end| end <= 1 << 32)
+            .with_context(|| format!("segment at {address:#010X} extends past 4 GiB"))?;
+        if segment.p_paddr < FLASH_START || end > FLASH_END {
+            tracing::warn!(
+                "segment at {address:#010X}..{end:#010X} is outside the device flash \
+                 ({FLASH_START:#010X}..{FLASH_END:#010X})"
+            );
+        }
+        let data = file
+            .segment_data(&segment)
+            .map_err(|e| anyhow::anyhow!("segment at {address:#010X}: {e}"))?;
+        chunks.push((address, data.to_vec()));
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 64-bit little-endian ELF file with one `PT_LOAD` segment of
+    /// `data`, declared `filesz` bytes long, at `paddr`.
+    fn elf(paddr: u64, filesz: u64, data: &[u8]) -> Vec<u8> {
+        let mut elf = b"\x7FELF\x02\x01\x01".to_vec();
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes()); // e_type: ET_EXEC
+        elf.extend(40u16.to_le_bytes()); // e_machine: EM_ARM
+        elf.extend(1u32.to_le_bytes()); // e_version
+        elf.extend(paddr.to_le_bytes()); // e_entry
+        elf.extend(64u64.to_le_bytes()); // e_phoff
+        elf.extend(0u64.to_le_bytes()); // e_shoff
+        elf.extend(0u32.to_le_bytes()); // e_flags
+        elf.extend(64u16.to_le_bytes()); // e_ehsize
+        elf.extend(56u16.to_le_bytes()); // e_phentsize
+        elf.extend(1u16.to_le_bytes()); // e_phnum
+        elf.extend([0; 6]); // e_shentsize, e_shnum, e_shstrndx
+        elf.extend(PT_LOAD.to_le_bytes()); // p_type
+        elf.extend(5u32.to_le_bytes()); // p_flags: R+X
+        elf.extend(120u64.to_le_bytes()); // p_offset
+        elf.extend(paddr.to_le_bytes()); // p_vaddr
+        elf.extend(paddr.to_le_bytes()); // p_paddr
+        elf.extend(filesz.to_le_bytes()); // p_filesz
+        elf.extend(filesz.to_le_bytes()); // p_memsz
+        elf.extend(4u64.to_le_bytes()); // p_align
+        elf.extend(data);
+        elf
+    }
+
+    #[test]
+    fn load_segment_at_its_lma() {
+        let data = [1, 2, 3, 4];
+        assert_eq!(
+            parse(&elf(0x0800_4000, 4, &data)).unwrap(),
+            [(0x0800_4000, data.to_vec())]
+        );
+    }
+
+    #[test]
+    fn segment_past_4_gib_is_rejected() {
+        let error = parse(&elf(0xFFFF_FFF0, u64::MAX, &[])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "segment at 0xFFFFFFF0 extends past 4 GiB"
+        );
+        assert!(parse(&elf(0xFFFF_FFF0, 0x20, &[0; 0x20])).is_err());
+    }
+
+    #[test]
+    fn segment_above_4_gib_is_rejected() {
+        assert!(parse(&elf(1 << 32, 4, &[0; 4])).is_err());
+    }
+
+    #[test]
+    fn not_an_elf_file() {
+        assert!(parse(b"\x7FELG").is_err());
+    }
+}
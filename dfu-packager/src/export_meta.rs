@@ -0,0 +1,153 @@
+//! `export-meta`: the version, IDs, address and hashes of a packaged .dfu as
+//! a Rust module or JSON, for host tools that embed the file, e.g.
+//! bikesafe-util's bundled fallback firmware with
+//! `include!(concat!(env!("OUT_DIR"), "/firmware_meta.rs"))`.
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuFile;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(clap::Args)]
+pub struct ExportMetaArgs {
+    /// The packaged .dfu file.
+    file: PathBuf,
+
+    /// Language to write.
+    #[clap(long, value_enum, default_value = "rust")]
+    lang: Lang,
+
+    /// Byte filling the gaps of the payload the hashes are of.
+    #[clap(long, default_value = "0xFF", value_parser = crate::Cli::parse_fill)]
+    fill: u8,
+
+    /// Output file [default: standard output]
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Lang {
+    Rust,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Meta {
+    version: String,
+    /// Build ID from the metadata block; empty without one.
+    build_id: String,
+    vid: u16,
+    pid: u16,
+    /// Start address and size of the payload, the first target as one
+    /// image.
+    address: u32,
+    size: usize,
+    crc32: u32,
+    sha256: String,
+    dfu_file: String,
+    dfu_size: usize,
+    dfu_sha256: String,
+}
+
+impl ExportMetaArgs {
+    pub fn run(self) -> Result<()> {
+        let meta = self.meta()?;
+        let text = match self.lang {
+            Lang::Rust => rust(&meta),
+            Lang::Json => serde_json::to_string_pretty(&meta)? + "\n",
+        };
+        match &self.output {
+            Some(path) => {
+                std::fs::write(path, text)
+                    .with_context(|| format!("could not write `{}`", path.display()))?;
+                tracing::info!("Metadata -> {}", path.display());
+            }
+            None => print!("{text}"),
+        }
+        Ok(())
+    }
+
+    fn meta(&self) -> Result<Meta> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let dfu = DfuFile::from_bytes(&bytes)
+            .with_context(|| format!("`{}` is not a valid DfuSe file", self.file.display()))?;
+        let target = dfu.targets.first().context("the file has no targets")?;
+        let (address, payload) = crate::flatten(target, self.fill)?;
+        let metadata = crate::patch::read_metadata(&dfu, self.fill);
+        let version = match (dfu.bcd_device, &metadata) {
+            (0, Some(metadata)) => metadata.version_string(),
+            (bcd_device, _) => crate::manifest::version(bcd_device),
+        };
+        Ok(Meta {
+            version,
+            build_id: metadata.map(|m| m.build_id).unwrap_or_default(),
+            vid: dfu.device_vid,
+            pid: dfu.device_pid,
+            address,
+            size: payload.len(),
+            crc32: crc32fast::hash(&payload),
+            sha256: hex::encode(Sha256::digest(&payload)),
+            dfu_file: self
+                .file
+                .file_name()
+                .unwrap_or(self.file.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            dfu_size: bytes.len(),
+            dfu_sha256: hex::encode(Sha256::digest(&bytes)),
+        })
+    }
+}
+
+fn rust(meta: &Meta) -> String {
+    // Plain comments, so the module can be `include!`d.
+    let mut text = format!(
+        "// Generated by {} {} export-meta; do not edit.\n\
+         \n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let hash = |hex: &str| {
+        let bytes: Vec<String> = (0..hex.len())
+            .step_by(2)
+            .map(|i| format!("0x{}", &hex[i..i + 2]))
+            .collect();
+        format!("[{}]", bytes.join(", "))
+    };
+    let _ = write!(
+        text,
+        "/// Firmware version, `major.minor.patch`.\n\
+         pub const VERSION: &str = {:?};\n\
+         /// Build ID from the image's metadata block; empty without one.\n\
+         pub const BUILD_ID: &str = {:?};\n\
+         pub const VID: u16 = 0x{:04X};\n\
+         pub const PID: u16 = 0x{:04X};\n\
+         /// Start address and size of the application image.\n\
+         pub const ADDRESS: u32 = 0x{:08X};\n\
+         pub const SIZE: usize = {};\n\
+         /// CRC32 and SHA-256 of the application image.\n\
+         pub const CRC32: u32 = 0x{:08X};\n\
+         pub const SHA256: [u8; 32] = {};\n\
+         /// The .dfu file: name, size and SHA-256.\n\
+         pub const DFU_FILE: &str = {:?};\n\
+         pub const DFU_SIZE: usize = {};\n\
+         pub const DFU_SHA256: [u8; 32] = {};\n",
+        meta.version,
+        meta.build_id,
+        meta.vid,
+        meta.pid,
+        meta.address,
+        meta.size,
+        meta.crc32,
+        hash(&meta.sha256),
+        meta.dfu_file,
+        meta.dfu_size,
+        hash(&meta.dfu_sha256),
+    );
+    text
+}
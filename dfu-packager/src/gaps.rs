@@ -0,0 +1,77 @@
+//! Overlaps and gaps between elements. The device writes elements in file
+//! order, so overlapping ones leave flash depending on that order, and
+//! the bytes in a gap are not written at all.
+
+use anyhow::Result;
+use dfu_file::{DfuElement, DfuFile};
+
+/// Gaps from this size on (a page on the STM32F103) are warned about.
+const LARGE_GAP: u64 = 1024;
+
+/// Fail if any two elements overlap, in one target or across targets.
+pub fn check_overlaps(dfu: &DfuFile) -> Result<()> {
+    let mut elements: Vec<(u8, &DfuElement)> = dfu
+        .targets
+        .iter()
+        .flat_map(|target| {
+            target
+                .elements
+                .iter()
+                .map(|element| (target.alternate_setting, element))
+        })
+        .collect();
+    elements.sort_by_key(|(_, element)| element.address);
+    for pair in elements.windows(2) {
+        let [(alt, element), (next_alt, next)] = pair else {
+            unreachable!()
+        };
+        anyhow::ensure!(
+            element.end() <= next.address as u64,
+            "the element at {:#010X}..{:#010X} (alt {alt}) overlaps the one at {:#010X}..{:#010X} (alt {next_alt}); \
+             flash would depend on the order they are written in",
+            element.address,
+            element.end(),
+            next.address,
+            next.end()
+        );
+    }
+    Ok(())
+}
+
+/// Warn about large gaps between the elements of a target.
+pub fn warn_gaps(dfu: &DfuFile) {
+    for target in &dfu.targets {
+        let mut elements: Vec<&DfuElement> = target.elements.iter().collect();
+        elements.sort_by_key(|element| element.address);
+        for pair in elements.windows(2) {
+            let gap = pair[1].address as u64 - pair[0].end();
+            if gap >= LARGE_GAP {
+                tracing::warn!(
+                    "{gap} bytes between {:#010X} and {:#010X} (alt {}) are not written; \
+                     --fill-gaps writes --fill there",
+                    pair[0].end(),
+                    pair[1].address,
+                    target.alternate_setting
+                );
+            }
+        }
+    }
+}
+
+/// Join the elements of every target into one, filling the gaps.
+pub fn fill(dfu: &mut DfuFile, fill: u8) -> Result<()> {
+    for target in &mut dfu.targets {
+        if target.elements.len() < 2 {
+            continue;
+        }
+        let (address, data) = crate::flatten(target, fill)?;
+        tracing::debug!(
+            "Joined {} elements of alt {} into {} bytes at {address:#010X}",
+            target.elements.len(),
+            target.alternate_setting,
+            data.len()
+        );
+        target.elements = vec![DfuElement { address, data }];
+    }
+    Ok(())
+}
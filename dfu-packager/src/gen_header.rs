@@ -0,0 +1,167 @@
+//! `gen-header`: the metadata block layout `--set-version` and
+//! `--set-build-id` patch, and optionally the `--patch-crc` and
+//! `--signature-offset` slots, as a C header or Rust module for the
+//! firmware build, so firmware and packager share one definition.
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use firmware_metadata::{
+    BUILD_ID_LEN, LAYOUT_VERSION, LEN, MAGIC, OFFSET_BUILD_ID, OFFSET_CRC, OFFSET_LAYOUT_VERSION,
+    OFFSET_LENGTH, OFFSET_VERSION,
+};
+
+use crate::Cli;
+use crate::patch::SIGNATURE_LEN;
+
+#[derive(clap::Args)]
+pub struct GenHeaderArgs {
+    /// Language to write.
+    #[clap(long, value_enum, default_value = "c")]
+    lang: Lang,
+
+    /// Offset of the image CRC slot, as given to --patch-crc.
+    #[clap(long, value_name = "OFFSET", value_parser = Cli::parse_address)]
+    patch_crc: Option<u32>,
+
+    /// Offset of the signature slot, as given to --signature-offset.
+    #[clap(long, value_parser = Cli::parse_address)]
+    signature_offset: Option<u32>,
+
+    /// Output file [default: standard output]
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Lang {
+    C,
+    Rust,
+}
+
+impl GenHeaderArgs {
+    pub fn run(self) -> Result<()> {
+        let text = match self.lang {
+            Lang::C => self.c(),
+            Lang::Rust => self.rust(),
+        };
+        match &self.output {
+            Some(path) => {
+                std::fs::write(path, text)
+                    .with_context(|| format!("could not write `{}`", path.display()))?;
+                tracing::info!("Header -> {}", path.display());
+            }
+            None => print!("{text}"),
+        }
+        Ok(())
+    }
+
+    fn c(&self) -> String {
+        let magic = String::from_utf8_lossy(MAGIC);
+        let mut text = format!(
+            "/* Generated by {} {} gen-header; do not edit. */\n\
+             #ifndef BBFW_METADATA_H\n\
+             #define BBFW_METADATA_H\n\
+             \n\
+             #include <stdint.h>\n\
+             \n\
+             /* Firmware metadata block, found by its magic on a 4-byte boundary. A length of\n\
+             \x20* 0 or 0xFFFFFFFF leaves the CRC unset; otherwise the CRC32 covers the first\n\
+             \x20* `length` bytes of the image with the CRC field zeroed. */\n\
+             #define BBFW_MAGIC \"{magic}\"\n\
+             #define BBFW_LAYOUT_VERSION {LAYOUT_VERSION}\n\
+             #define BBFW_METADATA_LEN {LEN}\n\
+             #define BBFW_OFFSET_LAYOUT_VERSION 0x{OFFSET_LAYOUT_VERSION:02X}\n\
+             #define BBFW_OFFSET_VERSION 0x{OFFSET_VERSION:02X}\n\
+             #define BBFW_OFFSET_BUILD_ID 0x{OFFSET_BUILD_ID:02X}\n\
+             #define BBFW_BUILD_ID_LEN {BUILD_ID_LEN}\n\
+             #define BBFW_OFFSET_LENGTH 0x{OFFSET_LENGTH:02X}\n\
+             #define BBFW_OFFSET_CRC 0x{OFFSET_CRC:02X}\n\
+             \n\
+             typedef struct __attribute__((packed, aligned(4))) {{\n\
+             \x20   char magic[4];\n\
+             \x20   uint8_t layout_version;\n\
+             \x20   uint8_t version_major;\n\
+             \x20   uint8_t version_minor;\n\
+             \x20   uint8_t version_patch;\n\
+             \x20   char build_id[BBFW_BUILD_ID_LEN];\n\
+             \x20   uint32_t length;\n\
+             \x20   uint32_t crc;\n\
+             }} bbfw_metadata_t;\n\
+             \n\
+             _Static_assert(sizeof(bbfw_metadata_t) == BBFW_METADATA_LEN, \"metadata block size\");\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        if let Some(offset) = self.patch_crc {
+            text += "\n/* Image CRC32 (little endian) from --patch-crc, from the image start. */\n";
+            let _ = writeln!(text, "#define BBFW_IMAGE_CRC_OFFSET 0x{offset:X}");
+        }
+        if let Some(offset) = self.signature_offset {
+            text += "\n/* ed25519 signature from --signature-offset, from the image start. */\n";
+            let _ = writeln!(text, "#define BBFW_SIGNATURE_OFFSET 0x{offset:X}");
+            let _ = writeln!(text, "#define BBFW_SIGNATURE_LEN {SIGNATURE_LEN}");
+        }
+        text + "\n#endif /* BBFW_METADATA_H */\n"
+    }
+
+    fn rust(&self) -> String {
+        let mut text = format!(
+            "// Generated by {} {} gen-header; do not edit. Plain comments, so the file\n\
+             // can be `include!`d as well as used as a module.\n\
+             //\n\
+             // Firmware metadata block, found by its magic on a 4-byte boundary. A length\n\
+             // of 0 or 0xFFFFFFFF leaves the CRC unset; otherwise the CRC32 covers the\n\
+             // first `length` bytes of the image with the CRC field zeroed.\n\
+             \n\
+             pub const MAGIC: [u8; 4] = *b\"{}\";\n\
+             pub const LAYOUT_VERSION: u8 = {LAYOUT_VERSION};\n\
+             pub const METADATA_LEN: usize = {LEN};\n\
+             pub const OFFSET_LAYOUT_VERSION: usize = 0x{OFFSET_LAYOUT_VERSION:02X};\n\
+             pub const OFFSET_VERSION: usize = 0x{OFFSET_VERSION:02X};\n\
+             pub const OFFSET_BUILD_ID: usize = 0x{OFFSET_BUILD_ID:02X};\n\
+             pub const BUILD_ID_LEN: usize = {BUILD_ID_LEN};\n\
+             pub const OFFSET_LENGTH: usize = 0x{OFFSET_LENGTH:02X};\n\
+             pub const OFFSET_CRC: usize = 0x{OFFSET_CRC:02X};\n\
+             \n\
+             #[repr(C, align(4))]\n\
+             pub struct Metadata {{\n\
+             \x20   pub magic: [u8; 4],\n\
+             \x20   pub layout_version: u8,\n\
+             \x20   /// Major, minor and patch version.\n\
+             \x20   pub version: [u8; 3],\n\
+             \x20   pub build_id: [u8; BUILD_ID_LEN],\n\
+             \x20   pub length: u32,\n\
+             \x20   pub crc: u32,\n\
+             }}\n\
+             \n\
+             impl Metadata {{\n\
+             \x20   /// A block for the packager to fill in: no version or build ID, CRC unset.\n\
+             \x20   pub const EMPTY: Self = Self {{\n\
+             \x20       magic: MAGIC,\n\
+             \x20       layout_version: LAYOUT_VERSION,\n\
+             \x20       version: [0; 3],\n\
+             \x20       build_id: [0; BUILD_ID_LEN],\n\
+             \x20       length: 0,\n\
+             \x20       crc: 0,\n\
+             \x20   }};\n\
+             }}\n\
+             \n\
+             const _: () = assert!(core::mem::size_of::<Metadata>() == METADATA_LEN);\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            String::from_utf8_lossy(MAGIC),
+        );
+        if let Some(offset) = self.patch_crc {
+            text += "\n/// Image CRC32 (little endian) from --patch-crc, from the image start.\n";
+            let _ = writeln!(text, "pub const IMAGE_CRC_OFFSET: usize = 0x{offset:X};");
+        }
+        if let Some(offset) = self.signature_offset {
+            text += "\n/// ed25519 signature from --signature-offset, from the image start.\n";
+            let _ = writeln!(text, "pub const SIGNATURE_OFFSET: usize = 0x{offset:X};");
+            let _ = writeln!(text, "pub const SIGNATURE_LEN: usize = {SIGNATURE_LEN};");
+        }
+        text
+    }
+}
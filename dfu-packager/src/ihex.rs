@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use ihex::Record;
+
+/// Data records of an Intel HEX file as `(address, bytes)` chunks, with
+/// segment and linear address extensions applied.
+pub fn parse(text: &str) -> Result<Vec<(u32, Vec<u8>)>> {
+    let mut base = 0u32;
+    let mut chunks = Vec::new();
+    for (line, record) in ihex::Reader::new(text).enumerate() {
+        match record.with_context(|| format!("line {}", line + 1))? {
+            Record::Data { offset, value } => chunks.push((base + offset as u32, value)),
+            Record::ExtendedSegmentAddress(segment) => base = (segment as u32) << 4,
+            Record::ExtendedLinearAddress(upper) => base = (upper as u32) << 16,
+            Record::EndOfFile => break,
+            Record::StartSegmentAddress { .. } | Record::StartLinearAddress(_) => {}
+        }
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_address_extension() {
+        let text = ":020000040800F2\n\
+                    :0440000001020304B2\n\
+                    :00000001FF\n\
+                    :0400000005060708DE\n";
+        assert_eq!(parse(text).unwrap(), [(0x0800_4000, vec![1, 2, 3, 4])]);
+    }
+
+    #[test]
+    fn segment_address_extension() {
+        let text = ":020000021000EC\n:020010001122BB\n:00000001FF\n";
+        assert_eq!(parse(text).unwrap(), [(0x1_0010, vec![0x11, 0x22])]);
+    }
+
+    #[test]
+    fn bad_checksum_names_the_line() {
+        let error = parse(":020000040800F2\n:0440000001020304B3\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2");
+    }
+}
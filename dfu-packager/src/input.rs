@@ -0,0 +1,135 @@
+//! Input files: raw binaries at a given address, and formats that carry
+//! their own addresses.
+
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuElement;
+
+use crate::Image;
+
+/// File name that stands for standard input, which takes a raw binary.
+pub const STDIN: &str = "-";
+
+/// Whether `file` is in a format that carries its own addresses.
+pub fn carries_addresses(file: &Path) -> bool {
+    format(file).is_some()
+}
+
+/// Formats with addresses, by file extension.
+#[derive(Clone, Copy)]
+enum Format {
+    IntelHex,
+    SRecord,
+    Elf,
+    Uf2,
+}
+
+/// Format of `file`, by extension; ELF files are also recognized by their
+/// magic, since linkers often write them without an extension.
+fn format(file: &Path) -> Option<Format> {
+    let extension = file
+        .extension()
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.to_str()? {
+        "hex" | "ihex" => Some(Format::IntelHex),
+        "srec" | "s19" | "s28" | "s37" | "mot" => Some(Format::SRecord),
+        "elf" => Some(Format::Elf),
+        "uf2" => Some(Format::Uf2),
+        _ => {
+            let mut magic = [0; 4];
+            let mut file = std::fs::File::open(file).ok()?;
+            std::io::Read::read_exact(&mut file, &mut magic).ok()?;
+            (&magic == b"\x7FELF").then_some(Format::Elf)
+        }
+    }
+}
+
+/// Read `image` into elements. Raw binaries need an address; for other
+/// formats the addresses come from the file and gaps split it into
+/// several elements.
+pub fn read(image: &Image) -> Result<Vec<DfuElement>> {
+    let file = &image.file;
+    let chunks = match format(file) {
+        Some(Format::IntelHex) => {
+            let text = std::fs::read_to_string(file)
+                .with_context(|| format!("Cannot read hex file `{}`", file.display()))?;
+            crate::ihex::parse(&text)
+                .with_context(|| format!("`{}` is not a valid Intel HEX file", file.display()))?
+        }
+        Some(Format::SRecord) => {
+            let text = std::fs::read_to_string(file)
+                .with_context(|| format!("Cannot read S-record file `{}`", file.display()))?;
+            crate::srec::parse(&text)
+                .with_context(|| format!("`{}` is not a valid S-record file", file.display()))?
+        }
+        Some(Format::Elf) => {
+            let bytes = std::fs::read(file)
+                .with_context(|| format!("Cannot read ELF file `{}`", file.display()))?;
+            crate::elf::parse(&bytes)
+                .with_context(|| format!("`{}` is not a valid ELF file", file.display()))?
+        }
+        Some(Format::Uf2) => {
+            let bytes = std::fs::read(file)
+                .with_context(|| format!("Cannot read UF2 file `{}`", file.display()))?;
+            crate::uf2::parse(&bytes)
+                .with_context(|| format!("`{}` is not a valid UF2 file", file.display()))?
+        }
+        None => {
+            let address = image
+                .address
+                .with_context(|| format!("`{}` needs an address (FILE:ADDRESS)", file.display()))?;
+            let data = read_bytes(file)
+                .with_context(|| format!("Cannot read bin file `{}`", file.display()))?;
+            return Ok(vec![DfuElement { address, data }]);
+        }
+    };
+    anyhow::ensure!(
+        image.address.is_none(),
+        "`{}` carries its own addresses; drop the address",
+        file.display()
+    );
+    let elements = merge(chunks)?;
+    anyhow::ensure!(
+        !elements.is_empty(),
+        "`{}` contains no data",
+        file.display()
+    );
+    Ok(elements)
+}
+
+/// The contents of `file`, or of standard input for `-`. Standard input is
+/// read once and kept, since --check-reproducible reads the inputs twice.
+pub fn read_bytes(file: &Path) -> std::io::Result<Vec<u8>> {
+    static STDIN_BYTES: OnceLock<Vec<u8>> = OnceLock::new();
+    if file != Path::new(STDIN) {
+        return std::fs::read(file);
+    }
+    if let Some(bytes) = STDIN_BYTES.get() {
+        return Ok(bytes.clone());
+    }
+    let mut bytes = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(STDIN_BYTES.get_or_init(|| bytes).clone())
+}
+
+/// Sort addressed chunks and join adjacent ones into elements, splitting at
+/// gaps.
+pub fn merge(mut chunks: Vec<(u32, Vec<u8>)>) -> Result<Vec<DfuElement>> {
+    chunks.sort_by_key(|(address, _)| *address);
+    let mut elements: Vec<DfuElement> = Vec::new();
+    for (address, data) in chunks {
+        match elements.last_mut() {
+            Some(last) if last.end() == address as u64 => last.data.extend(data),
+            Some(last) if last.end() > address as u64 => anyhow::bail!(
+                "data at {address:#010X} overlaps data up to {:#010X}",
+                last.end()
+            ),
+            _ => elements.push(DfuElement { address, data }),
+        }
+    }
+    Ok(elements)
+}
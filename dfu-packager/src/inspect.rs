@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuFile;
+
+use crate::description::Description;
+
+#[derive(clap::Args)]
+pub struct InspectArgs {
+    /// The .dfu file to list.
+    file: PathBuf,
+
+    /// Print the contents as JSON instead of a tree.
+    #[clap(long)]
+    json: bool,
+}
+
+impl InspectArgs {
+    /// Print the device IDs, targets and elements of the file.
+    pub fn run(self) -> Result<()> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let dfu = DfuFile::from_bytes(&bytes)
+            .with_context(|| format!("`{}` is not a valid DfuSe file", self.file.display()))?;
+
+        if self.json {
+            let description = Description::from(&dfu);
+            println!("{}", serde_json::to_string_pretty(&description)?);
+            return Ok(());
+        }
+
+        println!(
+            "{}: {:04x}:{:04x}, bcdDevice {:#06x} (version {}), {} bytes",
+            self.file.display(),
+            dfu.device_vid,
+            dfu.device_pid,
+            dfu.bcd_device,
+            crate::manifest::version(dfu.bcd_device),
+            bytes.len()
+        );
+        for (index, target) in dfu.targets.iter().enumerate() {
+            let last_target = index + 1 == dfu.targets.len();
+            let (branch, indent) = if last_target {
+                ("└─", "   ")
+            } else {
+                ("├─", "│  ")
+            };
+            let name = if target.name.is_empty() {
+                "(unnamed)".to_string()
+            } else {
+                format!("\"{}\"", target.name)
+            };
+            println!(
+                "{branch} Target {index} {name}, alt {}, {} element(s)",
+                target.alternate_setting,
+                target.elements.len()
+            );
+            for (index, element) in target.elements.iter().enumerate() {
+                let branch = if index + 1 == target.elements.len() {
+                    "└─"
+                } else {
+                    "├─"
+                };
+                let end = element.address as u64 + element.data.len() as u64;
+                println!(
+                    "{indent}{branch} {:#010X}..{end:#010X}  {} bytes",
+                    element.address,
+                    element.data.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1114 @@
+//! Packaging of firmware images into DfuSe, plain DFU, UF2 and bundle
+//! files, as the `dfu-packager` binary does it. [`Packager`] runs the same
+//! pipeline from a firmware project's build script or xtask.
+
+mod batch;
+mod bundle;
+mod compat;
+mod description;
+mod dump;
+mod elf;
+mod export_meta;
+mod gaps;
+mod gen_header;
+mod ihex;
+mod input;
+mod inspect;
+mod linker_script;
+mod manifest;
+mod manifest_dir;
+mod memory_map;
+mod merge;
+mod packager;
+mod pages;
+mod patch;
+mod repack;
+mod sign;
+mod srec;
+mod strip;
+mod template;
+mod uf2;
+mod unpack;
+mod variants;
+mod vectors;
+mod verify;
+mod watch;
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuElement, DfuFile, DfuTarget, MemoryLayout};
+use logging::LogFormat;
+use memory_map::MemoryMap;
+pub use packager::Packager;
+
+#[derive(clap::Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    pub(crate) options: PackageOptions,
+
+    /// Package every .bin and .hex file in this directory, each with the
+    /// options of its `<stem>.toml` sidecar if there is one, into
+    /// `<stem>.dfu` (or the --format extension) in --output.
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["file", "image", "description", "compat_check", "suffix_only"]
+    )]
+    batch: Option<PathBuf>,
+
+    /// Package FILE for hardware revision HWREV of --memory-map, as
+    /// `HWREV=FILE`: checked against that revision's map, raw binaries
+    /// placed at its flash origin, and the revision recorded in the
+    /// manifest. Repeat for one output per variant, named
+    /// `<stem>-<HWREV>` or after an --output template with {hwrev}.
+    #[clap(
+        long,
+        value_name = "HWREV=FILE",
+        value_parser = Self::parse_variant,
+        requires = "memory_map",
+        conflicts_with_all = ["file", "image", "description", "batch", "hw_rev", "suffix_only"]
+    )]
+    variant: Vec<(String, PathBuf)>,
+
+    /// After writing, print `sha256=<hex> size=<bytes> file=<path>` for the
+    /// output, or append that line to FILE (one line per file with
+    /// --batch).
+    #[clap(long, value_name = "FILE")]
+    checksum: Option<Option<PathBuf>>,
+
+    /// Keep running and package again whenever an input file changes, e.g.
+    /// after each firmware build. Failed runs are logged and retried on the
+    /// next change.
+    #[clap(long, conflicts_with_all = ["batch", "variant", "suffix_only", "check_reproducible"])]
+    watch: bool,
+
+    /// Print the written .dfu field by field: the prefix, each target and
+    /// element header, and the suffix, with offsets and raw bytes.
+    #[clap(long)]
+    dump: bool,
+
+    /// Append only the 16-byte DFU suffix to the raw binary given with
+    /// `--file`, for plain DFU 1.1 bootloaders (like `dfu-suffix -a`). The
+    /// VID/PID default to FFFF:FFFF, which matches any device.
+    #[clap(
+        long,
+        requires = "file",
+        conflicts_with_all = ["image", "description", "address", "target_name", "unnamed_targets", "format", "pad_to", "layout", "compat_check", "check_reproducible", "manifest", "signature_offset", "patch_crc", "set_version", "set_build_id", "force", "memory_map", "linker_script", "signing_key", "release_notes", "build_info", "dump", "checksum", "require_magic"]
+    )]
+    suffix_only: bool,
+
+    /// Enable verbose logs.
+    #[clap(long, short, global = true)]
+    verbose: bool,
+
+    /// Log format on stderr.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        env = "BIKESAFE_LOG_FORMAT",
+        global = true
+    )]
+    log_format: LogFormat,
+}
+
+// What to package and how: the options of one packaging run, from the
+// command line or from `Packager`. A plain comment, as clap would take a
+// doc comment for the about text of `dfu-packager`.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub(crate) struct PackageOptions {
+    /// Path to the firmware file: a raw .bin, or an Intel .hex, Motorola
+    /// S-record, ELF or UF2 file that carries its own addresses. `-` reads
+    /// a raw binary from standard input (needs --output).
+    #[clap(
+        long,
+        short,
+        required_unless_present_any = ["image", "description", "batch", "variant"],
+        conflicts_with = "image"
+    )]
+    file: Option<PathBuf>,
+
+    /// Image to package as `FILE[:ADDRESS][@ALT]`, e.g. `config.bin:0800F800`,
+    /// `options.bin:1FFFF800@1`, `app.hex` or `app.elf`. Raw binaries need the
+    /// address. Repeat to put several elements into one target; images for
+    /// another alternate setting go into their own target. `-:ADDRESS`
+    /// reads a raw binary from standard input.
+    #[clap(
+        long,
+        value_parser = Cli::parse_image,
+        conflicts_with = "address",
+        allow_hyphen_values = true
+    )]
+    image: Vec<Image>,
+
+    /// JSON file listing the targets and their images, in the format
+    /// written by `unpack`. File names are relative to the description.
+    #[clap(long, conflicts_with_all = ["file", "image", "address"])]
+    description: Option<PathBuf>,
+
+    /// Target name, e.g. "Internal Flash", as `[ALT=]NAME`; without `ALT=`
+    /// it names the target for alternate setting 0. Repeat for several
+    /// targets. Defaults to "Flash".
+    #[clap(long, value_parser = Cli::parse_target_name, conflicts_with = "description")]
+    target_name: Vec<(u8, String)>,
+
+    /// Write unnamed targets (dwNamed = 0, zeroed name), for tools and
+    /// bootloaders that reject named ones.
+    #[clap(long, conflicts_with = "target_name")]
+    unnamed_targets: bool,
+
+    /// output file name (the output directory with --batch). May contain
+    /// {version}, {hwrev}, {build_id}, {vid}, {pid} and {stem} (of the
+    /// first input), e.g. "brakebright-{version}-{hwrev}.dfu"; version and
+    /// build ID come from the options or the image's metadata block.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// Specify Vendor/Product ID(s) of DFU device.
+    /// i.e. 1209:2444. Required for .dfu output unless the
+    /// description gives them.
+    #[clap(
+        long,
+        short,
+        value_parser = Cli::parse_vid_pid, name = "VID>:<PID",
+    )]
+    device: Option<(u16, u16)>,
+
+    /// Firmware version for the DFU suffix (`bcdDevice`), as
+    /// `MAJOR.MINOR[.PATCH]` with MAJOR up to 99 and MINOR/PATCH up to 9,
+    /// e.g. 1.2 (0x0120) or 1.4.2 (0x0142). Defaults to 0.0.0.
+    #[clap(long, value_parser = Cli::parse_fw_version)]
+    fw_version: Option<u16>,
+
+    /// Output format: a DfuSe file, a plain DFU 1.1 file (the raw binary of
+    /// the single target with a DFU suffix, for standard dfu-util and
+    /// non-ST bootloaders), UF2 for mass-storage bootloaders, a raw binary
+    /// of the single target (gaps filled with 0xFF), or a release bundle (.bbfw
+    /// zip with the .dfu, the raw binary, the manifest, its signature with
+    /// --signing-key, and --release-notes).
+    #[clap(long, value_enum, default_value = "dfu")]
+    format: Format,
+
+    /// UF2 family ID, e.g. 0x5EE21072 for STM32F1.
+    #[clap(long, default_value = "0x5EE21072", value_parser = Cli::parse_address)]
+    family_id: u32,
+
+    /// Pad every element to a multiple of this size, e.g. the erase-page
+    /// size (1K) or, for fixed-size images, the size of the image slot
+    /// (48K). Decimal with an optional K suffix, or hex with 0x.
+    #[clap(long, value_parser = Cli::parse_size)]
+    pad_to: Option<u32>,
+
+    /// Byte used for --pad-to padding and for the gaps between elements
+    /// in --format bin output.
+    #[clap(long, default_value = "0xFF", value_parser = Cli::parse_fill)]
+    fill: u8,
+
+    /// Join the elements of each target into one, writing --fill into the
+    /// gaps between them, so the device writes every byte in between.
+    #[clap(long)]
+    fill_gaps: bool,
+
+    /// Firmware version to patch into the image's metadata block (`BBFW`
+    /// magic) and the DFU suffix, as `MAJOR.MINOR[.PATCH]` like
+    /// --fw-version. Recomputes the block's CRC if it is filled in.
+    #[clap(long, value_parser = Cli::parse_fw_version, conflicts_with = "fw_version")]
+    set_version: Option<u16>,
+
+    /// Build ID to patch into the image's metadata block, e.g. a short git
+    /// hash (at most 16 ASCII characters). Also recorded in --manifest.
+    #[clap(long)]
+    set_build_id: Option<String>,
+
+    /// Write the CRC32 of the image at this offset from the image start,
+    /// little endian, for bootloaders that check the application at boot.
+    /// The CRC covers the first target's image, gaps filled with --fill,
+    /// without the 4 CRC bytes; a signature (--signature-offset) is made
+    /// after it and covers it.
+    #[clap(long, value_name = "OFFSET", value_parser = Cli::parse_address)]
+    patch_crc: Option<u32>,
+
+    /// Sign the image with --signing-key and write the 64-byte ed25519
+    /// signature at this offset from the image start, for bootloaders that
+    /// check it at boot. The signature covers the first target's image,
+    /// gaps filled with --fill, without the signature slot.
+    #[clap(long, value_parser = Cli::parse_address, requires = "signing_key")]
+    signature_offset: Option<u32>,
+
+    /// Hex-encoded ed25519 secret key (32-byte seed) for --signature-offset,
+    /// and to sign the manifest of --format bundle.
+    #[clap(long, value_name = "FILE")]
+    signing_key: Option<PathBuf>,
+
+    /// Compress the raw payload in --format bundle output with zstd, for
+    /// smaller transfers; the manifest keeps the hash of the decompressed
+    /// payload.
+    #[clap(long)]
+    compress: bool,
+
+    /// Release notes to put into --format bundle output, under their file
+    /// name.
+    #[clap(long, value_name = "FILE")]
+    release_notes: Option<PathBuf>,
+
+    /// DfuSe memory layout of a target as `[ALT=]LAYOUT`, as the device
+    /// reports it, e.g. "@Internal Flash /0x08000000/16*001Ka,48*001Kg".
+    /// Warns about elements reaching into protected or unlisted pages.
+    /// Repeat for several targets.
+    #[clap(long, value_parser = Cli::parse_layout)]
+    layout: Vec<(u8, MemoryLayout)>,
+
+    /// Split elements at the page boundaries of --layout, one element per
+    /// page, like ST's DfuSe tools.
+    #[clap(long, requires = "layout")]
+    split_pages: bool,
+
+    /// Compare the .dfu output with a reference file for the same inputs,
+    /// e.g. from dfu-util's dfuse-pack.py, and fail listing every
+    /// structural difference.
+    #[clap(long, value_name = "REFERENCE")]
+    compat_check: Option<PathBuf>,
+
+    /// Also write a JSON manifest with the version, VID/PID, elements,
+    /// SHA-256 of the payload and of the .dfu, and build metadata, to FILE
+    /// or next to the .dfu. Its bundle fields describe the payload as
+    /// `<output stem>.bin`, as `strip` writes it.
+    #[clap(long, value_name = "FILE")]
+    manifest: Option<Option<PathBuf>>,
+
+    /// Build metadata for the manifest (--manifest or --format bundle) as
+    /// `KEY=VALUE`, e.g. `commit=1a2b3c4` or `ci_job=1234`. Repeatable.
+    #[clap(long, value_parser = Cli::parse_build_info)]
+    build_info: Vec<(String, String)>,
+
+    /// Package even if the application image's vector table does not suit
+    /// its address or the image does not fit the memory map (the built-in
+    /// map of --device, --memory-map, --linker-script or --layout).
+    #[clap(long)]
+    force: bool,
+
+    /// Fail unless the image contains this 32-bit stay-in-boot magic: the
+    /// value the firmware leaves for the bootloader to stay in DFU mode,
+    /// without which a device cannot be updated in the field. Not skipped
+    /// by --force.
+    #[clap(long, value_name = "MAGIC", value_parser = Cli::parse_address)]
+    require_magic: Option<u32>,
+
+    /// TOML file with the flash, RAM, page size and reserved regions of
+    /// each hardware revision, to check the image against instead of the
+    /// built-in map of --device.
+    #[clap(long, value_name = "FILE")]
+    memory_map: Option<PathBuf>,
+
+    /// Hardware revision to take from --memory-map [default: the file's
+    /// `default`]
+    #[clap(long, requires = "memory_map")]
+    hw_rev: Option<String>,
+
+    /// Take the flash and RAM to check the image against from the FLASH and
+    /// RAM regions of the firmware's linker script (e.g. `memory.x`),
+    /// overriding those of --memory-map.
+    #[clap(long, value_name = "FILE")]
+    linker_script: Option<PathBuf>,
+
+    /// Package twice and fail unless both runs give identical bytes, as a
+    /// self-test before publishing a release.
+    #[clap(long)]
+    check_reproducible: bool,
+
+    /// bcdDFU of the DFU suffix: 0x011A (DfuSe) or 0x0100 (DFU 1.1).
+    /// DfuSe output must use 0x011A; --format plain-dfu and --suffix-only
+    /// output default to 0x0100.
+    #[clap(long, value_parser = Cli::parse_bcd_dfu)]
+    bcd_dfu: Option<u16>,
+
+    /// target address to flash the firmware (.bin only) [default: the
+    /// flash origin of the memory map]
+    #[clap(long, short, value_parser = Cli::parse_address)]
+    address: Option<u32>,
+}
+
+/// Output file format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Dfu,
+    PlainDfu,
+    Uf2,
+    Bin,
+    Bundle,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Dfu | Format::PlainDfu => "dfu",
+            Format::Uf2 => "uf2",
+            Format::Bin => "bin",
+            Format::Bundle => "bbfw",
+        }
+    }
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// List the targets and elements of a .dfu file.
+    Inspect(inspect::InspectArgs),
+    /// Extract every element of a .dfu file to a .bin, with a JSON
+    /// description of the file.
+    Unpack(unpack::UnpackArgs),
+    /// Check the structure, sizes and CRC of a .dfu file; exits non-zero
+    /// on any problem.
+    Verify(verify::VerifyArgs),
+    /// Combine the targets of several .dfu files into one, e.g. bootloader,
+    /// application and default configuration.
+    Merge(merge::MergeArgs),
+    /// Change the VID/PID, bcdDevice, target names or alternate settings of
+    /// a .dfu file, recomputing its sizes and CRC.
+    Repack(repack::RepackArgs),
+    /// Write the version, IDs, address and hashes of a .dfu as a Rust
+    /// module or JSON, for host tools that embed it.
+    ExportMeta(export_meta::ExportMetaArgs),
+    /// Write a signed index (files, sizes, SHA-256, versions) of the
+    /// firmware artifacts in a release directory, for use as an update feed.
+    ManifestDir(manifest_dir::ManifestDirArgs),
+    /// Sign a file with an ed25519 key, writing a detached signature.
+    Sign(sign::SignArgs),
+    /// Check a detached signature against a file and a public key.
+    VerifySig(sign::VerifySigArgs),
+    /// Remove the DfuSe wrapper and/or the DFU suffix, recovering the raw
+    /// binary.
+    Strip(strip::StripArgs),
+    /// Write the metadata block layout (and --patch-crc/--signature-offset
+    /// slots) as a C header or Rust module for the firmware build.
+    GenHeader(gen_header::GenHeaderArgs),
+}
+
+impl Cli {
+    pub fn run(mut self) -> Result<()> {
+        logging::init(self.verbose, self.log_format, true).map_err(|e| anyhow::anyhow!(e))?;
+        match self.command.take() {
+            Some(Command::Inspect(args)) => return args.run(),
+            Some(Command::Unpack(args)) => return args.run(),
+            Some(Command::Verify(args)) => return args.run(),
+            Some(Command::Strip(args)) => return args.run(),
+            Some(Command::Merge(args)) => return args.run(),
+            Some(Command::Repack(args)) => return args.run(),
+            Some(Command::ManifestDir(args)) => return args.run(),
+            Some(Command::ExportMeta(args)) => return args.run(),
+            Some(Command::Sign(args)) => return args.run(),
+            Some(Command::VerifySig(args)) => return args.run(),
+            Some(Command::GenHeader(args)) => return args.run(),
+            None => {}
+        }
+        let options = &self.options;
+        if let Some(file) = options.file.as_ref().filter(|_| self.suffix_only) {
+            return write_suffixed(
+                file,
+                options.output.as_deref(),
+                options.device,
+                options.fw_version,
+                options.bcd_dfu.unwrap_or(dfu_file::BCD_DFU_1_1),
+            );
+        }
+        if let Some(dir) = self.batch.take() {
+            return batch::run(self, &dir);
+        }
+        if !self.variant.is_empty() {
+            let variants = std::mem::take(&mut self.variant);
+            return variants::run(self, variants);
+        }
+        if self.watch {
+            return watch::run(self);
+        }
+        self.package()
+    }
+
+    /// Package the inputs and write the output, and the manifest if asked,
+    /// then print its checksum or fields if asked.
+    fn package(&self) -> Result<()> {
+        if self.dump && self.options.format != Format::Dfu {
+            anyhow::bail!("--dump shows .dfu output");
+        }
+        let (out_path, bytes) = self.options.package()?;
+        if let Some(path) = &self.checksum {
+            write_checksum(&bytes, &out_path, path.as_deref())?;
+        }
+        if self.dump {
+            dump::print(&bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_vid_pid(s: &str) -> Result<(u16, u16)> {
+        let (vid, pid) = s
+            .split_once(':')
+            .context("could not parse VID/PID (missing `:')")?;
+        let vid = u16::from_str_radix(vid, 16).context("could not parse VID")?;
+        let pid = u16::from_str_radix(pid, 16).context("could not parse PID")?;
+
+        Ok((vid, pid))
+    }
+
+    pub fn parse_address(s: &str) -> Result<u32> {
+        // remove leading 0x if present
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let address = u32::from_str_radix(s, 16).context("could not parse address")?;
+        Ok(address)
+    }
+
+    /// Encode `MAJOR.MINOR[.PATCH]` as BCD, the way USB `bcdDevice` is
+    /// read back (0xJJMN).
+    pub fn parse_fw_version(s: &str) -> Result<u16> {
+        let mut parts = s.split('.');
+        let mut part = |max: u16| -> Result<Option<u16>> {
+            let Some(part) = parts.next() else {
+                return Ok(None);
+            };
+            let value: u16 = part.parse().context("could not parse version")?;
+            anyhow::ensure!(value <= max, "version part {value} is larger than {max}");
+            Ok(Some(value))
+        };
+        let major = part(99)?.context("missing major version")?;
+        let minor = part(9)?.context("missing minor version (e.g. 1.2)")?;
+        let patch = part(9)?.unwrap_or(0);
+        anyhow::ensure!(parts.next().is_none(), "version has more than 3 parts");
+        Ok((major / 10) << 12 | (major % 10) << 8 | minor << 4 | patch)
+    }
+
+    pub fn parse_size(s: &str) -> Result<u32> {
+        let size = match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).context("could not parse size")?,
+            None => match s.strip_suffix(['K', 'k']) {
+                Some(kib) => kib
+                    .parse::<u32>()
+                    .context("could not parse size")?
+                    .checked_mul(1024)
+                    .context("size is too large")?,
+                None => s.parse().context("could not parse size")?,
+            },
+        };
+        anyhow::ensure!(size > 0, "size must not be 0");
+        Ok(size)
+    }
+
+    pub fn parse_bcd_dfu(s: &str) -> Result<u16> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let bcd_dfu = u16::from_str_radix(s, 16).context("could not parse bcdDFU")?;
+        anyhow::ensure!(
+            [dfu_file::BCD_DFU, dfu_file::BCD_DFU_1_1].contains(&bcd_dfu),
+            "bcdDFU must be 0x011A (DfuSe) or 0x0100 (DFU 1.1)"
+        );
+        Ok(bcd_dfu)
+    }
+
+    pub fn parse_fill(s: &str) -> Result<u8> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        u8::from_str_radix(s, 16).context("could not parse fill byte (e.g. 0xFF)")
+    }
+
+    pub fn parse_target_name(s: &str) -> Result<(u8, String)> {
+        match s.split_once('=') {
+            Some((alt, name)) if alt.bytes().all(|b| b.is_ascii_digit()) && !alt.is_empty() => {
+                Ok((
+                    alt.parse().context("could not parse ALT")?,
+                    name.to_string(),
+                ))
+            }
+            _ => Ok((0, s.to_string())),
+        }
+    }
+
+    pub fn parse_layout(s: &str) -> Result<(u8, MemoryLayout)> {
+        let (alt, layout) = match s.split_once('=') {
+            Some((alt, layout)) if !alt.is_empty() && alt.bytes().all(|b| b.is_ascii_digit()) => {
+                (alt.parse().context("could not parse ALT")?, layout)
+            }
+            _ => (0, s),
+        };
+        let layout = MemoryLayout::parse(layout)
+            .context("the memory layout must start with `@`")?
+            .context("could not parse the memory layout")?;
+        Ok((alt, layout))
+    }
+
+    pub fn parse_variant(s: &str) -> Result<(String, PathBuf)> {
+        let (hw_rev, file) = s.split_once('=').context("expected HWREV=FILE")?;
+        anyhow::ensure!(!hw_rev.is_empty(), "missing HWREV in HWREV=FILE");
+        Ok((hw_rev.to_string(), file.into()))
+    }
+
+    pub fn parse_build_info(s: &str) -> Result<(String, String)> {
+        let (key, value) = s.split_once('=').context("expected KEY=VALUE")?;
+        anyhow::ensure!(!key.is_empty(), "missing KEY in KEY=VALUE");
+        Ok((key.to_string(), value.to_string()))
+    }
+
+    pub fn parse_image(s: &str) -> Result<Image> {
+        let (s, alt) = match s.rsplit_once('@') {
+            Some((rest, alt)) if !alt.is_empty() && alt.bytes().all(|b| b.is_ascii_digit()) => {
+                (rest, alt.parse().context("could not parse ALT")?)
+            }
+            _ => (s, 0),
+        };
+        // The address is optional; a `:` followed by something else belongs
+        // to the path (e.g. `C:\firmware.hex`).
+        let (file, address) = match s.rsplit_once(':') {
+            Some((file, address)) => match Self::parse_address(address) {
+                Ok(address) => (file, Some(address)),
+                Err(_) => (s, None),
+            },
+            None => (s, None),
+        };
+        Ok(Image {
+            file: file.into(),
+            address,
+            alt,
+        })
+    }
+}
+
+impl PackageOptions {
+    /// Package the inputs and write the output, and the manifest if asked.
+    /// Returns the path written and its contents.
+    pub(crate) fn package(&self) -> Result<(PathBuf, Vec<u8>)> {
+        if self.compat_check.is_some() && self.format != Format::Dfu {
+            anyhow::bail!("--compat-check compares .dfu output");
+        }
+        match (self.bcd_dfu, self.format) {
+            (_, Format::PlainDfu)
+            | (None | Some(dfu_file::BCD_DFU), Format::Dfu | Format::Bundle) => {}
+            (Some(bcd_dfu), Format::Dfu | Format::Bundle) => anyhow::bail!(
+                "DfuSe files need bcdDFU {:#06x}, not {bcd_dfu:#06x}; plain DFU 1.1 takes --format plain-dfu",
+                dfu_file::BCD_DFU
+            ),
+            (Some(_), format) => anyhow::bail!(
+                "--format {} output has no DFU suffix for --bcd-dfu",
+                format.extension()
+            ),
+            (None, _) => {}
+        }
+        if self.manifest.is_some() && !matches!(self.format, Format::Dfu | Format::PlainDfu) {
+            anyhow::bail!("--manifest describes .dfu output (bundles contain their manifest)");
+        }
+        if !self.build_info.is_empty() && self.manifest.is_none() && self.format != Format::Bundle {
+            anyhow::bail!("--build-info needs --manifest or --format bundle");
+        }
+        if self.compress && self.format != Format::Bundle {
+            anyhow::bail!("--compress applies to --format bundle output");
+        }
+        if self.release_notes.is_some() && self.format != Format::Bundle {
+            anyhow::bail!("--release-notes go into --format bundle output");
+        }
+        if self.signing_key.is_some()
+            && self.signature_offset.is_none()
+            && self.format != Format::Bundle
+        {
+            anyhow::bail!("--signing-key needs --signature-offset or --format bundle");
+        }
+
+        let (dfu_file, first_input) = self.build()?;
+        if let Some(magic) = self.require_magic {
+            vectors::check_magic(&dfu_file, magic, self.fill)?;
+        }
+        if !self.force {
+            let first_alt = dfu_file.targets.first().map(|t| t.alternate_setting);
+            let layout = self
+                .layout
+                .iter()
+                .find(|(alt, _)| Some(*alt) == first_alt)
+                .map(|(_, layout)| layout);
+            let map = self.memory_map()?;
+            vectors::check(&dfu_file, &map)
+                .context("refusing to package a mis-linked image (--force packages it anyway)")?;
+            vectors::check_fits(&dfu_file, layout, &map).context(
+                "refusing to package an image that does not fit the memory map (--force packages it anyway)",
+            )?;
+        }
+        let extension = self.format.extension();
+        anyhow::ensure!(
+            self.output.is_some() || first_input != Path::new(input::STDIN),
+            "--output is required when reading from standard input"
+        );
+        let output = match &self.output {
+            Some(output) => Some(self.expand_output(output, &dfu_file, &first_input)?),
+            None => None,
+        };
+        let mut out_path = output.unwrap_or_else(|| {
+            let mut path = first_input;
+            path.set_extension(extension);
+            path
+        });
+
+        if out_path.extension() != Some(OsStr::new(extension)) {
+            eprintln!("Changing the output file to have .{extension} extension");
+            out_path.set_extension(extension);
+        }
+
+        let bytes = self.encode(&dfu_file, &out_path)?;
+        if self.check_reproducible {
+            tracing::info!("Packaging again to check that the output is reproducible");
+            let again = self.encode(&self.build()?.0, &out_path)?;
+            if let Some(offset) =
+                (0..bytes.len().max(again.len())).find(|&i| bytes.get(i) != again.get(i))
+            {
+                anyhow::bail!(
+                    "output is not reproducible: two runs differ first at byte {offset:#x}"
+                );
+            }
+            tracing::info!(
+                "Reproducible: both runs gave the same {} bytes",
+                bytes.len()
+            );
+        }
+
+        std::fs::write(&out_path, &bytes)
+            .with_context(|| format!("could not write `{}`", out_path.display()))?;
+        if let Some(path) = &self.manifest {
+            let path = path
+                .clone()
+                .unwrap_or_else(|| out_path.with_extension("json"));
+            manifest::write(
+                &path,
+                &dfu_file,
+                &bytes,
+                &out_path,
+                &self.manifest_options(),
+            )?;
+        }
+        if let Some(reference) = &self.compat_check {
+            check_compat(&bytes, reference)?;
+        }
+
+        Ok((out_path, bytes))
+    }
+
+    /// Read the inputs and apply the options to them. Returns the file and
+    /// the first input, whose name the output is named after.
+    #[tracing::instrument(skip_all)]
+    fn build(&self) -> Result<(DfuFile, PathBuf)> {
+        let (mut dfu_file, first_input) = match &self.description {
+            Some(description) => {
+                let mut dfu_file = description::load(description)?;
+                if let Some((vid, pid)) = self.device {
+                    (dfu_file.device_vid, dfu_file.device_pid) = (vid, pid);
+                }
+                (dfu_file, description.clone())
+            }
+            None => {
+                // UF2 has no device IDs.
+                let (vid, pid) = match self.device {
+                    Some(device) => device,
+                    None if matches!(self.format, Format::Uf2 | Format::Bin) => (0, 0),
+                    // Matches any device, like --suffix-only.
+                    None if self.format == Format::PlainDfu => (0xFFFF, 0xFFFF),
+                    None => anyhow::bail!("--device is required for .dfu and bundle output"),
+                };
+                let images = match &self.file {
+                    Some(file) => vec![Image {
+                        address: match self.address {
+                            Some(address) => Some(address),
+                            None if input::carries_addresses(file) => None,
+                            None => Some(self.memory_map()?.flash.origin),
+                        },
+                        file: file.clone(),
+                        alt: 0,
+                    }],
+                    None => self.image.clone(),
+                };
+                let dfu_file = DfuFile {
+                    device_vid: vid,
+                    device_pid: pid,
+                    bcd_device: 0,
+                    targets: read_targets(&images, &self.target_name)?,
+                };
+                (dfu_file, images[0].file.clone())
+            }
+        };
+
+        if let Some(version) = self.fw_version.or(self.set_version) {
+            dfu_file.bcd_device = version;
+        }
+        if self.unnamed_targets {
+            for target in &mut dfu_file.targets {
+                target.name.clear();
+            }
+        }
+        if let Some(size) = self.pad_to {
+            pad_elements(&mut dfu_file, size, self.fill)?;
+        }
+        gaps::check_overlaps(&dfu_file)?;
+        if self.fill_gaps {
+            gaps::fill(&mut dfu_file, self.fill)?;
+        } else {
+            gaps::warn_gaps(&dfu_file);
+        }
+        if self.set_version.is_some() || self.set_build_id.is_some() {
+            patch::patch_metadata(
+                &mut dfu_file,
+                self.set_version,
+                self.set_build_id.as_deref(),
+                self.fill,
+            )?;
+        }
+        if let Some(offset) = self.patch_crc {
+            patch::patch_crc(&mut dfu_file, offset, self.fill)?;
+        }
+        if let (Some(offset), Some(key)) = (self.signature_offset, &self.signing_key) {
+            let key = ed25519_dalek::SigningKey::from_bytes(&firmware_manifest::read_key(key)?);
+            patch::embed_signature(&mut dfu_file, offset, &key, self.fill)?;
+        }
+        for (alt, layout) in &self.layout {
+            let Some(target) = dfu_file
+                .targets
+                .iter_mut()
+                .find(|target| target.alternate_setting == *alt)
+            else {
+                anyhow::bail!("--layout given for alternate setting {alt}, which has no images");
+            };
+            pages::check(target, layout);
+            if self.split_pages {
+                pages::split(target, layout);
+            }
+        }
+        Ok((dfu_file, first_input))
+    }
+
+    /// `dfu` in the output format, to be written to `path`.
+    #[tracing::instrument(skip_all, fields(format = self.format.extension()))]
+    fn encode(&self, dfu: &DfuFile, path: &Path) -> Result<Vec<u8>> {
+        match self.format {
+            Format::Dfu => Ok(dfu.to_bytes()?),
+            Format::Uf2 => uf2::to_bytes(dfu, self.family_id),
+            Format::Bin => to_bin(dfu, self.fill),
+            Format::PlainDfu => {
+                let mut bytes = to_bin(dfu, self.fill)?;
+                dfu_file::append_suffix(
+                    &mut bytes,
+                    dfu.bcd_device,
+                    dfu.device_pid,
+                    dfu.device_vid,
+                    self.bcd_dfu.unwrap_or(dfu_file::BCD_DFU_1_1),
+                );
+                Ok(bytes)
+            }
+            Format::Bundle => {
+                let key = match &self.signing_key {
+                    Some(key) => Some(ed25519_dalek::SigningKey::from_bytes(
+                        &firmware_manifest::read_key(key)?,
+                    )),
+                    None => None,
+                };
+                bundle::to_bytes(
+                    path,
+                    dfu,
+                    &self.manifest_options(),
+                    key.as_ref(),
+                    self.release_notes.as_deref(),
+                )
+            }
+        }
+    }
+
+    /// `output` with its placeholders filled in for `dfu`, packaged from
+    /// `first_input`.
+    fn expand_output(&self, output: &Path, dfu: &DfuFile, first_input: &Path) -> Result<PathBuf> {
+        let template = output
+            .to_str()
+            .with_context(|| format!("`{}` is not valid UTF-8", output.display()))?;
+        let metadata = || patch::read_metadata(dfu, self.fill);
+        let expanded = template::expand(template, |name| match name {
+            // bcdDevice, set by --fw-version, --set-version or a description.
+            "version" if dfu.bcd_device != 0 => Ok(manifest::version(dfu.bcd_device)),
+            "version" => metadata().map(|m| m.version_string()).context(
+                "no version: give --fw-version or --set-version, or embed a metadata block",
+            ),
+            "hwrev" => self
+                .hw_rev
+                .clone()
+                .context("no hardware revision: give --hw-rev"),
+            "build_id" => match (&self.set_build_id, metadata()) {
+                (Some(build_id), _) => Ok(build_id.clone()),
+                (None, Some(metadata)) if !metadata.build_id.is_empty() => Ok(metadata.build_id),
+                _ => anyhow::bail!(
+                    "no build ID: give --set-build-id, or embed one in the metadata block"
+                ),
+            },
+            "vid" => Ok(format!("{:04x}", dfu.device_vid)),
+            "pid" => Ok(format!("{:04x}", dfu.device_pid)),
+            "stem" => Ok(first_input
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()),
+            _ => anyhow::bail!("unknown placeholder; known are {}", template::PLACEHOLDERS),
+        })?;
+        Ok(expanded.into())
+    }
+
+    /// The memory map to check the image against: --memory-map or the
+    /// built-in map of --device, with the flash and RAM of --linker-script.
+    fn memory_map(&self) -> Result<MemoryMap> {
+        let mut map = match &self.memory_map {
+            Some(path) => MemoryMap::load(path, self.hw_rev.as_deref())?,
+            None => MemoryMap::builtin(self.device),
+        };
+        if let Some(path) = &self.linker_script {
+            linker_script::apply(path, &mut map)?;
+        }
+        Ok(map)
+    }
+
+    /// What the manifest records: `--build-info` and the build ID of
+    /// `--set-build-id`, and `--hw-rev` as the compatible hardware.
+    fn manifest_options(&self) -> manifest::Options {
+        let mut build = self.build_info.clone();
+        if let Some(build_id) = &self.set_build_id {
+            build.push(("build_id".into(), build_id.clone()));
+        }
+        manifest::Options {
+            fill: self.fill,
+            build,
+            hardware: self.hw_rev.iter().cloned().collect(),
+            compressed: self.compress,
+            release_notes: None,
+        }
+    }
+}
+
+/// Largest raw binary `--format bin` writes, to catch elements in distant
+/// memory regions.
+const MAX_BIN_LEN: u64 = 16 * 1024 * 1024;
+
+/// A file to package, in the target for alternate setting `alt`. `address`
+/// places raw binaries; other formats carry their own addresses.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub file: PathBuf,
+    pub address: Option<u32>,
+    pub alt: u8,
+}
+
+/// Group the images into one target per alternate setting, in ascending
+/// order.
+fn read_targets(images: &[Image], names: &[(u8, String)]) -> Result<Vec<DfuTarget>> {
+    if let Some((alt, _)) = names
+        .iter()
+        .find(|(alt, _)| images.iter().all(|image| image.alt != *alt))
+    {
+        anyhow::bail!("--target-name given for alternate setting {alt}, which has no images");
+    }
+    let mut alts: Vec<u8> = images.iter().map(|image| image.alt).collect();
+    alts.sort_unstable();
+    alts.dedup();
+    alts.into_iter()
+        .map(|alt| {
+            let images: Vec<_> = images
+                .iter()
+                .filter(|image| image.alt == alt)
+                .cloned()
+                .collect();
+            let name = names
+                .iter()
+                .rfind(|(name_alt, _)| *name_alt == alt)
+                .map_or("Flash", |(_, name)| name);
+            Ok(DfuTarget {
+                name: name.to_string(),
+                alternate_setting: alt,
+                elements: read_elements(&images)?,
+            })
+        })
+        .collect()
+}
+
+/// Read the images into elements, refusing overlapping ones.
+fn read_elements(images: &[Image]) -> Result<Vec<DfuElement>> {
+    let mut elements = Vec::new();
+    for image in images {
+        add_elements(&mut elements, &image.file, input::read(image)?)?;
+    }
+    Ok(elements)
+}
+
+/// Append the elements read from `source`, refusing overlaps with those
+/// already there.
+fn add_elements(elements: &mut Vec<DfuElement>, source: &Path, new: Vec<DfuElement>) -> Result<()> {
+    for element in new {
+        if let Some(other) = elements.iter().find(|other| {
+            (other.address as u64) < element.end() && (element.address as u64) < other.end()
+        }) {
+            anyhow::bail!(
+                "`{}` ({:#010X}..{:#010X}) overlaps the image at {:#010X}..{:#010X}",
+                source.display(),
+                element.address,
+                element.end(),
+                other.address,
+                other.end()
+            );
+        }
+        elements.push(element);
+    }
+    Ok(())
+}
+
+/// Compare `bytes` with the file at `reference`, logging every difference.
+fn check_compat(bytes: &[u8], reference: &Path) -> Result<()> {
+    let reference_bytes = std::fs::read(reference)
+        .with_context(|| format!("could not read `{}`", reference.display()))?;
+    let differences = compat::compare(bytes, &reference_bytes);
+    for difference in &differences {
+        tracing::error!("{difference}");
+    }
+    match differences.len() {
+        0 => {
+            tracing::info!("Identical to `{}`", reference.display());
+            Ok(())
+        }
+        1 => anyhow::bail!("1 difference from `{}`", reference.display()),
+        n => anyhow::bail!("{n} differences from `{}`", reference.display()),
+    }
+}
+
+/// Pad every element of `dfu` with `fill` to a multiple of `size` bytes,
+/// refusing to pad one into the next.
+fn pad_elements(dfu: &mut DfuFile, size: u32, fill: u8) -> Result<()> {
+    for target in &mut dfu.targets {
+        for i in 0..target.elements.len() {
+            let element = &target.elements[i];
+            let len = element.data.len().next_multiple_of(size as usize);
+            let end = element.address as u64 + len as u64;
+            anyhow::ensure!(
+                end <= 1 << 32,
+                "padding the element at {:#010X} to {len} bytes runs past 4 GiB",
+                element.address
+            );
+            if let Some(other) = target
+                .elements
+                .iter()
+                .find(|other| other.address > element.address && (other.address as u64) < end)
+            {
+                anyhow::bail!(
+                    "padding the element at {:#010X} to {len} bytes would overlap the one at {:#010X}",
+                    element.address,
+                    other.address
+                );
+            }
+            let element = &mut target.elements[i];
+            if element.data.len() != len {
+                tracing::debug!(
+                    "Padding the element at {:#010X} from {} to {len} bytes",
+                    element.address,
+                    element.data.len()
+                );
+                element.data.resize(len, fill);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `file` with a plain DFU 1.1 suffix appended, to `output` or
+/// `<file stem>.dfu`.
+fn write_suffixed(
+    file: &Path,
+    output: Option<&Path>,
+    device: Option<(u16, u16)>,
+    fw_version: Option<u16>,
+    bcd_dfu: u16,
+) -> Result<()> {
+    anyhow::ensure!(
+        !input::carries_addresses(file),
+        "--suffix-only takes a raw binary, not `{}`",
+        file.display()
+    );
+    anyhow::ensure!(
+        output.is_some() || file != Path::new(input::STDIN),
+        "--output is required when reading from standard input"
+    );
+    let mut bytes =
+        input::read_bytes(file).with_context(|| format!("could not read `{}`", file.display()))?;
+    if dfu_file::Suffix::parse(&bytes).is_ok() {
+        anyhow::bail!("`{}` already has a DFU suffix", file.display());
+    }
+    let (vid, pid) = device.unwrap_or((0xFFFF, 0xFFFF));
+    let bcd_device = fw_version.unwrap_or(0);
+    dfu_file::append_suffix(&mut bytes, bcd_device, pid, vid, bcd_dfu);
+    let out_path = output.map_or_else(|| file.with_extension("dfu"), Path::to_path_buf);
+    std::fs::write(&out_path, &bytes)
+        .with_context(|| format!("could not write `{}`", out_path.display()))?;
+    tracing::info!(
+        "{} bytes with a DFU suffix for {vid:04x}:{pid:04x} -> {}",
+        bytes.len(),
+        out_path.display()
+    );
+    Ok(())
+}
+
+/// Print the SHA-256 and size of `bytes`, written to `out_path`, as one
+/// line, or append the line to `path`.
+fn write_checksum(bytes: &[u8], out_path: &Path, path: Option<&Path>) -> Result<()> {
+    use std::io::Write;
+
+    use sha2::Digest;
+
+    let line = format!(
+        "sha256={} size={} file={}",
+        hex::encode(sha2::Sha256::digest(bytes)),
+        bytes.len(),
+        out_path.display()
+    );
+    match path {
+        None => println!("{line}"),
+        Some(path) => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{line}"))
+            .with_context(|| format!("could not write `{}`", path.display()))?,
+    }
+    Ok(())
+}
+
+/// The single target of `dfu` as a raw binary from its lowest to its
+/// highest address, filling gaps between elements with `fill`.
+fn to_bin(dfu: &DfuFile, fill: u8) -> Result<Vec<u8>> {
+    let [target] = dfu.targets.as_slice() else {
+        anyhow::bail!("a raw binary holds a single target");
+    };
+    let (start, bin) = flatten(target, fill)?;
+    tracing::info!("{} bytes starting at {start:#010X}", bin.len());
+    Ok(bin)
+}
+
+/// The elements of `target` as one image from its lowest to its highest
+/// address, with gaps filled with `fill`, and the image's start address.
+pub fn flatten(target: &DfuTarget, fill: u8) -> Result<(u32, Vec<u8>)> {
+    let start = target
+        .elements
+        .iter()
+        .map(|element| element.address)
+        .min()
+        .context("nothing to write")?;
+    let end = target
+        .elements
+        .iter()
+        .map(DfuElement::end)
+        .max()
+        .unwrap_or(0);
+    anyhow::ensure!(
+        end - start as u64 <= MAX_BIN_LEN,
+        "elements span {start:#010X}..{end:#010X}, too far apart for one binary"
+    );
+    let mut bin = vec![fill; (end - start as u64) as usize];
+    for element in &target.elements {
+        let offset = (element.address - start) as usize;
+        bin[offset..offset + element.data.len()].copy_from_slice(&element.data);
+    }
+    Ok((start, bin))
+}
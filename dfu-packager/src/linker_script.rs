@@ -0,0 +1,254 @@
+//! FLASH and RAM regions from the `MEMORY` command of a GNU ld linker
+//! script, such as cortex-m-rt's `memory.x`:
+//!
+//! ```text
+//! MEMORY
+//! {
+//!   FLASH (rx) : ORIGIN = 0x08000000 + 16K, LENGTH = 48K
+//!   RAM (rwx)  : ORIGIN = 0x20000010, LENGTH = 20K - 0x10
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::memory_map::{MemoryMap, Region};
+
+/// Replace the flash and RAM of `map` with the `FLASH` and `RAM` regions of
+/// the linker script at `path`. `RAM` is optional.
+pub fn apply(path: &Path, map: &mut MemoryMap) -> Result<()> {
+    let script = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read `{}`", path.display()))?;
+    let mut regions =
+        regions(&script).with_context(|| format!("could not parse `{}`", path.display()))?;
+    map.flash = regions
+        .remove("FLASH")
+        .with_context(|| format!("`{}` has no FLASH memory region", path.display()))?;
+    if let Some(ram) = regions.remove("RAM") {
+        map.ram = ram;
+    }
+    tracing::debug!(
+        "Linker script {}: FLASH {:#010X}..{:#010X}, RAM {:#010X}..{:#010X}",
+        path.display(),
+        map.flash.origin,
+        map.flash.end(),
+        map.ram.origin,
+        map.ram.end()
+    );
+    Ok(())
+}
+
+/// Every region of the script's `MEMORY` command, by name.
+pub fn regions(script: &str) -> Result<BTreeMap<String, Region>> {
+    let tokens = tokenize(&strip_comments(script));
+    let start = tokens
+        .windows(2)
+        .position(|pair| pair[0] == "MEMORY" && pair[1] == "{")
+        .context("no MEMORY command")?;
+    let mut tokens = tokens[start + 2..].iter().map(String::as_str).peekable();
+
+    let mut regions = BTreeMap::new();
+    loop {
+        let name = match tokens.next() {
+            Some("}") => return Ok(regions),
+            Some(name) => name.to_string(),
+            None => anyhow::bail!("MEMORY command is not closed"),
+        };
+        // Attributes such as `(rx)`.
+        if tokens.next_if_eq(&"(").is_some() {
+            while tokens.next().context("region attributes are not closed")? != ")" {}
+        }
+        anyhow::ensure!(tokens.next() == Some(":"), "expected `:` after `{name}`");
+
+        let (mut origin, mut length) = (None, None);
+        while origin.is_none() || length.is_none() {
+            let key = tokens
+                .next()
+                .with_context(|| format!("`{name}` is incomplete"))?;
+            anyhow::ensure!(tokens.next() == Some("="), "expected `=` after `{key}`");
+            let value = expression(&mut tokens, &regions)
+                .with_context(|| format!("invalid {key} of `{name}`"))?;
+            match key {
+                "ORIGIN" | "org" | "o" => origin = Some(value),
+                "LENGTH" | "len" | "l" => length = Some(value),
+                _ => anyhow::bail!("unexpected `{key}` in `{name}`"),
+            }
+            tokens.next_if_eq(&",");
+        }
+        let (origin, length) = (origin.unwrap(), length.unwrap());
+        anyhow::ensure!(
+            origin.checked_add(length).is_some_and(|end| end <= 1 << 32),
+            "`{name}` ends beyond the 32-bit address space"
+        );
+        regions.insert(
+            name.clone(),
+            Region {
+                name: Some(name),
+                origin: origin as u32,
+                length: length as u32,
+            },
+        );
+    }
+}
+
+/// Sum and difference of numbers and `ORIGIN(REGION)`/`LENGTH(REGION)`
+/// of earlier regions.
+fn expression<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    regions: &BTreeMap<String, Region>,
+) -> Result<u64> {
+    let mut value = 0i64;
+    let mut sign = 1;
+    loop {
+        let token = tokens.next().context("missing value")?;
+        let term = match token {
+            "ORIGIN" | "LENGTH" => {
+                anyhow::ensure!(tokens.next() == Some("("), "expected `(` after {token}");
+                let name = tokens.next().context("missing region name")?;
+                anyhow::ensure!(tokens.next() == Some(")"), "expected `)` after `{name}`");
+                let region = regions
+                    .get(name)
+                    .with_context(|| format!("no earlier region `{name}`"))?;
+                if token == "ORIGIN" {
+                    region.origin as u64
+                } else {
+                    region.length as u64
+                }
+            }
+            _ => number(token)?,
+        };
+        value = value
+            .checked_add(sign * term as i64)
+            .context("value is out of range")?;
+        sign = match tokens.peek() {
+            Some(&"+") => 1,
+            Some(&"-") => -1,
+            _ => break,
+        };
+        tokens.next();
+    }
+    anyhow::ensure!(value >= 0, "value is negative");
+    anyhow::ensure!(
+        value <= u32::MAX as i64,
+        "value is beyond the 32-bit address space"
+    );
+    Ok(value as u64)
+}
+
+/// A decimal, `0x` hex or leading-zero octal number, with an optional `K`
+/// or `M` multiplier, that fits in 32 bits.
+fn number(token: &str) -> Result<u64> {
+    let (digits, multiplier) = match token.as_bytes().last() {
+        Some(b'K' | b'k') => (&token[..token.len() - 1], 1024),
+        Some(b'M' | b'm') => (&token[..token.len() - 1], 1024 * 1024),
+        _ => (token, 1),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    }
+    .with_context(|| format!("`{token}` is not a number"))?;
+    value
+        .checked_mul(multiplier)
+        .filter(|&value| value <= u32::MAX as u64)
+        .with_context(|| format!("`{token}` is too large"))
+}
+
+fn strip_comments(script: &str) -> String {
+    let mut stripped = String::with_capacity(script.len());
+    let mut rest = script;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        stripped.push(' ');
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Words (names and numbers) and single punctuation characters.
+fn tokenize(script: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in script.chars() {
+        if c.is_ascii_alphanumeric() || "_.$".contains(c) {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(script: &str) -> String {
+        format!("{:#}", regions(script).unwrap_err())
+    }
+
+    #[test]
+    fn memory_x() {
+        let regions = regions(
+            "MEMORY\n\
+             {\n\
+               FLASH (rx) : ORIGIN = 0x08000000 + 16K, LENGTH = 48K /* app */\n\
+               RAM (rwx)  : ORIGIN = 0x20000010, LENGTH = 20K - 0x10\n\
+               STACK : org = ORIGIN(RAM) + LENGTH(RAM) - 1K, len = 1K\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!(regions["FLASH"].origin, 0x0800_4000);
+        assert_eq!(regions["FLASH"].length, 48 * 1024);
+        assert_eq!(regions["RAM"].origin, 0x2000_0010);
+        assert_eq!(regions["RAM"].length, 20 * 1024 - 0x10);
+        assert_eq!(regions["STACK"].origin, 0x2000_4C00);
+    }
+
+    #[test]
+    fn numbers_beyond_32_bits_are_rejected() {
+        assert_eq!(
+            error("MEMORY { FLASH : ORIGIN = 0xFFFFFFFFFFFFFFFF, LENGTH = 1 }"),
+            "invalid ORIGIN of `FLASH`: `0xFFFFFFFFFFFFFFFF` is too large"
+        );
+        assert_eq!(
+            error("MEMORY { FLASH : ORIGIN = 0, LENGTH = 4096M }"),
+            "invalid LENGTH of `FLASH`: `4096M` is too large"
+        );
+    }
+
+    #[test]
+    fn sums_beyond_32_bits_are_rejected() {
+        assert_eq!(
+            error("MEMORY { FLASH : ORIGIN = 0xFFFFFFFF + 0xFFFFFFFF, LENGTH = 1 }"),
+            "invalid ORIGIN of `FLASH`: value is beyond the 32-bit address space"
+        );
+        assert_eq!(
+            error("MEMORY { FLASH : ORIGIN = 0xFFFFF000, LENGTH = 8K }"),
+            "`FLASH` ends beyond the 32-bit address space"
+        );
+        assert_eq!(
+            error("MEMORY { FLASH : ORIGIN = 1K - 2K, LENGTH = 1 }"),
+            "invalid ORIGIN of `FLASH`: value is negative"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Sidecar JSON written next to the .dfu with `--manifest`, in the
+//! `firmware-manifest` format, for the raw payload `firmware` names, so the
+//! payload and the manifest can be bundled as they are. Besides the `dfu`
+//! file and the `build` metadata it records the `bcd_device` and the
+//! `targets` of the .dfu:
+//!
+//! ```json
+//! {
+//!   "format": 1,
+//!   "version": "1.4.2",
+//!   "firmware": "firmware.bin",
+//!   "sha256": "<hex SHA-256 of the payload>",
+//!   "address": 134234112,
+//!   "size": 47204,
+//!   "compatible": { "vid": 4617, "pid": 9284, "hardware": [] },
+//!   "dfu": { "file": "firmware.dfu", "size": 47529, "sha256": "<hex SHA-256>" },
+//!   "build": { "tool": "dfu-packager 2.8.0", "commit": "1a2b3c4" },
+//!   "bcd_device": "0x0142",
+//!   "targets": [{ "name": "Flash", "alternate_setting": 0,
+//!                 "elements": [{ "address": "0x08004000", "size": 47204 }] }]
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuFile;
+use firmware_manifest::{Compatibility, Compression, FORMAT, Manifest, Package};
+use sha2::{Digest, Sha256};
+
+use crate::description::Description;
+
+/// What goes into a manifest besides the packaged file.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Byte filling the gaps of the payload.
+    pub fill: u8,
+    /// Build metadata, recorded after the tool's name and version.
+    pub build: Vec<(String, String)>,
+    /// Hardware revisions the firmware is for; empty for any.
+    pub hardware: Vec<String>,
+    /// Whether the payload is zstd-compressed, as `<name>.bin.zst`.
+    pub compressed: bool,
+    /// Release notes file next to the manifest in a bundle.
+    pub release_notes: Option<String>,
+}
+
+/// Write the manifest for `dfu`, packaged as `bytes` to `dfu_path`, to
+/// `path`.
+pub fn write(
+    path: &Path,
+    dfu: &DfuFile,
+    bytes: &[u8],
+    dfu_path: &Path,
+    options: &Options,
+) -> Result<()> {
+    let manifest = to_bytes(dfu, bytes, dfu_path, options)?;
+    std::fs::write(path, manifest)
+        .with_context(|| format!("could not write `{}`", path.display()))?;
+    tracing::info!("Manifest -> {}", path.display());
+    Ok(())
+}
+
+/// The manifest for `dfu`, packaged as `bytes` to `dfu_path`.
+pub fn to_bytes(
+    dfu: &DfuFile,
+    bytes: &[u8],
+    dfu_path: &Path,
+    options: &Options,
+) -> Result<Vec<u8>> {
+    let target = dfu.targets.first().context("nothing to describe")?;
+    let (address, payload) = crate::flatten(target, options.fill)?;
+    let file_name = |path: &Path| {
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let mut build_info = BTreeMap::from([(
+        "tool".to_string(),
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    )]);
+    build_info.extend(options.build.iter().cloned());
+
+    let manifest = Manifest {
+        format: FORMAT,
+        version: version(dfu.bcd_device),
+        firmware: file_name(&dfu_path.with_extension(match options.compressed {
+            true => "bin.zst",
+            false => "bin",
+        })),
+        sha256: hex::encode(Sha256::digest(&payload)),
+        address,
+        size: Some(payload.len() as u64),
+        compression: options.compressed.then_some(Compression::Zstd),
+        compatible: Compatibility {
+            vid: dfu.device_vid,
+            pid: dfu.device_pid,
+            hardware: options.hardware.clone(),
+        },
+        release_notes: options.release_notes.clone(),
+        dfu: Some(Package {
+            file: file_name(dfu_path),
+            size: bytes.len() as u64,
+            sha256: hex::encode(Sha256::digest(bytes)),
+        }),
+        build: build_info,
+        extra: BTreeMap::from([
+            (
+                "bcd_device".to_string(),
+                format!("{:#06x}", dfu.bcd_device).into(),
+            ),
+            (
+                "targets".to_string(),
+                serde_json::to_value(Description::from(dfu).targets)?,
+            ),
+        ]),
+    };
+    manifest
+        .validate()
+        .context("the manifest would not be valid")?;
+    Ok(manifest.to_vec()?)
+}
+
+/// `bcdDevice` as `major.minor.sub`, the way the device reports it.
+pub fn version(bcd_device: u16) -> String {
+    format!(
+        "{}.{}.{}",
+        (bcd_device >> 12) * 10 + (bcd_device >> 8 & 0xF),
+        bcd_device >> 4 & 0xF,
+        bcd_device & 0xF
+    )
+}
@@ -0,0 +1,154 @@
+//! Index of the firmware artifacts in a release directory (see
+//! `firmware_manifest::Index`), the update feed `fetch --feed` reads
+//! instead of the GitHub releases API. Signed like any other release file
+//! (see `sign.rs`), in `index.json.sig`:
+//!
+//! ```json
+//! {
+//!   "generator": "dfu-packager 2.8.0",
+//!   "files": [
+//!     { "file": "firmware-1.4.2.bbfw", "size": 48213, "sha256": "<hex>", "version": "1.4.2" },
+//!     { "file": "firmware-1.4.2.dfu", "size": 47529, "sha256": "<hex>", "version": "1.4.2" }
+//!   ]
+//! }
+//! ```
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuFile, Suffix};
+use firmware_manifest::{INDEX_FILE, Index, IndexEntry};
+use firmware_metadata::Metadata;
+use sha2::{Digest, Sha256};
+
+use crate::sign;
+
+/// Extensions of the files that are indexed.
+const ARTIFACTS: &[&str] = &["dfu", "bbfw", "zip", "bin", "hex", "uf2"];
+
+#[derive(clap::Args)]
+pub struct ManifestDirArgs {
+    /// The release directory.
+    dir: PathBuf,
+
+    /// Where to write the index [default: <dir>/index.json]
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// Hex-encoded ed25519 secret key (32-byte seed) to sign the index
+    /// with, writing `<index>.sig`.
+    #[clap(long, value_name = "FILE")]
+    key: Option<PathBuf>,
+}
+
+impl ManifestDirArgs {
+    /// Index every artifact of the directory, in name order.
+    pub fn run(self) -> Result<()> {
+        let out_path = self.output.unwrap_or_else(|| self.dir.join(INDEX_FILE));
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)
+            .with_context(|| format!("could not read `{}`", self.dir.display()))?
+        {
+            let path = entry?.path();
+            let is_artifact = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ARTIFACTS.contains(&ext.to_ascii_lowercase().as_str()));
+            if path.is_file() && is_artifact {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        anyhow::ensure!(
+            !paths.is_empty(),
+            "no firmware artifacts in `{}`",
+            self.dir.display()
+        );
+
+        let mut files = Vec::new();
+        for path in &paths {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("could not read `{}`", path.display()))?;
+            let version = version(path, &bytes);
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+            tracing::info!(
+                "{file}: {} bytes, version {}",
+                bytes.len(),
+                version.as_deref().unwrap_or("unknown")
+            );
+            files.push(IndexEntry {
+                file,
+                size: bytes.len() as u64,
+                sha256: hex::encode(Sha256::digest(&bytes)),
+                version,
+            });
+        }
+
+        let index = Index {
+            generator: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            files,
+        };
+        let bytes = index.to_vec()?;
+        std::fs::write(&out_path, &bytes)
+            .with_context(|| format!("could not write `{}`", out_path.display()))?;
+        tracing::info!("{} files -> {}", index.files.len(), out_path.display());
+
+        if let Some(key) = &self.key {
+            let key = ed25519_dalek::SigningKey::from_bytes(&firmware_manifest::read_key(key)?);
+            let signature = ed25519_dalek::Signer::sign(&key, &bytes);
+            let sig_path = sign::signature_path(&out_path);
+            std::fs::write(&sig_path, signature.to_bytes())
+                .with_context(|| format!("could not write `{}`", sig_path.display()))?;
+            tracing::info!(
+                "Signature -> {} (public key {})",
+                sig_path.display(),
+                hex::encode(key.verifying_key().as_bytes())
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The firmware version a file records: `bcdDevice` of a .dfu (or its
+/// metadata block when that is zero), the manifest version of a bundle, or
+/// the metadata block of a raw image.
+fn version(path: &Path, bytes: &[u8]) -> Option<String> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "dfu" if bytes.starts_with(b"DfuSe") => {
+            let dfu = DfuFile::from_bytes(bytes).ok()?;
+            match dfu.bcd_device {
+                0 => crate::patch::read_metadata(&dfu, 0xFF).map(|m| m.version_string()),
+                bcd_device => Some(crate::manifest::version(bcd_device)),
+            }
+        }
+        "dfu" => {
+            let suffix = Suffix::parse(bytes).ok()?;
+            match suffix.bcd_device {
+                0 | 0xFFFF => image_version(&bytes[..bytes.len() - dfu_file::SUFFIX_LEN]),
+                bcd_device => Some(crate::manifest::version(bcd_device)),
+            }
+        }
+        "bbfw" | "zip" => bundle_version(bytes),
+        "bin" => image_version(bytes),
+        _ => None,
+    }
+}
+
+/// Version in the metadata block of a raw image.
+fn image_version(image: &[u8]) -> Option<String> {
+    Some(Metadata::find(image)?.ok()?.version_string())
+}
+
+/// `version` of a bundle's manifest.
+fn bundle_version(bytes: &[u8]) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).ok()?;
+    let mut json = Vec::new();
+    archive
+        .by_name(firmware_manifest::MANIFEST_FILE)
+        .ok()?
+        .read_to_end(&mut json)
+        .ok()?;
+    Some(firmware_manifest::Manifest::from_slice(&json).ok()?.version)
+}
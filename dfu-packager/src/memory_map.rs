@@ -0,0 +1,135 @@
+//! Memory maps per hardware revision, from a `memory.toml` such as:
+//!
+//! ```toml
+//! # Used without --hw-rev; a file with one revision needs no default.
+//! default = "2.0.0"
+//!
+//! [hw-rev."2.0.0"]
+//! flash = { origin = 0x0800_4000, length = 0xC000 }
+//! ram = { origin = 0x2000_0010, length = 0x4FF0 }
+//! page_size = 0x400
+//! reserved = [{ name = "config", origin = 0x0800_F800, length = 0x800 }]
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Where the application may go on one hardware revision.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryMap {
+    /// Flash the application may be written to.
+    pub flash: Region,
+    /// RAM the initial stack pointer may point into.
+    pub ram: Region,
+    /// Erase page size; elements should start on a page boundary.
+    pub page_size: Option<u32>,
+    /// Flash regions no element may touch, e.g. calibration data.
+    #[serde(default)]
+    pub reserved: Vec<Region>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub name: Option<String>,
+    pub origin: u32,
+    pub length: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    default: Option<String>,
+    #[serde(rename = "hw-rev")]
+    hw_rev: BTreeMap<String, MemoryMap>,
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.origin as u64 + self.length as u64
+    }
+
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        (self.origin as u64) < end && start < self.end()
+    }
+}
+
+impl From<&Region> for device_memory::Region {
+    fn from(region: &Region) -> Self {
+        Self {
+            origin: region.origin,
+            length: region.length,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// The built-in map of the device `vid:pid`, or else of the
+    /// BrakeBright.
+    pub fn builtin(device: Option<(u16, u16)>) -> Self {
+        device
+            .and_then(|(vid, pid)| device_memory::find(vid, pid, None))
+            .unwrap_or(&device_memory::BRAKEBRIGHT)
+            .into()
+    }
+
+    /// The map for `hw_rev` from the file at `path`, or its default.
+    pub fn load(path: &Path, hw_rev: Option<&str>) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read `{}`", path.display()))?;
+        let mut file: File = toml::from_str(&text)
+            .with_context(|| format!("could not parse `{}`", path.display()))?;
+        let revisions = || file.hw_rev.keys().cloned().collect::<Vec<_>>().join(", ");
+        let hw_rev = match (hw_rev, &file.default) {
+            (Some(hw_rev), _) => hw_rev.to_string(),
+            (None, Some(default)) => default.clone(),
+            (None, None) if file.hw_rev.len() == 1 => revisions(),
+            (None, None) => anyhow::bail!(
+                "`{}` has several hardware revisions ({}); choose one with --hw-rev",
+                path.display(),
+                revisions()
+            ),
+        };
+        let revisions = revisions();
+        let map = file.hw_rev.remove(&hw_rev).with_context(|| {
+            format!(
+                "no hardware revision `{hw_rev}` in `{}` (it has {revisions})",
+                path.display()
+            )
+        })?;
+        tracing::debug!("Memory map for hardware revision {hw_rev}: {map:?}");
+        Ok(map)
+    }
+}
+
+impl From<&device_memory::MemoryMap> for MemoryMap {
+    fn from(map: &device_memory::MemoryMap) -> Self {
+        Self {
+            flash: map.flash.into(),
+            ram: map.ram.into(),
+            page_size: Some(map.page_size),
+            reserved: map
+                .reserved
+                .iter()
+                .map(|reserved| Region {
+                    name: Some(reserved.name.to_string()),
+                    ..reserved.region.into()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<device_memory::Region> for Region {
+    fn from(region: device_memory::Region) -> Self {
+        Self {
+            name: None,
+            origin: region.origin,
+            length: region.length,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuFile, DfuTarget};
+
+use crate::Cli;
+
+#[derive(clap::Args)]
+pub struct MergeArgs {
+    /// The .dfu files to merge, in order. All must be for the same VID/PID.
+    #[clap(required = true, num_args = 2.., value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Output file name.
+    #[clap(long, short)]
+    output: PathBuf,
+
+    /// Firmware version for the DFU suffix (`bcdDevice`), as
+    /// `MAJOR.MINOR[.PATCH]`. Defaults to the version of the first file.
+    #[clap(long, value_parser = Cli::parse_fw_version)]
+    fw_version: Option<u16>,
+}
+
+impl MergeArgs {
+    /// Put the targets of every file into one, joining the elements of
+    /// targets with the same alternate setting and refusing overlaps.
+    pub fn run(self) -> Result<()> {
+        let mut merged: Option<DfuFile> = None;
+        for path in &self.files {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("could not read `{}`", path.display()))?;
+            let dfu = DfuFile::from_bytes(&bytes)
+                .with_context(|| format!("`{}` is not a valid DfuSe file", path.display()))?;
+            let Some(merged) = &mut merged else {
+                merged = Some(dfu);
+                continue;
+            };
+
+            anyhow::ensure!(
+                (dfu.device_vid, dfu.device_pid) == (merged.device_vid, merged.device_pid),
+                "`{}` is for {:04x}:{:04x}, `{}` for {:04x}:{:04x}",
+                path.display(),
+                dfu.device_vid,
+                dfu.device_pid,
+                self.files[0].display(),
+                merged.device_vid,
+                merged.device_pid
+            );
+            if dfu.bcd_device != merged.bcd_device && self.fw_version.is_none() {
+                tracing::warn!(
+                    "`{}` has bcdDevice {:#06x}, keeping {:#06x} of `{}`",
+                    path.display(),
+                    dfu.bcd_device,
+                    merged.bcd_device,
+                    self.files[0].display()
+                );
+            }
+            for target in dfu.targets {
+                let alt = target.alternate_setting;
+                match merged
+                    .targets
+                    .iter_mut()
+                    .find(|other| other.alternate_setting == alt)
+                {
+                    Some(other) => {
+                        if target.name != other.name {
+                            tracing::warn!(
+                                "`{}` names alternate setting {alt} {:?}, keeping {:?}",
+                                path.display(),
+                                target.name,
+                                other.name
+                            );
+                        }
+                        crate::add_elements(&mut other.elements, path, target.elements)?;
+                    }
+                    None => merged.targets.push(target),
+                }
+            }
+        }
+
+        let mut merged = merged.context("no files to merge")?;
+        merged
+            .targets
+            .sort_by_key(|target: &DfuTarget| target.alternate_setting);
+        if let Some(version) = self.fw_version {
+            merged.bcd_device = version;
+        }
+        std::fs::write(&self.output, merged.to_bytes()?)
+            .with_context(|| format!("could not write `{}`", self.output.display()))?;
+        tracing::info!(
+            "{} target(s), {} element(s) -> {}",
+            merged.targets.len(),
+            merged.elements().count(),
+            self.output.display()
+        );
+        Ok(())
+    }
+}
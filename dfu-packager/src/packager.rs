@@ -0,0 +1,213 @@
+//! Library entry point to the packaging pipeline, for firmware builds that
+//! produce their .dfu without shelling out to `dfu-packager`:
+//!
+//! ```no_run
+//! // xtask/src/main.rs, after building the firmware
+//! dfu_packager::Packager::new("target/thumbv7m-none-eabi/release/firmware.elf")
+//!     .output("target/firmware.dfu")
+//!     .device(0x1209, 0x2444)
+//!     .cargo_version()
+//!     .signing_key("release.key")
+//!     .signature_offset(0x120)
+//!     .manifest()
+//!     .package()?;
+//! # anyhow::Ok(())
+//! ```
+//!
+//! The options are those of the command line and run through the same
+//! pipeline. Nothing is printed to standard output but the
+//! `cargo:rerun-if-changed` lines of [`Packager::cargo_version`], so it is
+//! safe to call from a build script.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{Cli, Format, PackageOptions};
+
+/// Options for one packaging run; see the `dfu-packager` options of the
+/// same names.
+#[derive(Clone, Debug)]
+pub struct Packager {
+    options: PackageOptions,
+    /// `--fw-version` and `--set-version`, parsed by [`package`](Self::package).
+    fw_version: Option<String>,
+    set_version: Option<String>,
+    inputs: Vec<PathBuf>,
+    rerun_if_changed: bool,
+}
+
+impl Packager {
+    /// Package `file`: a raw .bin, or an Intel .hex, S-record, ELF or UF2
+    /// file.
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        let file = file.into();
+        Packager {
+            // The defaults of the command line.
+            options: PackageOptions {
+                file: Some(file.clone()),
+                family_id: 0x5EE2_1072,
+                fill: 0xFF,
+                ..PackageOptions::default()
+            },
+            fw_version: None,
+            set_version: None,
+            inputs: vec![file],
+            rerun_if_changed: false,
+        }
+    }
+
+    /// Output file name; may contain the `--output` placeholders.
+    pub fn output(mut self, path: impl AsRef<Path>) -> Self {
+        self.options.output = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Vendor and Product ID of the DFU device.
+    pub fn device(mut self, vid: u16, pid: u16) -> Self {
+        self.options.device = Some((vid, pid));
+        self
+    }
+
+    /// Address of a raw binary.
+    pub fn address(mut self, address: u32) -> Self {
+        self.options.address = Some(address);
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// Firmware version for the DFU suffix, as `MAJOR.MINOR[.PATCH]`.
+    pub fn fw_version(mut self, version: &str) -> Self {
+        self.fw_version = Some(version.to_string());
+        self
+    }
+
+    /// Firmware version to patch into the metadata block and the suffix.
+    pub fn set_version(mut self, version: &str) -> Self {
+        self.set_version = Some(version.to_string());
+        self
+    }
+
+    pub fn set_build_id(mut self, build_id: &str) -> Self {
+        self.options.set_build_id = Some(build_id.to_string());
+        self
+    }
+
+    /// From a build script or xtask: take the version of the package being
+    /// built (`CARGO_PKG_VERSION`, without pre-release or build suffix) for
+    /// the suffix, and have [`package`](Self::package) print
+    /// `cargo:rerun-if-changed` for the inputs.
+    pub fn cargo_version(mut self) -> Self {
+        self.rerun_if_changed = true;
+        match std::env::var("CARGO_PKG_VERSION") {
+            Ok(version) => {
+                let version = version.split(['-', '+']).next().unwrap_or_default();
+                self.fw_version(version)
+            }
+            Err(_) => self,
+        }
+    }
+
+    pub fn patch_crc(mut self, offset: u32) -> Self {
+        self.options.patch_crc = Some(offset);
+        self
+    }
+
+    pub fn signature_offset(mut self, offset: u32) -> Self {
+        self.options.signature_offset = Some(offset);
+        self
+    }
+
+    pub fn signing_key(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        self.inputs.push(path.clone());
+        self.options.signing_key = Some(path);
+        self
+    }
+
+    /// Write the JSON manifest next to the output.
+    pub fn manifest(mut self) -> Self {
+        self.options.manifest = Some(None);
+        self
+    }
+
+    pub fn build_info(mut self, key: &str, value: &str) -> Self {
+        self.options
+            .build_info
+            .push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn memory_map(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.inputs.push(path.clone());
+        self.options.memory_map = Some(path);
+        self
+    }
+
+    pub fn hw_rev(mut self, hw_rev: &str) -> Self {
+        self.options.hw_rev = Some(hw_rev.to_string());
+        self
+    }
+
+    pub fn linker_script(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.inputs.push(path.clone());
+        self.options.linker_script = Some(path);
+        self
+    }
+
+    /// Fail unless the image contains this stay-in-boot magic.
+    pub fn require_magic(mut self, magic: u32) -> Self {
+        self.options.require_magic = Some(magic);
+        self
+    }
+
+    /// Package even if the image does not suit its address or memory map.
+    pub fn force(mut self) -> Self {
+        self.options.force = true;
+        self
+    }
+
+    /// Check the options, package and write the output. Returns the path
+    /// written, with the placeholders filled in.
+    pub fn package(mut self) -> Result<PathBuf> {
+        if self.rerun_if_changed {
+            for input in &self.inputs {
+                println!("cargo:rerun-if-changed={}", input.display());
+            }
+        }
+        let version = |version: &Option<String>| -> Result<Option<u16>> {
+            version
+                .as_deref()
+                .map(|version| {
+                    Cli::parse_fw_version(version)
+                        .with_context(|| format!("invalid firmware version `{version}`"))
+                })
+                .transpose()
+        };
+        self.options.fw_version = version(&self.fw_version)?;
+        self.options.set_version = version(&self.set_version)?;
+
+        // What clap checks for the command line.
+        let options = &self.options;
+        anyhow::ensure!(
+            options.fw_version.is_none() || options.set_version.is_none(),
+            "give the firmware version for the suffix or to patch in, not both"
+        );
+        anyhow::ensure!(
+            options.signature_offset.is_none() || options.signing_key.is_some(),
+            "a signature offset needs a signing key"
+        );
+        anyhow::ensure!(
+            options.hw_rev.is_none() || options.memory_map.is_some(),
+            "a hardware revision needs a memory map"
+        );
+        let (path, _) = options.package()?;
+        Ok(path)
+    }
+}
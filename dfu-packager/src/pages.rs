@@ -0,0 +1,75 @@
+//! Checking and splitting elements against a DfuSe memory layout, the
+//! `@Internal Flash  /0x08000000/16*001Ka,48*001Kg` string a device reports
+//! for each alternate setting.
+
+use dfu_file::{DfuElement, DfuTarget, MemoryLayout};
+
+/// Warn about every element of `target` that reaches into a page that is
+/// not writable, or past the pages of `layout`.
+pub fn check(target: &DfuTarget, layout: &MemoryLayout) {
+    for element in &target.elements {
+        let (start, end) = (element.address as u64, element.end());
+        let mut covered = start;
+        for (page, sectors) in layout.pages() {
+            let page_end = page + sectors.size as u64;
+            if page_end <= start || page >= end {
+                continue;
+            }
+            if page > covered {
+                warn_out_of_range(element, covered, page);
+            }
+            if !sectors.writable {
+                tracing::warn!(
+                    "Element at {:#010X}..{end:#010X} straddles the {} page at {page:#010X}..{page_end:#010X}",
+                    element.address,
+                    sectors.access()
+                );
+            }
+            covered = page_end;
+        }
+        if covered < end {
+            warn_out_of_range(element, covered, end);
+        }
+    }
+}
+
+fn warn_out_of_range(element: &DfuElement, from: u64, to: u64) {
+    tracing::warn!(
+        "Element at {:#010X}..{:#010X} covers {from:#010X}..{to:#010X}, outside the pages of the memory layout",
+        element.address,
+        element.end()
+    );
+}
+
+/// Split every element of `target` at the page boundaries of `layout`, so
+/// that each element lies within one page. Bytes outside the layout stay
+/// in one element.
+pub fn split(target: &mut DfuTarget, layout: &MemoryLayout) {
+    let boundaries: Vec<u64> = layout
+        .pages()
+        .flat_map(|(page, sectors)| [page, page + sectors.size as u64])
+        .collect();
+    let elements = std::mem::take(&mut target.elements);
+    for mut element in elements {
+        let mut cuts: Vec<u64> = boundaries
+            .iter()
+            .copied()
+            .filter(|&boundary| boundary > element.address as u64 && boundary < element.end())
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        // Cut from the end so the offsets into `element.data` stay valid.
+        let mut chunks = Vec::new();
+        for &cut in cuts.iter().rev() {
+            let data = element
+                .data
+                .split_off((cut - element.address as u64) as usize);
+            chunks.push(DfuElement {
+                address: cut as u32,
+                data,
+            });
+        }
+        chunks.push(element);
+        target.elements.extend(chunks.into_iter().rev());
+    }
+}
@@ -0,0 +1,147 @@
+//! Values patched into the firmware image before packaging. Offsets are
+//! relative to the start of the first target's image, the lowest address
+//! of its elements.
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuElement, DfuFile, DfuTarget};
+use ed25519_dalek::SigningKey;
+use firmware_metadata::Metadata;
+
+/// Length of an embedded ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The first target and the address offsets are relative to.
+fn image(dfu: &mut DfuFile) -> Result<(&mut DfuTarget, u32)> {
+    let target = dfu.targets.first_mut().context("nothing to patch")?;
+    let start = target
+        .elements
+        .iter()
+        .map(|element| element.address)
+        .min()
+        .context("nothing to patch")?;
+    Ok((target, start))
+}
+
+/// The `len` bytes at `address`, which must start within or right after
+/// one element, or outside all of them. Missing bytes are reserved: the
+/// element is extended, or a new one added, with `fill`.
+fn slot(target: &mut DfuTarget, address: u32, len: usize, fill: u8) -> Result<&mut [u8]> {
+    let end = address as u64 + len as u64;
+    anyhow::ensure!(end <= 1 << 32, "slot at {address:#010X} runs past 4 GiB");
+    let overlapping: Vec<usize> = (0..target.elements.len())
+        .filter(|&i| {
+            let element = &target.elements[i];
+            (element.address as u64) < end && (address as u64) < element.end()
+        })
+        .collect();
+    let adjacent = || {
+        target
+            .elements
+            .iter()
+            .position(|element| element.end() == address as u64)
+    };
+    let index = match overlapping.as_slice() {
+        [] => adjacent().unwrap_or_else(|| {
+            target.elements.push(DfuElement {
+                address,
+                data: Vec::new(),
+            });
+            target.elements.len() - 1
+        }),
+        &[index] if target.elements[index].address <= address => index,
+        _ => anyhow::bail!("slot {address:#010X}..{end:#010X} straddles the start of an element"),
+    };
+    let element = &mut target.elements[index];
+    let offset = (address - element.address) as usize;
+    if element.data.len() < offset + len {
+        element.data.resize(offset + len, fill);
+    }
+    Ok(&mut element.data[offset..offset + len])
+}
+
+/// Set the firmware version (from `bcd_device`) and build ID in the
+/// metadata block of the first target's image, found by its `BBFW` magic.
+/// A CRC already filled into the block is recomputed.
+pub fn patch_metadata(
+    dfu: &mut DfuFile,
+    bcd_device: Option<u16>,
+    build_id: Option<&str>,
+    fill: u8,
+) -> Result<()> {
+    let (target, start) = image(dfu)?;
+    let (_, mut image) = crate::flatten(target, fill)?;
+    let mut metadata =
+        Metadata::find(&image).context("no metadata block (`BBFW` magic) in the image")??;
+
+    if let Some(bcd) = bcd_device {
+        let major = (bcd >> 12) * 10 + (bcd >> 8 & 0xF);
+        metadata.version = (major as u8, (bcd >> 4 & 0xF) as u8, (bcd & 0xF) as u8);
+    }
+    if let Some(build_id) = build_id {
+        metadata.build_id = build_id.to_string();
+    }
+    metadata.write(&mut image)?;
+
+    if metadata.has_crc() {
+        let length = metadata.length;
+        metadata.crc = metadata
+            .compute_crc(&image)
+            .with_context(|| format!("metadata CRC covers {length} bytes, the image is shorter"))?;
+        tracing::info!(
+            "Metadata CRC of {length} bytes is now {:#010X}",
+            metadata.crc
+        );
+    }
+
+    let address = start + metadata.offset as u32;
+    slot(target, address, firmware_metadata::LEN, fill)?.copy_from_slice(&metadata.encode()?);
+    tracing::info!("Patched the metadata block at {address:#010X}");
+    Ok(())
+}
+
+/// The metadata block of the first target's image, if it has a valid one.
+pub fn read_metadata(dfu: &DfuFile, fill: u8) -> Option<Metadata> {
+    let (_, image) = crate::flatten(dfu.targets.first()?, fill).ok()?;
+    Metadata::find(&image)?.ok()
+}
+
+/// Write the CRC32 of the first target's image (gaps filled with `fill`)
+/// without the 4-byte CRC slot at `offset` into the slot, little endian.
+pub fn patch_crc(dfu: &mut DfuFile, offset: u32, fill: u8) -> Result<()> {
+    let (target, start) = image(dfu)?;
+    let address = start
+        .checked_add(offset)
+        .context("CRC offset is out of range")?;
+    slot(target, address, 4, fill)?;
+
+    let (_, mut covered) = crate::flatten(target, fill)?;
+    covered.drain(offset as usize..offset as usize + 4);
+    let crc = crc32fast::hash(&covered);
+    slot(target, address, 4, fill)?.copy_from_slice(&crc.to_le_bytes());
+    tracing::info!(
+        "CRC32 of {} bytes is {crc:#010X}, written at {address:#010X}",
+        covered.len()
+    );
+    Ok(())
+}
+
+/// Sign the first target's image (gaps filled with `fill`) without the
+/// signature slot at `offset`, and write the signature into the slot.
+pub fn embed_signature(dfu: &mut DfuFile, offset: u32, key: &SigningKey, fill: u8) -> Result<()> {
+    let (target, start) = image(dfu)?;
+    let address = start
+        .checked_add(offset)
+        .context("signature offset is out of range")?;
+    slot(target, address, SIGNATURE_LEN, fill)?;
+
+    let (_, mut message) = crate::flatten(target, fill)?;
+    message.drain(offset as usize..offset as usize + SIGNATURE_LEN);
+    let signature = ed25519_dalek::Signer::sign(key, &message);
+    slot(target, address, SIGNATURE_LEN, fill)?.copy_from_slice(&signature.to_bytes());
+    tracing::info!(
+        "Signed {} bytes, signature at {address:#010X} (public key {})",
+        message.len(),
+        hex::encode(key.verifying_key().as_bytes())
+    );
+    Ok(())
+}
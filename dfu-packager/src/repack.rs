@@ -0,0 +1,142 @@
+//! `repack`: change the IDs, firmware version, target names or alternate
+//! settings of an existing .dfu file without going back to its images.
+//! DfuSe files are decoded and encoded again, so the target and file sizes
+//! and the suffix CRC match the new contents; plain DFU files keep their
+//! payload and get a new suffix.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuFile, SUFFIX_LEN, Suffix};
+
+use crate::Cli;
+
+#[derive(clap::Args)]
+pub struct RepackArgs {
+    /// The .dfu file to edit.
+    file: PathBuf,
+
+    /// Output file name.
+    #[clap(long, short)]
+    output: PathBuf,
+
+    /// New Vendor/Product ID, e.g. 1209:2444.
+    #[clap(long, short, value_parser = Cli::parse_vid_pid, name = "VID>:<PID")]
+    device: Option<(u16, u16)>,
+
+    /// New firmware version for `bcdDevice`, as `MAJOR.MINOR[.PATCH]`.
+    #[clap(long, value_parser = Cli::parse_fw_version)]
+    fw_version: Option<u16>,
+
+    /// New target name as `[ALT=]NAME` (alternate setting 0 without
+    /// `ALT=`); applied before --alt. Repeatable.
+    #[clap(long, value_parser = Cli::parse_target_name)]
+    target_name: Vec<(u8, String)>,
+
+    /// Move a target to another alternate setting, as `OLD=NEW`.
+    /// Repeatable; all moves apply at once, so two targets can be swapped.
+    #[clap(long, value_parser = parse_alt)]
+    alt: Vec<(u8, u8)>,
+}
+
+fn parse_alt(s: &str) -> Result<(u8, u8)> {
+    let (old, new) = s.split_once('=').context("expected OLD=NEW")?;
+    Ok((
+        old.parse().context("could not parse OLD")?,
+        new.parse().context("could not parse NEW")?,
+    ))
+}
+
+impl RepackArgs {
+    /// Change the given fields and write the file again, with its sizes and
+    /// CRC recomputed. Plain DFU files only have a suffix to change.
+    pub fn run(self) -> Result<()> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let repacked = if bytes.starts_with(b"DfuSe") {
+            let dfu = DfuFile::from_bytes(&bytes)
+                .with_context(|| format!("`{}` is not a valid DfuSe file", self.file.display()))?;
+            self.repack(dfu)?.to_bytes()?
+        } else {
+            self.repack_suffix(&bytes)?
+        };
+        std::fs::write(&self.output, &repacked)
+            .with_context(|| format!("could not write `{}`", self.output.display()))?;
+        tracing::info!("{} bytes -> {}", repacked.len(), self.output.display());
+        Ok(())
+    }
+
+    fn repack(&self, mut dfu: DfuFile) -> Result<DfuFile> {
+        if let Some((vid, pid)) = self.device {
+            tracing::info!(
+                "Device {:04x}:{:04x} -> {vid:04x}:{pid:04x}",
+                dfu.device_vid,
+                dfu.device_pid
+            );
+            (dfu.device_vid, dfu.device_pid) = (vid, pid);
+        }
+        if let Some(version) = self.fw_version {
+            tracing::info!("bcdDevice {:#06x} -> {version:#06x}", dfu.bcd_device);
+            dfu.bcd_device = version;
+        }
+        for (alt, name) in &self.target_name {
+            let target = dfu
+                .targets
+                .iter_mut()
+                .find(|target| target.alternate_setting == *alt)
+                .with_context(|| format!("no target for alternate setting {alt}"))?;
+            tracing::info!("Target {alt} name {:?} -> {name:?}", target.name);
+            target.name.clone_from(name);
+        }
+
+        let olds: Vec<u8> = dfu.targets.iter().map(|t| t.alternate_setting).collect();
+        for (old, new) in &self.alt {
+            anyhow::ensure!(olds.contains(old), "no target for alternate setting {old}");
+            anyhow::ensure!(
+                self.alt.iter().filter(|(other, _)| other == old).count() == 1,
+                "--alt moves alternate setting {old} twice"
+            );
+            tracing::info!("Target {old} -> alternate setting {new}");
+        }
+        for (target, old) in dfu.targets.iter_mut().zip(&olds) {
+            if let Some((_, new)) = self.alt.iter().find(|(other, _)| other == old) {
+                target.alternate_setting = *new;
+            }
+        }
+        let mut alts: Vec<u8> = dfu.targets.iter().map(|t| t.alternate_setting).collect();
+        alts.sort_unstable();
+        if let Some(pair) = alts.windows(2).find(|pair| pair[0] == pair[1]) {
+            anyhow::bail!("two targets would have alternate setting {}", pair[0]);
+        }
+        dfu.targets.sort_by_key(|target| target.alternate_setting);
+        Ok(dfu)
+    }
+
+    /// A plain DFU file: the payload with a new suffix.
+    fn repack_suffix(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let suffix = Suffix::parse(bytes)
+            .with_context(|| format!("`{}` has no DFU suffix", self.file.display()))?;
+        anyhow::ensure!(
+            suffix.crc_valid(),
+            "DFU suffix CRC is {:#010X}, the file hashes to {:#010X}",
+            suffix.crc,
+            suffix.computed_crc
+        );
+        anyhow::ensure!(
+            self.target_name.is_empty() && self.alt.is_empty(),
+            "`{}` is a plain DFU file without targets",
+            self.file.display()
+        );
+        let (vid, pid) = self.device.unwrap_or((suffix.vid, suffix.pid));
+        let bcd_device = self.fw_version.unwrap_or(suffix.bcd_device);
+        tracing::info!(
+            "Suffix {:04x}:{:04x} {:#06x} -> {vid:04x}:{pid:04x} {bcd_device:#06x}",
+            suffix.vid,
+            suffix.pid,
+            suffix.bcd_device
+        );
+        let mut repacked = bytes[..bytes.len() - SUFFIX_LEN].to_vec();
+        dfu_file::append_suffix(&mut repacked, bcd_device, pid, vid, suffix.bcd_dfu);
+        Ok(repacked)
+    }
+}
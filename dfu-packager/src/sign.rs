@@ -0,0 +1,81 @@
+//! Detached ed25519 signatures over release files, in the format
+//! `bikesafe-cli verify-file --signature` checks: 64 raw bytes, by default
+//! in `<file>.sig`. Keys are hex files: the 32-byte secret seed for
+//! signing, the 32-byte public key for checking.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, SigningKey};
+
+#[derive(clap::Args)]
+pub struct SignArgs {
+    /// The file to sign, e.g. a .dfu.
+    file: PathBuf,
+
+    /// Hex-encoded ed25519 secret key (32-byte seed).
+    #[clap(long, value_name = "FILE")]
+    key: PathBuf,
+
+    /// Where to write the signature [default: <file>.sig]
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct VerifySigArgs {
+    /// The signed file.
+    file: PathBuf,
+
+    /// Hex-encoded ed25519 public key.
+    #[clap(long, value_name = "FILE", env = "BIKESAFE_PUBLIC_KEY")]
+    public_key: PathBuf,
+
+    /// The detached signature [default: <file>.sig]
+    #[clap(long, value_name = "FILE")]
+    signature: Option<PathBuf>,
+}
+
+impl SignArgs {
+    /// Sign the file, and log the public key to check it with.
+    pub fn run(self) -> Result<()> {
+        let key = SigningKey::from_bytes(&firmware_manifest::read_key(&self.key)?);
+        let file = read(&self.file)?;
+        let signature = ed25519_dalek::Signer::sign(&key, &file);
+        let out_path = self.output.unwrap_or_else(|| signature_path(&self.file));
+        std::fs::write(&out_path, signature.to_bytes())
+            .with_context(|| format!("could not write `{}`", out_path.display()))?;
+        tracing::info!(
+            "Signature -> {} (public key {})",
+            out_path.display(),
+            hex::encode(key.verifying_key().as_bytes())
+        );
+        Ok(())
+    }
+}
+
+impl VerifySigArgs {
+    /// Fail unless the signature is valid for the file and the key.
+    pub fn run(self) -> Result<()> {
+        let key = firmware_manifest::read_public_key(&self.public_key)?;
+        let file = read(&self.file)?;
+        let path = self.signature.unwrap_or_else(|| signature_path(&self.file));
+        let signature = Signature::from_slice(&read(&path)?).context("malformed signature")?;
+        key.verify_strict(&file, &signature).with_context(|| {
+            format!("`{}` is not a valid signature for this key", path.display())
+        })?;
+        tracing::info!("{}: signature OK", self.file.display());
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("could not read `{}`", path.display()))
+}
+
+/// `<file>.sig`, next to the file.
+pub fn signature_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+
+/// Data records (S1/S2/S3) of a Motorola S-record file as `(address,
+/// bytes)` chunks. Header, count and start-address records are checked
+/// but otherwise ignored.
+pub fn parse(text: &str) -> Result<Vec<(u32, Vec<u8>)>> {
+    let mut chunks = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = parse_record(line).with_context(|| format!("line {}", index + 1))?;
+        if let Some(chunk) = record {
+            chunks.push(chunk);
+        }
+    }
+    Ok(chunks)
+}
+
+/// Decode one record; `Some` for data records.
+fn parse_record(line: &str) -> Result<Option<(u32, Vec<u8>)>> {
+    anyhow::ensure!(line.is_ascii(), "record contains non-ASCII characters");
+    let kind = line
+        .strip_prefix('S')
+        .and_then(|rest| rest.chars().next())
+        .context("record does not start with `S`")?;
+    let hex = &line[2..];
+    anyhow::ensure!(hex.len().is_multiple_of(2), "odd number of hex digits");
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .context("malformed hex digits")?;
+    let (&count, rest) = bytes.split_first().context("empty record")?;
+    anyhow::ensure!(
+        count as usize == rest.len(),
+        "byte count {count} does not match the record length {}",
+        rest.len()
+    );
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    anyhow::ensure!(sum == 0xFF, "checksum mismatch");
+
+    let address_len = match kind {
+        '1' => 2,
+        '2' => 3,
+        '3' => 4,
+        '0' | '5' | '6' | '7' | '8' | '9' => return Ok(None),
+        _ => anyhow::bail!("unknown record type S{kind}"),
+    };
+    anyhow::ensure!(rest.len() > address_len, "record too short");
+    let (address, data) = rest[..rest.len() - 1].split_at(address_len);
+    let address = address
+        .iter()
+        .fold(0u32, |address, &b| address << 8 | b as u32);
+    Ok(Some((address, data.to_vec())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_records_of_each_address_size() {
+        let text = "S00600004844521B\n\
+                    S1051000AABB85\n\
+                    S2060100001122C5\n\
+                    S30708004000ABCD38\n\
+                    S70508004000B2\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                (0x1000, vec![0xAA, 0xBB]),
+                (0x1_0000, vec![0x11, 0x22]),
+                (0x0800_4000, vec![0xAB, 0xCD]),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let error = parse("S1051000AABB8E\n").unwrap_err();
+        assert_eq!(format!("{error:#}"), "line 1: checksum mismatch");
+    }
+
+    #[test]
+    fn byte_count_must_match() {
+        let error = parse("S1061000AABB8E\n").unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "line 1: byte count 6 does not match the record length 5"
+        );
+    }
+
+    #[test]
+    fn unknown_record_type() {
+        let error = parse("S4030000FC\n").unwrap_err();
+        assert_eq!(format!("{error:#}"), "line 1: unknown record type S4");
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::{DfuFile, SUFFIX_LEN, Suffix};
+
+#[derive(clap::Args)]
+pub struct StripArgs {
+    /// The .dfu file to strip.
+    file: PathBuf,
+
+    /// Output file name [default: the input with a .bin extension]
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// Alternate setting of the target to extract from a DfuSe file with
+    /// several targets.
+    #[clap(long)]
+    alt: Option<u8>,
+}
+
+impl StripArgs {
+    /// Write the payload of the file: the image of one target of a DfuSe
+    /// file (gaps filled with 0xFF), or the bytes before a plain DFU suffix.
+    pub fn run(self) -> Result<()> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let suffix = Suffix::parse(&bytes)
+            .with_context(|| format!("`{}` has no DFU suffix", self.file.display()))?;
+        if !suffix.crc_valid() {
+            tracing::warn!(
+                "DFU suffix CRC is {:#010X}, the file hashes to {:#010X}",
+                suffix.crc,
+                suffix.computed_crc
+            );
+        }
+        let payload = if bytes.starts_with(b"DfuSe") {
+            let dfu = DfuFile::from_bytes_unchecked(&bytes)
+                .with_context(|| format!("`{}` is not a valid DfuSe file", self.file.display()))?;
+            let target = match (self.alt, dfu.targets.as_slice()) {
+                (Some(alt), _) => dfu
+                    .target(alt)
+                    .with_context(|| format!("no target for alternate setting {alt}"))?,
+                (None, [target]) => target,
+                (None, targets) => anyhow::bail!(
+                    "the file has {} targets, choose one with --alt",
+                    targets.len()
+                ),
+            };
+            let (start, bin) = crate::flatten(target, 0xFF)?;
+            tracing::info!(
+                "Removed the DfuSe prefix, {} target prefix(es) and {} element header(s); \
+                 target {:?} (alt {}) starts at {start:#010X}",
+                dfu.targets.len(),
+                dfu.elements().count(),
+                target.name,
+                target.alternate_setting
+            );
+            bin
+        } else {
+            anyhow::ensure!(self.alt.is_none(), "--alt needs a DfuSe file");
+            bytes[..bytes.len() - SUFFIX_LEN].to_vec()
+        };
+
+        tracing::info!(
+            "Removed the DFU suffix: {:04x}:{:04x}, bcdDevice {:#06x}, bcdDFU {:#06x}",
+            suffix.vid,
+            suffix.pid,
+            suffix.bcd_device,
+            suffix.bcd_dfu
+        );
+
+        let out_path = self
+            .output
+            .unwrap_or_else(|| self.file.with_extension("bin"));
+        std::fs::write(&out_path, &payload)
+            .with_context(|| format!("could not write `{}`", out_path.display()))?;
+        tracing::info!("{} bytes -> {}", payload.len(), out_path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+//! Output file name templates such as `brakebright-{version}-{hwrev}.dfu`.
+//! `{{` and `}}` stand for literal braces.
+
+use anyhow::{Context, Result};
+
+/// Placeholders `expand` fills in, for error messages.
+pub const PLACEHOLDERS: &str = "{version}, {hwrev}, {build_id}, {vid}, {pid}, {stem}";
+
+/// `template` with every `{NAME}` replaced by `value(NAME)`.
+pub fn expand(template: &str, value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        let brace = &rest[start..start + 1];
+        if rest[start + 1..].starts_with(brace) {
+            expanded.push_str(brace);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        anyhow::ensure!(brace == "{", "unmatched `}}` in `{template}`");
+        let len = rest[start..]
+            .find('}')
+            .with_context(|| format!("unclosed `{{` in `{template}`"))?;
+        let name = &rest[start + 1..start + len];
+        expanded.push_str(&value(name).with_context(|| format!("cannot fill in `{{{name}}}`"))?);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
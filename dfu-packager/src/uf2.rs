@@ -0,0 +1,173 @@
+//! UF2 (https://github.com/microsoft/uf2) reader and writer, for
+//! bootloaders that update over USB mass storage.
+
+use anyhow::{Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use dfu_file::DfuFile;
+
+const MAGIC_START0: u32 = 0x0A32_4655;
+const MAGIC_START1: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+/// `flags`: the block is not meant for the main flash.
+const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// `flags`: the block is part of a file container, not a flash image.
+const FLAG_FILE_CONTAINER: u32 = 0x0000_1000;
+/// `flags`: the file-size field holds the family ID.
+const FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const BLOCK_LEN: usize = 512;
+const DATA_LEN: usize = 476;
+/// Payload per block; blocks never cross a 256-byte boundary.
+const PAYLOAD_LEN: u32 = 256;
+
+/// The elements of all targets of `dfu` as a UF2 file. UF2 has no
+/// alternate settings, so only single-target files can be written.
+pub fn to_bytes(dfu: &DfuFile, family_id: u32) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        dfu.targets.len() <= 1,
+        "UF2 has no alternate settings; package one target at a time"
+    );
+
+    // Split the elements into payloads aligned to PAYLOAD_LEN.
+    let mut payloads = Vec::new();
+    for element in dfu.elements() {
+        let mut address = element.address;
+        let mut data = element.data.as_slice();
+        while !data.is_empty() {
+            let len = ((PAYLOAD_LEN - address % PAYLOAD_LEN) as usize).min(data.len());
+            let (payload, rest) = data.split_at(len);
+            payloads.push((address, payload));
+            address = address.checked_add(len as u32).with_context(|| {
+                format!("element at {:#010X} extends past 4 GiB", element.address)
+            })?;
+            data = rest;
+        }
+    }
+
+    let count = payloads.len() as u32;
+    let mut uf2 = Vec::with_capacity(payloads.len() * BLOCK_LEN);
+    for (number, (address, payload)) in payloads.into_iter().enumerate() {
+        uf2.write_u32::<LittleEndian>(MAGIC_START0)?;
+        uf2.write_u32::<LittleEndian>(MAGIC_START1)?;
+        uf2.write_u32::<LittleEndian>(FLAG_FAMILY_ID)?; // flags
+        uf2.write_u32::<LittleEndian>(address)?; // targetAddr
+        uf2.write_u32::<LittleEndian>(payload.len() as u32)?; // payloadSize
+        uf2.write_u32::<LittleEndian>(number as u32)?; // blockNo
+        uf2.write_u32::<LittleEndian>(count)?; // numBlocks
+        uf2.write_u32::<LittleEndian>(family_id)?; // familyID
+        let mut data = payload.to_vec();
+        data.resize(DATA_LEN, 0);
+        uf2.extend(data);
+        uf2.write_u32::<LittleEndian>(MAGIC_END)?;
+    }
+
+    Ok(uf2)
+}
+
+/// Payloads of a UF2 file as `(address, bytes)` chunks. Blocks flagged as
+/// not for the main flash are skipped.
+pub fn parse(bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>> {
+    anyhow::ensure!(
+        bytes.len().is_multiple_of(BLOCK_LEN),
+        "size {} is not a multiple of {BLOCK_LEN}",
+        bytes.len()
+    );
+    let word = |block: &[u8], offset: usize| {
+        u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+    };
+
+    let mut chunks = Vec::new();
+    let mut families = Vec::new();
+    for (number, block) in bytes.chunks(BLOCK_LEN).enumerate() {
+        anyhow::ensure!(
+            word(block, 0) == MAGIC_START0
+                && word(block, 4) == MAGIC_START1
+                && word(block, BLOCK_LEN - 4) == MAGIC_END,
+            "block {number} has no UF2 magic"
+        );
+        let flags = word(block, 8);
+        anyhow::ensure!(
+            flags & FLAG_FILE_CONTAINER == 0,
+            "block {number} belongs to a file container, not a flash image"
+        );
+        if flags & FLAG_NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+        if flags & FLAG_FAMILY_ID != 0 && !families.contains(&word(block, 28)) {
+            families.push(word(block, 28));
+        }
+        let address = word(block, 12);
+        let len = word(block, 16) as usize;
+        let payload = block[32..]
+            .get(..len)
+            .filter(|_| len <= DATA_LEN)
+            .with_context(|| format!("block {number} has an invalid payload size {len}"))?;
+        chunks.push((address, payload.to_vec()));
+    }
+    if families.len() > 1 {
+        tracing::warn!(
+            "UF2 file mixes family IDs {}; all blocks are packaged",
+            families
+                .iter()
+                .map(|family| format!("{family:#010X}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dfu_file::{DfuElement, DfuTarget};
+
+    fn dfu(address: u32, data: Vec<u8>) -> DfuFile {
+        DfuFile {
+            device_vid: 0x0483,
+            device_pid: 0xDF11,
+            bcd_device: 0,
+            targets: vec![DfuTarget {
+                name: "ST...".into(),
+                alternate_setting: 0,
+                elements: vec![DfuElement { address, data }],
+            }],
+        }
+    }
+
+    #[test]
+    fn blocks_split_at_payload_boundaries() {
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let uf2 = to_bytes(&dfu(0x0800_4080, data.clone()), 0x57755A57).unwrap();
+        assert_eq!(uf2.len(), 2 * BLOCK_LEN);
+        assert_eq!(
+            parse(&uf2).unwrap(),
+            [
+                (0x0800_4080, data[..128].to_vec()),
+                (0x0800_4100, data[128..].to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn element_past_4_gib_is_rejected() {
+        let error = to_bytes(&dfu(0xFFFF_FF80, vec![0; 0x100]), 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "element at 0xFFFFFF80 extends past 4 GiB"
+        );
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let uf2 = to_bytes(&dfu(0x0800_4000, vec![0; 16]), 0).unwrap();
+        assert!(parse(&uf2[..BLOCK_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn oversize_payload_is_rejected() {
+        let mut uf2 = to_bytes(&dfu(0x0800_4000, vec![0; 16]), 0).unwrap();
+        uf2[16..20].copy_from_slice(&(DATA_LEN as u32 + 1).to_le_bytes());
+        let error = parse(&uf2).unwrap_err();
+        assert_eq!(error.to_string(), "block 0 has an invalid payload size 477");
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::DfuFile;
+
+use crate::description::Description;
+
+#[derive(clap::Args)]
+pub struct UnpackArgs {
+    /// The .dfu file to unpack.
+    file: PathBuf,
+
+    /// Directory to write the binaries and the JSON description to.
+    #[clap(long, short, default_value = ".")]
+    output: PathBuf,
+}
+
+impl UnpackArgs {
+    /// Write each element to `<name>_0x<address>.bin` and describe the file
+    /// in `<file stem>.json`.
+    pub fn run(self) -> Result<()> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let dfu = DfuFile::from_bytes(&bytes)
+            .with_context(|| format!("`{}` is not a valid DfuSe file", self.file.display()))?;
+        std::fs::create_dir_all(&self.output)
+            .with_context(|| format!("could not create `{}`", self.output.display()))?;
+
+        let mut description = Description::from(&dfu);
+        for (target, described) in dfu.targets.iter().zip(&mut description.targets) {
+            let name = file_name_part(&target.name, target.alternate_setting);
+            for (element, described) in target.elements.iter().zip(&mut described.elements) {
+                let file = format!("{name}_0x{:08X}.bin", element.address);
+                let path = self.output.join(&file);
+                std::fs::write(&path, &element.data)
+                    .with_context(|| format!("could not write `{}`", path.display()))?;
+                tracing::info!(
+                    "{} bytes at {:#010X} -> {}",
+                    element.data.len(),
+                    element.address,
+                    path.display()
+                );
+                described.file = Some(file);
+            }
+        }
+
+        let stem = self.file.file_stem().unwrap_or("dfu".as_ref());
+        let path = self.output.join(stem).with_extension("json");
+        std::fs::write(&path, serde_json::to_string_pretty(&description)? + "\n")
+            .with_context(|| format!("could not write `{}`", path.display()))?;
+        tracing::info!("Description -> {}", path.display());
+        Ok(())
+    }
+}
+
+/// Target name made safe for a file name; unnamed targets are called
+/// `alt<n>`.
+fn file_name_part(name: &str, alternate_setting: u8) -> String {
+    if name.is_empty() {
+        return format!("alt{alternate_setting}");
+    }
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
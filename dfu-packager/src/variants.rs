@@ -0,0 +1,55 @@
+//! `--variant HWREV=FILE`: one output per hardware revision of
+//! `--memory-map`, each checked against and placed by that revision's
+//! memory map and marked compatible with it in the manifest.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::Cli;
+
+/// Package every variant, carrying on after failures and failing at the
+/// end if any variant failed.
+pub fn run(mut cli: Cli, variants: Vec<(String, PathBuf)>) -> Result<()> {
+    let template = cli.options.output.take();
+    if variants.len() > 1 {
+        anyhow::ensure!(
+            !matches!(cli.options.manifest, Some(Some(_))),
+            "--manifest FILE would be overwritten for every variant; leave out FILE to write one next to each output"
+        );
+        anyhow::ensure!(
+            template
+                .as_ref()
+                .is_none_or(|output| output.to_string_lossy().contains("{hwrev}")),
+            "--output needs a {{hwrev}} placeholder to name several variants apart"
+        );
+    }
+
+    let mut failed = Vec::new();
+    for (hw_rev, file) in &variants {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        cli.options.output = Some(template.clone().unwrap_or_else(|| {
+            file.with_file_name(format!(
+                "{stem}-{hw_rev}.{}",
+                cli.options.format.extension()
+            ))
+        }));
+        cli.options.hw_rev = Some(hw_rev.clone());
+        cli.options.file = Some(file.clone());
+        match cli.package() {
+            Ok(()) => tracing::info!("Hardware {hw_rev}: {} packaged", file.display()),
+            Err(e) => {
+                tracing::error!("Hardware {hw_rev}: {e:#}");
+                failed.push(hw_rev.as_str());
+            }
+        }
+    }
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} of {} variants failed: hardware {}",
+        failed.len(),
+        variants.len(),
+        failed.join(", ")
+    );
+    Ok(())
+}
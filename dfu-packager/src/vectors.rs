@@ -0,0 +1,123 @@
+//! Checks of the application image before packaging, as the GUI makes
+//! before flashing: the vector table must suit the address the image is
+//! packaged for, the image must fit the memory map, and with
+//! `--require-magic` it must contain the bootloader hand-off magic.
+
+use anyhow::{Context, Result};
+use device_memory::VectorTable;
+use dfu_file::{DfuElement, DfuFile, MemoryLayout};
+
+use crate::memory_map::MemoryMap;
+
+/// Check that the lowest element of the first target starts with a vector
+/// table whose initial SP points into the RAM of `map` and whose reset
+/// vector points into that element.
+pub fn check(dfu: &DfuFile, map: &MemoryMap) -> Result<()> {
+    let target = dfu.targets.first().context("nothing to package")?;
+    let app = target
+        .elements
+        .iter()
+        .min_by_key(|element| element.address)
+        .context("nothing to package")?;
+
+    let VectorTable { sp, reset } = VectorTable::check(
+        &app.data,
+        app.address,
+        device_memory::Region::from(&map.ram),
+    )?;
+    tracing::debug!("Vector table: SP {sp:#010X}, reset {reset:#010X}");
+    Ok(())
+}
+
+/// Check that every element of the first target lies in the flash of `map`
+/// (or the writable pages of `layout`), outside its reserved regions.
+pub fn check_fits(dfu: &DfuFile, layout: Option<&MemoryLayout>, map: &MemoryMap) -> Result<()> {
+    let target = dfu.targets.first().context("nothing to package")?;
+    for (index, element) in target.elements.iter().enumerate() {
+        let name = format!(
+            "element {index} of alt {} ({:#010X}..{:#010X}, {} bytes)",
+            target.alternate_setting,
+            element.address,
+            element.end(),
+            element.data.len()
+        );
+        check_element_fits(element, &name, layout, map)?;
+        if let Some(region) = map
+            .reserved
+            .iter()
+            .find(|region| region.overlaps(element.address as u64, element.end()))
+        {
+            anyhow::bail!(
+                "{name} overlaps the reserved region {}at {:#010X}..{:#010X}",
+                region
+                    .name
+                    .as_ref()
+                    .map_or(String::new(), |name| format!("`{name}` ")),
+                region.origin,
+                region.end()
+            );
+        }
+        if let Some(page_size) = map.page_size
+            && !element.address.is_multiple_of(page_size)
+        {
+            tracing::warn!(
+                "Element at {:#010X} does not start on a {page_size}-byte page boundary; \
+                 erasing its first page erases what precedes it",
+                element.address
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The element `name` must lie in writable flash: the flash of `map`, or
+/// the writable pages of `layout`.
+fn check_element_fits(
+    element: &DfuElement,
+    name: &str,
+    layout: Option<&MemoryLayout>,
+    map: &MemoryMap,
+) -> Result<()> {
+    let (regions, flash): (Vec<(u64, u64)>, _) = match layout {
+        None => (
+            vec![(map.flash.origin as u64, map.flash.end())],
+            format!(
+                "flash {:#010X}..{:#010X}",
+                map.flash.origin,
+                map.flash.end()
+            ),
+        ),
+        Some(layout) => (
+            layout
+                .pages()
+                .filter(|(_, sectors)| sectors.writable)
+                .map(|(address, sectors)| (address, address + sectors.size as u64))
+                .collect(),
+            "the writable pages of --layout".to_string(),
+        ),
+    };
+    device_memory::check_fits(element.address as u64, element.end(), &regions)
+        .with_context(|| format!("{name} ({flash})"))
+}
+
+/// Check that the first target holds `magic` as a little-endian word on a
+/// 4-byte boundary, as the constant of the code that hands over to the
+/// bootloader (`KEY_STAY_IN_BOOT`) is stored.
+pub fn check_magic(dfu: &DfuFile, magic: u32, fill: u8) -> Result<()> {
+    let target = dfu.targets.first().context("nothing to package")?;
+    let (start, image) = crate::flatten(target, fill)?;
+    let bytes = magic.to_le_bytes();
+    match image.chunks_exact(4).position(|word| word == bytes) {
+        Some(index) => {
+            tracing::debug!(
+                "Stay-in-boot magic {magic:#010X} at {:#010X}",
+                start as usize + index * 4
+            );
+            Ok(())
+        }
+        None => anyhow::bail!(
+            "the image does not contain the stay-in-boot magic {magic:#010X}, so it could not \
+             return to the bootloader for field updates"
+        ),
+    }
+}
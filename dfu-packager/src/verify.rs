@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dfu_file::{BCD_DFU, PREFIX_LEN, SUFFIX_LEN, TARGET_PREFIX_LEN};
+
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// The .dfu file to check.
+    file: PathBuf,
+}
+
+impl VerifyArgs {
+    /// Check the file's structure, reporting every problem found, and fail
+    /// if there was any.
+    pub fn run(self) -> Result<()> {
+        let bytes = std::fs::read(&self.file)
+            .with_context(|| format!("could not read `{}`", self.file.display()))?;
+        let problems = check(&bytes);
+        for problem in &problems {
+            tracing::error!("{problem}");
+        }
+        match problems.len() {
+            0 => {
+                tracing::info!("{}: OK", self.file.display());
+                Ok(())
+            }
+            1 => anyhow::bail!("{}: 1 problem found", self.file.display()),
+            n => anyhow::bail!("{}: {n} problems found", self.file.display()),
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Every structural problem of a DfuSe file.
+fn check(bytes: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if bytes.len() < PREFIX_LEN + SUFFIX_LEN {
+        problems.push(format!("file is too short ({} bytes)", bytes.len()));
+        return problems;
+    }
+
+    // Suffix: bcdDevice, idProduct, idVendor, bcdDFU, "UFD", bLength, dwCRC
+    let body_len = bytes.len() - SUFFIX_LEN;
+    let suffix = &bytes[body_len..];
+    if &suffix[8..11] != b"UFD" {
+        problems.push("DFU suffix signature is not `UFD`".into());
+    }
+    if suffix[11] as usize != SUFFIX_LEN {
+        problems.push(format!(
+            "DFU suffix bLength is {}, expected {SUFFIX_LEN}",
+            suffix[11]
+        ));
+    }
+    let bcd_dfu = u16_at(suffix, 6);
+    if bcd_dfu != BCD_DFU {
+        problems.push(format!("bcdDFU is {bcd_dfu:#06x}, expected {BCD_DFU:#06x}"));
+    }
+    let crc = u32_at(suffix, 12);
+    let computed = !crc32fast::hash(&bytes[..bytes.len() - 4]);
+    if crc != computed {
+        problems.push(format!(
+            "DFU suffix CRC is {crc:#010X}, the file hashes to {computed:#010X}"
+        ));
+    }
+
+    // Prefix: "DfuSe", bVersion, dwSize, bTargets
+    if &bytes[..5] != b"DfuSe" {
+        problems.push("DfuSe prefix signature is missing".into());
+        return problems;
+    }
+    if bytes[5] != 1 {
+        problems.push(format!("bVersion is {}, expected 1", bytes[5]));
+    }
+    let size = u32_at(bytes, 6);
+    if size as usize != body_len {
+        problems.push(format!(
+            "dwSize is {size}, but prefix and targets take {body_len} bytes"
+        ));
+    }
+
+    let mut offset = PREFIX_LEN;
+    for target in 0..bytes[10] {
+        let Some(prefix) = bytes[..body_len].get(offset..offset + TARGET_PREFIX_LEN) else {
+            problems.push(format!("target {target} is truncated"));
+            return problems;
+        };
+        if &prefix[..6] != b"Target" {
+            problems.push(format!("target {target} has no `Target` signature"));
+            return problems;
+        }
+        let target_size = u32_at(prefix, 266) as usize;
+        let element_count = u32_at(prefix, 270);
+        offset += TARGET_PREFIX_LEN;
+
+        let start = offset;
+        for element in 0..element_count {
+            let Some(header) = bytes[..body_len].get(offset..offset + 8) else {
+                problems.push(format!("element {element} of target {target} is truncated"));
+                return problems;
+            };
+            let element_size = u32_at(header, 4) as usize;
+            offset += 8 + element_size;
+            if offset > body_len {
+                problems.push(format!(
+                    "element {element} of target {target} ({element_size} bytes) runs past the end of the file"
+                ));
+                return problems;
+            }
+        }
+        if offset - start != target_size {
+            problems.push(format!(
+                "dwTargetSize of target {target} is {target_size}, but its elements take {} bytes",
+                offset - start
+            ));
+        }
+    }
+    if offset != body_len {
+        problems.push(format!(
+            "{} bytes of trailing data after the last target",
+            body_len - offset
+        ));
+    }
+    problems
+}
@@ -0,0 +1,93 @@
+//! `--watch`: package, then package again whenever an input changes, for
+//! the edit-build-flash loop. Inputs are polled rather than watched through
+//! the OS, which also copes with build tools that replace the file.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use crate::Cli;
+
+/// How often the inputs are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Package every time the inputs change, until interrupted. Failures are
+/// logged and wait for the next change.
+pub fn run(cli: Cli) -> Result<()> {
+    let inputs = watched(&cli);
+    anyhow::ensure!(
+        inputs
+            .iter()
+            .all(|path| path.as_os_str() != crate::input::STDIN),
+        "--watch cannot read the firmware from standard input"
+    );
+
+    let mut last = stamps(&inputs);
+    package(&cli);
+    tracing::info!(
+        "Watching {} for changes (Ctrl-C to stop)",
+        inputs
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        // A changed description may list other images.
+        let inputs = watched(&cli);
+        let current = stamps(&inputs);
+        if current == last {
+            continue;
+        }
+        // Let the build finish writing before reading the files.
+        std::thread::sleep(POLL_INTERVAL);
+        last = stamps(&inputs);
+        if last.iter().any(Option::is_none) {
+            continue;
+        }
+        package(&cli);
+    }
+}
+
+fn package(cli: &Cli) {
+    match cli.package() {
+        Ok(()) => tracing::info!("Packaged; waiting for changes"),
+        Err(e) => tracing::error!("{e:#}"),
+    }
+}
+
+/// The files the output is made from, with the images a --description
+/// lists. A description that cannot be read is watched without them until
+/// it is fixed.
+fn watched(cli: &Cli) -> Vec<PathBuf> {
+    let described = match &cli.options.description {
+        Some(path) => crate::description::files(path).unwrap_or_else(|e| {
+            tracing::debug!("{e:#}");
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    cli.options
+        .file
+        .iter()
+        .chain(cli.options.image.iter().map(|image| &image.file))
+        .chain(&cli.options.description)
+        .chain(&described)
+        .chain(&cli.options.memory_map)
+        .chain(&cli.options.linker_script)
+        .chain(&cli.options.release_notes)
+        .cloned()
+        .collect()
+}
+
+/// Modification time and size of each file, or `None` while it is missing.
+fn stamps(paths: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    paths.iter().map(|path| stamp(path)).collect()
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
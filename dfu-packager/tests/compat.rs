@@ -0,0 +1,159 @@
+//! `--compat-check` against the dfuse-pack.py fixtures in `tests/fixtures`
+//! (see `make_reference.py` there).
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use dfu_file::{DfuFile, Suffix};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn output(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+fn packager(args: &[&str]) -> Output {
+    // The fixtures are not firmware, so skip the vector table checks.
+    Command::new(env!("CARGO_BIN_EXE_dfu-packager"))
+        .arg("--force")
+        .args(args)
+        .current_dir(fixture(""))
+        .output()
+        .unwrap()
+}
+
+fn stderr_and_stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned() + &String::from_utf8_lossy(&output.stdout)
+}
+
+#[test]
+fn single_image_matches_dfuse_pack() {
+    let out = output("compat-app.dfu");
+    let result = packager(&[
+        "--file",
+        "app.bin",
+        "--device",
+        "0483:df11",
+        "--target-name",
+        "ST...",
+        "-o",
+        out.to_str().unwrap(),
+        "--compat-check",
+        "app.dfu",
+    ]);
+    assert!(result.status.success(), "{}", stderr_and_stdout(&result));
+    assert_eq!(
+        std::fs::read(&out).unwrap(),
+        std::fs::read(fixture("app.dfu")).unwrap()
+    );
+}
+
+#[test]
+fn several_images_match_dfuse_pack() {
+    let out = output("compat-multi.dfu");
+    let result = packager(&[
+        "--image",
+        "app.bin:08004000",
+        "--image",
+        "config.bin:0800F800",
+        "--device",
+        "0483:df11",
+        "--target-name",
+        "ST...",
+        "-o",
+        out.to_str().unwrap(),
+        "--compat-check",
+        "multi.dfu",
+    ]);
+    assert!(result.status.success(), "{}", stderr_and_stdout(&result));
+}
+
+#[test]
+fn structural_differences_are_reported() {
+    let out = output("compat-named.dfu");
+    let result = packager(&[
+        "--file",
+        "app.bin",
+        "--device",
+        "1209:2444",
+        "--fw-version",
+        "1.2",
+        "-o",
+        out.to_str().unwrap(),
+        "--compat-check",
+        "app.dfu",
+    ]);
+    assert!(!result.status.success());
+    let log = stderr_and_stdout(&result);
+    assert!(
+        log.contains("idVendor:idProduct: 1209:2444, reference 0483:df11"),
+        "{log}"
+    );
+    assert!(log.contains("bcdDevice: 0x0120, reference 0x0000"), "{log}");
+    assert!(
+        log.contains(r#"target 0 szTargetName: "Flash", reference "ST...""#),
+        "{log}"
+    );
+    assert!(log.contains("3 differences"), "{log}");
+}
+
+#[test]
+fn byte_differences_name_the_field() {
+    // Garbage after the name's NUL is invisible to the parser.
+    let mut reference = std::fs::read(fixture("app.dfu")).unwrap();
+    reference[11 + 11 + 100] = 0xAA;
+    let reference_path = output("compat-garbage.dfu");
+    std::fs::write(&reference_path, reference).unwrap();
+
+    let out = output("compat-garbage-out.dfu");
+    let result = packager(&[
+        "--file",
+        "app.bin",
+        "--device",
+        "0483:df11",
+        "--target-name",
+        "ST...",
+        "-o",
+        out.to_str().unwrap(),
+        "--compat-check",
+        reference_path.to_str().unwrap(),
+    ]);
+    assert!(!result.status.success());
+    let log = stderr_and_stdout(&result);
+    assert!(
+        log.contains("files differ first at byte 0x7a (target 0 szTargetName)"),
+        "{log}"
+    );
+}
+
+#[test]
+fn repack_changes_ids_and_crc() {
+    let out = output("repack-app.dfu");
+    let result = Command::new(env!("CARGO_BIN_EXE_dfu-packager"))
+        .args(["repack", "app.dfu", "-o", out.to_str().unwrap()])
+        .args(["--device", "1209:2444", "--fw-version", "1.2"])
+        .args(["--target-name", "Flash"])
+        .current_dir(fixture(""))
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", stderr_and_stdout(&result));
+
+    let bytes = std::fs::read(&out).unwrap();
+    let suffix = Suffix::parse(&bytes).unwrap();
+    assert_eq!((suffix.vid, suffix.pid), (0x1209, 0x2444));
+    assert_eq!(suffix.bcd_device, 0x0120);
+    assert_eq!(suffix.crc, !crc32fast::hash(&bytes[..bytes.len() - 4]));
+
+    let repacked = DfuFile::from_bytes(&bytes).unwrap();
+    let reference = DfuFile::from_bytes(&std::fs::read(fixture("app.dfu")).unwrap()).unwrap();
+    assert_eq!(repacked.targets[0].name, "Flash");
+    assert_eq!(repacked.targets[0].elements, reference.targets[0].elements);
+    assert_eq!(
+        bytes.len(),
+        std::fs::read(fixture("app.dfu")).unwrap().len()
+    );
+}
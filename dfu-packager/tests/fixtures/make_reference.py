@@ -0,0 +1,39 @@
+#!/usr/bin/env python3
+# Writes the reference .dfu fixtures with the build() routine of dfu-util's
+# dfuse-pack.py (target "ST...", alternate setting 0, bcdDevice 0), so the
+# tests run without dfu-util. Regenerate with `python3 make_reference.py`;
+# running dfuse-pack.py itself on the same inputs gives the same files:
+#
+#   dfuse-pack.py -b 0x08004000:app.bin -D 0x0483:0xdf11 app.dfu
+#   dfuse-pack.py -b 0x08004000:app.bin -b 0x0800F800:config.bin -D 0x0483:0xdf11 multi.dfu
+
+import struct
+import zlib
+
+
+def build(file, targets, device):
+    data = b''
+    for target in targets:
+        tdata = b''
+        for image in target:
+            tdata += struct.pack('<2I', image['address'], len(image['data'])) + image['data']
+        tdata = struct.pack('<6sBI255s2I', b'Target', 0, 1, b'ST...', len(tdata), len(target)) + tdata
+        data += tdata
+    data = struct.pack('<5sBIB', b'DfuSe', 1, len(data) + 11, len(targets)) + data
+    v, d = map(lambda x: int(x, 0) & 0xFFFF, device.split(':', 1))
+    data += struct.pack('<4H3sB', 0, d, v, 0x011a, b'UFD', 16)
+    crc = (0xFFFFFFFF & -zlib.crc32(data) - 1)
+    data += struct.pack('<I', crc)
+    open(file, 'wb').write(data)
+
+
+def image(address, path):
+    return {'address': address, 'data': open(path, 'rb').read()}
+
+
+# Deterministic payloads, so the fixtures can be regenerated byte for byte.
+open('app.bin', 'wb').write(bytes((i * 7 + 3) & 0xFF for i in range(1000)))
+open('config.bin', 'wb').write(bytes(range(64)))
+
+build('app.dfu', [[image(0x08004000, 'app.bin')]], '0x0483:0xdf11')
+build('multi.dfu', [[image(0x08004000, 'app.bin'), image(0x0800F800, 'config.bin')]], '0x0483:0xdf11')
@@ -0,0 +1,81 @@
+//! The library entry point packages like the command line.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use dfu_packager::Packager;
+use firmware_manifest::Manifest;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn output(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+#[test]
+fn same_output_as_the_command_line() {
+    let cli = output("packager-cli.dfu");
+    let status = Command::new(env!("CARGO_BIN_EXE_dfu-packager"))
+        .args(["--file", fixture("app.bin").to_str().unwrap()])
+        .args(["--device", "0483:df11", "--address", "08000000"])
+        .args(["--fw-version", "1.4.2", "--force", "-o"])
+        .arg(&cli)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let written = Packager::new(fixture("app.bin"))
+        .device(0x0483, 0xDF11)
+        .address(0x0800_0000)
+        .fw_version("1.4.2")
+        .force()
+        .output(output("packager-lib-{version}.dfu"))
+        .package()
+        .unwrap();
+    assert_eq!(written, output("packager-lib-1.4.2.dfu"));
+    assert_eq!(std::fs::read(written).unwrap(), std::fs::read(cli).unwrap());
+}
+
+#[test]
+fn options_are_checked() {
+    let packager = Packager::new(fixture("app.bin"))
+        .device(0x0483, 0xDF11)
+        .force()
+        .output(output("packager-checked.dfu"));
+    let error = packager.clone().fw_version("1.10").package().unwrap_err();
+    assert!(format!("{error:#}").contains("1.10"), "{error:#}");
+    assert!(
+        packager
+            .fw_version("1.4")
+            .set_version("1.4")
+            .package()
+            .is_err()
+    );
+}
+
+#[test]
+fn manifest_writes_the_sidecar() {
+    let dfu = output("manifest-cli.dfu");
+    let sidecar = output("manifest-cli.manifest.json");
+    let status = Command::new(env!("CARGO_BIN_EXE_dfu-packager"))
+        .args(["--file", fixture("app.bin").to_str().unwrap()])
+        .args(["--device", "0483:df11", "--address", "08000000"])
+        .args(["--fw-version", "1.4.2", "--force", "--manifest"])
+        .arg(&sidecar)
+        .arg("-o")
+        .arg(&dfu)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let manifest = Manifest::from_slice(&std::fs::read(sidecar).unwrap()).unwrap();
+    assert_eq!(manifest.version, "1.4.2");
+    assert_eq!(manifest.firmware, "manifest-cli.bin");
+    let package = manifest.dfu.unwrap();
+    assert_eq!(package.file, "manifest-cli.dfu");
+    assert_eq!(package.size, std::fs::metadata(dfu).unwrap().len());
+}
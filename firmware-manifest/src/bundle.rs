@@ -0,0 +1,172 @@
+//! Release bundles (`.bbfw`, or `.zip` from older releases): a zip archive
+//! holding the firmware image, its [`MANIFEST_FILE`] and an ed25519
+//! signature over the manifest bytes in [`SIGNATURE_FILE`]. With
+//! `"compression": "zstd"` the image is stored zstd-compressed (e.g.
+//! `firmware.bin.zst`).
+//!
+//! The command line and the GUI open bundles through [`Bundle::open`], so
+//! both check the same signature, hashes and compatibility.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::{Compression, MANIFEST_FILE, Manifest, SIGNATURE_FILE};
+
+/// Extension of release bundles.
+pub const EXTENSION: &str = "bbfw";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not open bundle `{}`", path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("bundle is not a zip archive")]
+    Archive(#[source] zip::result::ZipError),
+    #[error("bundle has no `{0}`")]
+    Missing(String),
+    #[error("could not read `{name}` from the bundle")]
+    Read {
+        name: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("malformed bundle signature")]
+    MalformedSignature(#[source] ed25519_dalek::SignatureError),
+    #[error("bundle signature is not valid for this key")]
+    Signature(#[source] ed25519_dalek::SignatureError),
+    #[error(transparent)]
+    Manifest(#[from] crate::Error),
+    #[error("manifest gives no size for the compressed firmware")]
+    NoSize,
+    #[error("could not decompress the firmware")]
+    Decompress(#[source] io::Error),
+    #[error("firmware decompresses to more than the {0} bytes of the manifest")]
+    TooLarge(u64),
+    #[error(
+        "bundle is for device {:04x}:{:04x}, connected device is {:04x}:{:04x}",
+        expected.0, expected.1, actual.0, actual.1
+    )]
+    WrongDevice {
+        expected: (u16, u16),
+        actual: (u16, u16),
+    },
+    #[error(
+        "bundle supports hardware {}, connected device is {}",
+        supported.join(", "), actual.join(" or ")
+    )]
+    WrongHardware {
+        supported: Vec<String>,
+        actual: Vec<String>,
+    },
+    #[error("bundle needs flash up to {end:#010X}, the chip's ends at {flash_end:#010X}")]
+    DoesNotFit { end: u64, flash_end: u64 },
+}
+
+/// A bundle whose manifest and firmware were checked.
+pub struct Bundle {
+    pub manifest: Manifest,
+    /// The (decompressed) image.
+    pub firmware: Vec<u8>,
+}
+
+impl Bundle {
+    /// Read the bundle at `path`, checking the manifest signature against
+    /// `key` (skipped when `None`) and the firmware hash against the
+    /// manifest.
+    pub fn open(path: &Path, key: Option<&VerifyingKey>) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|source| Error::Open {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut archive = zip::ZipArchive::new(file).map_err(Error::Archive)?;
+        let mut read = |name: &str| -> Result<Vec<u8>, Error> {
+            let mut data = Vec::new();
+            archive
+                .by_name(name)
+                .map_err(|_| Error::Missing(name.to_string()))?
+                .read_to_end(&mut data)
+                .map_err(|source| Error::Read {
+                    name: name.to_string(),
+                    source,
+                })?;
+            Ok(data)
+        };
+
+        let manifest_bytes = read(MANIFEST_FILE)?;
+        match key {
+            Some(key) => {
+                let signature = Signature::from_slice(&read(SIGNATURE_FILE)?)
+                    .map_err(Error::MalformedSignature)?;
+                key.verify_strict(&manifest_bytes, &signature)
+                    .map_err(Error::Signature)?;
+            }
+            None => tracing::warn!("Not checking the bundle signature"),
+        }
+
+        let manifest = Manifest::from_slice(&manifest_bytes)?;
+        let firmware = read(&manifest.firmware)?;
+        let firmware = match manifest.compression {
+            None => firmware,
+            Some(Compression::Zstd) => decompress(&firmware, manifest.size)?,
+        };
+        manifest.check_firmware(&firmware)?;
+
+        Ok(Self { manifest, firmware })
+    }
+
+    /// Fail unless a device `vid:pid` of one of the hardware `revisions`
+    /// (bcdDevice as `major.minor.sub`) is listed in the manifest, and the
+    /// image ends within its flash, if `flash_end` is known.
+    pub fn check_compatible(
+        &self,
+        vid: u16,
+        pid: u16,
+        revisions: &[String],
+        flash_end: Option<u64>,
+    ) -> Result<(), Error> {
+        let end = self.manifest.address as u64 + self.firmware.len() as u64;
+        if let Some(flash_end) = flash_end
+            && end > flash_end
+        {
+            return Err(Error::DoesNotFit { end, flash_end });
+        }
+        let compatible = &self.manifest.compatible;
+        if revisions
+            .iter()
+            .any(|hardware| compatible.accepts(vid, pid, hardware))
+        {
+            return Ok(());
+        }
+        if (vid, pid) != (compatible.vid, compatible.pid) {
+            return Err(Error::WrongDevice {
+                expected: (compatible.vid, compatible.pid),
+                actual: (vid, pid),
+            });
+        }
+        Err(Error::WrongHardware {
+            supported: compatible.hardware.clone(),
+            actual: revisions.to_vec(),
+        })
+    }
+}
+
+/// Decompress a zstd image that the manifest says is `size` bytes. Reading
+/// stops one byte past it, so a corrupt or hostile bundle cannot make it
+/// inflate without bound.
+fn decompress(firmware: &[u8], size: Option<u64>) -> Result<Vec<u8>, Error> {
+    let size = size.ok_or(Error::NoSize)?;
+    let mut decompressed = Vec::new();
+    zstd::Decoder::new(firmware)
+        .and_then(|decoder| decoder.take(size + 1).read_to_end(&mut decompressed))
+        .map_err(Error::Decompress)?;
+    if decompressed.len() as u64 > size {
+        return Err(Error::TooLarge(size));
+    }
+    Ok(decompressed)
+}
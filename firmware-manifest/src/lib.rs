@@ -0,0 +1,329 @@
+//! The manifest describing a firmware release: what the image is, where it
+//! goes, which devices accept it and how to check it. `dfu-packager` writes
+//! it, next to a .dfu or into a release bundle; the updaters read it. It is
+//! signed as a whole, with the detached ed25519 signature in
+//! [`SIGNATURE_FILE`] next to it, so it has no signature field.
+//!
+//! ```json
+//! {
+//!   "format": 1,
+//!   "version": "1.4.2",
+//!   "firmware": "firmware.bin",
+//!   "sha256": "<hex SHA-256 of the image>",
+//!   "address": 134234112,
+//!   "size": 47204,
+//!   "compatible": { "vid": 4617, "pid": 9284, "hardware": ["2.0.0"] },
+//!   "release_notes": "CHANGELOG.md"
+//! }
+//! ```
+//!
+//! `size`, `compression` (`"zstd"`, with `sha256` and `size` those of the
+//! decompressed image), `release_notes`, `dfu` and `build` are optional.
+//! Fields this crate does not know are kept in [`Manifest::extra`].
+//! Manifests without `format` are format 1.
+//!
+//! A release directory's [`Index`] lists its files with their sizes,
+//! hashes and versions, as the update feed of a channel.
+//!
+//! With the `bundle` feature, [`bundle::Bundle`] opens and checks release
+//! bundles.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let manifest = firmware_manifest::Manifest::from_slice(&std::fs::read("manifest.json")?)?;
+//! manifest.check_firmware(&std::fs::read(&manifest.firmware)?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "bundle")]
+pub mod bundle;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Newest format this crate reads and the one it writes.
+pub const FORMAT: u32 = 1;
+/// Name of the manifest in a release bundle.
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Name of the manifest's detached signature in a release bundle.
+pub const SIGNATURE_FILE: &str = "manifest.json.sig";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not parse the manifest")]
+    Json(#[from] serde_json::Error),
+    #[error("manifest format {0} is not supported (newest: {FORMAT})")]
+    UnsupportedFormat(u32),
+    #[error("invalid manifest `{field}` {value:?}: {reason}")]
+    Invalid {
+        field: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    #[error("firmware hash {actual} does not match the manifest ({expected})")]
+    HashMismatch { expected: String, actual: String },
+    #[error("firmware is {actual} bytes, the manifest says {expected}")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("could not read key file `{}`", path.display())]
+    ReadKey {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("key file `{}` does not hold a 32-byte hex key", path.display())]
+    InvalidKey { path: PathBuf },
+    #[error("key file `{}` does not hold a valid public key", path.display())]
+    InvalidPublicKey {
+        path: PathBuf,
+        #[source]
+        source: ed25519_dalek::SignatureError,
+    },
+}
+
+/// Description of one firmware release.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the manifest format.
+    #[serde(default = "first_format")]
+    pub format: u32,
+    /// Firmware version as `major.minor.patch`.
+    pub version: String,
+    /// File name of the firmware image, next to the manifest.
+    pub firmware: String,
+    /// Hex SHA-256 of the (decompressed) image.
+    pub sha256: String,
+    /// Address the image is written to.
+    pub address: u32,
+    /// Size of the (decompressed) image in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// How `firmware` is compressed, if it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    pub compatible: Compatibility,
+    /// File name of the release notes, next to the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// The .dfu file made from the same image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dfu: Option<Package>,
+    /// Build metadata, e.g. the tool and the commit.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build: BTreeMap<String, String>,
+    /// Fields this crate does not know, e.g. added by a newer tool.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+fn first_format() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+}
+
+/// Devices a release may be flashed onto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Compatibility {
+    pub vid: u16,
+    pub pid: u16,
+    /// Accepted hardware revisions (bcdDevice as `major.minor.sub`); an
+    /// empty list accepts any.
+    #[serde(default)]
+    pub hardware: Vec<String>,
+}
+
+impl Compatibility {
+    /// Whether a device `vid:pid` of hardware revision `hardware` accepts
+    /// the release.
+    pub fn accepts(&self, vid: u16, pid: u16, hardware: &str) -> bool {
+        (vid, pid) == (self.vid, self.pid)
+            && (self.hardware.is_empty() || self.hardware.iter().any(|h| h == hardware))
+    }
+}
+
+/// A file shipped alongside the image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Package {
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Parse and [`validate`](Self::validate) a manifest.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let manifest: Self = serde_json::from_slice(bytes)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// The manifest as pretty-printed JSON with a final newline, the bytes
+    /// that get signed.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        Ok((serde_json::to_string_pretty(self)? + "\n").into_bytes())
+    }
+
+    /// Check that the fields are well-formed: a supported format, a
+    /// `major.minor.patch` version and hardware revisions, a bare file
+    /// name and a SHA-256 hash.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(1..=FORMAT).contains(&self.format) {
+            return Err(Error::UnsupportedFormat(self.format));
+        }
+        check_version("version", &self.version)?;
+        for hardware in &self.compatible.hardware {
+            check_version("compatible.hardware", hardware)?;
+        }
+        check_file_name("firmware", &self.firmware)?;
+        check_hash("sha256", &self.sha256)?;
+        if let Some(notes) = &self.release_notes {
+            check_file_name("release_notes", notes)?;
+        }
+        if let Some(dfu) = &self.dfu {
+            check_file_name("dfu.file", &dfu.file)?;
+            check_hash("dfu.sha256", &dfu.sha256)?;
+        }
+        Ok(())
+    }
+
+    /// Check the (decompressed) image against `sha256` and `size`.
+    pub fn check_firmware(&self, firmware: &[u8]) -> Result<(), Error> {
+        if let Some(size) = self.size
+            && size != firmware.len() as u64
+        {
+            return Err(Error::SizeMismatch {
+                expected: size,
+                actual: firmware.len() as u64,
+            });
+        }
+        let hash = hex::encode(Sha256::digest(firmware));
+        if !hash.eq_ignore_ascii_case(&self.sha256) {
+            return Err(Error::HashMismatch {
+                expected: self.sha256.clone(),
+                actual: hash,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn invalid(field: &'static str, value: &str, reason: &'static str) -> Error {
+    Error::Invalid {
+        field,
+        value: value.to_string(),
+        reason,
+    }
+}
+
+fn check_version(field: &'static str, version: &str) -> Result<(), Error> {
+    let parts: Vec<_> = version.split('.').collect();
+    if parts.len() != 3 || parts.iter().any(|part| part.parse::<u16>().is_err()) {
+        return Err(invalid(field, version, "expected major.minor.patch"));
+    }
+    Ok(())
+}
+
+fn check_file_name(field: &'static str, name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(invalid(field, name, "expected a file name without a path"));
+    }
+    Ok(())
+}
+
+fn check_hash(field: &'static str, hash: &str) -> Result<(), Error> {
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid(field, hash, "expected 64 hex digits"));
+    }
+    Ok(())
+}
+
+/// The 32-byte ed25519 key, hex-encoded, in the file at `path`: a public
+/// key to check manifest and index signatures with, or the secret seed that
+/// makes them.
+pub fn read_key(path: &Path) -> Result<[u8; 32], Error> {
+    let text = std::fs::read_to_string(path).map_err(|source| Error::ReadKey {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    hex::decode(text.trim())
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| Error::InvalidKey {
+            path: path.to_path_buf(),
+        })
+}
+
+/// The ed25519 public key, hex-encoded, in the file at `path`.
+pub fn read_public_key(path: &Path) -> Result<VerifyingKey, Error> {
+    VerifyingKey::from_bytes(&read_key(path)?).map_err(|source| Error::InvalidPublicKey {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Name of a release directory's index.
+pub const INDEX_FILE: &str = "index.json";
+
+/// Index of the firmware files in a release directory, the update feed of
+/// a channel; signed like the manifest, in `index.json.sig`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    /// Tool and version that wrote the index.
+    pub generator: String,
+    pub files: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// File name, relative to the index.
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Firmware version, when the file records one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Index {
+    /// Parse an index, checking its file names and hashes.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let index: Self = serde_json::from_slice(bytes)?;
+        for entry in &index.files {
+            check_file_name("files.file", &entry.file)?;
+            check_hash("files.sha256", &entry.sha256)?;
+        }
+        Ok(index)
+    }
+
+    /// The index as pretty-printed JSON with a final newline, the bytes
+    /// that get signed.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        Ok((serde_json::to_string_pretty(self)? + "\n").into_bytes())
+    }
+
+    /// The entry with the highest version among those `filter` accepts.
+    pub fn newest(&self, filter: impl Fn(&IndexEntry) -> bool) -> Option<&IndexEntry> {
+        self.files
+            .iter()
+            .filter(|entry| filter(entry))
+            .filter_map(|entry| Some((parse_version(entry.version.as_deref()?)?, entry)))
+            .max_by_key(|(version, _)| *version)
+            .map(|(_, entry)| entry)
+    }
+}
+
+/// `major.minor.patch` as numbers, for ordering.
+fn parse_version(version: &str) -> Option<(u16, u16, u16)> {
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(version)
+}
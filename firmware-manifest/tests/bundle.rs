@@ -0,0 +1,77 @@
+#![cfg(feature = "bundle")]
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use ed25519_dalek::{Signer, SigningKey};
+use firmware_manifest::bundle::{Bundle, Error};
+use firmware_manifest::{MANIFEST_FILE, SIGNATURE_FILE};
+use sha2::{Digest, Sha256};
+
+const FIRMWARE: &[u8] = b"\x00\x50\x00\x20\x01\x41\x00\x08firmware";
+
+fn key() -> SigningKey {
+    SigningKey::from_bytes(&[7; 32])
+}
+
+/// A bundle for 1209:2444 hardware 2.0.0, signed with [`key`].
+fn bundle(name: &str) -> PathBuf {
+    let manifest = serde_json::to_vec(&serde_json::json!({
+        "version": "1.4.2",
+        "firmware": "firmware.bin",
+        "sha256": hex::encode(Sha256::digest(FIRMWARE)),
+        "address": 0x0800_4000,
+        "size": FIRMWARE.len(),
+        "compatible": { "vid": 0x1209, "pid": 0x2444, "hardware": ["2.0.0"] },
+    }))
+    .unwrap();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    for (name, data) in [
+        (MANIFEST_FILE, manifest.clone()),
+        (SIGNATURE_FILE, key().sign(&manifest).to_vec()),
+        ("firmware.bin", FIRMWARE.to_vec()),
+    ] {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&data).unwrap();
+    }
+    zip.finish().unwrap();
+    path
+}
+
+#[test]
+fn open_checks_the_signature() {
+    let path = bundle("signed.bbfw");
+    let bundle = Bundle::open(&path, Some(&key().verifying_key())).unwrap();
+    assert_eq!(bundle.firmware, FIRMWARE);
+    assert_eq!(bundle.manifest.version, "1.4.2");
+
+    let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+    assert!(matches!(
+        Bundle::open(&path, Some(&other)),
+        Err(Error::Signature(_))
+    ));
+    Bundle::open(&path, None).unwrap();
+}
+
+#[test]
+fn compatibility() {
+    let bundle = Bundle::open(&bundle("compatible.bbfw"), None).unwrap();
+    let revisions = ["1.0.0".to_string(), "2.0.0".to_string()];
+    bundle
+        .check_compatible(0x1209, 0x2444, &revisions, Some(0x0801_0000))
+        .unwrap();
+    assert!(matches!(
+        bundle.check_compatible(0x0483, 0xDF11, &revisions, None),
+        Err(Error::WrongDevice { .. })
+    ));
+    assert!(matches!(
+        bundle.check_compatible(0x1209, 0x2444, &revisions[..1], None),
+        Err(Error::WrongHardware { .. })
+    ));
+    assert!(matches!(
+        bundle.check_compatible(0x1209, 0x2444, &revisions, Some(0x0800_4008)),
+        Err(Error::DoesNotFit { .. })
+    ));
+}
@@ -0,0 +1,177 @@
+use firmware_manifest::{Error, FORMAT, Index, IndexEntry, Manifest};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+const FIRMWARE: &[u8] = b"\x00\x50\x00\x20\x01\x41\x00\x08firmware";
+
+fn manifest() -> serde_json::Value {
+    json!({
+        "format": 1,
+        "version": "1.4.2",
+        "firmware": "firmware.bin",
+        "sha256": hex::encode(Sha256::digest(FIRMWARE)),
+        "address": 0x0800_4000,
+        "size": FIRMWARE.len(),
+        "compatible": { "vid": 0x1209, "pid": 0x2444, "hardware": ["2.0.0"] },
+    })
+}
+
+fn parse(value: &serde_json::Value) -> Result<Manifest, Error> {
+    Manifest::from_slice(&serde_json::to_vec(value).unwrap())
+}
+
+fn entry(file: &str, version: Option<&str>) -> IndexEntry {
+    IndexEntry {
+        file: file.into(),
+        size: 0,
+        sha256: "0".repeat(64),
+        version: version.map(Into::into),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let manifest = parse(&manifest()).unwrap();
+    assert_eq!(
+        Manifest::from_slice(&manifest.to_vec().unwrap()).unwrap(),
+        manifest
+    );
+    manifest.check_firmware(FIRMWARE).unwrap();
+}
+
+#[test]
+fn unknown_format_is_rejected() {
+    let mut value = manifest();
+    value["format"] = json!(FORMAT + 1);
+    assert!(matches!(parse(&value), Err(Error::UnsupportedFormat(2))));
+    value["format"] = json!(0);
+    assert!(matches!(parse(&value), Err(Error::UnsupportedFormat(0))));
+}
+
+#[test]
+fn missing_format_is_the_first() {
+    let mut value = manifest();
+    value.as_object_mut().unwrap().remove("format");
+    assert_eq!(parse(&value).unwrap().format, 1);
+}
+
+#[test]
+fn file_names_cannot_be_paths() {
+    for name in [
+        "../firmware.bin",
+        "/etc/passwd",
+        "sub\\firmware.bin",
+        "..",
+        "",
+    ] {
+        for field in ["firmware", "release_notes"] {
+            let mut value = manifest();
+            value[field] = json!(name);
+            assert!(
+                matches!(parse(&value), Err(Error::Invalid { .. })),
+                "{field} {name:?}"
+            );
+        }
+        let index = json!({
+            "generator": "dfu-packager",
+            "files": [{ "file": name, "size": 0, "sha256": "0".repeat(64) }],
+        });
+        assert!(matches!(
+            Index::from_slice(&serde_json::to_vec(&index).unwrap()),
+            Err(Error::Invalid { .. })
+        ));
+    }
+}
+
+#[test]
+fn size_mismatch_is_reported() {
+    let manifest = parse(&manifest()).unwrap();
+    let mut longer = FIRMWARE.to_vec();
+    longer.push(0);
+    assert!(matches!(
+        manifest.check_firmware(&longer),
+        Err(Error::SizeMismatch { expected, actual })
+            if expected == FIRMWARE.len() as u64 && actual == expected + 1
+    ));
+}
+
+#[test]
+fn hash_mismatch_is_reported() {
+    let manifest = parse(&manifest()).unwrap();
+    let mut corrupted = FIRMWARE.to_vec();
+    corrupted[8] ^= 0xFF;
+    assert!(matches!(
+        manifest.check_firmware(&corrupted),
+        Err(Error::HashMismatch { .. })
+    ));
+
+    // Without a size, only the hash is checked.
+    let mut value = manifest.clone();
+    value.size = None;
+    assert!(matches!(
+        value.check_firmware(&[]),
+        Err(Error::HashMismatch { .. })
+    ));
+}
+
+#[test]
+fn unknown_fields_are_kept() {
+    let mut value = manifest();
+    value["signed_by"] = json!({ "key": "release-2026" });
+    let manifest = parse(&value).unwrap();
+    assert_eq!(
+        manifest.extra["signed_by"],
+        json!({ "key": "release-2026" })
+    );
+    let written: serde_json::Value = serde_json::from_slice(&manifest.to_vec().unwrap()).unwrap();
+    assert_eq!(written, value);
+}
+
+#[test]
+fn newest_orders_versions_numerically() {
+    let index = Index {
+        generator: "dfu-packager".into(),
+        files: vec![
+            entry("brakebright-1.9.0.dfu", Some("1.9.0")),
+            entry("brakebright-1.10.0.dfu", Some("1.10.0")),
+            entry("brakebright-1.10.0.bbfw", Some("1.10.0")),
+            entry("notes.md", None),
+            entry("brakebright-2.0.0-rc1.dfu", Some("2.0.0-rc1")),
+        ],
+    };
+    let newest = index.newest(|entry| entry.file.ends_with(".dfu")).unwrap();
+    assert_eq!(newest.file, "brakebright-1.10.0.dfu");
+    assert_eq!(
+        index
+            .newest(|entry| entry.file.ends_with(".bbfw"))
+            .unwrap()
+            .file,
+        "brakebright-1.10.0.bbfw"
+    );
+    assert!(index.newest(|entry| entry.file.ends_with(".md")).is_none());
+}
+
+#[test]
+fn key_files() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]).verifying_key();
+    let path = dir.join("manifest-key.pub");
+    std::fs::write(&path, hex::encode(key.as_bytes()) + "\n").unwrap();
+    assert_eq!(firmware_manifest::read_public_key(&path).unwrap(), key);
+
+    for (name, text) in [
+        ("manifest-key.short", "abcd"),
+        ("manifest-key.text", "not hex"),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        assert!(matches!(
+            firmware_manifest::read_key(&path),
+            Err(Error::InvalidKey { .. })
+        ));
+    }
+    assert!(matches!(
+        firmware_manifest::read_key(&dir.join("manifest-key.missing")),
+        Err(Error::ReadKey { .. })
+    ));
+}
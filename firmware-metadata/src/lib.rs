@@ -0,0 +1,156 @@
+//! Firmware metadata block, placed anywhere in the image on a 4-byte
+//! boundary and found by its magic. `dfu-packager` patches it and writes
+//! its layout out for firmware builds with `gen-header`; `verify-file`
+//! checks it.
+//!
+//! | Offset | Size | Field                                                 |
+//! |--------|------|-------------------------------------------------------|
+//! | 0x00   | 4    | magic `BBFW`                                          |
+//! | 0x04   | 1    | layout version, currently 1                           |
+//! | 0x05   | 3    | firmware version: major, minor, patch                 |
+//! | 0x08   | 16   | build ID, ASCII, NUL-padded (e.g. a git hash)         |
+//! | 0x18   | 4    | image length covered by the CRC, little endian        |
+//! | 0x1C   | 4    | CRC32 of the image with this field zeroed, LE         |
+//!
+//! A length of 0 or 0xFFFFFFFF means the CRC has not been filled in yet.
+//!
+//! ```
+//! use firmware_metadata::Metadata;
+//!
+//! let mut image = vec![0xFF; 64];
+//! let mut metadata = Metadata::new(8);
+//! metadata.version = (1, 4, 2);
+//! metadata.write(&mut image)?;
+//! let found = Metadata::find(&image).unwrap()?;
+//! assert_eq!(found.offset, 8);
+//! assert_eq!(found.version_string(), "1.4.2");
+//! # Ok::<(), firmware_metadata::Error>(())
+//! ```
+
+pub const MAGIC: &[u8; 4] = b"BBFW";
+pub const LAYOUT_VERSION: u8 = 1;
+pub const LEN: usize = 32;
+// Offsets of the fields within the block.
+pub const OFFSET_LAYOUT_VERSION: usize = 0x04;
+pub const OFFSET_VERSION: usize = 0x05;
+pub const OFFSET_BUILD_ID: usize = 0x08;
+pub const BUILD_ID_LEN: usize = 16;
+pub const OFFSET_LENGTH: usize = 0x18;
+pub const OFFSET_CRC: usize = 0x1C;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("metadata block at {offset:#X} has unknown layout version {layout}")]
+    UnknownLayout { offset: usize, layout: u8 },
+    #[error("build ID `{0}` must be ASCII of at most {BUILD_ID_LEN} characters")]
+    BuildId(String),
+    #[error("metadata block at {0:#X} runs past the end of the image")]
+    OutOfRange(usize),
+}
+
+/// Decoded metadata block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Offset of the block in the image.
+    pub offset: usize,
+    pub version: (u8, u8, u8),
+    pub build_id: String,
+    pub length: u32,
+    pub crc: u32,
+}
+
+impl Metadata {
+    /// An empty block at `offset`: no version or build ID, CRC unset.
+    pub fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            version: (0, 0, 0),
+            build_id: String::new(),
+            length: 0,
+            crc: 0,
+        }
+    }
+
+    /// Offset of the first block magic on a word boundary of `image`.
+    pub fn position(image: &[u8]) -> Option<usize> {
+        (0..image.len().saturating_sub(LEN - 1))
+            .step_by(4)
+            .find(|&offset| &image[offset..offset + 4] == MAGIC)
+    }
+
+    /// Find and decode the metadata block in `image`. `None` if there is
+    /// none.
+    pub fn find(image: &[u8]) -> Option<Result<Self, Error>> {
+        let offset = Self::position(image)?;
+        Some(Self::decode(&image[offset..offset + LEN], offset))
+    }
+
+    /// Decode the `LEN` bytes of `block`, found at `offset` in the image.
+    pub fn decode(block: &[u8], offset: usize) -> Result<Self, Error> {
+        let block = block.get(..LEN).ok_or(Error::OutOfRange(offset))?;
+        let layout = block[OFFSET_LAYOUT_VERSION];
+        if layout != LAYOUT_VERSION {
+            return Err(Error::UnknownLayout { offset, layout });
+        }
+        let build_id = &block[OFFSET_BUILD_ID..OFFSET_BUILD_ID + BUILD_ID_LEN];
+        let end = build_id
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(BUILD_ID_LEN);
+        let version = &block[OFFSET_VERSION..OFFSET_VERSION + 3];
+        Ok(Self {
+            offset,
+            version: (version[0], version[1], version[2]),
+            build_id: String::from_utf8_lossy(&build_id[..end]).into_owned(),
+            length: u32::from_le_bytes(block[OFFSET_LENGTH..OFFSET_CRC].try_into().unwrap()),
+            crc: u32::from_le_bytes(block[OFFSET_CRC..LEN].try_into().unwrap()),
+        })
+    }
+
+    /// The block as it is stored in the image.
+    pub fn encode(&self) -> Result<[u8; LEN], Error> {
+        if !self.build_id.is_ascii() || self.build_id.len() > BUILD_ID_LEN {
+            return Err(Error::BuildId(self.build_id.clone()));
+        }
+        let mut block = [0; LEN];
+        block[..4].copy_from_slice(MAGIC);
+        block[OFFSET_LAYOUT_VERSION] = LAYOUT_VERSION;
+        let (major, minor, patch) = self.version;
+        block[OFFSET_VERSION..OFFSET_VERSION + 3].copy_from_slice(&[major, minor, patch]);
+        block[OFFSET_BUILD_ID..][..self.build_id.len()].copy_from_slice(self.build_id.as_bytes());
+        block[OFFSET_LENGTH..OFFSET_CRC].copy_from_slice(&self.length.to_le_bytes());
+        block[OFFSET_CRC..].copy_from_slice(&self.crc.to_le_bytes());
+        Ok(block)
+    }
+
+    /// Store the block at its offset in `image`.
+    pub fn write(&self, image: &mut [u8]) -> Result<(), Error> {
+        let block = self.encode()?;
+        image
+            .get_mut(self.offset..self.offset + LEN)
+            .ok_or(Error::OutOfRange(self.offset))?
+            .copy_from_slice(&block);
+        Ok(())
+    }
+
+    /// Whether the length and CRC fields have been filled in.
+    pub fn has_crc(&self) -> bool {
+        !matches!(self.length, 0 | u32::MAX)
+    }
+
+    /// CRC32 over the first `self.length` bytes of `image` with the CRC
+    /// field zeroed, or `None` if the image is shorter than that.
+    pub fn compute_crc(&self, image: &[u8]) -> Option<u32> {
+        let mut covered = image.get(..self.length as usize)?.to_vec();
+        let field = self.offset + OFFSET_CRC;
+        if let Some(crc) = covered.get_mut(field..field + 4) {
+            crc.fill(0);
+        }
+        Some(crc32fast::hash(&covered))
+    }
+
+    pub fn version_string(&self) -> String {
+        let (major, minor, patch) = self.version;
+        format!("{major}.{minor}.{patch}")
+    }
+}
@@ -0,0 +1,62 @@
+use firmware_metadata::{Error, LEN, Metadata, OFFSET_LAYOUT_VERSION};
+
+fn image_with(metadata: &Metadata) -> Vec<u8> {
+    let mut image = vec![0xFF; 256];
+    metadata.write(&mut image).unwrap();
+    image
+}
+
+#[test]
+fn round_trip() {
+    let metadata = Metadata {
+        offset: 0x40,
+        version: (1, 4, 2),
+        build_id: "3f2a9c1".into(),
+        length: 256,
+        crc: 0x1234_5678,
+    };
+    let image = image_with(&metadata);
+    assert_eq!(Metadata::find(&image).unwrap().unwrap(), metadata);
+}
+
+#[test]
+fn not_found_off_a_word_boundary() {
+    let mut image = vec![0; 256];
+    image[0x41..0x41 + LEN].copy_from_slice(&Metadata::new(0).encode().unwrap());
+    assert!(Metadata::find(&image).is_none());
+}
+
+#[test]
+fn unknown_layout_is_rejected() {
+    let mut image = image_with(&Metadata::new(0x20));
+    image[0x20 + OFFSET_LAYOUT_VERSION] = 2;
+    assert!(matches!(
+        Metadata::find(&image),
+        Some(Err(Error::UnknownLayout {
+            offset: 0x20,
+            layout: 2
+        }))
+    ));
+}
+
+#[test]
+fn long_build_id_is_rejected() {
+    let mut metadata = Metadata::new(0);
+    metadata.build_id = "0123456789abcdef0".into();
+    assert!(matches!(metadata.encode(), Err(Error::BuildId(_))));
+}
+
+#[test]
+fn crc_ignores_its_own_field() {
+    let mut metadata = Metadata::new(0x20);
+    metadata.length = 256;
+    let image = image_with(&metadata);
+    let crc = metadata.compute_crc(&image).unwrap();
+    metadata.crc = crc;
+    let image = image_with(&metadata);
+    assert_eq!(metadata.compute_crc(&image), Some(crc));
+    assert!(metadata.has_crc());
+
+    metadata.length = 257;
+    assert_eq!(metadata.compute_crc(&image), None);
+}
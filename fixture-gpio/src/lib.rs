@@ -0,0 +1,324 @@
+//! Bench fixtures that put the unit under test into DFU mode themselves, so
+//! that production needs no button press: a USB GPIO adapter on the
+//! fixture holds the unit's BOOT pin while pulsing its reset line, and the
+//! bootloader comes up as if the boot button had been held.
+//!
+//! Two adapters are supported, both driven through libusb without vendor
+//! drivers: FTDI chips in asynchronous bit-bang mode (pins D0–D7 of
+//! interface A) and the Silicon Labs CP2112 (GPIO.0–GPIO.7). Only the two
+//! configured pins are made outputs.
+//!
+//! ```no_run
+//! use fixture_gpio::{Adapter, Config, Fixture};
+//!
+//! let config = Config {
+//!     adapter: Adapter::Ftdi,
+//!     serial: None,
+//!     boot: "0".parse()?,
+//!     reset: "1:low".parse()?,
+//! };
+//! Fixture::open(&config)?.enter_dfu()?;
+//! # Ok::<(), fixture_gpio::Error>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use rusb::{Context, DeviceHandle, UsbContext};
+
+/// Variables [`Config::from_env`] reads; the CLI's `--fixture*` options
+/// take their defaults from the same ones.
+pub const ADAPTER_ENV: &str = "BIKESAFE_FIXTURE";
+pub const SERIAL_ENV: &str = "BIKESAFE_FIXTURE_SERIAL";
+pub const BOOT_ENV: &str = "BIKESAFE_FIXTURE_BOOT";
+pub const RESET_ENV: &str = "BIKESAFE_FIXTURE_RESET";
+
+/// Pins used when none are configured.
+pub const DEFAULT_BOOT: &str = "0";
+pub const DEFAULT_RESET: &str = "1:low";
+
+/// How long reset is held, and how long BOOT stays asserted after reset
+/// is released, for the bootloader to sample it.
+const RESET_PULSE: Duration = Duration::from_millis(50);
+const BOOT_HOLD: Duration = Duration::from_millis(200);
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no {0} fixture adapter found")]
+    NotFound(Adapter),
+    #[error("unknown fixture adapter `{0}` (expected `ftdi` or `cp2112`)")]
+    UnknownAdapter(String),
+    #[error("invalid fixture pin `{0}` (expected 0-7, optionally with `:high` or `:low`)")]
+    InvalidPin(String),
+    #[error("BOOT and reset are both on pin {0}")]
+    SamePin(u8),
+    #[error("could not drive the fixture adapter")]
+    Usb(#[from] rusb::Error),
+}
+
+/// The USB GPIO adapter of the fixture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Adapter {
+    /// FT232R, FT2232, FT4232, FT232H or FT-X in bit-bang mode.
+    Ftdi,
+    /// Silicon Labs CP2112.
+    Cp2112,
+}
+
+impl Adapter {
+    /// VID:PIDs the adapter enumerates with by default.
+    fn ids(self) -> &'static [(u16, u16)] {
+        match self {
+            Adapter::Ftdi => &[
+                (0x0403, 0x6001),
+                (0x0403, 0x6010),
+                (0x0403, 0x6011),
+                (0x0403, 0x6014),
+                (0x0403, 0x6015),
+            ],
+            Adapter::Cp2112 => &[(0x10C4, 0xEA90)],
+        }
+    }
+}
+
+impl fmt::Display for Adapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Adapter::Ftdi => "ftdi",
+            Adapter::Cp2112 => "cp2112",
+        })
+    }
+}
+
+impl FromStr for Adapter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "ftdi" => Ok(Adapter::Ftdi),
+            "cp2112" => Ok(Adapter::Cp2112),
+            _ => Err(Error::UnknownAdapter(s.into())),
+        }
+    }
+}
+
+/// One adapter pin and the level that asserts it, written `N`, `N:high` or
+/// `N:low`; a bare `N` is active high.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub number: u8,
+    pub active_high: bool,
+}
+
+impl Pin {
+    fn mask(self) -> u8 {
+        1 << self.number
+    }
+}
+
+impl FromStr for Pin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidPin(s.into());
+        let (number, level) = s.split_once(':').unwrap_or((s, "high"));
+        let number = number.parse().ok().filter(|&n| n < 8).ok_or_else(invalid)?;
+        let active_high = match level {
+            "high" => true,
+            "low" => false,
+            _ => return Err(invalid()),
+        };
+        Ok(Pin {
+            number,
+            active_high,
+        })
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.active_high { "high" } else { "low" };
+        write!(f, "{}:{level}", self.number)
+    }
+}
+
+/// Which adapter to use and how the unit is wired to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub adapter: Adapter,
+    /// USB serial number of the adapter, to pick one of several.
+    pub serial: Option<String>,
+    pub boot: Pin,
+    pub reset: Pin,
+}
+
+impl Config {
+    /// The fixture configured in [`ADAPTER_ENV`] and the other variables,
+    /// with [`DEFAULT_BOOT`] and [`DEFAULT_RESET`] for unset pins. `None`
+    /// if no adapter is set.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let Some(adapter) = var(ADAPTER_ENV) else {
+            return Ok(None);
+        };
+        Ok(Some(Config {
+            adapter: adapter.parse()?,
+            serial: var(SERIAL_ENV),
+            boot: var(BOOT_ENV).as_deref().unwrap_or(DEFAULT_BOOT).parse()?,
+            reset: var(RESET_ENV).as_deref().unwrap_or(DEFAULT_RESET).parse()?,
+        }))
+    }
+}
+
+/// An opened fixture adapter, with both pins released.
+pub struct Fixture {
+    handle: DeviceHandle<Context>,
+    adapter: Adapter,
+    boot: Pin,
+    reset: Pin,
+}
+
+impl Fixture {
+    /// Open the configured adapter, make the BOOT and reset pins outputs and
+    /// release both.
+    pub fn open(config: &Config) -> Result<Self, Error> {
+        if config.boot.number == config.reset.number {
+            return Err(Error::SamePin(config.boot.number));
+        }
+        let device = Context::new()?
+            .devices()?
+            .iter()
+            .find(|device| {
+                device.device_descriptor().is_ok_and(|desc| {
+                    config
+                        .adapter
+                        .ids()
+                        .contains(&(desc.vendor_id(), desc.product_id()))
+                        && config.serial.as_ref().is_none_or(|serial| {
+                            device
+                                .open()
+                                .and_then(|handle| handle.read_serial_number_string_ascii(&desc))
+                                .is_ok_and(|found| &found == serial)
+                        })
+                })
+            })
+            .ok_or(Error::NotFound(config.adapter))?;
+        let handle = device.open()?;
+        match handle.set_auto_detach_kernel_driver(true) {
+            Ok(()) | Err(rusb::Error::NotSupported) => (),
+            Err(e) => return Err(e.into()),
+        }
+        handle.claim_interface(0)?;
+        let fixture = Fixture {
+            handle,
+            adapter: config.adapter,
+            boot: config.boot,
+            reset: config.reset,
+        };
+        let outputs = fixture.boot.mask() | fixture.reset.mask();
+        match fixture.adapter {
+            Adapter::Ftdi => {
+                fixture.ftdi_request(FTDI_RESET, 0)?;
+                fixture.ftdi_request(FTDI_SET_BITMODE, FTDI_BITBANG << 8 | outputs as u16)?;
+                fixture.write(false, false)?;
+            }
+            Adapter::Cp2112 => {
+                // Set the latch first, so that the pins come up released.
+                fixture.write(false, false)?;
+                // Direction and push-pull; no special functions.
+                fixture.cp2112_report(&[CP2112_GPIO_CONFIG, outputs, outputs, 0, 0])?;
+            }
+        }
+        Ok(fixture)
+    }
+
+    /// Restart the unit into its bootloader: assert BOOT, pulse reset, and
+    /// release BOOT once the bootloader has sampled it. The bootloader then
+    /// enumerates as usual.
+    pub fn enter_dfu(&self) -> Result<(), Error> {
+        self.write(true, true)?;
+        thread::sleep(RESET_PULSE);
+        self.write(true, false)?;
+        thread::sleep(BOOT_HOLD);
+        self.write(false, false)
+    }
+
+    /// Pulse reset with BOOT released, starting the unit's application.
+    pub fn reset(&self) -> Result<(), Error> {
+        self.write(false, true)?;
+        thread::sleep(RESET_PULSE);
+        self.write(false, false)
+    }
+
+    /// Drive the pins to their asserted or released levels.
+    fn write(&self, boot: bool, reset: bool) -> Result<(), Error> {
+        let level = |pin: Pin, asserted: bool| {
+            if asserted == pin.active_high {
+                pin.mask()
+            } else {
+                0
+            }
+        };
+        let values = level(self.boot, boot) | level(self.reset, reset);
+        match self.adapter {
+            Adapter::Ftdi => {
+                self.handle.write_bulk(FTDI_ENDPOINT, &[values], TIMEOUT)?;
+            }
+            Adapter::Cp2112 => {
+                let mask = self.boot.mask() | self.reset.mask();
+                self.cp2112_report(&[CP2112_GPIO_SET, values, mask])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ftdi_request(&self, request: u8, value: u16) -> Result<(), Error> {
+        self.handle.write_control(
+            rusb::request_type(
+                rusb::Direction::Out,
+                rusb::RequestType::Vendor,
+                rusb::Recipient::Device,
+            ),
+            request,
+            value,
+            FTDI_INTERFACE_A,
+            &[],
+            TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    fn cp2112_report(&self, report: &[u8]) -> Result<(), Error> {
+        self.handle.write_control(
+            rusb::request_type(
+                rusb::Direction::Out,
+                rusb::RequestType::Class,
+                rusb::Recipient::Interface,
+            ),
+            HID_SET_REPORT,
+            FEATURE_REPORT | report[0] as u16,
+            0,
+            report,
+            TIMEOUT,
+        )?;
+        Ok(())
+    }
+}
+
+// FTDI vendor requests (as in libftdi) on interface A, whose bulk OUT
+// endpoint sets the pins in bit-bang mode.
+const FTDI_RESET: u8 = 0x00;
+const FTDI_SET_BITMODE: u8 = 0x0B;
+const FTDI_BITBANG: u16 = 0x01;
+const FTDI_INTERFACE_A: u16 = 1;
+const FTDI_ENDPOINT: u8 = 0x02;
+
+// CP2112 feature reports (AN495).
+const HID_SET_REPORT: u8 = 0x09;
+const FEATURE_REPORT: u16 = 0x0300;
+const CP2112_GPIO_CONFIG: u8 = 0x02;
+const CP2112_GPIO_SET: u8 = 0x04;
@@ -0,0 +1,102 @@
+# User-facing text of bikesafe-util (gui-*), bikesafe-web (web-*, and the
+# gui-* messages it shares) and bikesafe-cli (cli-*, doctor-*); hint-*
+# messages are shared by the GUI and the CLI. This catalog is the
+# reference: every message must be here, translations may leave some out.
+
+## Remedies for device errors
+
+hint-permission-denied = Permission denied opening the device. On Windows, install the WinUSB driver with Zadig; on Linux, install the udev rule (`sudo bikesafe-cli udev-rule --install`).
+hint-busy = The device is in use by another updater. Close it and try again.
+hint-reconnect = The device was disconnected. Plug it in again in DFU mode and retry.
+
+## GUI
+
+gui-title = BrakeBright Firmware Update Util
+gui-select-firmware = Select a firmware file to update your BrakeBright device.
+gui-firmware-path = Firmware Path:
+gui-crashed = BrakeBright Util closed unexpectedly last time.
+gui-include-crash = Include the crash report in the diagnostic bundle
+gui-discard-crash = Discard report
+gui-save-diagnostics = Save diagnostic bundle…
+gui-app-version = Device running firmware { $version }
+gui-app-running = Device running its application
+gui-battery = battery { $battery }
+gui-switch-to-dfu = Switch to DFU mode
+gui-restart-failed = could not restart the device
+gui-run-self-test = Run self-test
+gui-self-test-running = Running self-test...
+gui-self-test-result = Self-test { $result }
+gui-self-test-error = Self-test could not run: { $error }
+gui-open-file = Open file…
+gui-check-updates = Check for updates
+gui-checking-updates = Checking for updates…
+gui-update-downloaded = Downloaded firmware { $version } from the update feed
+gui-feed-empty = The update feed lists no .dfu firmware
+gui-feed-needs-key = BIKESAFE_FEED needs BIKESAFE_PUBLIC_KEY, the key its index is signed with
+gui-invalid-file-type = Invalid file type. Please select a .bin, .dfu or .bbfw file.
+gui-bundle-needs-key = Release bundles need BIKESAFE_PUBLIC_KEY, the key they are signed with
+gui-invalid-firmware = Invalid firmware file: { $reason }
+gui-share-telemetry = Send an anonymous report of how the update went
+gui-update = Update Firmware
+gui-updating = Updating firmware...
+gui-phase-erase = Erasing flash…
+gui-phase-download = Writing firmware…
+gui-phase-upload = Reading memory…
+gui-phase-verify = Verifying…
+gui-connect-dfu = Please make sure the USB is connected and the device is in DFU mode. (LED blinking constantly)
+gui-fixture-restart = Restart into DFU mode
+gui-flash-complete = Flash complete! Please test the device function by tilting it.
+gui-cancel = Cancel
+gui-update-cancelled = Update cancelled. The device stays in DFU mode; update it again before using it.
+gui-select-valid-firmware = Please select a valid firmware file.
+gui-no-firmware = No firmware file selected.
+
+## Web updater
+
+web-no-webusb = This browser cannot reach USB devices. Open the page in Chrome, Edge or another Chromium-based browser, or use the desktop updater.
+web-connect = Connect the device in DFU mode (LED blinking constantly), then choose it in the list the browser shows.
+web-permission-denied = The browser could not open the device. Close other programs using it and try again; on Linux, the device also needs the udev rule of the desktop tools.
+
+## CLI
+
+cli-error = Error: { $error }
+cli-hint = Hint: { $hint }
+cli-cancelling = Cancelling; press Ctrl-C again to quit at once
+
+## bikesafe-cli doctor
+
+doctor-device = { $device } (bus { $bus }, address { $address })
+doctor-libusb-ok = libusb { $version } is available
+doctor-libusb-failed = libusb could not be initialised: { $error }
+doctor-dfu-found = DFU device { $device } found
+doctor-running-app = { $device } is running its application, not the bootloader
+doctor-other-dfu = no DFU device { $device }, but found { $found }
+doctor-no-dfu = no DFU device { $device } found
+doctor-can-open = device can be opened
+doctor-cannot-open = device cannot be opened: { $error }
+doctor-kernel-driver = a kernel driver is bound to interface { $intf }
+doctor-can-claim = interface { $intf } can be claimed
+doctor-interface-busy = interface { $intf } is in use by another program
+doctor-cannot-claim = interface { $intf } cannot be claimed: { $error }
+doctor-udev-found = udev rule for { $device } in { $path }
+doctor-udev-missing = no udev rule for { $device }; only root can access the device
+doctor-passed = All checks passed
+doctor-failed =
+    { $count ->
+        [one] 1 check failed
+       *[other] { $count } checks failed
+    }
+
+doctor-fix-select-device = select it with --device VID:PID
+doctor-fix-zadig = install the WinUSB driver for the bootloader with Zadig (see README)
+doctor-fix-replug = unplug and replug the device, then try again
+doctor-fix-kernel-driver = unbind it, or check that no other DFU tool (dfu-util, STM32CubeProgrammer) is running
+doctor-fix-close-tools = close other DFU tools (dfu-util, STM32CubeProgrammer, another bikesafe instance)
+doctor-fix-intf = check --intf against the interfaces listed by `bikesafe-cli info`
+doctor-fix-udev =
+    run: sudo bikesafe-cli udev-rule --install --device { $device }
+    or add this line to { $path } and reload udev:
+    { $rule }
+doctor-fix-libusb-linux = install libusb 1.0, e.g. `sudo apt install libusb-1.0-0`
+doctor-fix-libusb = reinstall bikesafe-util; libusb ships with it
+doctor-fix-enter-dfu = hold the boot button while plugging in the USB cable (see README), and make sure the cable carries data
@@ -0,0 +1,132 @@
+//! User-facing text of the GUI and the CLI: messages, hints and status
+//! lines, kept in one [Fluent](https://projectfluent.org) catalog per
+//! language under `locales/<language>/bikesafe.ftl` instead of as English
+//! literals in each binary, so that both front-ends say the same thing and
+//! can be translated together.
+//!
+//! Messages are looked up by ID, with named arguments:
+//!
+//! ```
+//! use localization::tr;
+//!
+//! let title = tr!("gui-title");
+//! let failed = tr!("doctor-failed", count = 2);
+//! # assert_eq!(failed, "2 checks failed");
+//! ```
+//!
+//! The language is [`LANGUAGE_ENV`] if set, and else the system's. A
+//! message missing from its catalog (or a language without one) falls back
+//! to US English, whose catalog is the reference every ID must be in.
+//! Adding a language means adding its catalog to [`CATALOGS`].
+
+use std::sync::OnceLock;
+
+use bikesafe_core::BikesafeError;
+use fluent_bundle::FluentResource;
+use fluent_bundle::concurrent::FluentBundle;
+use unic_langid::LanguageIdentifier;
+
+pub use fluent_bundle::FluentArgs;
+
+/// Variable selecting the language, e.g. `de` or `de-AT`, over the
+/// system's.
+pub const LANGUAGE_ENV: &str = "BIKESAFE_LANG";
+
+/// Catalogs compiled in, by language tag. The first is the fallback.
+pub const CATALOGS: &[(&str, &str)] = &[("en-US", include_str!("../locales/en-US/bikesafe.ftl"))];
+
+static BUNDLES: OnceLock<Vec<FluentBundle<FluentResource>>> = OnceLock::new();
+
+/// Message `id` in the current language; `tr!(id, name = value, ...)`
+/// passes arguments, which may be strings or numbers.
+#[macro_export]
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::message($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = $crate::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::message($id, Some(&args))
+    }};
+}
+
+/// Format message `id` with `args`. Unknown IDs come back as they are, so
+/// a missing message shows up without taking the program down.
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String {
+    for bundle in BUNDLES.get_or_init(|| load(&requested())) {
+        if let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) {
+            let mut errors = Vec::new();
+            return bundle
+                .format_pattern(pattern, args, &mut errors)
+                .into_owned();
+        }
+    }
+    id.to_owned()
+}
+
+/// Show messages in `language` rather than the default. Only takes effect
+/// before the first message is formatted.
+pub fn set_language(language: &str) {
+    let _ = BUNDLES.set(load(language));
+}
+
+/// What the user can do about `error`, where there is an obvious remedy.
+/// The GUI shows it in place of the error, the CLI after it.
+pub fn hint(error: &BikesafeError) -> Option<String> {
+    Some(match error {
+        BikesafeError::PermissionDenied => tr!("hint-permission-denied"),
+        #[cfg(feature = "libusb")]
+        BikesafeError::Busy(_) => tr!("hint-busy"),
+        BikesafeError::DeviceNotFound { .. } | BikesafeError::Disconnected => {
+            tr!("hint-reconnect")
+        }
+        _ => return None,
+    })
+}
+
+fn requested() -> String {
+    std::env::var(LANGUAGE_ENV)
+        .ok()
+        .filter(|language| !language.is_empty())
+        .or_else(sys_locale::get_locale)
+        .unwrap_or_default()
+}
+
+/// The bundle of the catalog best matching `requested` (a BCP 47 tag or a
+/// POSIX locale such as `de_DE.UTF-8`), then the fallback's.
+fn load(requested: &str) -> Vec<FluentBundle<FluentResource>> {
+    let tag = requested.split('.').next().unwrap_or_default();
+    let best = tag
+        .replace('_', "-")
+        .parse::<LanguageIdentifier>()
+        .ok()
+        .and_then(|requested| {
+            let language = |&(tag, _): &(&str, &str)| tag.parse::<LanguageIdentifier>().ok();
+            CATALOGS
+                .iter()
+                .position(|catalog| language(catalog) == Some(requested.clone()))
+                .or_else(|| {
+                    CATALOGS.iter().position(|catalog| {
+                        language(catalog).is_some_and(|l| l.language == requested.language)
+                    })
+                })
+        });
+    let mut order = Vec::new();
+    order.extend(best.filter(|&i| i != 0));
+    order.push(0);
+    order.into_iter().map(|i| bundle(CATALOGS[i])).collect()
+}
+
+fn bundle((tag, source): (&str, &str)) -> FluentBundle<FluentResource> {
+    let language: LanguageIdentifier = tag.parse().expect("catalog tags are valid");
+    let resource = FluentResource::try_new(source.to_owned())
+        .unwrap_or_else(|(_, errors)| panic!("catalog {tag} does not parse: {errors:?}"));
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // No bidi isolation marks: terminals print them as stray characters.
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .unwrap_or_else(|errors| panic!("catalog {tag} has duplicate messages: {errors:?}"));
+    bundle
+}
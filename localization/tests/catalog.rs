@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+
+use localization::tr;
+
+/// IDs of every `tr!` in the `.rs` files under `dir`.
+fn used_ids(dir: &Path, ids: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            used_ids(&path, ids);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let source = fs::read_to_string(&path).unwrap();
+            for (i, _) in source.match_indices("tr!(\"") {
+                // Not the end of another macro, such as `include_str!`.
+                if source[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                    continue;
+                }
+                let rest = &source[i + 5..];
+                ids.push(rest[..rest.find('"').unwrap()].to_owned());
+            }
+        }
+    }
+}
+
+#[test]
+fn front_ends_only_use_known_messages() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let mut ids = Vec::new();
+    for crate_dir in [
+        "bikesafe-cli",
+        "bikesafe-util",
+        "bikesafe-web",
+        "localization",
+    ] {
+        used_ids(&root.join(crate_dir).join("src"), &mut ids);
+    }
+    assert!(!ids.is_empty());
+    localization::set_language("en-US");
+    let missing: Vec<_> = ids
+        .iter()
+        .filter(|id| localization::message(id, None) == **id)
+        .collect();
+    assert!(missing.is_empty(), "not in the catalog: {missing:?}");
+}
+
+#[test]
+fn formats_arguments_and_plurals() {
+    localization::set_language("en-US");
+    assert_eq!(tr!("doctor-failed", count = 1), "1 check failed");
+    assert_eq!(tr!("doctor-failed", count = 3), "3 checks failed");
+    assert_eq!(
+        tr!("gui-app-version", version = "1.4.0"),
+        "Device running firmware 1.4.0"
+    );
+    assert_eq!(tr!("no-such-message"), "no-such-message");
+}
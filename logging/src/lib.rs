@@ -0,0 +1,78 @@
+//! Logging to stderr through `tracing`, set up the same way by every tool:
+//! text or one JSON object per line (`--log-format`, or
+//! [`LOG_FORMAT_ENV`]), with `log` records from the DFU crates forwarded.
+//! A closing span logs how long its phase took (`time.busy`); JSON logs are
+//! read by tools, so they always carry it.
+//!
+//! ```no_run
+//! logging::init(false, logging::LogFormat::Text, true).unwrap();
+//! tracing::info!("ready");
+//! ```
+
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::TryInitError;
+
+/// Environment variable choosing the log format, as `--log-format` does.
+pub const LOG_FORMAT_ENV: &str = "BIKESAFE_LOG_FORMAT";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    /// The format [`LOG_FORMAT_ENV`] asks for, text unless it is `json`.
+    pub fn from_env() -> Self {
+        match std::env::var(LOG_FORMAT_ENV) {
+            Ok(format) if format == "json" => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+}
+
+/// Log info and higher to stderr, or everything if `verbose`. Span times
+/// are logged when `verbose` or in JSON. `color` allows escape codes.
+pub fn init(verbose: bool, format: LogFormat, color: bool) -> Result<(), TryInitError> {
+    let level = if verbose {
+        LevelFilter::TRACE
+    } else {
+        LevelFilter::INFO
+    };
+    let timings = verbose || format == LogFormat::Json;
+    tracing_subscriber::registry()
+        .with(stderr(format, timings, color).with_filter(level))
+        .try_init()
+}
+
+/// The stderr layer of [`init`], for callers that add layers or filters of
+/// their own. `timings` logs closing spans with how long they took.
+pub fn stderr<S>(
+    format: LogFormat,
+    timings: bool,
+    color: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_span_events(if timings {
+            FmtSpan::CLOSE
+        } else {
+            FmtSpan::NONE
+        })
+        .with_ansi(color)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
@@ -0,0 +1,161 @@
+//! Opt-in reports on how firmware updates go in the field, so that
+//! maintainers can tell driver problems from USB errors and bad files.
+//!
+//! Nothing is sent unless the user configured an endpoint (`--telemetry` or
+//! `BIKESAFE_TELEMETRY`), and each report is one anonymous JSON object per
+//! update, POSTed there:
+//!
+//! ```json
+//! {"tool": "bikesafe-cli", "tool_version": "2.8.0", "os": "linux", "arch": "x86_64",
+//!  "firmware_version": "1.4.0", "outcome": "usb_error", "duration_ms": 5120}
+//! ```
+//!
+//! There are no serial numbers, USB addresses, paths, error messages or
+//! client IDs: reports cannot be linked to each other or to a device.
+
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use bikesafe_core::BikesafeError;
+use serde::Serialize;
+
+/// Environment variable holding the endpoint.
+pub const ENDPOINT_ENV: &str = "BIKESAFE_TELEMETRY";
+
+/// Reports are best-effort; an unreachable endpoint must not hold up the
+/// tool for long.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+#[error("could not send telemetry to {url}")]
+pub struct Error {
+    url: String,
+    #[source]
+    source: Box<ureq::Error>,
+}
+
+/// How an update ended, coarse enough to say nothing about the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    /// No device was found.
+    DeviceNotFound,
+    /// The device was there but could not be opened: missing driver or
+    /// permissions.
+    DriverError,
+    /// The device went away mid-update.
+    Disconnected,
+    /// Any other USB failure: stalls, timeouts, I/O errors.
+    UsbError,
+    /// The firmware file or bundle was rejected before touching the device.
+    ValidationError,
+    /// The firmware read back differs from the file.
+    VerifyError,
+    /// The device did not come back, or reported the wrong version.
+    Other,
+}
+
+impl Outcome {
+    /// Class of a failure from the first [`BikesafeError`] or USB error
+    /// among `error` and its sources; [`Outcome::Other`] without one.
+    pub fn of(error: &(dyn StdError + 'static)) -> Self {
+        let mut next = Some(error);
+        while let Some(error) = next {
+            if let Some(error) = error.downcast_ref::<BikesafeError>() {
+                return match error {
+                    BikesafeError::DeviceNotFound { .. } => Outcome::DeviceNotFound,
+                    BikesafeError::PermissionDenied => Outcome::DriverError,
+                    BikesafeError::Disconnected => Outcome::Disconnected,
+                    BikesafeError::ReadFirmware { .. } | BikesafeError::ValidationFailed(_) => {
+                        Outcome::ValidationError
+                    }
+                    BikesafeError::Dfu(_)
+                    | BikesafeError::Protocol(_)
+                    | BikesafeError::UsbIo(_)
+                    | BikesafeError::Backend(_) => Outcome::UsbError,
+                    BikesafeError::VerifyFailed { .. } => Outcome::VerifyError,
+                    _ => Outcome::Other,
+                };
+            }
+            if let Some(usb) = error.downcast_ref::<rusb::Error>() {
+                return match usb {
+                    rusb::Error::Access | rusb::Error::NotSupported | rusb::Error::NotFound => {
+                        Outcome::DriverError
+                    }
+                    rusb::Error::NoDevice => Outcome::Disconnected,
+                    _ => Outcome::UsbError,
+                };
+            }
+            next = error.source();
+        }
+        Outcome::Other
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    tool: &'a str,
+    tool_version: &'a str,
+    os: &'static str,
+    arch: &'static str,
+    firmware_version: Option<&'a str>,
+    outcome: Outcome,
+    duration_ms: u64,
+}
+
+/// Where and as which tool to report.
+#[derive(Clone, Debug)]
+pub struct Telemetry {
+    endpoint: String,
+    tool: &'static str,
+    tool_version: &'static str,
+}
+
+impl Telemetry {
+    /// Report to `endpoint` as `tool` (e.g. `bikesafe-cli`) at
+    /// `tool_version`.
+    pub fn new(endpoint: String, tool: &'static str, tool_version: &'static str) -> Self {
+        Telemetry {
+            endpoint,
+            tool,
+            tool_version,
+        }
+    }
+
+    /// Report to the endpoint in [`ENDPOINT_ENV`], if set.
+    pub fn from_env(tool: &'static str, tool_version: &'static str) -> Option<Self> {
+        let endpoint = std::env::var(ENDPOINT_ENV).ok()?;
+        (!endpoint.is_empty()).then(|| Self::new(endpoint, tool, tool_version))
+    }
+
+    /// Send the outcome of one update that took `duration`, installing
+    /// `firmware_version` if known.
+    pub fn report(
+        &self,
+        firmware_version: Option<&str>,
+        outcome: Outcome,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let event = Event {
+            tool: self.tool,
+            tool_version: self.tool_version,
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            firmware_version,
+            outcome,
+            duration_ms: duration.as_millis() as u64,
+        };
+        ureq::AgentBuilder::new()
+            .timeout(TIMEOUT)
+            .user_agent(&format!("{}/{}", self.tool, self.tool_version))
+            .build()
+            .post(&self.endpoint)
+            .send_json(&event)
+            .map_err(|source| Error {
+                url: self.endpoint.clone(),
+                source: Box::new(source),
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! HTTP client for the update server: fetches channel indexes and firmware
+//! files for `bikesafe-cli fetch` and the GUI's "Check for updates".
+//!
+//! - Responses are cached in [`cache_dir`] and revalidated with `If-None-Match`
+//!   / `If-Modified-Since`, so an unchanged index costs one `304 Not Modified`.
+//! - Downloads go to `<file>.part` first and continue from where an interrupted
+//!   one stopped, with a `Range` request.
+//! - Index signatures and file hashes are checked before anything is handed
+//!   out.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), update_client::Error> {
+//! let client = update_client::Client::new("my-tool/1.0");
+//! let url = "https://updates.example.com/stable/index.json";
+//! let index = client.index(url, None)?;
+//! if let Some(entry) = index.newest(|entry| entry.file.ends_with(".bbfw")) {
+//!     client.download_entry(url, entry, "firmware".as_ref())?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use firmware_manifest::{Index, IndexEntry};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request to {url} failed")]
+    Http {
+        url: String,
+        #[source]
+        source: Box<ureq::Error>,
+    },
+    #[error("could not read the response from {url}")]
+    Read {
+        url: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not write `{}`", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Manifest(#[from] firmware_manifest::Error),
+    #[error("the signature of {url} is not valid for this key")]
+    Signature { url: String },
+    #[error("{file} is {actual} bytes, the index says {expected}")]
+    SizeMismatch {
+        file: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("{file} has SHA-256 {actual}, the index says {expected}")]
+    HashMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Validators of a cached response.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// How long to wait for the connection to the server.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a response may stall before the request fails. Downloads that
+/// fail this way continue where they stopped on the next try.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Client {
+    agent: ureq::Agent,
+    cache_dir: PathBuf,
+}
+
+impl Client {
+    /// A client sending `user_agent`, caching in [`cache_dir`]. A server
+    /// that does not answer within [`CONNECT_TIMEOUT`], or stalls a
+    /// response for [`READ_TIMEOUT`], fails the request instead of hanging
+    /// the caller.
+    pub fn new(user_agent: &str) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .user_agent(user_agent)
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout_read(READ_TIMEOUT)
+                .build(),
+            cache_dir: cache_dir(),
+        }
+    }
+
+    /// Cache responses in `dir` instead.
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = dir.into();
+        self
+    }
+
+    /// The body of `url`, from the cache if the server says it has not
+    /// changed.
+    pub fn get(&self, url: &str) -> Result<Vec<u8>, Error> {
+        let key = hex::encode(Sha256::digest(url.as_bytes()));
+        let body_path = self.cache_dir.join(&key);
+        let meta_path = self.cache_dir.join(key + ".json");
+        let cached = std::fs::read(&meta_path)
+            .ok()
+            .and_then(|meta| serde_json::from_slice::<CacheEntry>(&meta).ok())
+            .filter(|meta| meta.url == url && body_path.exists());
+
+        let mut request = self.agent.get(url);
+        if let Some(meta) = &cached {
+            if let Some(etag) = &meta.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+        let response = request.call().map_err(|e| http(url, e))?;
+        if response.status() == 304
+            && let Ok(body) = std::fs::read(&body_path)
+        {
+            return Ok(body);
+        }
+
+        let meta = CacheEntry {
+            url: url.to_string(),
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+        };
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .map_err(|source| Error::Read {
+                url: url.to_string(),
+                source,
+            })?;
+        if meta.etag.is_some() || meta.last_modified.is_some() {
+            // The cache only saves transfers; failing to fill it is harmless.
+            let _ = std::fs::create_dir_all(&self.cache_dir)
+                .and_then(|()| std::fs::write(&body_path, &body))
+                .and_then(|()| std::fs::write(&meta_path, serde_json::to_vec(&meta)?));
+        }
+        Ok(body)
+    }
+
+    /// The index at `url`, its signature (`<url>.sig`) checked against
+    /// `key` unless that is `None`.
+    pub fn index(&self, url: &str, key: Option<&VerifyingKey>) -> Result<Index, Error> {
+        let bytes = self.get(url)?;
+        if let Some(key) = key {
+            let sig_url = format!("{url}.sig");
+            let signature = Signature::from_slice(&self.get(&sig_url)?)
+                .map_err(|_| Error::Signature { url: url.into() })?;
+            key.verify_strict(&bytes, &signature)
+                .map_err(|_| Error::Signature { url: url.into() })?;
+        }
+        Ok(Index::from_slice(&bytes)?)
+    }
+
+    /// Download `entry` of the index at `index_url` into `dir`, checking
+    /// its size and hash, and return its path.
+    pub fn download_entry(
+        &self,
+        index_url: &str,
+        entry: &IndexEntry,
+        dir: &Path,
+    ) -> Result<PathBuf, Error> {
+        let path = dir.join(&entry.file);
+        self.download(&resolve(index_url, &entry.file), &path, |data| {
+            if data.len() as u64 != entry.size {
+                return Err(Error::SizeMismatch {
+                    file: entry.file.clone(),
+                    expected: entry.size,
+                    actual: data.len() as u64,
+                });
+            }
+            let hash = hex::encode(Sha256::digest(data));
+            if !hash.eq_ignore_ascii_case(&entry.sha256) {
+                return Err(Error::HashMismatch {
+                    file: entry.file.clone(),
+                    expected: entry.sha256.clone(),
+                    actual: hash,
+                });
+            }
+            Ok(())
+        })?;
+        Ok(path)
+    }
+
+    /// Download `url` to `path` through `<path>.part`, continuing a
+    /// previous partial download. The file is only moved into place once
+    /// `check` accepts its contents; a rejected file is deleted.
+    pub fn download(
+        &self,
+        url: &str,
+        path: &Path,
+        check: impl FnOnce(&[u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let write_error = |source| Error::Write {
+            path: partial.clone(),
+            source,
+        };
+
+        let offset = std::fs::metadata(&partial).map_or(0, |meta| meta.len());
+        let mut request = self.agent.get(url);
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+        match request.call() {
+            // Partial content: append to what is there.
+            Ok(response) if response.status() == 206 => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(&partial)
+                    .map_err(write_error)?;
+                copy(url, response, file, &partial)?;
+            }
+            Ok(response) => {
+                let file = File::create(&partial).map_err(write_error)?;
+                copy(url, response, file, &partial)?;
+            }
+            // The part file already holds everything.
+            Err(ureq::Error::Status(416, _)) if offset > 0 => (),
+            Err(e) => return Err(http(url, e)),
+        }
+
+        let data = std::fs::read(&partial).map_err(write_error)?;
+        if let Err(e) = check(&data) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+        std::fs::rename(&partial, path).map_err(|source| Error::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+fn http(url: &str, error: ureq::Error) -> Error {
+    Error::Http {
+        url: url.to_string(),
+        source: Box::new(error),
+    }
+}
+
+fn copy(url: &str, response: ureq::Response, mut file: File, path: &Path) -> Result<(), Error> {
+    let mut reader = response.into_reader();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buffer).map_err(|source| Error::Read {
+            url: url.to_string(),
+            source,
+        })?;
+        if n == 0 {
+            return Ok(());
+        }
+        file.write_all(&buffer[..n])
+            .map_err(|source| Error::Write {
+                path: path.to_path_buf(),
+                source,
+            })?;
+    }
+}
+
+/// URL of `file` next to `index_url`.
+pub fn resolve(index_url: &str, file: &str) -> String {
+    match index_url.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/{file}"),
+        None => file.to_string(),
+    }
+}
+
+/// Directory cached responses are kept in: `bikesafe/http` in the user's
+/// cache directory (`$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`), or
+/// in the temporary directory.
+pub fn cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("bikesafe")
+        .join("http")
+}
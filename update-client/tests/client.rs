@@ -0,0 +1,117 @@
+//! The client against a local server that answers a fixed list of
+//! requests, recording their headers.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use firmware_manifest::IndexEntry;
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Response, Server};
+use update_client::{Client, Error};
+
+/// Status, headers and body of one answer.
+type Answer = (u16, Vec<(&'static str, &'static str)>, Vec<u8>);
+
+/// Serve `answers` in order; returns the base URL and, per request, its
+/// headers as `name: value` lines.
+fn serve(answers: Vec<Answer>) -> (String, mpsc::Receiver<Vec<String>>) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+    let (sent, received) = mpsc::channel();
+    thread::spawn(move || {
+        for (status, headers, body) in answers {
+            let request = server.recv().unwrap();
+            let seen = request
+                .headers()
+                .iter()
+                .map(|h| format!("{}: {}", h.field, h.value))
+                .collect();
+            let mut response = Response::from_data(body).with_status_code(status);
+            for (name, value) in headers {
+                response.add_header(Header::from_bytes(name, value).unwrap());
+            }
+            request.respond(response).unwrap();
+            sent.send(seen).unwrap();
+        }
+    });
+    (url, received)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn has(headers: &[String], header: &str) -> bool {
+    headers.iter().any(|h| h.eq_ignore_ascii_case(header))
+}
+
+#[test]
+fn unchanged_response_comes_from_the_cache() {
+    let (url, requests) = serve(vec![
+        (200, vec![("ETag", "\"v1\"")], b"index".to_vec()),
+        (304, vec![("ETag", "\"v1\"")], Vec::new()),
+    ]);
+    let client = Client::new("test").with_cache_dir(temp_dir("cache-etag"));
+    let url = format!("{url}/stable/index.json");
+
+    assert_eq!(client.get(&url).unwrap(), b"index");
+    assert!(!has(&requests.recv().unwrap(), "If-None-Match: \"v1\""));
+    assert_eq!(client.get(&url).unwrap(), b"index");
+    assert!(has(&requests.recv().unwrap(), "If-None-Match: \"v1\""));
+}
+
+#[test]
+fn interrupted_download_resumes() {
+    let (url, requests) = serve(vec![(
+        206,
+        vec![("Content-Range", "bytes 4-9/10")],
+        b"456789".to_vec(),
+    )]);
+    let dir = temp_dir("resume");
+    let path = dir.join("firmware.dfu");
+    std::fs::write(dir.join("firmware.dfu.part"), b"0123").unwrap();
+
+    Client::new("test")
+        .download(&format!("{url}/firmware.dfu"), &path, |_| Ok(()))
+        .unwrap();
+    assert!(has(&requests.recv().unwrap(), "Range: bytes=4-"));
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+    assert!(!dir.join("firmware.dfu.part").exists());
+}
+
+#[test]
+fn complete_part_file_is_taken_on_416() {
+    let (url, _requests) = serve(vec![(416, Vec::new(), Vec::new())]);
+    let dir = temp_dir("complete");
+    let path = dir.join("firmware.dfu");
+    std::fs::write(dir.join("firmware.dfu.part"), b"0123456789").unwrap();
+
+    Client::new("test")
+        .download(&format!("{url}/firmware.dfu"), &path, |_| Ok(()))
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+}
+
+#[test]
+fn file_with_the_wrong_hash_is_removed() {
+    let (url, _requests) = serve(vec![(200, Vec::new(), b"tampered!!".to_vec())]);
+    let dir = temp_dir("hash");
+    let entry = IndexEntry {
+        file: "firmware.dfu".into(),
+        size: 10,
+        sha256: hex::encode(Sha256::digest(b"0123456789")),
+        version: Some("1.4.2".into()),
+    };
+
+    let result = Client::new("test").download_entry(&format!("{url}/index.json"), &entry, &dir);
+    assert!(
+        matches!(result, Err(Error::HashMismatch { .. })),
+        "{result:?}"
+    );
+    assert!(!dir.join("firmware.dfu").exists());
+    assert!(!dir.join("firmware.dfu.part").exists());
+}